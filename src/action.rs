@@ -0,0 +1,235 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::apu::mixer::Channel;
+use crate::input_config::BUTTONS;
+use crate::joypads::JoypadButton;
+use crate::volume::Volume;
+
+// Everything a player (or a tool) can ask of the emulator, whichever way they ask: a hotkey in
+// the window, a command in a `--console` or `--remote` session, a script piped into one. The
+// control surfaces only turn their input into an Action and hand it to the one place that
+// performs them all (main.rs, `perform`), so whatever is added here can be reached from every
+// one of them at once. The text form is what the console takes:
+//
+//   save-state [slot]         load-state [slot]    slots 0-9; 0, the default, is F11/F12's
+//   turbo <player> <button>   auto-fire on or off for a button: turbo 1 b
+//   screenshot                the picture, as game.<frame>.png next to the ROM
+//   record-audio              starts or stops writing the sound to game.<frame>.wav
+//   speed <percent>           speed +<steps>, speed -<steps>: 5% steps (see crate::speed)
+//   overlay <name>            a debug overlay on or off: sprite0, ruler, heatmap, apu
+//   volume <percent>          volume +<steps>, volume -<steps>: 10% steps, for every game
+//   game-volume <percent>     the same for this game only (see crate::volume)
+//   mute                      all the sound off or on again, the volumes kept
+//   mute <channel>            a sound channel off or on: pulse1, pulse2, triangle, noise, dmc,
+//   solo <channel>            cartridge; solo plays only that one, or all again
+//   unmute-all
+//   barcode <digits>          swipes a card through the Datach's reader: barcode 4902425123455
+//   next-barcode              swipes the next card from --barcodes
+//   reset-timer               stops the speedrun timer's run (see crate::speedrun)
+//   export-splits             the timer's best run and segments, as game.lss for LiveSplit
+//   power                     off and on again; battery saves survive it
+//   reset   next-disk   export-dumps   import-dumps
+//
+// The debugger's controls (resume, step out, ...) aren't actions: they steer the session, not
+// the game, and stay console commands.
+
+pub const SLOTS: u8 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    SaveState(u8),
+    LoadState(u8),
+    ToggleTurbo(usize, JoypadButton), // player 0 or 1
+    Screenshot,
+    ToggleAudioRecording,
+    SetSpeed(u32),    // percent
+    AdjustSpeed(i32), // in speed::STEP_PERCENT steps, negative for slower
+    SetVolume(Volume, u32),    // percent
+    AdjustVolume(Volume, i32), // in volume::STEP_PERCENT steps, negative for quieter
+    ToggleMasterMute,
+    ToggleOverlay(String),
+    ToggleMute(Channel),
+    Solo(Channel),
+    UnmuteAll,
+    Barcode(String),
+    NextBarcode,
+    ResetTimer,
+    ExportSplits,
+    Reset,
+    Power,
+    NextDisk,
+    ExportDumps,
+    ImportDumps,
+}
+
+impl Action {
+    pub fn parse(text: &str) -> Result<Action, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let Some((name, args)) = words.split_first() else {
+            return Err("no action".to_string());
+        };
+        let bad = || format!("can't do '{}'", text.trim());
+        let action = match (name.to_ascii_lowercase().as_str(), args) {
+            ("save-state", []) => Action::SaveState(0),
+            ("save-state", [slot]) => Action::SaveState(parse_slot(slot)?),
+            ("load-state", []) => Action::LoadState(0),
+            ("load-state", [slot]) => Action::LoadState(parse_slot(slot)?),
+            ("turbo", [player, button]) => {
+                let player = match *player {
+                    "1" => 0,
+                    "2" => 1,
+                    _ => return Err(format!("'{}' is not player 1 or 2", player)),
+                };
+                let button = BUTTONS
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(button))
+                    .ok_or_else(|| format!("'{}' is not a button", button))?;
+                Action::ToggleTurbo(player, button.1)
+            }
+            ("screenshot", []) => Action::Screenshot,
+            ("record-audio", []) => Action::ToggleAudioRecording,
+            ("speed", [percent]) => {
+                let number = |digits: &str| digits.parse().map_err(|_| format!("'{}' is not a speed", percent));
+                if let Some(steps) = percent.strip_prefix('+') {
+                    Action::AdjustSpeed(number(steps)?)
+                } else if let Some(steps) = percent.strip_prefix('-') {
+                    Action::AdjustSpeed(-number(steps)?)
+                } else {
+                    Action::SetSpeed(number(percent.trim_end_matches('%'))? as u32)
+                }
+            }
+            ("volume", [percent]) => parse_volume(Volume::Master, percent)?,
+            ("game-volume", [percent]) => parse_volume(Volume::Game, percent)?,
+            ("mute", []) => Action::ToggleMasterMute,
+            ("overlay", [name]) => Action::ToggleOverlay(name.to_string()),
+            ("mute", [channel]) => Action::ToggleMute(parse_channel(channel)?),
+            ("solo", [channel]) => Action::Solo(parse_channel(channel)?),
+            ("unmute-all", []) => Action::UnmuteAll,
+            ("barcode", [digits]) => Action::Barcode(digits.to_string()),
+            ("next-barcode", []) => Action::NextBarcode,
+            ("reset-timer", []) => Action::ResetTimer,
+            ("export-splits", []) => Action::ExportSplits,
+            ("reset", []) => Action::Reset,
+            ("power", []) => Action::Power,
+            ("next-disk", []) => Action::NextDisk,
+            ("export-dumps", []) => Action::ExportDumps,
+            ("import-dumps", []) => Action::ImportDumps,
+            _ => return Err(bad()),
+        };
+        Ok(action)
+    }
+}
+
+fn parse_slot(text: &str) -> Result<u8, String> {
+    match text.parse() {
+        Ok(slot) if slot < SLOTS => Ok(slot),
+        _ => Err(format!("'{}' is not a slot (0-{})", text, SLOTS - 1)),
+    }
+}
+
+fn parse_volume(which: Volume, percent: &str) -> Result<Action, String> {
+    let number = |digits: &str| digits.parse().map_err(|_| format!("'{}' is not a volume", percent));
+    let action = if let Some(steps) = percent.strip_prefix('+') {
+        Action::AdjustVolume(which, number(steps)?)
+    } else if let Some(steps) = percent.strip_prefix('-') {
+        Action::AdjustVolume(which, -number(steps)?)
+    } else {
+        Action::SetVolume(which, number(percent.trim_end_matches('%'))? as u32)
+    };
+    Ok(action)
+}
+
+fn parse_channel(text: &str) -> Result<Channel, String> {
+    Channel::from_name(text).ok_or_else(|| format!("'{}' is not a sound channel", text))
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::SaveState(slot) => write!(f, "save-state {}", slot),
+            Action::LoadState(slot) => write!(f, "load-state {}", slot),
+            Action::ToggleTurbo(player, button) => {
+                let name = BUTTONS.iter().find(|(_, b)| b == button).map_or("?", |(name, _)| name);
+                write!(f, "turbo {} {}", player + 1, name)
+            }
+            Action::Screenshot => write!(f, "screenshot"),
+            Action::ToggleAudioRecording => write!(f, "record-audio"),
+            Action::SetSpeed(percent) => write!(f, "speed {}", percent),
+            Action::AdjustSpeed(steps) => write!(f, "speed {:+}", steps),
+            Action::SetVolume(which, percent) => write!(f, "{} {}", which.name(), percent),
+            Action::AdjustVolume(which, steps) => write!(f, "{} {:+}", which.name(), steps),
+            Action::ToggleMasterMute => write!(f, "mute"),
+            Action::ToggleOverlay(name) => write!(f, "overlay {}", name),
+            Action::ToggleMute(channel) => write!(f, "mute {}", channel.name()),
+            Action::Solo(channel) => write!(f, "solo {}", channel.name()),
+            Action::UnmuteAll => write!(f, "unmute-all"),
+            Action::Barcode(digits) => write!(f, "barcode {}", digits),
+            Action::NextBarcode => write!(f, "next-barcode"),
+            Action::ResetTimer => write!(f, "reset-timer"),
+            Action::ExportSplits => write!(f, "export-splits"),
+            Action::Reset => write!(f, "reset"),
+            Action::Power => write!(f, "power"),
+            Action::NextDisk => write!(f, "next-disk"),
+            Action::ExportDumps => write!(f, "export-dumps"),
+            Action::ImportDumps => write!(f, "import-dumps"),
+        }
+    }
+}
+
+// game.nes -> game.state for slot 0 (the file quick save always used), game.3.state for slot 3.
+pub fn state_path(rom_path: impl AsRef<Path>, slot: u8) -> PathBuf {
+    match slot {
+        0 => rom_path.as_ref().with_extension("state"),
+        slot => rom_path.as_ref().with_extension(format!("{}.state", slot)),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_form() {
+        let actions = [
+            Action::SaveState(3),
+            Action::LoadState(0),
+            Action::ToggleTurbo(1, JoypadButton::BUTTON_B),
+            Action::Screenshot,
+            Action::ToggleAudioRecording,
+            Action::SetSpeed(75),
+            Action::AdjustSpeed(-2),
+            Action::SetVolume(Volume::Master, 40),
+            Action::AdjustVolume(Volume::Game, -3),
+            Action::ToggleMasterMute,
+            Action::ToggleOverlay("heatmap".to_string()),
+            Action::ToggleMute(Channel::Dmc),
+            Action::Solo(Channel::Pulse2),
+            Action::UnmuteAll,
+            Action::Barcode("96385074".to_string()),
+            Action::NextBarcode,
+            Action::ResetTimer,
+            Action::ExportSplits,
+            Action::Reset,
+            Action::Power,
+            Action::NextDisk,
+            Action::ExportDumps,
+            Action::ImportDumps,
+        ];
+        for action in actions {
+            assert_eq!(Action::parse(&action.to_string()), Ok(action));
+        }
+        assert_eq!(Action::parse("save-state"), Ok(Action::SaveState(0)));
+        assert_eq!(Action::parse("speed +1"), Ok(Action::AdjustSpeed(1)));
+        assert_eq!(Action::parse("game-volume 80%"), Ok(Action::SetVolume(Volume::Game, 80)));
+        assert!(Action::parse("volume loud").is_err());
+        assert_eq!(Action::parse("TURBO 1 A"), Ok(Action::ToggleTurbo(0, JoypadButton::BUTTON_A)));
+        assert!(Action::parse("save-state 10").is_err());
+        assert!(Action::parse("turbo 3 a").is_err());
+        assert_eq!(Action::parse("mute square"), Err("'square' is not a sound channel".to_string()));
+        assert_eq!(Action::parse("jump"), Err("can't do 'jump'".to_string()));
+
+        assert_eq!(state_path("games/smb.nes", 0), Path::new("games/smb.state"));
+        assert_eq!(state_path("games/smb.nes", 3), Path::new("games/smb.3.state"));
+    }
+}
@@ -0,0 +1,104 @@
+use std::f64::consts::PI;
+
+// Band-limited resampling from the CPU clock (~1.79MHz) down to the audio device's rate, the
+// way blargg's Blip_Buffer does it. Taking every 40th or so level would alias: the channels
+// are square waves, full of harmonics far above 22kHz, and they'd fold back down as
+// audible whistles. Instead the APU only reports the moments its output changes, as a delta
+// at a clock time, and each one is added into the output as a band-limited step: a short
+// windowed sinc kernel, picked from PHASES precomputed ones by where between two output
+// samples the change fell. Reading sums the deltas up (integrates) into the waveform.
+//
+// Time is counted in clocks since the start of the current frame; end_frame says how long
+// the frame was, and the samples up to there can be read. What's left over, a fraction of a
+// sample and the kernel tails still to come, carries into the next frame.
+// See: http://slack.net/~ant/bl-synth/
+
+const PHASES: usize = 32;
+const WIDTH: usize = 16; // taps per kernel, so the output lags the input by WIDTH / 2 samples
+const CUTOFF: f64 = 0.9; // of the output's Nyquist frequency, leaving room for the window
+
+pub struct Blip {
+    samples_per_clock: f64,
+    offset: f64, // the start of the frame, in samples from buffer[0]
+    buffer: Vec<f32>, // deltas not read yet
+    integrator: f32,
+    kernels: Vec<[f32; WIDTH]>,
+}
+
+impl Blip {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        let kernels = (0..PHASES)
+            .map(|phase| {
+                let shift = phase as f64 / PHASES as f64;
+                let mut kernel = [0.0; WIDTH];
+                for (i, tap) in kernel.iter_mut().enumerate() {
+                    let x = i as f64 - (WIDTH / 2) as f64 - shift;
+                    let sinc = if x == 0.0 { 1.0 } else { (PI * CUTOFF * x).sin() / (PI * CUTOFF * x) };
+                    let window = 0.42 + 0.5 * (PI * x / (WIDTH / 2) as f64).cos()
+                        + 0.08 * (2.0 * PI * x / (WIDTH / 2) as f64).cos();
+                    *tap = (sinc * window) as f32;
+                }
+                // each kernel adds up to exactly 1, so a step ends up exactly `delta` high
+                let sum: f32 = kernel.iter().sum();
+                kernel.iter_mut().for_each(|tap| *tap /= sum);
+                kernel
+            })
+            .collect();
+        Blip {
+            samples_per_clock: sample_rate / clock_rate,
+            offset: 0.0,
+            buffer: vec![0.0; WIDTH],
+            integrator: 0.0,
+            kernels,
+        }
+    }
+
+    // The output steps by `delta` at `clock` clocks into the frame.
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let time = self.offset + clock as f64 * self.samples_per_clock;
+        let index = time as usize;
+        let phase = ((time - index as f64) * PHASES as f64) as usize;
+        if self.buffer.len() < index + WIDTH {
+            self.buffer.resize(index + WIDTH, 0.0);
+        }
+        for (slot, tap) in self.buffer[index..].iter_mut().zip(&self.kernels[phase.min(PHASES - 1)]) {
+            *slot += delta * tap;
+        }
+    }
+
+    // Ends the frame after `clocks` clocks and hands over the samples it completed.
+    pub fn end_frame(&mut self, clocks: u32, mut out: impl FnMut(f32)) {
+        let end = self.offset + clocks as f64 * self.samples_per_clock;
+        let count = end as usize;
+        if self.buffer.len() < count + WIDTH {
+            self.buffer.resize(count + WIDTH, 0.0);
+        }
+        for delta in self.buffer.drain(..count) {
+            self.integrator += delta;
+            out(self.integrator);
+        }
+        self.offset = end - count as f64;
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_step_and_rate() {
+        let mut blip = Blip::new(1_789_773.0, 44_100.0);
+        let mut samples = vec![];
+        blip.add_delta(100, 0.5);
+        for _ in 0..60 {
+            blip.end_frame(29781, |sample| samples.push(sample));
+        }
+        // a second of frames is a second of samples, give or take the last fraction
+        assert!((samples.len() as i32 - 44_028).abs() <= 1);
+
+        // the step rises smoothly (with a little ringing) and settles at its height
+        assert!(samples[0].abs() < 0.01);
+        assert!(samples[100..].iter().all(|&sample| (sample - 0.5).abs() < 1e-4));
+        assert!(samples[..20].iter().all(|&sample| sample < 0.6 && sample > -0.075));
+    }
+}
@@ -0,0 +1,264 @@
+// The delta modulation channel ($4010-$4013) plays 1-bit delta encoded samples straight out
+// of cartridge memory: drums, bass and voices in the games that can spare the ROM.
+//
+//   $4010  IL-- RRRR  IRQ enable, loop, rate index
+//   $4011  -DDD DDDD  output level, loaded directly (games use it for raw 7-bit PCM)
+//   $4012  AAAA AAAA  sample address, $C000 + A * 64
+//   $4013  LLLL LLLL  sample length, L * 16 + 1 bytes
+//
+// Every `rate` CPU cycles the output unit takes one bit of the current byte and moves the
+// level up or down by 2. Once the byte is used up it takes the next one from the sample
+// buffer, which the memory reader refills by fetching from the cartridge: a DMA that halts
+// the CPU for a few cycles, done by the Bus (see Bus::tick) since the channel can't reach
+// memory itself. At the end of the sample it either loops or raises its IRQ.
+// See: https://www.nesdev.org/wiki/APU_DMC
+
+use crate::mapper::state::{StateReader, StateWriter};
+
+// CPU cycles per output bit (NTSC).
+const RATE_TABLE: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+#[derive(Debug, Clone)]
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    rate: u16,
+    timer: u16,
+    level: u8, // 0-127
+    sample_addr: u16,
+    sample_length: u16,
+
+    // memory reader
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    // output unit
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    irq: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            rate: RATE_TABLE[0],
+            timer: RATE_TABLE[0] - 1,
+            level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    // `reg` is 0-3, the register's offset from $4010.
+    pub fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.rate = RATE_TABLE[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_addr = 0xC000 + data as u16 * 64,
+            _ => self.sample_length = data as u16 * 16 + 1,
+        }
+    }
+
+    // Bit 4 of a $4015 write. Enabling only starts the sample over if the last one has
+    // finished; disabling stops it after the byte already in the buffer.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    // The reset button clears the output level, all but its lowest bit.
+    pub fn reset(&mut self) {
+        self.level &= 1;
+    }
+
+    // Any write to $4015 acknowledges the IRQ.
+    pub fn clear_irq(&mut self) {
+        self.irq = false;
+    }
+
+    // Where the memory reader wants its next byte from, while the buffer is empty and the
+    // sample isn't over.
+    pub fn fetch_address(&self) -> Option<u16> {
+        (self.buffer.is_none() && self.bytes_remaining > 0).then_some(self.current_addr)
+    }
+
+    // The byte fetched from fetch_address().
+    pub fn fill(&mut self, byte: u8) {
+        self.buffer = Some(byte);
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000); // wraps to $8000
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Once per CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.shift = byte;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // 0-127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state
+            .bool(self.irq_enabled)
+            .bool(self.looping)
+            .u16(self.rate)
+            .u16(self.timer)
+            .u8(self.level)
+            .u16(self.sample_addr)
+            .u16(self.sample_length)
+            .u16(self.current_addr)
+            .u16(self.bytes_remaining)
+            .bool(self.buffer.is_some())
+            .u8(self.buffer.unwrap_or(0))
+            .u8(self.shift)
+            .u8(self.bits_remaining)
+            .bool(self.silence)
+            .bool(self.irq);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = state.bool()?;
+        self.looping = state.bool()?;
+        self.rate = state.u16()?.max(1); // clock_timer reloads from rate - 1
+        self.timer = state.u16()?;
+        self.level = state.u8()? & 0x7F;
+        self.sample_addr = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.current_addr = state.u16()?;
+        self.bytes_remaining = state.u16()?;
+        let (buffered, byte) = (state.bool()?, state.u8()?);
+        self.buffer = buffered.then_some(byte);
+        self.shift = state.u8()?;
+        self.bits_remaining = state.u8()?.clamp(1, 8);
+        self.silence = state.bool()?;
+        self.irq = state.bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // Runs the channel for `cycles` CPU cycles, serving its fetches from `memory` (at $C000).
+    fn run(dmc: &mut Dmc, memory: &[u8], cycles: usize) -> usize {
+        let mut fetches = 0;
+        for _ in 0..cycles {
+            dmc.clock_timer();
+            if let Some(addr) = dmc.fetch_address() {
+                dmc.fill(memory[(addr - 0xC000) as usize]);
+                fetches += 1;
+            }
+        }
+        fetches
+    }
+
+    #[test]
+    fn test_sample_plays_and_raises_irq() {
+        let memory = [0xFF; 17];
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x8F); // IRQ on, fastest rate (54 cycles a bit)
+        dmc.write(1, 0x40);
+        dmc.write(2, 0x00); // $C000
+        dmc.write(3, 0x01); // 17 bytes
+        dmc.set_enabled(true);
+        assert!(dmc.is_active());
+
+        // the timer still runs at the power-on rate until its first bit, then the first byte
+        // moves into the shifter after 8 bits and the buffer is refilled
+        assert_eq!(run(&mut dmc, &memory, 428 + 54 * 8), 2);
+        assert!(dmc.output() > 0x40); // all ones: the level only goes up
+        run(&mut dmc, &memory, 54 * 8 * 16);
+        assert!(!dmc.is_active());
+        assert!(dmc.irq_pending());
+        assert_eq!(dmc.output(), 0x7F - 1); // clamped: the last step up doesn't fit
+
+        // looping: the sample never ends, and no IRQ
+        dmc.clear_irq();
+        dmc.write(0, 0xCF);
+        dmc.set_enabled(true);
+        assert!(run(&mut dmc, &memory, 54 * 8 * 40) >= 39); // one byte every 8 bits
+        assert!(dmc.is_active());
+        assert!(!dmc.irq_pending());
+
+        dmc.set_enabled(false);
+        assert!(!dmc.is_active());
+        assert_eq!(dmc.fetch_address(), None);
+    }
+}
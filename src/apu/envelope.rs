@@ -0,0 +1,62 @@
+use crate::mapper::state::{StateReader, StateWriter};
+
+// The envelope gives a channel either a constant volume or a decay: from 15 down to 0, one
+// step every (V + 1) quarter frames, where V is the low nibble of the channel's first
+// register. With the loop flag set it starts again from 15 instead of staying silent.
+// Writing the channel's last register restarts the decay on the next quarter frame.
+// See: https://www.nesdev.org/wiki/APU_Envelope
+
+#[derive(Debug, Default, Clone)]
+pub struct Envelope {
+    pub constant: bool,
+    pub looping: bool,
+    pub volume: u8, // the constant volume, or the decay's divider period
+    start: bool,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state.bool(self.constant).bool(self.looping).u8(self.volume).bool(self.start).u8(self.divider).u8(self.decay);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.constant = state.bool()?;
+        self.looping = state.bool()?;
+        self.volume = state.u8()?;
+        self.start = state.bool()?;
+        self.divider = state.u8()?;
+        self.decay = state.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::f32::consts::PI;
+
+// What the console does to its sound between the APU and the TV: the output stage has two
+// first-order high-pass filters, at 90Hz and 440Hz, and a first-order low-pass at 14kHz. The
+// high-passes take out the DC offset of the mixer (silence is 0.0, a note is all positive)
+// and thin out the bass a little; the low-pass softens the edges of the square waves. Run at
+// the output sample rate, after the resampler, on the mixer's 0.0-1.0 output. Without them
+// the sound is the APU's exact output, which some players prefer, but rides on a DC offset.
+// See: https://www.nesdev.org/wiki/APU_Mixer
+
+pub const HIGH_PASS_1: f32 = 90.0; // Hz
+pub const HIGH_PASS_2: f32 = 440.0;
+pub const LOW_PASS: f32 = 14_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    HighPass,
+    LowPass,
+}
+
+// One RC filter, as difference equations:
+//   high-pass  y[n] = a * (y[n-1] + x[n] - x[n-1])  a = RC / (RC + dt)
+//   low-pass   y[n] = y[n-1] + a * (x[n] - y[n-1])  a = dt / (RC + dt)
+#[derive(Debug, Clone)]
+struct FirstOrder {
+    kind: Kind,
+    a: f32,
+    last_in: f32,
+    last_out: f32,
+}
+
+impl FirstOrder {
+    fn new(kind: Kind, cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let a = match kind {
+            Kind::HighPass => rc / (rc + dt),
+            Kind::LowPass => dt / (rc + dt),
+        };
+        FirstOrder { kind, a, last_in: 0.0, last_out: 0.0 }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = match self.kind {
+            Kind::HighPass => self.a * (self.last_out + sample - self.last_in),
+            Kind::LowPass => self.last_out + self.a * (sample - self.last_out),
+        };
+        self.last_in = sample;
+        self.last_out = out;
+        out
+    }
+}
+
+pub struct OutputFilters {
+    enabled: bool,
+    stages: [FirstOrder; 3],
+}
+
+impl OutputFilters {
+    pub fn new(sample_rate: f32) -> Self {
+        OutputFilters {
+            enabled: true,
+            stages: [
+                FirstOrder::new(Kind::HighPass, HIGH_PASS_1, sample_rate),
+                FirstOrder::new(Kind::HighPass, HIGH_PASS_2, sample_rate),
+                FirstOrder::new(Kind::LowPass, LOW_PASS, sample_rate),
+            ],
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        if !self.enabled {
+            return sample;
+        }
+        self.stages.iter_mut().fold(sample, |sample, stage| stage.process(sample))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // Peak output for a full-scale sine at `freq`, once the filters have settled.
+    fn gain(filters: &mut OutputFilters, freq: f32) -> f32 {
+        let samples = (0..44_100).map(|n| (2.0 * PI * freq * n as f32 / 44_100.0).sin());
+        let out: Vec<f32> = samples.map(|sample| filters.process(sample)).collect();
+        out[22_050..].iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut filters = OutputFilters::new(44_100.0);
+        // DC goes away
+        let settled = (0..44_100).fold(0.0, |_, _| filters.process(0.5));
+        assert!(settled.abs() < 1e-3);
+
+        // the mids pass, the deep bass and the top end are cut
+        assert!(gain(&mut filters, 2_000.0) > 0.9);
+        assert!(gain(&mut filters, 50.0) < 0.15);
+        assert!(gain(&mut filters, 18_000.0) < 0.75);
+
+        filters.set_enabled(false);
+        assert_eq!(filters.process(0.5), 0.5);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::mapper::state::{StateReader, StateWriter};
+
+// The length counter silences a channel after a set time: games write a note's length along
+// with its pitch and let the APU cut it off. Writing the channel's last register loads the
+// counter from LENGTH_TABLE (indexed by the top 5 bits of the write), every half frame counts
+// it down, and the channel goes quiet at 0. The halt flag (shared with the envelope's loop
+// flag) stops the counting, for notes the game ends itself.
+// See: https://www.nesdev.org/wiki/APU_Length_Counter
+
+pub const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct LengthCounter {
+    counter: u8,
+    pub halt: bool,
+    enabled: bool, // the channel's bit in $4015
+}
+
+impl LengthCounter {
+    // Loads are ignored while the channel is disabled in $4015.
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    // Disabling a channel also clears its counter, silencing it right away.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state.u8(self.counter).bool(self.halt).bool(self.enabled);
+    }
+
+    // From a savestate; load() is the register write.
+    pub fn restore(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.counter = state.u8()?;
+        self.halt = state.bool()?;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // The table isn't arbitrary: with bit 0 of the index set it holds lengths 254, then 2-30 in
+    // steps of 2; with it clear, note lengths at two tempos (10, 20, 40, ... in the first half,
+    // 12, 24, 48, ... in the second).
+    #[test]
+    fn test_load_values() {
+        let tempos = [[10, 20, 40, 80, 160, 60, 14, 26], [12, 24, 48, 96, 192, 72, 16, 32]];
+        for index in 0..32u8 {
+            let expected = match index {
+                1 => 254,
+                _ if index & 1 == 1 => index - 1,
+                _ => tempos[index as usize / 16][(index as usize % 16) / 2],
+            };
+            let mut length = LengthCounter::default();
+            length.set_enabled(true);
+            length.load(index | 0xE0); // only the low 5 bits count
+            assert_eq!(length.counter, expected, "index {}", index);
+        }
+    }
+
+    #[test]
+    fn test_halt_and_enable() {
+        // (halted, enabled, index loaded, half frames clocked, counter after)
+        let cases = [
+            (false, true, 3, 1, 1),    // 2, counted down once
+            (false, true, 3, 2, 0),    // run out: the channel goes quiet
+            (false, true, 3, 5, 0),    // and stays at 0
+            (true, true, 3, 5, 2),     // halted: doesn't count
+            (true, true, 1, 255, 254), // however long
+            (false, false, 3, 0, 0),   // disabled: the load is ignored
+            (true, false, 3, 0, 0),
+        ];
+        for (i, &(halt, enabled, index, clocks, counter)) in cases.iter().enumerate() {
+            let mut length = LengthCounter { halt, ..LengthCounter::default() };
+            length.set_enabled(enabled);
+            length.load(index);
+            for _ in 0..clocks {
+                length.clock_half_frame();
+            }
+            assert_eq!(length.counter, counter, "case {}", i);
+            assert_eq!(length.is_active(), counter > 0, "case {}", i);
+        }
+
+        // halting keeps the count, releasing picks up where it was
+        let mut length = LengthCounter::default();
+        length.set_enabled(true);
+        length.load(4); // 40
+        length.clock_half_frame();
+        length.halt = true;
+        length.clock_half_frame();
+        length.halt = false;
+        length.clock_half_frame();
+        assert_eq!(length.counter, 38);
+        length.set_enabled(false); // clears it, halted or not
+        assert!(!length.is_active());
+    }
+}
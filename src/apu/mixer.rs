@@ -0,0 +1,201 @@
+// The channels aren't added together: the 2A03 mixes them through resistor networks, one for
+// the two pulses and one for triangle, noise and DMC, and the result is non-linear. Two loud
+// channels are less than twice as loud as one, and a loud DMC sample audibly ducks the
+// triangle and noise. The formulas are the usual approximation:
+//
+//   pulse = 95.88 / (8128 / (pulse1 + pulse2) + 100)
+//   tnd   = 159.79 / (1 / (triangle / 8227 + noise / 12241 + dmc / 22638) + 100)
+//
+// each 0 when its inputs are all 0, so the output is 0.0 to about 1.0. This runs every CPU
+// cycle, so both come out of tables: the pulse one indexed by pulse1 + pulse2 (0-30), the tnd
+// one by 3 * triangle + 2 * noise + dmc (0-202), with its constants refitted to that index so
+// it stays within a percent or so of the exact formula.
+//
+// Cartridges with their own sound chips (VRC6, Namco 163, Sunsoft 5B, the Disk System) mix
+// it in on the cartridge connector, linearly, after the console's mix. Mappers report their
+// channels through Mapper::expansion_audio_sample and the Bus passes that on already scaled
+// to the chip's loudness, so the mixer only adds it. Each source has its own volume on top,
+// for players who find a chip too loud (or want to hear only the console).
+//
+// Channels can also be muted one by one, to hear what each plays (or check one against a
+// reference). A muted channel goes into the mix as 0, so the others sound as they would if
+// it were silent on the console, non-linearity included.
+//
+// Last, the master volume scales everything: how loud the player wants the emulator, set from
+// the frontend's volume controls (see crate::volume) rather than for any one chip.
+// See: https://www.nesdev.org/wiki/APU_Mixer
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Console,   // the 2A03's five channels
+    Cartridge, // the cartridge's sound chip, if it has one
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Cartridge, // all of the cartridge's sound chip
+}
+
+impl Channel {
+    pub const ALL: [Channel; 6] =
+        [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc, Channel::Cartridge];
+
+    // As the console and remote take them: "pulse1", "dmc", ...
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+            Channel::Cartridge => "cartridge",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Channel> {
+        Channel::ALL.into_iter().find(|channel| channel.name().eq_ignore_ascii_case(name))
+    }
+}
+
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+    volumes: [f32; 2], // by Source
+    muted: [bool; 6],  // by Channel
+    master: f32,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, out) in pulse_table.iter_mut().enumerate().skip(1) {
+            *out = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, out) in tnd_table.iter_mut().enumerate().skip(1) {
+            *out = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Mixer { pulse_table, tnd_table, volumes: [1.0; 2], muted: [false; 6], master: 1.0 }
+    }
+
+    // 1.0 is as loud as on the console, 0.0 silent.
+    pub fn set_volume(&mut self, source: Source, volume: f32) {
+        self.volumes[source as usize] = volume.max(0.0);
+    }
+
+    pub fn volume(&self, source: Source) -> f32 {
+        self.volumes[source as usize]
+    }
+
+    // 1.0 leaves the mix as it is, 0.0 silences it.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master = volume.max(0.0);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    // Mutes every channel but `channel`; if it was the only one playing already, unmutes them
+    // all instead, so the same solo switches it back.
+    pub fn solo(&mut self, channel: Channel) {
+        let soloed = Channel::ALL.iter().all(|other| self.muted(*other) == (*other != channel));
+        for other in Channel::ALL {
+            self.set_muted(other, !soloed && other != channel);
+        }
+    }
+
+    // The channel outputs: pulses, triangle and noise 0-15, DMC 0-127, and the cartridge's
+    // in the mixer's units (1.0 is the console's channels all at full volume).
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8, cartridge: f32) -> f32 {
+        let on = |channel: Channel, output: u8| if self.muted(channel) { 0 } else { output as usize };
+        let pulse = self.pulse_table[on(Channel::Pulse1, pulse1) + on(Channel::Pulse2, pulse2)];
+        let tnd = self.tnd_table[3 * on(Channel::Triangle, triangle) + 2 * on(Channel::Noise, noise) + on(Channel::Dmc, dmc)];
+        let cartridge = if self.muted(Channel::Cartridge) { 0.0 } else { cartridge };
+        let mix = (pulse + tnd) * self.volumes[Source::Console as usize]
+            + cartridge * self.volumes[Source::Cartridge as usize];
+        mix * self.master
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_mix_is_non_linear() {
+        let mixer = Mixer::new();
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0, 0.0), 0.0);
+
+        let one = mixer.mix(15, 0, 0, 0, 0, 0.0);
+        let two = mixer.mix(15, 15, 0, 0, 0, 0.0);
+        assert!(two > one && two < 2.0 * one);
+
+        // the table tracks the exact tnd formula closely
+        let exact = 159.79 / (1.0 / (15.0 / 8227.0 + 15.0 / 12241.0 + 127.0 / 22638.0) + 100.0);
+        assert!((mixer.mix(0, 0, 15, 15, 127, 0.0) - exact).abs() < 0.01);
+        let full = mixer.mix(15, 15, 15, 15, 127, 0.0);
+        assert!(full > 0.95 && full < 1.05);
+    }
+
+    #[test]
+    fn test_source_volumes() {
+        let mut mixer = Mixer::new();
+        let console = mixer.mix(15, 0, 0, 0, 0, 0.0);
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0, 0.25), console + 0.25); // the cartridge adds on
+
+        mixer.set_volume(Source::Cartridge, 0.5);
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0, 0.25), console + 0.125);
+        mixer.set_volume(Source::Console, 0.0);
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0, 0.25), 0.125);
+        mixer.set_volume(Source::Console, -1.0);
+        assert_eq!(mixer.volume(Source::Console), 0.0);
+    }
+
+    #[test]
+    fn test_master_volume() {
+        let mut mixer = Mixer::new();
+        let full = mixer.mix(15, 15, 15, 15, 127, 0.25);
+        mixer.set_master_volume(0.5);
+        assert_eq!(mixer.mix(15, 15, 15, 15, 127, 0.25), full * 0.5);
+        mixer.set_volume(Source::Cartridge, 0.0);
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0, 0.25), 0.0);
+        mixer.set_master_volume(-1.0);
+        assert_eq!(mixer.master_volume(), 0.0);
+    }
+
+    #[test]
+    fn test_mute_and_solo() {
+        let mut mixer = Mixer::new();
+        let triangle = mixer.mix(0, 0, 15, 0, 0, 0.0);
+        mixer.set_muted(Channel::Pulse1, true);
+        assert_eq!(mixer.mix(15, 0, 15, 0, 0, 0.0), triangle);
+        assert_eq!(mixer.mix(0, 15, 0, 0, 0, 0.0), Mixer::new().mix(0, 15, 0, 0, 0, 0.0)); // pulse 2 still plays
+
+        mixer.solo(Channel::Triangle);
+        assert_eq!(mixer.mix(15, 15, 15, 15, 127, 0.5), triangle);
+        assert!(Channel::ALL.iter().all(|c| mixer.muted(*c) == (*c != Channel::Triangle)));
+        mixer.solo(Channel::Triangle); // again: everything back on
+        assert!(Channel::ALL.iter().all(|c| !mixer.muted(*c)));
+        assert_eq!(Channel::from_name("DMC"), Some(Channel::Dmc));
+    }
+}
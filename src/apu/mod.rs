@@ -0,0 +1,594 @@
+use crate::audio::SAMPLE_RATE;
+use crate::mapper::state::{StateReader, StateWriter};
+use blip::Blip;
+use dmc::Dmc;
+use filter::OutputFilters;
+use mixer::{Channel, Mixer, Source};
+use noise::Noise;
+use pulse::Pulse;
+use scope::SharedScope;
+use stretch::{StretchMode, TimeStretch};
+use triangle::Triangle;
+
+pub mod blip;
+pub mod dmc;
+pub mod envelope;
+pub mod filter;
+pub mod length;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod scope;
+pub mod stretch;
+pub mod triangle;
+
+// The 2A03's audio processing unit, clocked by the Bus along with the PPU. Registers:
+//
+//   $4000-$4003  pulse 1        $4004-$4007  pulse 2        $4008-$400B  triangle
+//   $400C-$400F  noise          $4010-$4013  DMC
+//   $4015        write: channel enables; read: which channels are still running, the IRQs
+//   $4017        frame counter: MI-- ----  5-step mode, frame IRQ inhibit
+//
+// Every CPU cycle the channel outputs, and the cartridge's sound chip if it has one, go
+// through the mixer (see mixer.rs), and whenever the mixed level changes the change goes into
+// the resampler (see blip.rs). At the end of each video frame the Nes has the frame's worth
+// of samples moved into its AudioBuffer, at SAMPLE_RATE, for the frontend to play, through
+// the console's output filters unless they're turned off (see filter.rs). When the frontend
+// runs the game faster than the console, the last stage drops what there's no time to play
+// (see stretch.rs).
+//
+// The frame counter drives the envelopes, sweeps and length counters: four times a frame
+// (every "quarter frame") it clocks the envelopes, on every other one (a "half frame") the
+// sweeps and length counters too. In its power-on 4-step mode it also raises the frame IRQ at
+// the end of each sequence, unless inhibited; some games time their music (or split the
+// screen) with it. The 5-step mode adds a silent fifth step, so the steps come a little less
+// than four times a frame, and never raises the IRQ. Reading $4015 acknowledges the IRQ.
+//
+// A $4017 write doesn't restart the sequence at once: the new mode and the restart land 3 or
+// 4 CPU cycles later, depending on where in the APU's two-cycle clock the write falls. Only
+// the inhibit bit acts right away. Pressing reset writes 0 to $4015 (everything silent, the
+// length counters cleared) and writes $4017 again with what the game last put there, so the
+// mode survives but the sequence restarts; the DMC keeps its output level's low bit. Power-on
+// is $4017 written with 0, ready by the first instruction. blargg's apu_reset tests check all
+// of this.
+//
+// Savestates keep everything that decides what the channels play and when the IRQs come (see
+// save_state), but not the output side: the mixer's settings belong to the player, and the
+// resampler and filters just carry on from where they are.
+// See: https://www.nesdev.org/wiki/APU and https://www.nesdev.org/wiki/APU_Frame_Counter
+
+// CPU cycles into the sequence at which the steps fall, and whether each is a half frame too.
+const FOUR_STEP: [(u32, bool); 4] = [(7457, false), (14913, true), (22371, false), (29829, true)];
+const FIVE_STEP: [(u32, bool); 4] = [(7457, false), (14913, true), (22371, false), (37281, true)];
+const FOUR_STEP_LENGTH: u32 = 29830;
+const FIVE_STEP_LENGTH: u32 = 37282;
+
+pub const CPU_CLOCK: f64 = 1_789_773.0; // Hz, NTSC
+
+pub struct Apu {
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_cycle: u32, // CPU cycles into the frame sequence
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    odd_cycle: bool, // the channel timers run at half the CPU clock
+    frame_write: u8,       // the last value written to $4017, written again on reset
+    frame_write_delay: u8, // CPU cycles until it takes effect; 0 once it has
+
+    mixer: Mixer,
+    blip: Blip,
+    filters: OutputFilters,
+    stretch: TimeStretch,
+    frame_samples: Vec<f32>, // end_frame's, on their way to the stretch
+    cartridge: f32,   // the cartridge's sound, as the Bus last passed it on
+    level: f32,       // the mixed output, as the resampler last heard it
+    frame_clock: u32, // CPU cycles since end_frame
+    scope: Option<SharedScope>, // the visualizer's, see scope.rs
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulses: [Pulse::new(1), Pulse::new(2)],
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            odd_cycle: false,
+            frame_write: 0,
+            frame_write_delay: 0,
+            mixer: Mixer::new(),
+            blip: Blip::new(CPU_CLOCK, SAMPLE_RATE as f64),
+            filters: OutputFilters::new(SAMPLE_RATE as f32),
+            stretch: TimeStretch::new(),
+            frame_samples: vec![],
+            cartridge: 0.0,
+            level: 0.0,
+            frame_clock: 0,
+            scope: None,
+        }
+    }
+
+    // $4000-$4013, $4015 and $4017
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4007 => {
+                let pulse = &mut self.pulses[(addr as usize - 0x4000) / 4];
+                pulse.write(addr & 0b11, data);
+            }
+            0x4008..=0x400B => self.triangle.write(addr & 0b11, data),
+            0x400C..=0x400F => self.noise.write(addr & 0b11, data),
+            0x4010..=0x4013 => self.dmc.write(addr & 0b11, data),
+            0x4015 => {
+                for (i, pulse) in self.pulses.iter_mut().enumerate() {
+                    pulse.length.set_enabled(data & (1 << i) != 0);
+                }
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+                self.dmc.clear_irq();
+            }
+            0x4017 => {
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_write = data;
+                // 3 cycles if the write lands on the APU's clock, 4 if between (see tick)
+                self.frame_write_delay = if self.odd_cycle { 4 } else { 3 };
+            }
+            _ => {}
+        }
+    }
+
+    // $4015: bits 0-3 are set while the pulse, triangle and noise length counters are
+    // running, bit 4 while the DMC has sample bytes left, bit 6 and 7 while the frame and DMC IRQs are up.
+    // Reading it acknowledges the frame IRQ (but not the DMC's).
+    pub fn read_status(&mut self) -> u8 {
+        let pulses = self.pulses.iter().enumerate().fold(0, |status, (i, pulse)| {
+            status | (pulse.length.is_active() as u8) << i
+        });
+        let status = pulses
+            | (self.triangle.length.is_active() as u8) << 2
+            | (self.noise.length.is_active() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq_pending() as u8) << 7;
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc.irq_pending()
+    }
+
+    // The reset button; the CPU and the rest of the console are reset by their own.
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0x00);
+        self.frame_irq = false;
+        self.dmc.reset();
+        self.write_register(0x4017, self.frame_write);
+    }
+
+    // The delayed half of a $4017 write: the sequence starts over, and switching to 5-step
+    // mode clocks everything right away.
+    fn restart_frame_sequence(&mut self) {
+        self.five_step = self.frame_write & 0x80 != 0;
+        self.frame_cycle = 0;
+        if self.five_step {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    // The DMC's sample fetches go over the CPU bus, which the Bus does for it: it asks here
+    // after every tick and hands the byte back with dmc_fill.
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    pub fn dmc_fill(&mut self, byte: u8) {
+        self.dmc.fill(byte);
+    }
+
+    pub fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.frame_write_delay > 0 {
+                self.frame_write_delay -= 1;
+                if self.frame_write_delay == 0 {
+                    self.restart_frame_sequence();
+                }
+            }
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            self.odd_cycle = !self.odd_cycle;
+            if !self.odd_cycle {
+                self.pulses.iter_mut().for_each(Pulse::clock_timer);
+            }
+
+            self.frame_cycle += 1;
+            let (steps, length) = match self.five_step {
+                false => (FOUR_STEP, FOUR_STEP_LENGTH),
+                true => (FIVE_STEP, FIVE_STEP_LENGTH),
+            };
+            if let Some(&(_, half)) = steps.iter().find(|(at, _)| *at == self.frame_cycle) {
+                if let Some(scope) = &self.scope {
+                    scope.borrow_mut().step(self.frame_clock, half);
+                }
+                self.clock_quarter_frame();
+                if half {
+                    self.clock_half_frame();
+                }
+            }
+            // The IRQ flag is set over the last three cycles of the 4-step sequence, so
+            // acknowledging it on the first of them doesn't stick.
+            if !self.five_step && !self.irq_inhibit && self.frame_cycle >= FOUR_STEP_LENGTH - 2 {
+                self.frame_irq = true;
+            }
+            if self.frame_cycle == length {
+                self.frame_cycle = 0;
+            }
+
+            if let Some(scope) = &self.scope {
+                scope.borrow_mut().record(self.frame_clock, self.levels());
+            }
+            let level = self.mix();
+            if level != self.level {
+                self.blip.add_delta(self.frame_clock, level - self.level);
+                self.level = level;
+            }
+            self.frame_clock += 1;
+        }
+    }
+
+    // Each channel's output right now, before the mixer: 0-15, the DMC 0-127.
+    pub fn levels(&self) -> [u8; 5] {
+        let [pulse1, pulse2] = &self.pulses;
+        [pulse1.output(), pulse2.output(), self.triangle.output(), self.noise.output(), self.dmc.output()]
+    }
+
+    fn mix(&self) -> f32 {
+        let [pulse1, pulse2] = &self.pulses;
+        let (triangle, noise, dmc) = (self.triangle.output(), self.noise.output(), self.dmc.output());
+        self.mixer.mix(pulse1.output(), pulse2.output(), triangle, noise, dmc, self.cartridge)
+    }
+
+    // The cartridge's sound channels, mixed in from the next tick on (see mixer.rs).
+    pub fn set_cartridge_audio(&mut self, sample: f32) {
+        self.cartridge = sample;
+    }
+
+    pub fn set_volume(&mut self, source: Source, volume: f32) {
+        self.mixer.set_volume(source, volume);
+    }
+
+    pub fn volume(&self, source: Source) -> f32 {
+        self.mixer.volume(source)
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.mixer.set_master_volume(volume);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.mixer.master_volume()
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.mixer.set_muted(channel, muted);
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.mixer.muted(channel)
+    }
+
+    pub fn solo(&mut self, channel: Channel) {
+        self.mixer.solo(channel);
+    }
+
+    // Hands the samples resampled since the last call to `out`; the Nes calls it once a frame,
+    // to fill its AudioBuffer (and an AudioRecorder's file).
+    pub fn end_frame(&mut self, out: impl FnMut(f32)) {
+        let (filters, samples) = (&mut self.filters, &mut self.frame_samples);
+        samples.clear();
+        self.blip.end_frame(self.frame_clock, |sample| samples.push(filters.process(sample)));
+        self.stretch.process(&self.frame_samples, out);
+        if let Some(scope) = &self.scope {
+            scope.borrow_mut().end_frame(self.frame_cycle, self.five_step);
+        }
+        self.frame_clock = 0;
+    }
+
+    // Where the channels' levels go for the visualizer overlay; None stops them.
+    pub fn set_scope(&mut self, scope: Option<SharedScope>) {
+        self.scope = scope;
+    }
+
+    pub fn scope(&self) -> Option<SharedScope> {
+        self.scope.clone()
+    }
+
+    // How many frames the frontend runs for each one it shows, for the stretch.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.stretch.set_speed(speed);
+    }
+
+    pub fn set_stretch_mode(&mut self, mode: StretchMode) {
+        self.stretch.set_mode(mode);
+    }
+
+    pub fn stretch_mode(&self) -> StretchMode {
+        self.stretch.mode()
+    }
+
+    // The console's high-pass and low-pass filters on the output (on by default).
+    pub fn set_filters(&mut self, enabled: bool) {
+        self.filters.set_enabled(enabled);
+    }
+
+    pub fn filters(&self) -> bool {
+        self.filters.enabled()
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.envelope.clock_quarter_frame();
+        }
+        self.triangle.clock_quarter_frame();
+        self.noise.envelope.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulses.iter_mut().for_each(Pulse::clock_half_frame);
+        self.triangle.clock_half_frame();
+        self.noise.length.clock_half_frame();
+    }
+
+    // The savestate's "APU " chunk (see crate::savestate): the channels and the frame counter,
+    // with a $4017 write that hasn't landed yet. Written with the mappers' StateWriter, which
+    // their own sound channels use too.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        for pulse in &self.pulses {
+            pulse.save(&mut state);
+        }
+        self.triangle.save(&mut state);
+        self.noise.save(&mut state);
+        self.dmc.save(&mut state);
+        state
+            .u32(self.frame_cycle)
+            .bool(self.five_step)
+            .bool(self.irq_inhibit)
+            .bool(self.frame_irq)
+            .bool(self.odd_cycle)
+            .u8(self.frame_write)
+            .u8(self.frame_write_delay)
+            .finish()
+    }
+
+    // Checks the size before changing anything: a chunk that doesn't fit leaves the APU as it
+    // was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let expected = self.save_state().len();
+        if data.len() != expected {
+            return Err(format!("APU state: expected {} bytes, got {}", expected, data.len()));
+        }
+        let mut state = StateReader::new("APU", data);
+        for pulse in &mut self.pulses {
+            pulse.load(&mut state)?;
+        }
+        self.triangle.load(&mut state)?;
+        self.noise.load(&mut state)?;
+        self.dmc.load(&mut state)?;
+        self.frame_cycle = state.u32()?;
+        self.five_step = state.bool()?;
+        self.irq_inhibit = state.bool()?;
+        self.frame_irq = state.bool()?;
+        self.odd_cycle = state.bool()?;
+        self.frame_write = state.u8()?;
+        self.frame_write_delay = state.u8()?;
+        self.frame_cycle %= if self.five_step { FIVE_STEP_LENGTH } else { FOUR_STEP_LENGTH };
+        state.finish()
+    }
+
+    // `channel` is 0 for pulse 1, 1 for pulse 2.
+    pub fn pulse(&self, channel: usize) -> &Pulse {
+        &self.pulses[channel]
+    }
+
+    pub fn triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+
+    pub fn noise(&self) -> &Noise {
+        &self.noise
+    }
+
+    pub fn dmc(&self) -> &Dmc {
+        &self.dmc
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_pulse_plays_and_stops() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4003, 0x08); // disabled in $4015: the length isn't loaded
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b1001_1010); // 50% duty, constant volume 10
+        apu.write_register(0x4002, 0x20);
+        apu.write_register(0x4003, 0b0001_1000); // length index 3: 2 half frames
+        assert_eq!(apu.read_status(), 0b01);
+
+        // a square wave: 8 steps, each (period + 1) APU cycles long, high for 4 of them
+        let mut outputs = vec![];
+        for _ in 0..16 * 0x21 {
+            apu.tick(1);
+            outputs.push(apu.pulse(0).output());
+        }
+        assert!(outputs.iter().all(|&out| out == 0 || out == 10));
+        assert_eq!(outputs.iter().filter(|&&out| out == 10).count(), 8 * 0x21);
+        assert_eq!(apu.pulse(1).output(), 0);
+
+        // two half frames later the length counter has run out
+        apu.tick(255);
+        while apu.frame_cycle != 0 {
+            apu.tick(1);
+        }
+        assert_eq!(apu.read_status(), 0x40); // only the frame IRQ
+        assert_eq!(apu.pulse(0).output(), 0);
+    }
+
+    #[test]
+    fn test_length_halt_per_channel() {
+        // (halt register, halt bit, length register, $4015 bit)
+        let channels = [
+            (0x4000, 0x20, 0x4003, 0x01), // pulse 1
+            (0x4004, 0x20, 0x4007, 0x02), // pulse 2
+            (0x4008, 0x80, 0x400B, 0x04), // triangle
+            (0x400C, 0x20, 0x400F, 0x08), // noise
+        ];
+        for (halt_reg, halt_bit, length_reg, status) in channels {
+            let mut apu = Apu::new();
+            apu.write_register(0x4015, 0x0F);
+            apu.write_register(halt_reg, halt_bit);
+            apu.write_register(length_reg, 0b0001_1000); // 2 half frames
+            apu.clock_half_frame();
+            apu.clock_half_frame();
+            assert_eq!(apu.read_status() & 0x0F, status, "${:04X} halted", halt_reg);
+
+            apu.write_register(halt_reg, 0);
+            apu.clock_half_frame();
+            assert_eq!(apu.read_status() & 0x0F, status);
+            apu.clock_half_frame();
+            assert_eq!(apu.read_status() & 0x0F, 0, "${:04X} released", halt_reg);
+        }
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+        apu.tick(255);
+        while apu.frame_cycle < FOUR_STEP_LENGTH - 2 {
+            assert!(!apu.irq_pending());
+            apu.tick(1);
+        }
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq_pending()); // the read acknowledged it...
+        apu.tick(1);
+        assert!(apu.irq_pending()); // ...but the flag is still being set
+
+        apu.write_register(0x4017, 0x40); // inhibited
+        assert!(!apu.irq_pending());
+        for _ in 0..FOUR_STEP_LENGTH / 100 + 1 {
+            apu.tick(100);
+        }
+        assert!(!apu.irq_pending());
+
+        // 5-step mode: no IRQ, and the half frames come at the second and fifth step
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b0001_0000);
+        apu.write_register(0x4003, 0b0001_1000); // 2 half frames
+        apu.write_register(0x4017, 0x80); // one once the write lands
+        apu.tick(2);
+        assert_eq!(apu.read_status(), 0b01);
+        apu.tick(2);
+        assert_eq!(apu.read_status(), 0b01);
+        for _ in 0..FIVE_STEP[1].0 {
+            apu.tick(1);
+        }
+        assert_eq!(apu.read_status(), 0); // and the second
+        for _ in 0..FIVE_STEP_LENGTH {
+            apu.tick(1);
+        }
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_frame_counter_write_delay() {
+        let mut apu = Apu::new();
+        apu.tick(100);
+        apu.write_register(0x4017, 0x00);
+        apu.tick(2);
+        assert_eq!(apu.frame_cycle, 102); // not yet
+        apu.tick(1);
+        assert_eq!(apu.frame_cycle, 1); // 3 cycles after a write on the APU's clock
+
+        apu.tick(2);
+        apu.write_register(0x4017, 0x00);
+        apu.tick(3);
+        assert_eq!(apu.frame_cycle, 6);
+        apu.tick(1);
+        assert_eq!(apu.frame_cycle, 1); // 4 between
+    }
+
+    // blargg's apu_test, all eight of its tests in one ROM; it isn't shipped with the source, so
+    // this runs with `cargo test -- --ignored` once it's in test_roms/.
+    // See: https://github.com/christopherpow/nes-test-roms/tree/master/apu_test
+    #[test]
+    #[ignore = "needs test_roms/apu_test.nes"]
+    fn test_blargg_apu_test() {
+        use crate::nes::Nes;
+        use crate::test_status::TestStatus;
+
+        let rom = std::fs::read("test_roms/apu_test.nes").expect("test_roms/apu_test.nes");
+        let mut nes = Nes::new(&rom).unwrap();
+        for _ in 0..60 * 60 {
+            nes.run_frame();
+            match TestStatus::read(nes.prg_ram()) {
+                Some(status) if status.needs_reset() => {
+                    // the ROM asks for the button to be held a moment
+                    for _ in 0..10 {
+                        nes.run_frame();
+                    }
+                    nes.reset();
+                }
+                Some(status) if !status.running() => {
+                    assert!(status.passed(), "failed with code {}: {}", status.code, status.text);
+                    return;
+                }
+                _ => {}
+            }
+        }
+        panic!("no result after a minute: {:?}", TestStatus::read(nes.prg_ram()));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0x80);
+        apu.write_register(0x4015, 0b0_1111);
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.write_register(0x4011, 0x45);
+        apu.tick(200);
+        assert_eq!(apu.read_status(), 0b01);
+
+        apu.reset();
+        assert_eq!(apu.read_status(), 0); // $4015 cleared: the length counters too
+        assert_eq!(apu.dmc().output(), 1);
+        apu.tick(3);
+        assert!(apu.five_step); // the mode stays
+        assert_eq!(apu.frame_cycle, 1);
+        apu.write_register(0x4003, 0b0001_1000); // disabled: the length doesn't load
+        assert_eq!(apu.read_status(), 0);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length::LengthCounter;
+use crate::mapper::state::{StateReader, StateWriter};
+
+// The noise channel ($400C-$400F), for drums, explosions and wind:
+//
+//   reg 0  --LC VVVV  length counter halt / envelope loop, constant volume, volume
+//   reg 1  ---- ----  unused
+//   reg 2  M--- PPPP  mode, period index
+//   reg 3  LLLL L---  length counter load
+//
+// The output is pseudo-random bits from a 15 bit linear feedback shift register, clocked
+// every PERIOD_TABLE[P] CPU cycles. The feedback is bit 0 XOR bit 1, or bit 0 XOR bit 6 in
+// mode 1, which repeats after 93 steps and sounds metallic instead of hissing.
+// See: https://www.nesdev.org/wiki/APU_Noise
+
+// CPU cycles per shift (NTSC).
+const PERIOD_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+
+#[derive(Debug, Clone)]
+pub struct Noise {
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            short_mode: false,
+            period: PERIOD_TABLE[0],
+            timer: 0,
+            shift: 1, // loaded with 1 at power on; all zeros would never change
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    // `reg` is 0-3, the register's offset from $400C.
+    pub fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.looping = data & 0x20 != 0;
+                self.envelope.constant = data & 0x10 != 0;
+                self.envelope.volume = data & 0x0F;
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = PERIOD_TABLE[(data & 0x0F) as usize];
+            }
+            _ => {
+                self.length.load(data >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    // Once per CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift ^ self.shift >> tap) & 1;
+        self.shift = self.shift >> 1 | feedback << 14;
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state.bool(self.short_mode).u16(self.period).u16(self.timer).u16(self.shift);
+        self.envelope.save(state);
+        self.length.save(state);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.short_mode = state.bool()?;
+        self.period = state.u16()?.max(1); // clock_timer reloads from period - 1
+        self.timer = state.u16()?;
+        self.shift = state.u16()?;
+        self.envelope.load(state)?;
+        self.length.restore(state)
+    }
+
+    // 0-15; silent while bit 0 of the shift register is set.
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_short_mode_repeats() {
+        // mode 1 cycles through 93 states (from most seeds), the long mode through 32767
+        let mut noise = Noise::new();
+        noise.write(2, 0x80);
+        let period = |noise: &mut Noise| {
+            let start = noise.shift;
+            (1..=32767).find(|_| {
+                for _ in 0..4 {
+                    noise.clock_timer();
+                }
+                noise.shift == start
+            })
+        };
+        assert_eq!(period(&mut noise), Some(93));
+        noise.write(2, 0x00);
+        assert_eq!(period(&mut noise), Some(32767));
+    }
+}
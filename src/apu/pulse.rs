@@ -0,0 +1,194 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length::LengthCounter;
+use crate::mapper::state::{StateReader, StateWriter};
+
+// One of the two square wave channels ($4000-$4003 and $4004-$4007):
+//
+//   reg 0  DDLC VVVV  duty, length counter halt / envelope loop, constant volume, volume
+//   reg 1  EPPP NSSS  sweep: enabled, period, negate, shift
+//   reg 2  TTTT TTTT  timer period, low 8 bits
+//   reg 3  LLLL LTTT  length counter load, timer period high 3 bits
+//
+// The timer counts down once per APU cycle (every other CPU cycle) and each time it runs out
+// the sequencer moves one step through the 8 step duty pattern, so the pitch is
+// CPU clock / (16 * (period + 1)). The sweep unit bends the pitch by changing the period on
+// its own, for slides and "laser" effects.
+// See: https://www.nesdev.org/wiki/APU_Pulse and https://www.nesdev.org/wiki/APU_Sweep
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Debug, Default, Clone)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pulse {
+    // Pulse 1 negates with ones' complement (period - change - 1), pulse 2 with two's
+    // complement, so the same sweep setting slides them to slightly different pitches.
+    ones_complement: bool,
+    duty: u8,
+    period: u16, // 11 bits
+    timer: u16,
+    step: u8,
+    sweep: Sweep,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            ones_complement: channel == 1,
+            duty: 0,
+            period: 0,
+            timer: 0,
+            step: 0,
+            sweep: Sweep::default(),
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    // `reg` is 0-3, the register's offset from the channel's first one.
+    pub fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.looping = data & 0x20 != 0;
+                self.envelope.constant = data & 0x10 != 0;
+                self.envelope.volume = data & 0x0F;
+            }
+            1 => {
+                self.sweep.enabled = data & 0x80 != 0;
+                self.sweep.period = (data >> 4) & 0b111;
+                self.sweep.negate = data & 0x08 != 0;
+                self.sweep.shift = data & 0b111;
+                self.sweep.reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.envelope.restart();
+                self.step = 0;
+            }
+        }
+    }
+
+    // Once per APU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.sweep_mutes() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+        self.length.clock_half_frame();
+    }
+
+    // The period the sweep would move to. Computed all the time, even with the sweep off.
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    // Periods below 8 are too high to hear, and a target past 11 bits can't be reached:
+    // either way the sweep unit silences the channel, whether or not it's enabled.
+    fn sweep_mutes(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state.u8(self.duty).u16(self.period).u16(self.timer).u8(self.step);
+        let sweep = &self.sweep;
+        state.bool(sweep.enabled).u8(sweep.period).bool(sweep.negate).u8(sweep.shift).u8(sweep.divider).bool(sweep.reload);
+        self.envelope.save(state);
+        self.length.save(state);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.duty = state.u8()? & 0b11;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.step = state.u8()? % 8;
+        let sweep = &mut self.sweep;
+        sweep.enabled = state.bool()?;
+        sweep.period = state.u8()?;
+        sweep.negate = state.bool()?;
+        sweep.shift = state.u8()? & 0b111;
+        sweep.divider = state.u8()?;
+        sweep.reload = state.bool()?;
+        self.envelope.load(state)?;
+        self.length.restore(state)
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active() || self.sweep_mutes() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let mut pulse = Pulse::new(1);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0b1001_1111); // 50% duty, constant volume 15
+        pulse.write(2, 0x00);
+        pulse.write(3, 0b0000_1001); // period $100
+        pulse.write(1, 0b1000_1001); // sweep on, every half frame, down by period >> 1
+
+        assert_eq!(pulse.sweep_target(), 0x100 - 0x80 - 1); // ones' complement on pulse 1
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x7F);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x3F);
+
+        // sweeping up past $7FF mutes the channel even with the sweep disabled
+        pulse.write(1, 0b0000_0000);
+        pulse.write(2, 0xFF);
+        pulse.write(3, 0b0000_1100);
+        assert_eq!(pulse.sweep_target(), 0x4FF + 0x4FF);
+        for _ in 0..16 {
+            pulse.clock_timer();
+            assert_eq!(pulse.output(), 0);
+        }
+    }
+}
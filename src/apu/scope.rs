@@ -0,0 +1,152 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{FIVE_STEP, FOUR_STEP};
+
+// What the APU's channels put out over a frame, for the visualizer overlay (see
+// render::overlays::ApuVisualizer): for each of the five channels, the lowest and highest
+// level in each of COLUMNS slices of the frame, and where in it the frame counter stepped.
+// That's enough to draw every channel's waveform a frame wide. A 440Hz square comes out about
+// 35 columns to a period; notes too high for that fill the band between the levels they swing
+// between, which is what their volume looks like.
+//
+// The APU and the overlay share it (SharedScope). Looking at every channel every CPU cycle
+// isn't free, so the APU only records while the overlay asks for it: the overlay sets
+// `wanted` whenever it's drawn, the APU clears it at the end of each frame. Once the overlay
+// is turned off it isn't drawn, and the recording stops with the frame after.
+
+pub const COLUMNS: usize = 256;
+pub const CHANNELS: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+const FRAME_CYCLES: u32 = 29781; // CPU cycles in an NTSC frame, rounded up; longer ones end in the last column
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub low: u8,
+    pub high: u8,
+}
+
+impl Span {
+    const NONE: Span = Span { low: u8::MAX, high: 0 }; // nothing recorded in the column
+
+    pub fn is_empty(&self) -> bool {
+        self.low > self.high
+    }
+}
+
+// A frame counter step in the frame: the column it fell in, and whether it was a half frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    pub column: usize,
+    pub half: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub wanted: bool,
+    pub columns: Vec<[Span; CHANNELS.len()]>, // the last frame's, in the order of CHANNELS
+    pub steps: Vec<Step>,                     // the last frame's
+    pub five_step: bool,                      // the frame counter's mode at the end of it
+    pub sequence_step: usize,                 // steps of the sequence done by then: 0-4, or 0-5
+    recording: Vec<[Span; CHANNELS.len()]>,   // the frame in progress
+    recording_steps: Vec<Step>,
+}
+
+pub type SharedScope = Rc<RefCell<Scope>>;
+
+impl Scope {
+    pub fn new() -> Self {
+        Scope {
+            wanted: false,
+            columns: vec![],
+            steps: vec![],
+            five_step: false,
+            sequence_step: 0,
+            recording: vec![[Span::NONE; CHANNELS.len()]; COLUMNS],
+            recording_steps: vec![],
+        }
+    }
+
+    pub fn shared() -> SharedScope {
+        Rc::new(RefCell::new(Scope::new()))
+    }
+
+    fn column(cycle: u32) -> usize {
+        (cycle as usize * COLUMNS / FRAME_CYCLES as usize).min(COLUMNS - 1)
+    }
+
+    // The channels' levels `cycle` CPU cycles into the frame.
+    pub fn record(&mut self, cycle: u32, levels: [u8; CHANNELS.len()]) {
+        if !self.wanted {
+            return;
+        }
+        for (span, level) in self.recording[Self::column(cycle)].iter_mut().zip(levels) {
+            span.low = span.low.min(level);
+            span.high = span.high.max(level);
+        }
+    }
+
+    pub fn step(&mut self, cycle: u32, half: bool) {
+        if self.wanted {
+            self.recording_steps.push(Step { column: Self::column(cycle), half });
+        }
+    }
+
+    // Keeps the frame just recorded for the overlay, if it asked for it, and starts the next.
+    // `frame_cycle` is how far into its sequence the frame counter is.
+    pub fn end_frame(&mut self, frame_cycle: u32, five_step: bool) {
+        if self.wanted {
+            let empty = vec![[Span::NONE; CHANNELS.len()]; COLUMNS];
+            self.columns = std::mem::replace(&mut self.recording, empty);
+            self.steps = std::mem::take(&mut self.recording_steps);
+            self.five_step = five_step;
+            // the 5-step sequence's steps fall where the 4-step one's do, then its own last:
+            // the fourth is the silent one, which clocks nothing
+            let mut steps: Vec<u32> = FOUR_STEP.iter().map(|(at, _)| *at).collect();
+            if five_step {
+                steps.push(FIVE_STEP[3].0);
+            }
+            self.sequence_step = steps.iter().filter(|&&at| at <= frame_cycle).count();
+        }
+        self.wanted = false;
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_records_only_when_wanted() {
+        let mut scope = Scope::new();
+        scope.record(0, [15; 5]);
+        scope.end_frame(0, false);
+        assert!(scope.columns.is_empty());
+
+        scope.wanted = true;
+        scope.record(0, [0, 1, 2, 3, 4]);
+        scope.record(100, [10, 1, 2, 3, 4]); // still column 0
+        scope.record(200, [5, 5, 5, 5, 5]);
+        scope.step(7457, false);
+        scope.record(40_000, [9, 9, 9, 9, 100]); // a long frame: the last column
+        scope.end_frame(14913, false);
+        assert!(!scope.wanted);
+        assert_eq!(scope.columns[0][0], Span { low: 0, high: 10 });
+        assert_eq!(scope.columns[1][4], Span { low: 5, high: 5 });
+        assert!(scope.columns[2][0].is_empty());
+        assert_eq!(scope.columns[COLUMNS - 1][4], Span { low: 100, high: 100 });
+        assert_eq!(scope.steps, [Step { column: 64, half: false }]);
+        assert_eq!(scope.sequence_step, 2);
+
+        // not asked for again: the frame shown stays
+        scope.record(0, [1; 5]);
+        scope.end_frame(0, true);
+        assert_eq!(scope.columns[0][0], Span { low: 0, high: 10 });
+        assert!(!scope.five_step);
+    }
+}
@@ -0,0 +1,171 @@
+// Sound while the frontend runs the game faster than the console (fast-forward, or a
+// spectator catching up with the host). Every emulated frame still makes a frame's worth of
+// samples, so at 4x there's four times the sound the device can play: queued as is it falls
+// further and further behind the picture until the buffer overflows, and squeezed into the
+// time available it plays four times higher. Instead, the last stage of the output path keeps
+// one frame's sound in every `speed` and drops the rest. What plays is the game's sound at its
+// own pitch, in 1/60s snatches, which is enough to follow the music while skipping ahead.
+// In Skip mode the fast-forward is silent instead.
+//
+// Where a kept frame doesn't follow on from the one before (frames were dropped in between,
+// or the mode went silent), the two are crossfaded over SPLICE_SAMPLES so the jump doesn't
+// click; for that the last SPLICE_SAMPLES of every kept frame are held back until the next
+// one comes, and overlap its start. At the console's speed nothing is dropped and the samples come out unchanged,
+// just those few samples late.
+
+pub const SPLICE_SAMPLES: usize = 64; // about 1.5ms
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StretchMode {
+    Stretch, // snatches of the sound at its pitch
+    Skip,    // silence
+}
+
+impl StretchMode {
+    // As --fast-forward-audio takes them.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StretchMode::Stretch => "stretch",
+            StretchMode::Skip => "skip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<StretchMode> {
+        [StretchMode::Stretch, StretchMode::Skip].into_iter().find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+}
+
+pub struct TimeStretch {
+    mode: StretchMode,
+    speed: f32,       // times the console's, 1.0 or more
+    credit: f32,      // frames owed to the output, as in SpeedControl::frames_to_run
+    tail: Vec<f32>,   // the end of the last kept frame, not handed on yet
+    spliced: bool,    // the next kept frame doesn't follow on from the tail
+    was_silent: bool, // the last kept frame was silenced by Skip
+}
+
+impl TimeStretch {
+    pub fn new() -> Self {
+        TimeStretch {
+            mode: StretchMode::Stretch,
+            speed: 1.0,
+            credit: 0.0,
+            tail: vec![],
+            spliced: false,
+            was_silent: false,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: StretchMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> StretchMode {
+        self.mode
+    }
+
+    // How many frames the frontend runs per frame shown; below 1.0 counts as 1.0.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(1.0);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    // One frame's samples in, the ones to play out.
+    pub fn process(&mut self, samples: &[f32], mut out: impl FnMut(f32)) {
+        self.credit += 1.0 / self.speed;
+        if self.credit < 1.0 {
+            self.spliced = true;
+            return;
+        }
+        self.credit -= 1.0;
+        let silent = self.mode == StretchMode::Skip && self.speed > 1.0;
+        let sample = |i: usize| if silent { 0.0 } else { samples[i] };
+        let spliced = std::mem::take(&mut self.spliced) || silent != self.was_silent;
+        self.was_silent = silent;
+
+        // the held back tail, crossfaded into the start of this frame if there's a jump
+        let overlap = self.tail.len().min(samples.len());
+        for (i, &tail) in self.tail.iter().enumerate() {
+            if spliced && i < overlap {
+                let gain = (i + 1) as f32 / (overlap + 1) as f32;
+                out(tail * (1.0 - gain) + sample(i) * gain);
+            } else {
+                out(tail);
+            }
+        }
+        let start = if spliced { overlap } else { 0 };
+        let held = samples.len().saturating_sub(start).min(SPLICE_SAMPLES);
+        for i in start..samples.len() - held {
+            out(sample(i));
+        }
+        self.tail = (samples.len() - held..samples.len()).map(sample).collect();
+    }
+}
+
+impl Default for TimeStretch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn run(stretch: &mut TimeStretch, frames: &[Vec<f32>]) -> Vec<f32> {
+        let mut out = vec![];
+        for frame in frames {
+            stretch.process(frame, |sample| out.push(sample));
+        }
+        out
+    }
+
+    fn frames(count: usize) -> Vec<Vec<f32>> {
+        (0..count).map(|frame| (0..735).map(|i| ((frame * 735 + i) % 100) as f32 / 100.0).collect()).collect()
+    }
+
+    #[test]
+    fn test_full_speed_passes_through() {
+        let input = frames(3);
+        let out = run(&mut TimeStretch::new(), &input);
+        let all: Vec<f32> = input.concat();
+        assert_eq!(out, all[..all.len() - SPLICE_SAMPLES]);
+    }
+
+    #[test]
+    fn test_fast_forward_keeps_pitch() {
+        let mut stretch = TimeStretch::new();
+        stretch.set_speed(4.0);
+        let input = frames(40);
+        let out = run(&mut stretch, &input);
+        assert_eq!(out.len(), 10 * (735 - SPLICE_SAMPLES)); // one frame in four, overlapping at the splices
+        // the kept frame (the fourth) comes out as it went in, apart from the splices
+        let kept = &input[3];
+        assert_eq!(out[SPLICE_SAMPLES..735 - SPLICE_SAMPLES], kept[SPLICE_SAMPLES..735 - SPLICE_SAMPLES]);
+
+        // no click where frames were dropped: the samples move on smoothly
+        let mut stretch = TimeStretch::new();
+        stretch.set_speed(2.0);
+        let steps: Vec<f32> = run(&mut stretch, &[vec![0.0; 735], vec![1.0; 735], vec![1.0; 735], vec![0.0; 735]]);
+        assert!(steps.windows(2).all(|pair| (pair[1] - pair[0]).abs() < 0.02));
+    }
+
+    #[test]
+    fn test_skip_is_silent() {
+        let mut stretch = TimeStretch::new();
+        stretch.set_mode(StretchMode::Skip);
+        stretch.set_speed(3.0);
+        let out = run(&mut stretch, &vec![vec![0.5; 735]; 30]);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+        assert_eq!(out.len(), 10 * (735 - SPLICE_SAMPLES));
+
+        stretch.set_speed(0.5); // slowed down: counts as the console's speed
+        let out = run(&mut stretch, &vec![vec![0.5; 735]; 2]);
+        assert_eq!(out.len(), 2 * 735 - SPLICE_SAMPLES);
+        assert!(out[0] < 0.02 && out[SPLICE_SAMPLES..].iter().all(|&sample| sample == 0.5)); // faded back in
+        assert_eq!(StretchMode::from_name("SKIP"), Some(StretchMode::Skip));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::apu::length::LengthCounter;
+use crate::mapper::state::{StateReader, StateWriter};
+
+// The triangle channel ($4008-$400B), the bass line in most games:
+//
+//   reg 0  CRRR RRRR  length counter halt / linear counter control, linear counter reload
+//   reg 1  ---- ----  unused
+//   reg 2  TTTT TTTT  timer period, low 8 bits
+//   reg 3  LLLL LTTT  length counter load, timer period high 3 bits
+//
+// No volume control: the sequencer steps through a fixed 32 step triangle, 15 down to 0 and
+// back up. Its timer runs at the full CPU clock, so the pitch is
+// CPU clock / (32 * (period + 1)), an octave below a pulse with the same period. Besides the
+// length counter it has a finer linear counter, clocked every quarter frame, and the
+// sequencer only moves while both are non-zero. Stopping leaves the output where it was
+// instead of dropping to 0, which would pop.
+// See: https://www.nesdev.org/wiki/APU_Triangle
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct Triangle {
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    period: u16, // 11 bits
+    timer: u16,
+    step: u8,
+    pub length: LengthCounter,
+}
+
+impl Triangle {
+    // `reg` is 0-3, the register's offset from $4008.
+    pub fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = data & 0x80 != 0;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // Once per CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        // Periods below 2 are ultrasonic; real hardware plays them anyway, as a hiss games
+        // don't expect, so they just hold the step like most emulators do.
+        if self.length.is_active() && self.linear_counter > 0 && self.period >= 2 {
+            self.step = (self.step + 1) % 32;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock_half_frame();
+    }
+
+    // 0-15
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+
+    pub fn save(&self, state: &mut StateWriter) {
+        state
+            .bool(self.control)
+            .u8(self.linear_reload_value)
+            .u8(self.linear_counter)
+            .bool(self.linear_reload)
+            .u16(self.period)
+            .u16(self.timer)
+            .u8(self.step);
+        self.length.save(state);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.control = state.bool()?;
+        self.linear_reload_value = state.u8()?;
+        self.linear_counter = state.u8()?;
+        self.linear_reload = state.bool()?;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.step = state.u8()? % 32;
+        self.length.restore(state)
+    }
+}
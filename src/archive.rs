@@ -0,0 +1,127 @@
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use crate::error::RunescoError;
+
+// ROM files straight out of compressed ROM sets: a .zip or .7z holding the .nes (or .unf,
+// .fds) file is opened in memory, so `runesco game.zip` works without unpacking it first.
+// Archives are recognised by their signature rather than the extension, so misnamed files
+// work too.
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+// The most that's set aside up front for an entry. Archives say how big their files are, but a
+// broken or hostile one can say anything; the biggest ROMs are a few MiB, and anything larger
+// still reads, it just grows as it goes.
+const MAX_RESERVE: u64 = 8 << 20;
+
+// The ROM image in `path`: the file itself, or the first ROM entry if it's an archive.
+pub fn read_image(path: impl AsRef<Path>) -> Result<Vec<u8>, RunescoError> {
+    let path = path.as_ref();
+    let data = std::fs::read(path).map_err(|source| RunescoError::Io { path: path.to_path_buf(), source })?;
+    extract_image(data).map_err(|err| RunescoError::Archive(format!("{}: {}", path.display(), err)))
+}
+
+pub fn extract_image(data: Vec<u8>) -> Result<Vec<u8>, RunescoError> {
+    let image = if data.starts_with(ZIP_MAGIC) {
+        from_zip(data)
+    } else if data.starts_with(SEVEN_ZIP_MAGIC) {
+        from_7z(data)
+    } else {
+        Ok(data)
+    };
+    image.map_err(RunescoError::Archive)
+}
+
+fn is_nes(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [".nes", ".unf", ".unif", ".fds"].iter().any(|ext| name.ends_with(ext))
+}
+
+fn from_zip(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data)).map_err(|err| err.to_string())?;
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(|err| err.to_string())?;
+        if entry.is_file() && is_nes(entry.name()) {
+            let mut image = Vec::with_capacity(entry.size().min(MAX_RESERVE) as usize);
+            entry.read_to_end(&mut image).map_err(|err| err.to_string())?;
+            return Ok(image);
+        }
+    }
+    Err("no ROM (.nes, .unf, .fds) in the zip archive".to_string())
+}
+
+fn from_7z(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let len = data.len() as u64;
+    let mut archive = sevenz_rust::SevenZReader::new(Cursor::new(data), len, sevenz_rust::Password::empty())
+        .map_err(|err| err.to_string())?;
+    let mut image = None;
+    archive
+        .for_each_entries(|entry, reader| {
+            if entry.is_directory() || !is_nes(entry.name()) {
+                // 7z archives are usually solid: the entries before ours still have to be decoded
+                io::copy(reader, &mut io::sink())?;
+                return Ok(true);
+            }
+            let mut buf = Vec::with_capacity(entry.size().min(MAX_RESERVE) as usize);
+            reader.read_to_end(&mut buf)?;
+            image = Some(buf);
+            Ok(false)
+        })
+        .map_err(|err| err.to_string())?;
+    image.ok_or_else(|| "no ROM (.nes, .unf, .fds) in the 7z archive".to_string())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in files {
+            let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn seven_zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = sevenz_rust::SevenZWriter::new(Cursor::new(vec![])).unwrap();
+        for (name, contents) in files {
+            let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            archive.push_archive_entry(entry, Some(*contents)).unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_zip() {
+        let rom = std::fs::read("nestest.nes").unwrap();
+        let zip = zip_of(&[("README.txt", b"not this one"), ("nestest.NES", &rom)]);
+        assert_eq!(extract_image(zip).unwrap(), rom);
+
+        let zip = zip_of(&[("README.txt", b"nothing else")]);
+        assert!(extract_image(zip).is_err());
+
+        // anything else is passed through untouched, for Rom::new to judge
+        assert_eq!(extract_image(rom.clone()).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_7z() {
+        let rom = std::fs::read("nestest.nes").unwrap();
+        let archive = seven_zip_of(&[("README.txt", b"not this one"), ("nestest.nes", &rom)]);
+        assert!(archive.starts_with(SEVEN_ZIP_MAGIC));
+        assert_eq!(extract_image(archive).unwrap(), rom);
+
+        let archive = seven_zip_of(&[("README.txt", b"nothing else")]);
+        assert_eq!(extract_image(archive).unwrap_err().to_string(), "no ROM (.nes, .unf, .fds) in the 7z archive");
+
+        // cut short: an error, not a panic
+        let archive = seven_zip_of(&[("nestest.nes", &rom)]);
+        assert!(extract_image(archive[..archive.len() / 2].to_vec()).is_err());
+    }
+}
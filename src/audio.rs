@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+// Samples on their way from the emulated sound hardware to the frontend's audio device.
+// The core pushes one sample at a time as it runs, the frontend drains them in blocks from
+// its audio callback or queue; the buffer sits in between so neither side waits on the other.
+//
+// Loading a savestate (or rewinding) is a jump in the waveform: the samples still queued
+// belong to a timeline that no longer exists, and going straight from the old level to the
+// new one is an audible click. So `discontinuity` throws the queue away and replaces it with
+// a short fade from the last sample the frontend actually played down to silence, a bit of
+// silence to re-prime the device, and fades the new audio back in.
+//
+// Samples are mono f32 in -1.0..=1.0, at SAMPLE_RATE. The APU resamples its output to that
+// and the Nes pushes a frame's worth at the end of every frame (see Apu::end_frame).
+
+pub const SAMPLE_RATE: u32 = 44_100;
+pub const CAPACITY: usize = 4096; // about 90ms at 44.1kHz
+pub const FADE_SAMPLES: usize = 64; // about 1.5ms, short enough not to be heard as a dip
+pub const PRIME_SAMPLES: usize = 512; // enough that the device doesn't run dry right away
+
+pub struct AudioBuffer {
+    samples: VecDeque<f32>,
+    last: f32,           // last sample handed to the frontend, where a fade out starts
+    fade_in_left: usize, // samples still to be faded in after a discontinuity
+}
+
+impl AudioBuffer {
+    pub fn new() -> Self {
+        AudioBuffer { samples: VecDeque::with_capacity(CAPACITY), last: 0.0, fade_in_left: 0 }
+    }
+
+    // When the frontend falls behind, the oldest samples are dropped: late audio is worse
+    // than a skip.
+    pub fn push(&mut self, sample: f32) {
+        let sample = if self.fade_in_left > 0 {
+            let gain = 1.0 - self.fade_in_left as f32 / FADE_SAMPLES as f32;
+            self.fade_in_left -= 1;
+            sample * gain
+        } else {
+            sample
+        };
+        if self.samples.len() == CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    // Fills `out` completely. If the core hasn't produced enough, the last sample is held
+    // instead of dropping to 0, which would click too.
+    pub fn pop_into(&mut self, out: &mut [f32]) {
+        for slot in out.iter_mut() {
+            if let Some(sample) = self.samples.pop_front() {
+                self.last = sample;
+            }
+            *slot = self.last;
+        }
+    }
+
+    // Call when the emulated timeline jumps (savestate load, rewind).
+    pub fn discontinuity(&mut self) {
+        self.samples.clear();
+        for i in 1..=FADE_SAMPLES {
+            self.samples.push_back(self.last * (1.0 - i as f32 / FADE_SAMPLES as f32));
+        }
+        self.samples.resize(FADE_SAMPLES + PRIME_SAMPLES, 0.0);
+        self.fade_in_left = FADE_SAMPLES;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+impl Default for AudioBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_discontinuity_fades() {
+        let mut audio = AudioBuffer::new();
+        for _ in 0..100 {
+            audio.push(0.5);
+        }
+        let mut out = [0.0; 10];
+        audio.pop_into(&mut out);
+        assert_eq!(out, [0.5; 10]);
+
+        // stale samples are gone, replaced by a fade from 0.5 down to silence
+        audio.discontinuity();
+        assert_eq!(audio.len(), FADE_SAMPLES + PRIME_SAMPLES);
+        let mut out = vec![0.0; FADE_SAMPLES + PRIME_SAMPLES];
+        audio.pop_into(&mut out);
+        assert!(out.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(out[0] < 0.5 && out[0] > 0.45);
+        assert_eq!(out[FADE_SAMPLES - 1], 0.0);
+        assert!(out[FADE_SAMPLES..].iter().all(|&s| s == 0.0));
+
+        // and the new audio comes back in gradually
+        for _ in 0..FADE_SAMPLES + 1 {
+            audio.push(1.0);
+        }
+        let mut out = vec![0.0; FADE_SAMPLES + 1];
+        audio.pop_into(&mut out);
+        assert_eq!(out[0], 0.0);
+        assert!(out.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(out[FADE_SAMPLES], 1.0);
+    }
+
+    #[test]
+    fn test_underrun_holds_last_sample() {
+        let mut audio = AudioBuffer::new();
+        audio.push(0.25);
+        let mut out = [0.0; 3];
+        audio.pop_into(&mut out);
+        assert_eq!(out, [0.25; 3]);
+
+        for _ in 0..CAPACITY + 10 {
+            audio.push(0.0);
+        }
+        assert_eq!(audio.len(), CAPACITY);
+    }
+}
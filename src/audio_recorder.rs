@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::audio::SAMPLE_RATE;
+
+// Writes the sound output to a .wav file as the game plays, for capturing a game's music.
+// What goes in is what the player hears: the mixed and filtered APU output (see
+// Apu::end_frame), one sample at a time, as 16-bit mono PCM at SAMPLE_RATE.
+//
+// A WAV file starts with the sizes of its data, which aren't known until the recording stops,
+// so the header goes out with 0 in them and finish() goes back to fill them in. A recording
+// that never gets finished (the emulator crashed) still has all its samples; most players
+// cope with the zero sizes, and any audio editor can repair them.
+// See: http://soundfile.sapp.org/doc/WaveFormat/
+
+const HEADER_SIZE: u32 = 44;
+const BYTES_PER_SAMPLE: u32 = 2;
+
+pub struct AudioRecorder<W: Write + Seek = BufWriter<File>> {
+    out: W,
+    samples: u32,
+    error: Option<io::Error>, // the first write that failed, reported by finish()
+}
+
+impl AudioRecorder {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        AudioRecorder::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> AudioRecorder<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        write_header(&mut out, 0)?;
+        Ok(AudioRecorder { out, samples: 0, error: None })
+    }
+
+    // -1.0..=1.0; anything outside is clipped.
+    pub fn push(&mut self, sample: f32) {
+        if self.error.is_some() {
+            return;
+        }
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        match self.out.write_all(&value.to_le_bytes()) {
+            Ok(()) => self.samples += 1,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn seconds(&self) -> f32 {
+        self.samples as f32 / SAMPLE_RATE as f32
+    }
+
+    // Fills in the sizes and hands back the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.samples)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_header(out: &mut impl Write, samples: u32) -> io::Result<()> {
+    let data_size = samples * BYTES_PER_SAMPLE;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // the size of the format chunk
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // channels
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * BYTES_PER_SAMPLE).to_le_bytes())?; // bytes per second
+    out.write_all(&(BYTES_PER_SAMPLE as u16).to_le_bytes())?; // bytes per sample frame
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_layout() {
+        let mut recorder = AudioRecorder::new(Cursor::new(vec![])).unwrap();
+        for sample in [0.0, 1.0, -1.0, 2.0] {
+            recorder.push(sample);
+        }
+        assert_eq!(recorder.samples(), 4);
+        let wav = recorder.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44_100);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+        let samples: Vec<i16> = wav[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        assert_eq!(samples, [0, i16::MAX, -i16::MAX, i16::MAX]); // clipped
+    }
+}
@@ -1,23 +1,46 @@
+use crate::apu::Apu;
 use crate::cpu::Mem;
 use crate::cartridge::Rom;
+use crate::cheats::Cheats;
+use crate::coverage::Coverage;
+use crate::error::RunescoError;
 use crate::ppu::NesPPU;
-use crate::joypads::Joypad;
+use crate::joypads::{InputDevice, Joypad, PORT_1, PORT_2};
+use crate::mapper::{self, SharedMapper};
+use std::any::Any;
+use crate::vs_system::VsPanel;
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 //const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const EXPANSION: u16 = 0x4020; // cartridge registers on a few boards, see Mapper::read_expansion
+const EXPANSION_END: u16 = 0x5FFF;
+const PRG_RAM: u16 = 0x6000;
+const TRAINER: u16 = 0x7000;
+const PRG_RAM_END: u16 = 0x7FFF;
 const PRG: u16 = 0x8000;
 const PRG_END: u16 = 0xFFFF;
 
+// CPU cycles the CPU is halted for while the DMC fetches a sample byte: the halt, a dummy
+// cycle, one to line the fetch up with the APU's read cycles, and the fetch itself. The CPU
+// can only be halted on a read, so a DMA that lands on a write waits for it to finish, and
+// that wait takes the place of the alignment cycle: 3. See tick and dmc_dma.
+// See: https://www.nesdev.org/wiki/DMA#DMC_DMA
+const DMC_STALL_CYCLES: u8 = 4;
+const DMC_STALL_CYCLES_ON_WRITE: u8 = 3;
+
 pub struct Bus<'call> {
     // <'call> is a lifetime parameter for the Bus struct. It indicates that some part of the Bus struct 
     // (specifically the gameloop_callback field) contains a reference 
     // (or borrowed data) that must live as long as 'call.
 
     cpu_vram: [u8; 2048], // 2KiB of Ram, from 0x0000 to 0x2000 (with higest two bits 0-ed)
-    prg_rom: Vec<u8>,
+    mapper: SharedMapper, // owns the PRG ROM and its banking, shared with the PPU
+    prg_ram: Vec<u8>,     // work RAM (or battery-backed SRAM) on the cartridge, the size the header says
+    battery: bool,
     ppu: NesPPU,
+    apu: Apu,
     cycles: usize,
 
     gameloop_callback: Box<dyn FnMut(&NesPPU, &mut Joypad, &mut Joypad) + 'call>,
@@ -42,29 +65,298 @@ pub struct Bus<'call> {
 
     joypad1: Joypad,
     joypad2: Joypad,
+    strobes: u64, // controller strobes ($4016 writes with bit 0 set) since power-on
+    dmc_read_conflict: bool, // emulate the DMC DMA controller glitch, see dmc_dma
+    vs: Option<VsPanel>,     // coins and DIP switches on Vs. System cabinets
+    devices: Vec<Box<dyn InputDevice>>, // plugged in beside the controllers, see plug
+    open_bus: u8,            // the last value on the CPU's data bus, see read_open_bus
+    last_read: u16,          // the address of the CPU's last read, see dmc_dma
+    last_was_write: bool,    // whether the CPU's last access was a write, see tick
+    open_bus_enabled: bool,
+    bus_conflicts: bool,     // emulate ROM/CPU bus conflicts on boards that have them, see bus_conflict
+    cheats: Cheats,          // what they change is only seen by the CPU, see mem_read
+    coverage: Option<Coverage>, // every CPU access counted, when asked for (see crate::coverage)
 }
 
 impl<'a> Bus<'a> { // can be any lifetime 'a
-    pub fn new<'call, F>(rom: Rom, gameloop_callback: F) -> Bus<'call>
+    // Fails if no mapper can run the cartridge.
+    pub fn new<'call, F>(mut rom: Rom, gameloop_callback: F) -> Result<Bus<'call>, RunescoError>
     where F: FnMut(&NesPPU, &mut Joypad, &mut Joypad) + 'call,
     {
-        let ppu = NesPPU::new(rom.chr_rom, rom.screen_mirroring);
+        // No CHR ROM in the file: the cartridge has CHR-RAM that the game fills through $2007.
+        let chr_ram = rom.chr_rom.is_empty();
+        if chr_ram {
+            rom.chr_rom = vec![0; mapper::chr_ram_size(rom.mapper)];
+        }
+
+        let mapper = mapper::for_rom(&rom)?;
+        let mut ppu = NesPPU::new_with_mapper(rom.chr_rom, mapper.clone());
+        ppu.chr_is_ram = chr_ram;
+
+        // The trainer sits in PRG-RAM from power-on, where the game (or the patch) expects it,
+        // so there has to be RAM at $7000 even if the header says there's less.
+        let mut prg_ram_size = rom.prg_ram_size;
+        if rom.trainer.is_some() {
+            prg_ram_size = prg_ram_size.max(crate::cartridge::PRG_RAM_PAGE_SIZE);
+        }
+        let mut prg_ram = vec![0; prg_ram_size];
+        if let Some(trainer) = &rom.trainer {
+            let start = (TRAINER - PRG_RAM) as usize;
+            prg_ram[start..start + trainer.len()].copy_from_slice(trainer);
+        }
 
-        Bus {
+        Ok(Bus {
             cpu_vram: [0; 2048],
-            prg_rom: rom.prg_rom,
+            mapper,
+            prg_ram,
+            battery: rom.battery,
             ppu: ppu,
+            apu: Apu::new(),
             cycles: 0,
             gameloop_callback: Box::from(gameloop_callback),
-            joypad1 : Joypad::new(),
-            joypad2 : Joypad::new(),
+            joypad1 : Joypad::new(PORT_1),
+            joypad2 : Joypad::new(PORT_2),
+            strobes: 0,
+            dmc_read_conflict: true,
+            vs: rom.vs_system.then(|| VsPanel::new(0)),
+            devices: vec![],
+            open_bus: 0,
+            last_read: 0,
+            last_was_write: false,
+            open_bus_enabled: true,
+            bus_conflicts: true,
+            cheats: Cheats::new(),
+            coverage: None,
+        })
+    }
+
+    pub fn cycles(&self) -> usize { // CPU cycles elapsed since power-on
+        self.cycles
+    }
+
+    pub fn restore_cycles(&mut self, cycles: usize) { // loading a savestate
+        self.cycles = cycles;
+    }
+
+    pub fn ppu(&self) -> &NesPPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut NesPPU {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.ppu.take_frame_ready()
+    }
+
+    pub fn ram(&self) -> &[u8; 2048] {
+        &self.cpu_vram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8; 2048] {
+        &mut self.cpu_vram
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn joypads(&mut self) -> (&mut Joypad, &mut Joypad) {
+        (&mut self.joypad1, &mut self.joypad2)
+    }
+
+    // None unless the cartridge is a Vs. System game.
+    pub fn vs_panel(&mut self) -> Option<&mut VsPanel> {
+        self.vs.as_mut()
+    }
+
+    // Plugs a device in beside the controllers (see crate::joypads::InputDevice), in place of
+    // one of the same kind if there was one.
+    pub fn plug<T: InputDevice>(&mut self, device: T) {
+        self.unplug::<T>();
+        self.devices.push(Box::new(device));
+    }
+
+    pub fn unplug<T: InputDevice>(&mut self) -> Option<T> {
+        let index = self.devices.iter().position(|device| (device.as_ref() as &dyn Any).is::<T>())?;
+        let device: Box<dyn Any> = self.devices.remove(index);
+        device.downcast().ok().map(|device| *device)
+    }
+
+    pub fn device<T: InputDevice>(&self) -> Option<&T> {
+        self.devices.iter().find_map(|device| (device.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    // Everything plugged in, to move over to another console (see Nes::insert_cartridge).
+    pub fn take_devices(&mut self) -> Vec<Box<dyn InputDevice>> {
+        std::mem::take(&mut self.devices)
+    }
+
+    pub fn plug_devices(&mut self, devices: Vec<Box<dyn InputDevice>>) {
+        self.devices.extend(devices);
+    }
+
+    // Whether the player is making noise into the Famicom's microphone. It only tells the game
+    // loud or quiet (a level comparator, no sound gets through), and games poll it: Zelda's
+    // Pols Voice die of it, Takeshi no Chousenjou wants singing.
+    // See: https://www.nesdev.org/wiki/Standard_controller#Microphone
+    pub fn set_microphone(&mut self, loud: bool) {
+        self.joypad2.set_microphone(loud);
+    }
+
+    pub fn microphone(&self) -> bool {
+        self.joypad2.microphone()
+    }
+
+    // Changes every time the game latches the controllers, see crate::movie.
+    pub fn strobe_count(&self) -> u64 {
+        self.strobes
+    }
+
+    // When the DMC fetches a sample byte (DMA), it halts the CPU, and the CPU repeats the read
+    // it was halted on while waiting. If that read was $4016/$4017 the controller sees the
+    // extra reads and shifts out bits the game never gets: a button "disappears" that frame.
+    // The controller only clocks when the read starts, so however many times the read repeats
+    // that's one bit. $2007 counts every one: each repeat moves the PPU address on (and
+    // through the read buffer), as dmc_dma_during_read4 checks. Games that read the pads
+    // while DMC samples play (and TAS tools replaying them) depend on it, so it's on by
+    // default. `cpu_read` is the address the CPU was reading when the DMA took over; tick
+    // calls this for every sample fetch that lands on a read.
+    // See: https://www.nesdev.org/wiki/APU_DMC#Conflict_with_controller_and_PPU_read
+    pub fn dmc_dma(&mut self, cpu_read: u16) {
+        if !self.dmc_read_conflict {
+            return;
+        }
+        match cpu_read {
+            PORT_1 | PORT_2 => {
+                self.read_ports(cpu_read);
+            }
+            // the repeats are every stalled cycle but the fetch
+            addr @ 0x2000..=PPU_REGISTERS_MIRRORS_END if addr & 0x0007 == 7 => {
+                for _ in 1..DMC_STALL_CYCLES {
+                    self.ppu.read_data();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_dmc_read_conflict(&mut self, enabled: bool) {
+        self.dmc_read_conflict = enabled;
+    }
+
+    pub fn dmc_read_conflict(&self) -> bool {
+        self.dmc_read_conflict
+    }
+
+    // Reading an address nothing answers (unmapped cartridge space, write-only APU registers,
+    // the unused bits of the controller ports) doesn't drive the data bus, so the CPU reads
+    // whatever was last on it: usually the high byte of the address, the last operand byte
+    // the CPU fetched (LDA $5000 reads $50). Test ROMs check for it, and a few games rely on
+    // it by accident. Turned off, those reads return 0.
+    // See: https://www.nesdev.org/wiki/Open_bus_behavior
+    fn read_open_bus(&self) -> u8 {
+        if self.open_bus_enabled {
+            self.open_bus
+        } else {
+            0
+        }
+    }
+
+    pub fn set_open_bus(&mut self, enabled: bool) {
+        self.open_bus_enabled = enabled;
+    }
+
+    pub fn open_bus_enabled(&self) -> bool {
+        self.open_bus_enabled
+    }
+
+    // What a write to ROM space leaves on the data bus: the value ANDed with the ROM byte
+    // there, on boards with bus conflicts (see Mapper::bus_conflicts). Games that don't mind
+    // them work either way, so this is on by default like the console; test ROMs check it.
+    // See: https://www.nesdev.org/wiki/Bus_conflict
+    fn bus_conflict(&mut self, addr: u16, data: u8) -> u8 {
+        let mut mapper = self.mapper.borrow_mut();
+        if self.bus_conflicts && mapper.bus_conflicts() {
+            data & mapper.read_prg(addr)
+        } else {
+            data
+        }
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    pub fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Set to Some(Coverage::new()) to start counting.
+    pub fn coverage_mut(&mut self) -> &mut Option<Coverage> {
+        &mut self.coverage
+    }
+
+    // Freeze cheats go straight into memory, like a debugger poking it, so they don't show up
+    // in the RAM heatmap or wake up the mapper's $6000 registers.
+    fn write_freezes(&mut self) {
+        for cheat in self.cheats.freezes() {
+            match cheat.addr {
+                RAM..=RAM_MIRRORS_END => self.cpu_vram[(cheat.addr & 0x07FF) as usize] = cheat.value,
+                PRG_RAM..=PRG_RAM_END => {
+                    let index = self.prg_ram_index(cheat.addr);
+                    self.prg_ram[index] = cheat.value
+                }
+                _ => {}
+            }
         }
     }
 
     pub fn tick(&mut self, cycles: u8) {
         self.cycles += cycles as usize;
+        self.mapper.borrow_mut().clock_cpu(cycles);
+        for device in &mut self.devices {
+            device.clock(cycles);
+        }
+        let cartridge_audio = {
+            let mapper = self.mapper.borrow();
+            mapper.expansion_audio_sample() * mapper.expansion_audio_gain()
+        };
+        self.apu.set_cartridge_audio(cartridge_audio);
+        self.apu.tick(cycles);
         let nmi_before = self.ppu.nmi_interrupt.is_some();
-        self.ppu.tick(cycles *3);
+        if self.ppu.tick(cycles *3) {
+            self.write_freezes();
+        }
         let nmi_after = self.ppu.nmi_interrupt.is_some();
         
         if !nmi_before && nmi_after {
@@ -73,26 +365,93 @@ impl<'a> Bus<'a> { // can be any lifetime 'a
 
         // If an NMI has just been triggered (i.e., the NMI flag was false before and is true now), the function calls gameloop_callback
         // to render the next frame.
+
+        // The DMC's sample fetch halts the CPU while everything else keeps running. Samples
+        // always come from $8000-$FFFF. The CPU runs whole instructions between ticks, so the
+        // DMA is taken to land on the instruction's last access: the read of LDA $4016, the
+        // write of STA or INC.
+        if let Some(addr) = self.apu.dmc_fetch_address() {
+            let byte = self.read_prg_rom(addr);
+            self.apu.dmc_fill(byte);
+            let stall = if self.last_was_write {
+                DMC_STALL_CYCLES_ON_WRITE
+            } else {
+                self.dmc_dma(self.last_read);
+                DMC_STALL_CYCLES
+            };
+            self.tick(stall);
+        }
     }
 
     pub fn poll_nmi_status(&mut self) -> Option<u8> {
         self.ppu.nmi_interrupt.take()
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr = addr - 0x8000; // gets the position of the "cursor" 
-        // (how far the position is from the start of the prg rom location)
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            // if length is 16KiB, and cursor has gone beyond this length,
-            // mirror it.
-            addr = addr % 0x4000; // by resetting the cursor
+    pub fn mapper(&self) -> &SharedMapper {
+        &self.mapper
+    }
+
+    // Unlike NMI, IRQ is a level: it stays asserted until the source is acknowledged.
+    pub fn irq_line(&self) -> bool {
+        self.mapper.borrow().irq_pending() || self.apu.irq_pending()
+    }
+
+    // Reads memory the way a debugger looks at it: without side effects, so I/O registers read as 0.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            PRG_RAM..=PRG_RAM_END => self.read_low(addr),
+            PRG..=PRG_END => self.read_prg_rom(addr),
+            _ => 0,
         }
-        self.prg_rom[addr as usize] // get that position from the prg rom
+    }
+
+    pub fn peek_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
+
+    fn read_low(&self, addr: u16) -> u8 {
+        let banked = self.mapper.borrow().read_low(addr);
+        banked.unwrap_or_else(|| self.prg_ram[self.prg_ram_index(addr)])
+    }
+
+    fn prg_ram_index(&self, addr: u16) -> usize {
+        let size = self.prg_ram.len();
+        self.mapper.borrow().prg_ram_offset(addr, size) % size
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.mapper.borrow_mut().read_prg(addr) // the mapper decides which bank is visible
     }
 }
 
 impl Mem for Bus<'_> {
     fn mem_read(&mut self, addr: u16) -> u8 {
+        self.last_read = addr;
+        self.last_was_write = false;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.read(addr);
+        }
+        let mut data = self.read(addr);
+        if !self.cheats.is_empty() {
+            data = self.cheats.apply(addr, data);
+        }
+        self.open_bus = data;
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.write(addr);
+        }
+        self.write(addr, data);
+        self.last_was_write = true; // after the write: $4014's DMA reads are the CPU's too
+    }
+}
+
+impl Bus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b0000_0111_1111_1111; 
@@ -110,36 +469,51 @@ impl Mem for Bus<'_> {
 
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00100000_00000111;
-                self.mem_read(mirror_down_addr)
+                self.read(mirror_down_addr)
             }
 
-            0x4000..=0x4015 => {
-                //ignore APU 
-                0
-            }
+            0x4000..=0x4013 => self.read_open_bus(), // APU registers are write-only
 
-            0x4016 => {
-                self.joypad1.read()
-                
-            }
+            0x4015 => self.read_open_bus() & 0x20 | self.apu.read_status(), // bit 5 isn't driven
 
-            0x4017 => {
-                self.joypad2.read()
-            }
+            PORT_1 | PORT_2 => self.read_ports(addr),
+
+            PRG_RAM..=PRG_RAM_END => self.read_low(addr),
 
             PRG..=PRG_END => self.read_prg_rom(addr),
             _ => {
-                println!("Ignoring mem access at {}", addr);
-                0
+                let open_bus = self.read_open_bus();
+                self.mapper.borrow_mut().read_expansion(addr, open_bus)
             }
         }
     }
 
-    fn mem_write(&mut self, addr: u16, data: u8) {
+    // $4016/$4017: the controllers and everything plugged in beside them drive their bits (see
+    // crate::joypads::InputDevice), and on a Vs. System so do the cabinet's coins and switches,
+    // above the controllers. The top three bits float, all but $4016's top one on a Vs.
+    fn read_ports(&mut self, addr: u16) -> u8 {
+        let mut bits = self.joypad1.read(addr) | self.joypad2.read(addr);
+        for device in &mut self.devices {
+            bits |= device.read(addr);
+        }
+        match (&self.vs, addr) {
+            (Some(vs), PORT_1) => self.read_open_bus() & 0x80 | vs.bits_4016() | bits,
+            (Some(vs), _) => vs.bits_4017() | bits,
+            (None, _) => self.read_open_bus() & 0xE0 | bits,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let 0x2000..=0x2007 | 0x4014 = addr { // mirrors come back through here folded down
+            self.ppu.log_write(addr, data);
+        }
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b111_1111_1111;
                 self.cpu_vram[mirror_down_addr as usize] = data;
+                if self.ppu.record_ram_writes {
+                    self.ppu.record_ram_write(mirror_down_addr);
+                }
             }
             0x2000 => {
                 self.ppu.write_to_ctrl(data);
@@ -154,7 +528,7 @@ impl Mem for Bus<'_> {
             }
 
             0x2004 => {
-                println!("Writing to OAM data");
+                eprintln!("Writing to OAM data");
                 self.ppu.write_to_oam_data(data);
             }
             0x2005 => {
@@ -170,12 +544,10 @@ impl Mem for Bus<'_> {
 
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00100000_00000111;
-                self.mem_write(mirror_down_addr, data);
+                self.write(mirror_down_addr, data);
             }
 
-            0x4000..=0x4013 | 0x4015 => {
-                //ignore APU 
-            }
+            0x4000..=0x4013 | 0x4015 => self.apu.write_register(addr, data),
 
             0x4014 => { 
                 // OAM sprite write operations happen
@@ -218,15 +590,195 @@ impl Mem for Bus<'_> {
             0x4016 => {
                 self.joypad1.write(data);
                 self.joypad2.write(data);
+                for device in &mut self.devices {
+                    device.write(data);
+                }
+                if data & 1 == 1 {
+                    self.strobes += 1;
+                }
+                self.mapper.borrow_mut().write_strobe(data);
             }
 
-            0x4017 => {
+            0x4017 => self.apu.write_register(addr, data), // the frame counter, not the second pad
 
+            PRG_RAM..=PRG_RAM_END => {
+                self.mapper.borrow_mut().write_low(addr, data);
+                let index = self.prg_ram_index(addr);
+                self.prg_ram[index] = data;
+            }
+
+            PRG..=PRG_END => {
+                // Writes to ROM space go to the mapper's bank registers
+                let data = self.bus_conflict(addr, data);
+                self.mapper.borrow_mut().write_prg(addr, data);
+            }
+
+            EXPANSION..=EXPANSION_END => {
+                self.mapper.borrow_mut().write_expansion(addr, data);
             }
 
             _ => {
-                println!("Ignoring mem write-access at {}", addr);
+                eprintln!("Ignoring mem write-access at {}", addr);
+            }
+        }
+    }
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::joypads::JoypadButton;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_dmc_dma_deletes_a_controller_bit() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let bus = &mut nes.cpu().bus;
+        bus.joypads().0.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        // the DMA lands on the read of A: the game gets B's bit in its place
+        bus.dmc_dma(0x4016);
+        assert_eq!(bus.mem_read(0x4016), 1);
+        bus.dmc_dma(0xC000); // not a controller read, nothing happens
+        assert_eq!(bus.mem_read(0x4016), 0);
+
+        bus.set_dmc_read_conflict(false);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        bus.dmc_dma(0x4016);
+        assert_eq!(bus.mem_read(0x4016), 0);
+        assert_eq!(bus.mem_read(0x4016), 1);
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_the_cpu() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let bus = &mut nes.cpu().bus;
+        bus.mem_write(0x4010, 0x8F); // IRQ on
+        bus.mem_write(0x4013, 0x00); // a single byte
+        bus.mem_write(0x4015, 0x10);
+        assert_eq!(bus.mem_read(0x4015) & 0x90, 0x10);
+
+        let start = bus.cycles();
+        bus.tick(1);
+        assert_eq!(bus.cycles() - start, 1 + DMC_STALL_CYCLES as usize);
+        assert_eq!(bus.mem_read(0x4015) & 0x90, 0x80); // done, and asking for attention
+        assert!(bus.irq_line());
+
+        bus.mem_write(0x4015, 0x00);
+        assert!(!bus.irq_line());
+
+        // landing on a write, the DMA waits it out instead of aligning
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let bus = &mut nes.cpu().bus;
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0x10);
+        let start = bus.cycles();
+        bus.tick(1);
+        assert_eq!(bus.cycles() - start, 1 + DMC_STALL_CYCLES_ON_WRITE as usize);
+    }
+
+    #[test]
+    fn test_dmc_dma_repeats_ppu_data_reads() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let bus = &mut nes.cpu().bus;
+        for (i, value) in [0x11, 0x22, 0x33, 0x44, 0x55, 0x66].into_iter().enumerate() {
+            bus.mem_write(0x2006, 0x20);
+            bus.mem_write(0x2006, i as u8);
+            bus.mem_write(0x2007, value);
+        }
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_read(0x2007); // fills the read buffer with $11
+
+        // the read of $2007 that the DMA lands on repeats 3 times, so $22-$44 go by unseen
+        assert_eq!(bus.mem_read(0x2007), 0x11);
+        bus.dmc_dma(0x2007);
+        assert_eq!(bus.mem_read(0x3FFF), 0x55); // a mirror of $2007
+    }
+
+    // A trigger on the expansion port: pulled (D3 of $4017 low) for `cycles` after a strobe.
+    struct Trigger {
+        pulled_for: u32,
+        cycles: u32,
+    }
+
+    impl InputDevice for Trigger {
+        fn write(&mut self, data: u8) {
+            if data & 1 != 0 {
+                self.cycles = 0;
+            }
+        }
+
+        fn read(&mut self, addr: u16) -> u8 {
+            if addr == PORT_2 && self.cycles >= self.pulled_for {
+                0b1000
+            } else {
+                0
             }
         }
+
+        fn clock(&mut self, cycles: u8) {
+            self.cycles += cycles as u32;
+        }
+    }
+
+    #[test]
+    fn test_input_devices() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let bus = &mut nes.cpu().bus;
+        bus.plug(Trigger { pulled_for: 10, cycles: 0 });
+        bus.joypads().1.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        bus.set_open_bus(false);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4017), 0b0001); // the controller's A, the trigger pulled
+        bus.tick(10);
+        assert_eq!(bus.mem_read(0x4017), 0b1000);
+        assert_eq!(bus.mem_read(0x4016), 0); // not on this port
+
+        bus.plug(Trigger { pulled_for: 100, cycles: 0 }); // replaces the first
+        assert_eq!(bus.mem_read(0x4017), 0);
+        assert_eq!(bus.unplug::<Trigger>().map(|trigger| trigger.pulled_for), Some(100));
+        assert!(bus.device::<Trigger>().is_none());
+        assert_eq!(bus.mem_read(0x4017), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_open_bus() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let bus = &mut nes.cpu().bus;
+        bus.mem_write(0x0000, 0x5A);
+        assert_eq!(bus.mem_read(0x5000), 0x5A); // nothing there: the last value stays
+        assert_eq!(bus.mem_read(0x4000), 0x5A);
+
+        bus.joypads().0.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        bus.mem_write(0x0000, 0x40); // like the $40 operand byte of LDA $4016
+        assert_eq!(bus.mem_read(0x4016), 0x41);
+
+        bus.set_open_bus(false);
+        assert_eq!(bus.mem_read(0x5000), 0);
+        assert_eq!(bus.mem_read(0x4016), 0);
+
+        bus.set_microphone(true);
+        assert_eq!(bus.mem_read(0x4016), 0x04);
+        assert_eq!(bus.mem_read(0x4017) & 0x04, 0); // not on the second controller's own port
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = crate::cartridge::test::test_rom();
+        rom.mapper = 66; // GxROM
+        rom.prg_rom = (0..4).flat_map(|bank| vec![(bank as u8 * 0x10) | 0x0F; 0x8000]).collect();
+        let mut bus = Bus::new(rom, |_, _, _| {}).unwrap();
+        bus.mem_write(0x8000, 0x30);
+        assert_eq!(bus.mem_read(0x8000), 0x0F); // $30 & $0F: still bank 0
+
+        bus.set_bus_conflicts(false);
+        bus.mem_write(0x8000, 0x30);
+        assert_eq!(bus.mem_read(0x8000), 0x3F);
+    }
+}
@@ -1,26 +1,70 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::error::RunescoError;
+use crate::mapper::mmc3::IrqRevision;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const UNIF_TAG: &[u8] = b"UNIF";
+const FDS_TAG: &[u8] = b"FDS\x1A"; // fwNES header in front of the disk sides
+const FDS_DISK_INFO: &[u8] = b"\x01*NINTENDO-HVC*"; // how every disk side starts
+const UNIF_HEADER_SIZE: usize = 32; // tag, revision, then padding
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+pub const PRG_RAM_PAGE_SIZE: usize = 8192;
+const MAX_PRG_RAM_SIZE: usize = 0x8000; // the most any supported board can bank (MMC1's SXROM)
+pub const CHR_RAM_SIZE: usize = 8192; // what boards without CHR ROM carry instead
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum Mirroring { // PPU related, will be covered later
    VERTICAL,
    HORIZONTAL,
    FOUR_SCREEN,
+   SINGLE_SCREEN_A, // all four nametables show the first 1KiB of VRAM (mapper controlled)
+   SINGLE_SCREEN_B, // all four nametables show the second 1KiB of VRAM
+}
+
+// The TV system a game was made for. NTSC (Japan, America) and PAL (Europe, Australia)
+// consoles run at different speeds (60 vs 50 frames per second, different CPU clocks), and
+// games are written for one of them; "dual" games check which console they're on.
+// Only NTSC timing is emulated so far, whatever the cartridge says.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dual,
 }
 
+#[derive(Clone)]
 pub struct Rom {
    pub prg_rom: Vec<u8>, // program rom: contains the code data for the game
    pub chr_rom: Vec<u8>, // "character" rom: contains the visual data for the game
    pub mapper: u8, // to provide access to extra memory in the rom
    pub screen_mirroring: Mirroring,
+   pub battery: bool, // PRG-RAM at $6000-$7FFF keeps its contents with the power off (save games)
+   pub trainer: Option<Vec<u8>>, // 512 bytes that belong at $7000-$71FF (patches added by old copier devices)
+   pub disk_sides: Vec<Vec<u8>>, // Disk System games only, see crate::mapper::fds
+   pub region: Region,
+   pub vs_system: bool, // an arcade board (Nintendo Vs. System): coins, DIP switches, see crate::vs_system
+   pub playchoice: bool, // a PlayChoice-10 arcade game; they run like the console version
+   pub mmc3_irq: IrqRevision, // which MMC3 the board has; no header says, only the ROM database
+   pub prg_ram_size: usize,   // work RAM at $6000-$7FFF, mirrored across it when smaller than 8KiB
 }
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
+    pub fn new(raw: &[u8]) -> Result<Rom, RunescoError> {
+        if raw.starts_with(UNIF_TAG) {
+            return Rom::from_unif(raw);
+        }
+        if raw.starts_with(FDS_TAG) || raw.starts_with(FDS_DISK_INFO) {
+            return Rom::from_fds(raw);
+        }
+        if raw.len() < 16 { // not even room for the header
+            return Err(RunescoError::InvalidRom("File is not in iNES file format".to_string()));
+        }
         if &raw[0..4] != NES_TAG { // first four bits don't match NES format
-            return Err("File is not in iNES file format".to_string());
+            return Err(RunescoError::InvalidRom("File is not in iNES file format".to_string()));
         }
  
         let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
@@ -29,14 +73,20 @@ impl Rom {
         // the u8 mapper type
  
         let ines_ver = (raw[7] >> 2) & 0b11; // iNES vers. from control byte 2 (bit 3,2)
+        if ines_ver != 0 && has_garbage(raw) {
+            // not NES 2.0, the version bits are part of the junk
+            return Err(RunescoError::InvalidRom(HeaderWarning::Garbage(garbage_text(raw)).to_string()));
+        }
         if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+            return Err(RunescoError::UnsupportedFormat("NES2.0 format is not supported".to_string()));
         }
 
         // get mirroring type from CB 1 (byte 6)
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
         let screen_mirroring = match (four_screen, vertical_mirroring) {
+            // UNROM-512 reuses the four-screen bit alone for its one-screen boards
+            (true, false) if mapper == 30 => Mirroring::SINGLE_SCREEN_A,
             (true, _) => Mirroring::FOUR_SCREEN,
             (false, true) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
@@ -45,12 +95,46 @@ impl Rom {
         let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
         let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
  
+        let battery = raw[6] & 0b10 != 0;
+        let vs_system = raw[7] & 0b01 != 0; // arcade boards, in control byte 2
+        let playchoice = raw[7] & 0b10 != 0;
         let skip_trainer = raw[6] & 0b100 != 0;
         // gets whether trainer exists and if so whether it should be skipped or not.
  
+        let trainer = if skip_trainer { Some(raw[16..16 + 512].to_vec()) } else { None };
         let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 }; // if exits, skip it. 
         // Set starting position of code after the header accordingly.
         let chr_rom_start = prg_rom_start + prg_rom_size; // always starts after the prg rom.
+        if prg_rom_size == 0 {
+            return Err(RunescoError::InvalidRom("The header says there is no PRG ROM".to_string()));
+        }
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(RunescoError::InvalidRom(format!(
+                "The header promises {}KiB of PRG and {}KiB of CHR ROM, but the file is cut short",
+                prg_rom_size / 1024,
+                chr_rom_size / 1024
+            )));
+        }
+
+        // The TV system is in byte 9 bit 0 (the official flag, rarely set) and in byte 10 bits 0-1
+        // (unofficial, but what most PAL dumps use: 0 NTSC, 2 PAL, 1 or 3 dual). Old tools
+        // wrote their name ("DiskDude!") over bytes 7-15, so when bytes 12-15 aren't zero
+        // neither flag can be trusted.
+        let region = match (raw[9] & 1, raw[10] & 0b11) {
+            _ if has_garbage(raw) => Region::Ntsc,
+            (1, _) | (_, 2) => Region::Pal,
+            (_, 1) | (_, 3) => Region::Dual,
+            _ => Region::Ntsc,
+        };
+
+        // Byte 8 is the PRG-RAM size in 8KiB units, 0 meaning one for compatibility with the
+        // files written before the field existed (which is most of them). Nothing smaller than
+        // 8KiB fits in it: those boards come from the ROM database.
+        let prg_ram_size = match raw[8] as usize * PRG_RAM_PAGE_SIZE {
+            _ if has_garbage(raw) => PRG_RAM_PAGE_SIZE,
+            0 => PRG_RAM_PAGE_SIZE,
+            size => size.min(MAX_PRG_RAM_SIZE),
+        };
 
         // if all works correclty,
         Ok(Rom {
@@ -58,10 +142,263 @@ impl Rom {
             chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
             mapper: mapper,
             screen_mirroring: screen_mirroring,
+            battery,
+            trainer,
+            disk_sides: vec![],
+            region,
+            vs_system,
+            playchoice,
+            mmc3_irq: IrqRevision::default(),
+            prg_ram_size,
         })
     }
+
+    // UNIF, the other container format: mostly homebrew and pirate multicarts use it. Instead of
+    // a mapper number it names the circuit board ("NES-SLROM"), and the data comes in tagged
+    // chunks (4 character tag, u32 length, data) after a 32 byte header:
+    //
+    //   MAPR  board name, zero terminated
+    //   PRG0..PRGF, CHR0..CHRF  ROM chips, concatenated in chip order
+    //   MIRR  0 horizontal, 1 vertical, 2/3 single screen, 4 four screen, 5 mapper controlled
+    //   BATR  present if PRG-RAM has a battery
+    //   TVCI  0 NTSC, 1 PAL, 2 both
+    //
+    // Everything else (title, dumper info, CRCs) is skipped.
+    // See: https://www.nesdev.org/wiki/UNIF
+    fn from_unif(raw: &[u8]) -> Result<Rom, RunescoError> {
+        if raw.len() < UNIF_HEADER_SIZE {
+            return Err(RunescoError::InvalidRom("UNIF header is cut short".to_string()));
+        }
+
+        let mut board = None;
+        let mut prg_chips: Vec<(u8, &[u8])> = vec![];
+        let mut chr_chips: Vec<(u8, &[u8])> = vec![];
+        let mut screen_mirroring = Mirroring::HORIZONTAL;
+        let mut battery = false;
+        let mut region = Region::Ntsc;
+
+        let mut rest = &raw[UNIF_HEADER_SIZE..];
+        while rest.len() >= 8 {
+            let tag = &rest[0..4];
+            let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let data = rest
+                .get(8..8 + len)
+                .ok_or_else(|| RunescoError::InvalidRom(format!("UNIF chunk {} is cut short", String::from_utf8_lossy(tag))))?;
+            rest = &rest[8 + len..];
+
+            let chip = (tag[3] as char).to_digit(16).map(|n| (n as u8, data)); // PRG0..PRGF
+            match tag {
+                b"MAPR" => {
+                    let name = data.split(|&b| b == 0).next().unwrap_or_default();
+                    board = Some(String::from_utf8_lossy(name).trim().to_string());
+                }
+                _ if tag.starts_with(b"PRG") => prg_chips.extend(chip),
+                _ if tag.starts_with(b"CHR") => chr_chips.extend(chip),
+                b"MIRR" => {
+                    screen_mirroring = match data.first() {
+                        Some(1) => Mirroring::VERTICAL,
+                        Some(2) => Mirroring::SINGLE_SCREEN_A,
+                        Some(3) => Mirroring::SINGLE_SCREEN_B,
+                        Some(4) => Mirroring::FOUR_SCREEN,
+                        _ => Mirroring::HORIZONTAL, // 5: whatever the mapper sets
+                    };
+                }
+                b"BATR" => battery = true,
+                b"TVCI" => {
+                    region = match data.first() {
+                        Some(1) => Region::Pal,
+                        Some(2) => Region::Dual,
+                        _ => Region::Ntsc,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        let board = board
+            .ok_or_else(|| RunescoError::InvalidRom("UNIF file doesn't name its board (no MAPR chunk)".to_string()))?;
+        let mapper = unif_board_mapper(&board).ok_or(RunescoError::UnsupportedBoard(board))?;
+        prg_chips.sort_by_key(|(n, _)| *n);
+        chr_chips.sort_by_key(|(n, _)| *n);
+        Ok(Rom {
+            prg_rom: prg_chips.iter().flat_map(|(_, data)| data.iter().copied()).collect(),
+            chr_rom: chr_chips.iter().flat_map(|(_, data)| data.iter().copied()).collect(),
+            mapper,
+            screen_mirroring,
+            battery,
+            trainer: None,
+            disk_sides: vec![],
+            region,
+            vs_system: false,
+            playchoice: false,
+            mmc3_irq: IrqRevision::default(),
+            prg_ram_size: PRG_RAM_PAGE_SIZE,
+        })
+    }
+
+    // A Disk System image (.fds): the disk sides one after the other, 65500 bytes each, with or
+    // without a 16 byte header. There's no PRG ROM: the frontend puts the BIOS there before the
+    // console is powered on (see crate::mapper::fds).
+    fn from_fds(raw: &[u8]) -> Result<Rom, RunescoError> {
+        use crate::mapper::fds;
+
+        let data = if raw.starts_with(FDS_TAG) { &raw[16.min(raw.len())..] } else { raw };
+        let disk_sides: Vec<Vec<u8>> = data.chunks(fds::SIDE_SIZE).map(|side| side.to_vec()).collect();
+        if disk_sides.is_empty() || !disk_sides.iter().all(|side| side.starts_with(FDS_DISK_INFO)) {
+            return Err(RunescoError::InvalidRom("Disk image has a side that isn't a Disk System disk".to_string()));
+        }
+        Ok(Rom {
+            prg_rom: vec![],
+            chr_rom: vec![],
+            mapper: fds::MAPPER,
+            screen_mirroring: Mirroring::HORIZONTAL,
+            battery: false,
+            trainer: None,
+            disk_sides,
+            region: Region::Ntsc, // the Disk System was only sold in Japan
+            vs_system: false,
+            playchoice: false,
+            mmc3_irq: IrqRevision::default(),
+            prg_ram_size: PRG_RAM_PAGE_SIZE,
+        })
+    }
+
+    // Also opens .zip and .7z archives (see crate::archive).
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Rom, RunescoError> {
+        Rom::new(&crate::archive::read_image(path)?)
+    }
+
+    // Hashes of PRG ROM followed by CHR ROM (or the disk sides): the same for every dump of a
+    // game whatever its header says, which is what ROM databases are keyed by (see crate::romdb).
+    pub fn crc32(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.prg_rom);
+        hasher.update(&self.chr_rom);
+        self.disk_sides.iter().for_each(|side| hasher.update(side));
+        hasher.finalize()
+    }
+
+    pub fn sha1(&self) -> String {
+        let mut hasher = sha1_smol::Sha1::new();
+        hasher.update(&self.prg_rom);
+        hasher.update(&self.chr_rom);
+        self.disk_sides.iter().for_each(|side| hasher.update(side));
+        hasher.digest().to_string().to_ascii_uppercase()
+    }
 }
 
+// Things wrong with an iNES header that don't stop the game from loading, but may make it
+// run wrong: see check_header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderWarning {
+    // Bytes 7-15 hold leftover text from an old ROM tool ("DiskDude!"), so the mapper number's
+    // high half and the flags in byte 7 are really part of it. repair_header clears them.
+    Garbage(String),
+    // Bytes 11-15 are reserved (zero) in iNES headers; these offsets aren't.
+    ReservedBytes(Vec<usize>),
+    // Bytes after the PRG and CHR ROM that the header doesn't mention (PlayChoice-10 dumps
+    // carry 8KiB of INST-ROM and PROM there); they are ignored.
+    TrailingData(usize),
+}
+
+impl HeaderWarning {
+    // Short name for machine-readable reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HeaderWarning::Garbage(_) => "garbage",
+            HeaderWarning::ReservedBytes(_) => "reserved-bytes",
+            HeaderWarning::TrailingData(_) => "trailing-data",
+        }
+    }
+}
+
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderWarning::Garbage(text) => write!(
+                f,
+                "bytes 7-15 of the header hold leftover text (\"{}\"), so the mapper number may be wrong; --repair-header clears them",
+                text
+            ),
+            HeaderWarning::ReservedBytes(offsets) => {
+                let offsets: Vec<String> = offsets.iter().map(|offset| offset.to_string()).collect();
+                write!(f, "reserved header bytes {} aren't zero", offsets.join(", "))
+            }
+            HeaderWarning::TrailingData(bytes) => write!(f, "{} bytes after the ROM data aren't used", bytes),
+        }
+    }
+}
+
+// Old tools wrote their name over bytes 7-15 ("DiskDude!", "Ni03"...). Bytes 12-15 are
+// always zero in a clean iNES header, so anything there means the whole tail is junk.
+fn has_garbage(raw: &[u8]) -> bool {
+    raw[12..16].iter().any(|&b| b != 0)
+}
+
+fn garbage_text(raw: &[u8]) -> String {
+    let text: String = raw[7..16].iter().filter(|b| b.is_ascii_graphic() || **b == b' ').map(|&b| b as char).collect();
+    text.trim().to_string()
+}
+
+// Sanity checks an iNES image's header against its contents. Images whose sizes don't fit the
+// file are refused by Rom::new instead; other formats have nothing to check here.
+pub fn check_header(raw: &[u8]) -> Vec<HeaderWarning> {
+    if raw.len() < 16 || raw[0..4] != NES_TAG {
+        return vec![];
+    }
+    let mut warnings = vec![];
+    let reserved: Vec<usize> = (11..16).filter(|&offset| raw[offset] != 0).collect();
+    if has_garbage(raw) {
+        warnings.push(HeaderWarning::Garbage(garbage_text(raw)));
+    } else if !reserved.is_empty() {
+        warnings.push(HeaderWarning::ReservedBytes(reserved));
+    }
+    let trainer = if raw[6] & 0b100 != 0 { 512 } else { 0 };
+    let expected = 16 + trainer + raw[4] as usize * PRG_ROM_PAGE_SIZE + raw[5] as usize * CHR_ROM_PAGE_SIZE;
+    if raw.len() > expected {
+        warnings.push(HeaderWarning::TrailingData(raw.len() - expected));
+    }
+    warnings
+}
+
+// Clears the garbage check_header found in bytes 7-15, leaving a plain iNES header: mapper
+// number from byte 6 alone, no Vs./PlayChoice flags, NTSC. Returns whether anything changed.
+pub fn repair_header(raw: &mut [u8]) -> bool {
+    if raw.len() < 16 || raw[0..4] != NES_TAG || !has_garbage(raw) {
+        return false;
+    }
+    raw[7..16].fill(0);
+    true
+}
+
+
+// The iNES mapper number for a UNIF board name, so UNIF games run on the same mapper
+// implementations. Boards are listed even if their mapper isn't implemented yet: the mapper
+// lookup then reports it (and --mapper-fallback can still kick in).
+fn unif_board_mapper(board: &str) -> Option<u8> {
+    let name = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    let mapper = match name {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => 0,
+        "SAROM" | "SBROM" | "SCROM" | "SEROM" | "SFROM" | "SGROM" | "SHROM" | "SJROM" | "SKROM" | "SLROM"
+        | "SL1ROM" | "SNROM" | "SOROM" | "SUROM" | "SXROM" => 1,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TNROM" | "TR1ROM" | "TSROM" | "TVROM"
+        | "B4" => 4,
+        "AMROM" | "ANROM" | "AN1ROM" | "AOROM" => 7,
+        "PNROM" | "PEEOROM" => 9,
+        "BNROM" => 34,
+        "GNROM" | "MHROM" => 66,
+        "JLROM" | "JSROM" | "BTR" => 69,
+        "TLSROM" | "TKSROM" => 118,
+        "TQROM" => 119,
+        _ => return None,
+    };
+    Some(mapper)
+}
 
 // tests imported from book
 pub mod test {
@@ -125,6 +462,7 @@ pub mod test {
         assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert_eq!(rom.trainer, None);
     }
 
     #[test]
@@ -148,7 +486,7 @@ pub mod test {
                 00,
                 00,
             ],
-            trainer: Some(vec![0; 512]),
+            trainer: Some(vec![3; 512]),
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
             chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
         });
@@ -159,6 +497,119 @@ pub mod test {
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert_eq!(rom.trainer, Some(vec![3; 512]));
+    }
+
+    #[test]
+    fn test_cut_short() {
+        let mut raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        raw.pop();
+        assert!(matches!(Rom::new(&raw), Err(RunescoError::InvalidRom(_))));
+        raw[4] = 0;
+        assert!(matches!(Rom::new(&raw), Err(RunescoError::InvalidRom(_))));
+    }
+
+    #[test]
+    fn test_header_checks() {
+        let mut raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x10, 00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert!(check_header(&raw).is_empty());
+        raw.extend([0; 16]);
+        assert_eq!(check_header(&raw), vec![HeaderWarning::TrailingData(16)]);
+        raw.truncate(raw.len() - 16);
+
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(check_header(&raw), vec![HeaderWarning::Garbage("DiskDude!".to_string())]);
+        assert!(matches!(Rom::new(&raw), Err(RunescoError::InvalidRom(_)))); // 'D' looks like NES 2.0
+        assert!(repair_header(&mut raw));
+        assert!(check_header(&raw).is_empty());
+        assert_eq!(Rom::new(&raw).unwrap().mapper, 1);
+        assert!(!repair_header(&mut raw));
+
+        raw[11] = 1;
+        assert_eq!(check_header(&raw), vec![HeaderWarning::ReservedBytes(vec![11])]);
+    }
+
+    #[test]
+    fn test_trainer_is_loaded_at_7000() {
+        use crate::bus::Bus;
+        use crate::cpu::Mem;
+
+        let mut trainer = vec![0; 512];
+        trainer[0] = 0xAB;
+        trainer[511] = 0xCD;
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0b100, 00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: Some(trainer),
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let mut bus = Bus::new(Rom::new(&raw).unwrap(), |_, _, _| {}).unwrap();
+        assert_eq!(bus.mem_read(0x6FFF), 0);
+        assert_eq!(bus.mem_read(0x7000), 0xAB);
+        assert_eq!(bus.mem_read(0x71FF), 0xCD);
+    }
+
+    #[test]
+    fn test_unif() {
+        fn unif_chunk(tag: &[u8; 4], data: &[u8]) -> Vec<u8> {
+            let mut chunk = tag.to_vec();
+            chunk.extend((data.len() as u32).to_le_bytes());
+            chunk.extend(data);
+            chunk
+        }
+
+        let mut raw = b"UNIF".to_vec();
+        raw.extend(7u32.to_le_bytes());
+        raw.resize(UNIF_HEADER_SIZE, 0);
+        raw.extend(unif_chunk(b"NAME", b"Test\0"));
+        raw.extend(unif_chunk(b"MAPR", b"NES-SLROM\0"));
+        raw.extend(unif_chunk(b"PRG1", &[2; PRG_ROM_PAGE_SIZE])); // chips out of order
+        raw.extend(unif_chunk(b"PRG0", &[1; PRG_ROM_PAGE_SIZE]));
+        raw.extend(unif_chunk(b"CHR0", &[3; CHR_ROM_PAGE_SIZE]));
+        raw.extend(unif_chunk(b"MIRR", &[1]));
+        raw.extend(unif_chunk(b"BATR", &[0]));
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!((rom.prg_rom[0], rom.prg_rom[PRG_ROM_PAGE_SIZE]), (1, 2));
+        assert_eq!(rom.chr_rom, vec![3; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert!(rom.battery);
+
+        let mut unknown = raw[..UNIF_HEADER_SIZE].to_vec();
+        unknown.extend(unif_chunk(b"MAPR", b"UNL-SOMETHING-ELSE\0"));
+        assert_eq!(Rom::new(&unknown).err().unwrap().to_string(), "UNIF board UNL-SOMETHING-ELSE is not supported");
+        assert!(Rom::new(&raw[..raw.len() - 1]).is_err()); // last chunk cut short
+    }
+
+    #[test]
+    fn test_fds_image() {
+        let mut side = FDS_DISK_INFO.to_vec();
+        side.resize(crate::mapper::fds::SIDE_SIZE, 0);
+        let mut raw = FDS_TAG.to_vec();
+        raw.extend([2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.extend(&side);
+        raw.extend(&side);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, crate::mapper::fds::MAPPER);
+        assert_eq!(rom.disk_sides.len(), 2);
+        assert!(rom.prg_rom.is_empty());
+        assert_eq!(Rom::new(&raw[16..]).unwrap().disk_sides, rom.disk_sides); // headerless
+
+        raw[16 + crate::mapper::fds::SIDE_SIZE] = 0; // second side is garbage
+        assert!(Rom::new(&raw).is_err());
     }
 
     #[test]
@@ -174,7 +625,48 @@ pub mod test {
         let rom = Rom::new(&test_rom);
         match rom {
             Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
+            Result::Err(RunescoError::UnsupportedFormat(str)) => assert_eq!(str, "NES2.0 format is not supported"),
+            Result::Err(err) => panic!("wrong error: {:?}", err),
         }
     }
+
+    #[test]
+    fn test_region() {
+        let region = |byte9: u8, byte10: u8, byte12: u8| {
+            let test_rom = create_rom(TestRom {
+                header: vec![
+                    0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 00, 00, byte9, byte10, 00, byte12, 00, 00, 00,
+                ],
+                trainer: None,
+                pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+                chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+            });
+            Rom::new(&test_rom).unwrap().region
+        };
+        assert_eq!(region(0, 0, 0), Region::Ntsc);
+        assert_eq!(region(1, 0, 0), Region::Pal);
+        assert_eq!(region(0, 2, 0), Region::Pal);
+        assert_eq!(region(0, 3, 0), Region::Dual);
+        assert_eq!(region(0, 2, b'e'), Region::Ntsc); // "DiskDude!" style garbage in the header
+    }
+
+    #[test]
+    fn test_prg_ram_size() {
+        let prg_ram_size = |byte8: u8, byte12: u8| {
+            let test_rom = create_rom(TestRom {
+                header: vec![
+                    0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x10, 00, byte8, 00, 00, 00, byte12, 00, 00, 00,
+                ],
+                trainer: None,
+                pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+                chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+            });
+            Rom::new(&test_rom).unwrap().prg_ram_size
+        };
+        assert_eq!(prg_ram_size(0, 0), 0x2000);
+        assert_eq!(prg_ram_size(1, 0), 0x2000);
+        assert_eq!(prg_ram_size(4, 0), 0x8000);
+        assert_eq!(prg_ram_size(200, 0), MAX_PRG_RAM_SIZE);
+        assert_eq!(prg_ram_size(4, b'e'), 0x2000);
+    }
 }
\ No newline at end of file
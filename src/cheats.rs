@@ -0,0 +1,279 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Cheat codes: each one replaces what the CPU reads at one address. Nothing in memory is
+// changed, the Bus just hands the CPU a different value (see Bus::mem_read), which is how the
+// Game Genie works: it sits between the console and the cartridge and answers some ROM reads
+// itself. Codes are entered as:
+//
+//   Game Genie, 6 letters (SXIOPO): address in $8000-$FFFF and the value read there
+//   Game Genie, 8 letters (YEUZUGAA): the same, but only while the ROM holds a compare value,
+//     so the code doesn't hit other banks switched in at that address
+//   raw, as Pro Action Replay and other emulators write them: 0300:05 (address:value), or
+//     0300?03:05 (address?compare:value)
+//
+// Freeze cheats (0300=05) work the other way: at the start of every frame the value is
+// written into RAM ($0000-$07FF, or the cartridge's $6000-$7FFF), so the game finds it there
+// whatever it did the frame before. That's the "infinite lives" kind: the game may still take
+// a life away, but it's back by the next frame. Unlike a replaced read the change is real,
+// and stays once the cheat is switched off.
+//
+// See: https://www.nesdev.org/wiki/Game_Genie
+
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub code: String, // as entered, uppercased
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>, // only replace reads of this value
+    pub freeze: bool,        // written every frame instead of replacing reads
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_ascii_uppercase();
+        if let Some((addr, value)) = code.split_once('=') {
+            let (addr, value) = parse_freeze(addr, value)
+                .ok_or_else(|| format!("'{}' is not a freeze code: address=value, in $0000-$1FFF or $6000-$7FFF", code))?;
+            return Ok(Cheat { code, addr, value, compare: None, freeze: true, enabled: true });
+        }
+        let (addr, value, compare) = if code.contains(':') {
+            parse_raw(&code)
+        } else {
+            decode_game_genie(&code)
+        }
+        .ok_or_else(|| format!("'{}' is not a Game Genie code or address:value", code))?;
+        Ok(Cheat { code, addr, value, compare, freeze: false, enabled: true })
+    }
+
+    // What the CPU sees when it reads `data` at `addr`.
+    fn apply(&self, addr: u16, data: u8) -> u8 {
+        if self.enabled && !self.freeze && addr == self.addr && self.compare.is_none_or(|compare| compare == data) {
+            self.value
+        } else {
+            data
+        }
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let off = if self.enabled { "" } else { " off" };
+        if self.freeze {
+            return write!(f, "{} (freeze {:04X}={:02X}){}", self.code, self.addr, self.value, off);
+        }
+        write!(f, "{} ({:04X}", self.code, self.addr)?;
+        if let Some(compare) = self.compare {
+            write!(f, "?{:02X}", compare)?;
+        }
+        write!(f, ":{:02X}){}", self.value, off)
+    }
+}
+
+fn decode_game_genie(code: &str) -> Option<(u16, u8, Option<u8>)> {
+    let n: Vec<u8> = code
+        .bytes()
+        .map(|letter| GAME_GENIE_LETTERS.iter().position(|&l| l == letter).map(|n| n as u8))
+        .collect::<Option<_>>()?;
+    if n.len() != 6 && n.len() != 8 {
+        return None;
+    }
+    // the bits of the address and value are scattered over the letters
+    let addr = 0x8000
+        | ((n[3] & 7) as u16) << 12
+        | ((n[5] & 7) as u16) << 8
+        | ((n[4] & 8) as u16) << 8
+        | ((n[2] & 7) as u16) << 4
+        | ((n[1] & 8) as u16) << 4
+        | (n[4] & 7) as u16
+        | (n[3] & 8) as u16;
+    let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | n[0] & 7;
+    if n.len() == 6 {
+        return Some((addr, value | n[5] & 8, None));
+    }
+    let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | n[6] & 7 | n[5] & 8;
+    Some((addr, value | n[7] & 8, Some(compare)))
+}
+
+// AAAA=VV, with the address folded down to the RAM it mirrors.
+fn parse_freeze(addr: &str, value: &str) -> Option<(u16, u8)> {
+    let addr = match u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()? {
+        addr @ 0x0000..=0x1FFF => addr & 0x07FF,
+        addr @ 0x6000..=0x7FFF => addr,
+        _ => return None,
+    };
+    Some((addr, u8::from_str_radix(value, 16).ok()?))
+}
+
+// AAAA:VV or AAAA?CC:VV
+fn parse_raw(code: &str) -> Option<(u16, u8, Option<u8>)> {
+    let (target, value) = code.split_once(':')?;
+    let (addr, compare) = match target.split_once('?') {
+        Some((addr, compare)) => (addr, Some(u8::from_str_radix(compare, 16).ok()?)),
+        None => (target, None),
+    };
+    Some((u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()?, u8::from_str_radix(value, 16).ok()?, compare))
+}
+
+#[derive(Debug, Default)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats::default()
+    }
+
+    // A per-game cheat file, one code per line, optionally followed by a description:
+    //
+    //   # Super Mario Bros.
+    //   SXIOPO   infinite lives
+    //   -0756:02 start as Fire Mario (a leading '-' loads it switched off)
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let code = line.split_whitespace().next().unwrap();
+            let (code, enabled) = match code.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let mut cheat = Cheat::parse(code).map_err(|err| format!("line {}: {}", n + 1, err))?;
+            cheat.enabled = enabled;
+            cheats.list.push(cheat);
+        }
+        Ok(cheats)
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.list.retain(|other| other.code != cheat.code);
+        self.list.push(cheat);
+    }
+
+    // By code as entered. Returns whether there was such a cheat.
+    pub fn remove(&mut self, code: &str) -> bool {
+        let before = self.list.len();
+        self.list.retain(|cheat| !cheat.code.eq_ignore_ascii_case(code));
+        self.list.len() != before
+    }
+
+    pub fn set_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        match self.list.iter_mut().find(|cheat| cheat.code.eq_ignore_ascii_case(code)) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_all_enabled(&mut self, enabled: bool) {
+        self.list.iter_mut().for_each(|cheat| cheat.enabled = enabled);
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // Called by the Bus on every CPU read.
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {
+        self.list.iter().fold(data, |data, cheat| cheat.apply(addr, data))
+    }
+
+    // The freeze cheats switched on, for the Bus to write at the start of a frame.
+    pub fn freezes(&self) -> impl Iterator<Item = &Cheat> {
+        self.list.iter().filter(|cheat| cheat.enabled && cheat.freeze)
+    }
+
+    // Cheats switched on that fight over an address: each wants a different value there,
+    // and which one the game sees depends on the order they're applied in.
+    pub fn conflicts(&self) -> Vec<(&Cheat, &Cheat)> {
+        let on: Vec<&Cheat> = self.list.iter().filter(|cheat| cheat.enabled).collect();
+        let mut conflicts = vec![];
+        for (i, a) in on.iter().enumerate() {
+            for b in &on[i + 1..] {
+                if ram_addr(a.addr) == ram_addr(b.addr) && a.value != b.value {
+                    conflicts.push((*a, *b));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+// A replaced read of $0800 and a freeze of $0000 are the same byte.
+fn ram_addr(addr: u16) -> u16 {
+    if addr < 0x2000 {
+        addr & 0x07FF
+    } else {
+        addr
+    }
+}
+
+// game.nes -> game.cht
+pub fn cht_path(rom_path: impl AsRef<Path>) -> PathBuf {
+    rom_path.as_ref().with_extension("cht")
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        // Super Mario Bros. infinite lives: DEC $075A becomes LDA $075A
+        let cheat = Cheat::parse("sxiopo").unwrap();
+        assert_eq!((cheat.addr, cheat.value, cheat.compare), (0x91D9, 0xAD, None));
+        let cheat = Cheat::parse("APZLGITY").unwrap();
+        assert_eq!((cheat.addr, cheat.value, cheat.compare), (0xB524, 0x10, Some(0x76)));
+        let cheat = Cheat::parse("0300?03:05").unwrap();
+        assert_eq!((cheat.addr, cheat.value, cheat.compare), (0x0300, 0x05, Some(0x03)));
+        assert!(Cheat::parse("SXIOP").is_err());
+        assert!(Cheat::parse("0300:5G").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut cheats = Cheats::parse("# comment\nSXIOPO infinite lives\n-0300?03:05\n").unwrap();
+        assert_eq!(cheats.apply(0x91D9, 0xCE), 0xAD);
+        assert_eq!(cheats.apply(0x91DA, 0xCE), 0xCE);
+        assert_eq!(cheats.apply(0x0300, 0x03), 0x03); // loaded switched off
+
+        assert!(cheats.set_enabled("0300?03:05", true));
+        assert_eq!(cheats.apply(0x0300, 0x03), 0x05);
+        assert_eq!(cheats.apply(0x0300, 0x04), 0x04); // compare doesn't match
+        assert!(cheats.remove("sxiopo"));
+        assert_eq!(cheats.apply(0x91D9, 0xCE), 0xCE);
+        assert!(Cheats::parse("NOTACODE").unwrap_err().starts_with("line 1:"));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut cheats = Cheats::parse("075A=09 lives\n0300:05\n-6100=01\n").unwrap();
+        let freezes: Vec<(u16, u8)> = cheats.freezes().map(|cheat| (cheat.addr, cheat.value)).collect();
+        assert_eq!(freezes, [(0x075A, 0x09)]);
+        assert_eq!(cheats.apply(0x075A, 0x02), 0x02); // reads are left alone
+        assert_eq!(Cheat::parse("0F5A=09").unwrap().addr, 0x075A); // a mirror of $075A
+        assert!(Cheat::parse("8000=01").is_err()); // ROM can't be written
+        assert_eq!(cheats.list()[2].to_string(), "6100=01 (freeze 6100=01) off");
+
+        assert!(cheats.conflicts().is_empty());
+        cheats.add(Cheat::parse("0B00=06").unwrap()); // $0300 again, through a mirror
+        let conflicts = cheats.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].0.code.as_str(), conflicts[0].1.code.as_str()), ("0300:05", "0B00=06"));
+        cheats.set_enabled("0300:05", false);
+        assert!(cheats.conflicts().is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+
+use crate::cartridge::{self, HeaderWarning, Mirroring, Region, Rom};
+use crate::error::RunescoError;
+use crate::json::Json;
+use crate::mapper::{self, fds};
+
+// `runesco check game.nes`: what a ROM needs and how much of it the emulator has, before
+// anyone spends an evening wondering why a game glitches. The mapper side comes from the
+// mapper registry (mapper::MAPPERS); the rest from the header.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Support {
+    Full,
+    Partial, // runs, with something missing
+    Missing, // won't run
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub feature: String,
+    pub support: Support,
+    pub missing: Vec<String>, // what isn't emulated, empty unless Partial or None
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub header: Vec<String>, // what the file says, one fact per line
+    pub warnings: Vec<HeaderWarning>, // what's wrong with it (see cartridge::check_header)
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    // The worst of the findings.
+    pub fn verdict(&self) -> Support {
+        self.findings.iter().map(|finding| finding.support).max().unwrap_or(Support::Full)
+    }
+
+    pub fn missing(&self) -> Vec<&str> {
+        self.findings.iter().flat_map(|finding| finding.missing.iter().map(String::as_str)).collect()
+    }
+
+    // For `check --json`.
+    pub fn to_json(&self) -> Json {
+        let findings = self.findings.iter().map(|finding| {
+            Json::object()
+                .with("feature", finding.feature.as_str())
+                .with("support", finding.support.name())
+                .with("missing", finding.missing.clone())
+        });
+        let warnings = self.warnings.iter().map(|warning| {
+            Json::object().with("kind", warning.kind()).with("message", warning.to_string())
+        });
+        Json::object()
+            .with("header", self.header.clone())
+            .with("header_warnings", Json::Array(warnings.collect()))
+            .with("findings", Json::Array(findings.collect()))
+            .with("verdict", self.verdict().name())
+    }
+}
+
+impl Support {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Support::Full => "ok",
+            Support::Partial => "partial",
+            Support::Missing => "missing",
+        }
+    }
+}
+
+// `image` is the file as loaded (for the header format), `rom` what was made of it.
+pub fn check(image: &[u8], rom: &Rom) -> Report {
+    let kib = |bytes: usize| format!("{}KiB", bytes / 1024);
+    let mut header = vec![format!("format: {}", format_name(image))];
+    if rom.mapper == fds::MAPPER {
+        header.push(format!("disk sides: {}", rom.disk_sides.len()));
+    } else {
+        header.push(format!("PRG ROM: {}", kib(rom.prg_rom.len())));
+        header.push(match rom.chr_rom.len() {
+            0 => format!("CHR: {} of RAM", kib(mapper::chr_ram_size(rom.mapper))),
+            len => format!("CHR ROM: {}", kib(len)),
+        });
+    }
+    let mirroring = match rom.screen_mirroring {
+        Mirroring::VERTICAL => "vertical",
+        Mirroring::HORIZONTAL => "horizontal",
+        Mirroring::FOUR_SCREEN => "four-screen",
+        Mirroring::SINGLE_SCREEN_A | Mirroring::SINGLE_SCREEN_B => "single-screen",
+    };
+    header.push(format!("mirroring: {}", mirroring));
+    let region = match rom.region {
+        Region::Ntsc => "NTSC",
+        Region::Pal => "PAL",
+        Region::Dual => "NTSC and PAL",
+    };
+    header.push(format!("TV system: {}", region));
+    if rom.trainer.is_some() {
+        header.push("trainer: 512 bytes at $7000".to_string());
+    }
+
+    let mut findings = vec![mapper_finding(rom)];
+    let mut add = |feature: &str, support: Support, missing: &[&str]| {
+        findings.push(Finding {
+            feature: feature.to_string(),
+            support,
+            missing: missing.iter().map(|m| m.to_string()).collect(),
+        });
+    };
+    // PRG ROM the board couldn't have: a bad dump, or the header names the wrong mapper
+    if let Err(RunescoError::InvalidRom(problem)) = mapper::for_rom(rom) {
+        add("PRG ROM size", Support::Missing, &[problem.as_str()]);
+    }
+    if rom.chr_rom.is_empty() && rom.mapper != fds::MAPPER {
+        add("CHR-RAM", Support::Full, &[]);
+    }
+    let save_device = mapper::for_rom(rom).ok().and_then(|mapper| mapper.borrow().save_device());
+    if let Some(device) = save_device {
+        add(&format!("{} saves (kept in a .{} file)", device.name(), device.extension()), Support::Full, &[]);
+    } else if rom.battery {
+        add("battery-backed save RAM (kept in a .sav file)", Support::Full, &[]);
+    }
+    if rom.screen_mirroring == Mirroring::FOUR_SCREEN && !matches!(rom.mapper, 30 | 99 | 111) { // 30 and 111 bring their own
+        add("four-screen nametables", Support::Missing, &["four-screen VRAM"]);
+    }
+    if let Some(audio) = mapper::info(rom.mapper).and_then(|info| info.audio) {
+        let feature = format!("expansion audio: {}", audio);
+        add(&feature, Support::Full, &[]);
+    }
+    if rom.region == Region::Pal {
+        add("PAL timing", Support::Partial, &["PAL timing (the game runs too fast)"]);
+    }
+    if rom.vs_system {
+        add("Vs. System cabinet (coins, DIP switches)", Support::Full, &[]);
+        add("Vs. System PPU colours", Support::Partial, &["the Vs. palettes (needs a .pal file)"]);
+    }
+    if rom.mapper == fds::MAPPER {
+        add("Disk System BIOS (disksys.rom, --fds-bios)", Support::Full, &[]);
+    }
+    Report { header, warnings: cartridge::check_header(image), findings }
+}
+
+fn mapper_finding(rom: &Rom) -> Finding {
+    match mapper::info(rom.mapper) {
+        Some(info) => Finding {
+            feature: format!("mapper {} ({})", info.id, info.name),
+            support: if info.missing.is_empty() { Support::Full } else { Support::Partial },
+            missing: info.missing.iter().map(|m| m.to_string()).collect(),
+        },
+        None => {
+            let mut missing = format!("mapper {}", rom.mapper);
+            if let Some((to, note)) = mapper::fallback(rom.mapper) {
+                missing.push_str(&format!(" (--mapper-fallback runs it as mapper {}: {})", to, note));
+            }
+            Finding { feature: format!("mapper {}", rom.mapper), support: Support::Missing, missing: vec![missing] }
+        }
+    }
+}
+
+fn format_name(image: &[u8]) -> &'static str {
+    if image.starts_with(b"UNIF") {
+        "UNIF"
+    } else if image.starts_with(b"NES\x1a") {
+        if image.len() > 7 && image[7] & 0b0000_1100 == 0b0000_1000 {
+            "NES 2.0"
+        } else {
+            "iNES"
+        }
+    } else {
+        "Famicom Disk System image"
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.header {
+            writeln!(f, "  {}", line)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "  warning: {}", warning)?;
+        }
+        writeln!(f)?;
+        for finding in &self.findings {
+            writeln!(f, "  {:<8} {}", finding.support.name(), finding.feature)?;
+        }
+        writeln!(f)?;
+        match self.verdict() {
+            Support::Full => writeln!(f, "Verdict: supported"),
+            Support::Partial => writeln!(f, "Verdict: runs, without {}", self.missing().join("; ")),
+            Support::Missing => writeln!(f, "Verdict: won't run, needs {}", self.missing().join("; ")),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_verdicts() {
+        let image = std::fs::read("nestest.nes").unwrap();
+        let mut rom = Rom::new(&image).unwrap();
+        let report = check(&image, &rom);
+        assert_eq!(report.verdict(), Support::Full);
+        assert!(report.header.contains(&"format: iNES".to_string()));
+        assert!(report.to_string().contains("ok       mapper 0 (NROM)"));
+
+        rom.mapper = 24; // VRC6, sound chip and all
+        let report = check(&image, &rom);
+        assert_eq!(report.verdict(), Support::Full);
+        assert!(report.to_string().contains("expansion audio: 2 pulse channels and a sawtooth"));
+
+        rom.mapper = 69; // the 5B's squares play, the rest of it doesn't
+        let report = check(&image, &rom);
+        assert_eq!(report.verdict(), Support::Partial);
+        assert_eq!(report.missing(), ["the 5B's envelope and noise"]);
+
+        rom.mapper = 206;
+        rom.screen_mirroring = Mirroring::FOUR_SCREEN;
+        let report = check(&image, &rom);
+        assert_eq!(report.verdict(), Support::Missing);
+        assert!(report.missing()[0].contains("--mapper-fallback runs it as mapper 4"));
+        assert_eq!(report.missing()[1], "four-screen VRAM");
+
+        rom.mapper = 0;
+        rom.prg_rom.resize(0x10000, 0);
+        let report = check(&image, &rom);
+        assert_eq!(report.verdict(), Support::Missing);
+        assert!(report.missing()[0].starts_with("64KiB of PRG ROM is more than mapper 0"));
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::action::Action;
+use crate::cheats::Cheat;
+use crate::cpu::Mem;
+use crate::debugger::Breakpoint;
+use crate::nes::Nes;
+use crate::ppu::write_log::register_name;
+use crate::savestate;
+use crate::sram;
+use crate::storage::{FileStorage, Storage};
+use crate::trace::TraceEntry;
+
+// A line-based command interface, for driving the emulator from scripts and CI without a
+// window (see `--console` in main.rs). Commands are plain words and hex addresses:
+//
+//   pause / resume        stop or restart free-running emulation
+//   step [n]              run n instructions (default 1) and show where the CPU is
+//   frame [n]             run n frames (default 1), then pause
+//   peek addr [len]       hex dump of memory, read without side effects
+//   poke addr value       write a byte, through the bus like the CPU would
+//   regs                  the next instruction and the registers, as a trace line
+//   history               the last instructions executed
+//   ppuwrites [reg]       writes to the PPU registers over the last few frames, or just to reg
+//   break [addr]          set a breakpoint, or list them; bank:addr only stops in that PRG bank
+//   delete addr           remove a breakpoint
+//   banks                 which PRG bank is mapped where
+//   runto addr            resume, stopping once PC gets to addr
+//   stepout               resume until the current subroutine returns
+//   savestate path / loadstate path
+//   sram import path / sram export path
+//                         the save file (a raw .sav, or the board's EEPROM or flash image),
+//                         to and from other emulators
+//   quit
+//
+// plus every crate::action (save-state 2, turbo 1 b, speed 50, ...), which the console hands
+// back to the frontend to perform, the same way it performs a hotkey.
+//
+// The console only holds the paused flag and where savestates go; everything else is done
+// through the Nes facade.
+
+pub enum Outcome {
+    Output(String), // may be empty
+    Action(Action), // for the caller to perform
+    Quit,
+}
+
+pub struct Console {
+    paused: bool,
+    storage: Box<dyn Storage>,
+}
+
+impl Console {
+    // Starts paused, so that a script's first command always sees the same state.
+    pub fn new() -> Self {
+        Console::with_storage(Box::new(FileStorage))
+    }
+
+    // savestate/loadstate go through `storage` rather than the filesystem.
+    pub fn with_storage(storage: Box<dyn Storage>) -> Self {
+        Console { paused: true, storage }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn execute(&mut self, nes: &mut Nes, line: &str) -> Result<Outcome, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (command.to_ascii_lowercase(), args),
+            None => return Ok(Outcome::Output(String::new())),
+        };
+
+        let output = match (command.as_str(), args) {
+            ("pause", []) => {
+                self.paused = true;
+                format!("paused at frame {}", nes.frame_count())
+            }
+            ("resume", []) => {
+                self.paused = false;
+                nes.resume(); // also continues past a debugger break
+                String::new()
+            }
+            ("step", _) => {
+                for _ in 0..count(args)? {
+                    nes.step_instruction();
+                }
+                TraceEntry::capture(nes.cpu()).to_string()
+            }
+            ("frame", _) => {
+                for _ in 0..count(args)? {
+                    nes.run_frame();
+                }
+                self.paused = true;
+                format!("frame {}", nes.frame_count())
+            }
+            ("peek", [addr]) => hex_dump(nes, number(addr)?, 1),
+            ("peek", [addr, len]) => hex_dump(nes, number(addr)?, usize::from(number(len)?).min(0x100)),
+            ("poke", [addr, value]) => {
+                let value = u8::try_from(number(value)?).map_err(|_| format!("{} doesn't fit in a byte", value))?;
+                nes.cpu().bus.mem_write(number(addr)?, value);
+                String::new()
+            }
+            ("regs", []) => TraceEntry::capture(nes.cpu()).to_string(),
+            ("history", []) => nes.history().report().trim_end().to_string(),
+            ("ppuwrites", []) => nes.ppu_write_log().report(None).trim_end().to_string(),
+            ("ppuwrites", [reg]) => nes.ppu_write_log().report(Some(ppu_register(reg)?)).trim_end().to_string(),
+            ("break", []) => {
+                let breakpoints: Vec<String> = nes.debugger().breakpoints().map(|bp| bp.to_string()).collect();
+                breakpoints.join(" ")
+            }
+            ("break", [addr]) => {
+                let breakpoint = breakpoint(addr)?;
+                nes.debugger().add_breakpoint(breakpoint);
+                format!("breakpoint at {}", breakpoint)
+            }
+            ("delete", [addr]) => {
+                let breakpoint = breakpoint(addr)?;
+                if !nes.debugger().remove_breakpoint(breakpoint) {
+                    return Err(format!("no breakpoint at {}", breakpoint));
+                }
+                String::new()
+            }
+            ("banks", []) => nes.prg_bank_layout().trim_end().to_string(),
+            ("runto", [addr]) => {
+                self.paused = false;
+                nes.run_to(number(addr)?);
+                String::new()
+            }
+            ("stepout", []) => {
+                if !nes.step_out() {
+                    return Err("not in a subroutine".to_string());
+                }
+                self.paused = false;
+                String::new()
+            }
+            ("savestate", [path]) => {
+                savestate::write_file(self.storage.as_ref(), Path::new(path), &nes.save_state())
+                    .map_err(|err| format!("{}: {}", path, err))?;
+                format!("saved {}", path)
+            }
+            ("loadstate", [path]) => {
+                nes.load_state(&savestate::read_file(self.storage.as_ref(), Path::new(path))?)?;
+                format!("loaded {}", path)
+            }
+            ("sram", ["import", path]) => {
+                sram::import(self.storage.as_ref(), nes, path)?;
+                format!("imported {}", path)
+            }
+            ("sram", ["export", path]) => {
+                sram::export(self.storage.as_ref(), nes, path)?;
+                format!("exported {}", path)
+            }
+            ("cheat", []) => {
+                let cheats: Vec<String> = nes.cheats().list().iter().map(|cheat| cheat.to_string()).collect();
+                cheats.join("\n")
+            }
+            ("cheat", [state @ ("on" | "off"), code]) => {
+                if !nes.cheats_mut().set_enabled(code, *state == "on") {
+                    return Err(format!("no cheat {}", code));
+                }
+                format!("{} {}", code, state)
+            }
+            ("cheat", ["delete", code]) => {
+                if !nes.cheats_mut().remove(code) {
+                    return Err(format!("no cheat {}", code));
+                }
+                String::new()
+            }
+            ("cheat", [code]) => {
+                let cheat = Cheat::parse(code)?;
+                let mut output = cheat.to_string();
+                nes.cheats_mut().add(cheat);
+                for (a, b) in nes.cheats().conflicts() {
+                    if a.code.eq_ignore_ascii_case(code) || b.code.eq_ignore_ascii_case(code) {
+                        let _ = write!(output, "\nwarning: {} and {} both change {:04X}", a.code, b.code, a.addr);
+                    }
+                }
+                output
+            }
+            ("quit", []) | ("exit", []) => return Ok(Outcome::Quit),
+            ("help", []) => HELP.to_string(),
+            _ => return Action::parse(line).map(Outcome::Action).map_err(|err| format!("{} (try 'help')", err)),
+        };
+        Ok(Outcome::Output(output))
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const HELP: &str = "pause | resume | step [n] | frame [n] | peek addr [len] | poke addr value | regs | history | ppuwrites [reg] | \
+                    break [[bank:]addr] | delete [bank:]addr | banks | runto addr | stepout | savestate path | loadstate path | \
+                    sram import|export path | cheat [code | on code | off code | delete code] | quit, and the actions: save-state [slot] | \
+                    load-state [slot] | turbo player button | screenshot | record-audio | speed percent|+n|-n | overlay name | \
+                    volume percent|+n|-n | game-volume percent|+n|-n | mute | mute channel | solo channel | unmute-all | \
+                    barcode digits | next-barcode | reset-timer | export-splits | \
+                    reset | power | next-disk | export-dumps | import-dumps";
+
+// Hex, with or without '$' or '0x'.
+fn number(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", text))
+}
+
+// $2000-$2007 or $4014, by address or by name (PPUSCROLL).
+fn ppu_register(text: &str) -> Result<u16, String> {
+    let by_name = (0x2000..=0x2007).chain([0x4014]).find(|&addr| register_name(addr).eq_ignore_ascii_case(text));
+    match by_name.or_else(|| number(text).ok()) {
+        Some(addr @ (0x2000..=0x2007 | 0x4014)) => Ok(addr),
+        _ => Err(format!("'{}' is not a PPU register", text)),
+    }
+}
+
+// An address, or bank:address.
+fn breakpoint(text: &str) -> Result<Breakpoint, String> {
+    Breakpoint::parse(text).ok_or_else(|| format!("'{}' is not an address or bank:address", text))
+}
+
+// Repeat counts are decimal.
+fn count(args: &[&str]) -> Result<u32, String> {
+    match args {
+        [] => Ok(1),
+        [n] => n.parse().map_err(|_| format!("'{}' is not a count", n)),
+        _ => Err("expected at most one count".to_string()),
+    }
+}
+
+fn hex_dump(nes: &mut Nes, start: u16, len: usize) -> String {
+    let mut out = String::new();
+    for offset in (0..len).step_by(16) {
+        let row = start.wrapping_add(offset as u16);
+        let _ = write!(out, "{:04X}:", row);
+        for i in 0..16.min(len - offset) {
+            let _ = write!(out, " {:02X}", nes.cpu().bus.peek(row.wrapping_add(i as u16)));
+        }
+        out.push('\n');
+    }
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn output(console: &mut Console, nes: &mut Nes, line: &str) -> String {
+        match console.execute(nes, line) {
+            Ok(Outcome::Output(text)) => text,
+            Ok(Outcome::Quit) => panic!("'{}' quit", line),
+            Ok(Outcome::Action(action)) => panic!("'{}' is an action: {}", line, action),
+            Err(err) => panic!("'{}' failed: {}", line, err),
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let mut console = Console::new();
+        assert!(console.paused());
+
+        output(&mut console, &mut nes, "poke 0010 ab");
+        output(&mut console, &mut nes, "poke $11 0xCD");
+        assert_eq!(output(&mut console, &mut nes, "peek 10 3"), "0010: AB CD 00");
+
+        assert!(output(&mut console, &mut nes, "step 2").starts_with("C"));
+        assert_eq!(output(&mut console, &mut nes, "frame 3"), "frame 3");
+        assert_eq!(nes.frame_count(), 3);
+
+        output(&mut console, &mut nes, "resume");
+        assert!(!console.paused());
+
+        output(&mut console, &mut nes, "break c000");
+        output(&mut console, &mut nes, "break $C5F5");
+        assert_eq!(output(&mut console, &mut nes, "break"), "$C000 $C5F5");
+        output(&mut console, &mut nes, "delete c000");
+        assert!(console.execute(&mut nes, "delete c000").is_err());
+
+        assert_eq!(output(&mut console, &mut nes, "cheat 0010:77"), "0010:77 (0010:77)");
+        output(&mut console, &mut nes, "cheat off 0010:77");
+        assert_eq!(output(&mut console, &mut nes, "cheat"), "0010:77 (0010:77) off");
+        output(&mut console, &mut nes, "cheat delete 0010:77");
+        assert!(console.execute(&mut nes, "cheat on 0010:77").is_err());
+
+        output(&mut console, &mut nes, "cheat 0012=5A");
+        assert!(output(&mut console, &mut nes, "cheat 0012:01").ends_with("warning: 0012=5A and 0012:01 both change 0012"));
+        output(&mut console, &mut nes, "cheat delete 0012:01");
+        output(&mut console, &mut nes, "frame");
+        assert_eq!(output(&mut console, &mut nes, "peek 12"), "0012: 5A"); // written at the frame start
+
+        assert!(output(&mut console, &mut nes, "ppuwrites ppuctrl").lines().all(|line| line.contains("$2000 PPUCTRL")));
+        assert!(console.execute(&mut nes, "ppuwrites 2008").is_err());
+
+        assert!(console.execute(&mut nes, "poke 10 100").is_err());
+        assert!(console.execute(&mut nes, "jump").is_err());
+        assert!(matches!(console.execute(&mut nes, "save-state 2"), Ok(Outcome::Action(Action::SaveState(2)))));
+        assert!(matches!(console.execute(&mut nes, "quit"), Ok(Outcome::Quit)));
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::json::Json;
+
+// Which parts of the console a run touched: every CPU read and write, counted by the region of
+// the address space it went to, and by PPU register. For test ROMs and the integration tests
+// built on them: a test that claims to cover scrolling but never writes $2005, or a mapper
+// test whose game never touches $6000-$7FFF, shows up in the report.
+//
+//   region                          reads     writes  addresses
+//   RAM            $0000-$07FF     153467      79310   812/2048
+//   ...
+//   PPU register                    reads     writes
+//   PPUCTRL        $2000                0         14
+//
+// Addresses are the distinct ones read or written in the region. Accesses are counted as the
+// CPU makes them, so OAM DMA's 256 reads are in too; what the PPU and DMC fetch by themselves
+// isn't, and neither is what the debugger peeks. `runesco <rom> --headless <frames>
+// --coverage` prints it after the run (and adds it to the --json result).
+
+pub const REGIONS: [(&str, RangeInclusive<u16>); 8] = [
+    ("RAM", 0x0000..=0x07FF),
+    ("RAM mirrors", 0x0800..=0x1FFF),
+    ("PPU registers", 0x2000..=0x3FFF), // $2000-$2007, mirrored every 8 bytes
+    ("APU and I/O", 0x4000..=0x4017),
+    ("test mode", 0x4018..=0x401F), // disabled on retail consoles
+    ("expansion", 0x4020..=0x5FFF),
+    ("PRG-RAM", 0x6000..=0x7FFF),
+    ("PRG-ROM", 0x8000..=0xFFFF),
+];
+
+pub const PPU_REGISTERS: [(&str, u16); 9] = [
+    ("PPUCTRL", 0x2000),
+    ("PPUMASK", 0x2001),
+    ("PPUSTATUS", 0x2002),
+    ("OAMADDR", 0x2003),
+    ("OAMDATA", 0x2004),
+    ("PPUSCROLL", 0x2005),
+    ("PPUADDR", 0x2006),
+    ("PPUDATA", 0x2007),
+    ("OAMDMA", 0x4014),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+pub struct Coverage {
+    regions: [Counts; REGIONS.len()],
+    ppu_registers: [Counts; PPU_REGISTERS.len()],
+    touched: Vec<u64>, // a bit per address, set once it's read or written
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            regions: [Counts::default(); REGIONS.len()],
+            ppu_registers: [Counts::default(); PPU_REGISTERS.len()],
+            touched: vec![0; 0x10000 / 64],
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) {
+        for counts in self.counts(addr) {
+            counts.reads += 1;
+        }
+    }
+
+    pub fn write(&mut self, addr: u16) {
+        for counts in self.counts(addr) {
+            counts.writes += 1;
+        }
+    }
+
+    // The counters an access to `addr` goes into: its region's, and its PPU register's if any.
+    fn counts(&mut self, addr: u16) -> impl Iterator<Item = &mut Counts> {
+        self.touched[addr as usize / 64] |= 1 << (addr % 64);
+        let region = REGIONS.iter().position(|(_, range)| range.contains(&addr)).unwrap();
+        let register = match addr {
+            0x2000..=0x3FFF => Some(addr as usize & 7),
+            0x4014 => Some(8),
+            _ => None,
+        };
+        let register = register.map(|i| &mut self.ppu_registers[i]);
+        std::iter::once(&mut self.regions[region]).chain(register)
+    }
+
+    pub fn region(&self, name: &str) -> Option<Counts> {
+        REGIONS.iter().position(|(region, _)| *region == name).map(|i| self.regions[i])
+    }
+
+    pub fn ppu_register(&self, addr: u16) -> Option<Counts> {
+        PPU_REGISTERS.iter().position(|(_, register)| *register == addr).map(|i| self.ppu_registers[i])
+    }
+
+    // How many of the region's addresses were read or written at least once.
+    pub fn addresses(&self, range: &RangeInclusive<u16>) -> usize {
+        range.clone().filter(|&addr| self.touched[addr as usize / 64] >> (addr % 64) & 1 != 0).count()
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = format!("{:<30}{:>10} {:>10}  addresses\n", "region", "reads", "writes");
+        for ((name, range), counts) in REGIONS.iter().zip(&self.regions) {
+            let span = format!("${:04X}-${:04X}", range.start(), range.end());
+            let size = *range.end() as usize - *range.start() as usize + 1;
+            let addresses = format!("{}/{}", self.addresses(range), size);
+            let _ = writeln!(out, "{:<15}{:<15}{:>10} {:>10}  {}", name, span, counts.reads, counts.writes, addresses);
+        }
+        let _ = writeln!(out, "{:<30}{:>10} {:>10}", "PPU register", "reads", "writes");
+        for ((name, addr), counts) in PPU_REGISTERS.iter().zip(&self.ppu_registers) {
+            let _ = writeln!(out, "{:<15}${:<14X}{:>10} {:>10}", name, addr, counts.reads, counts.writes);
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Json {
+        let regions: Vec<Json> = REGIONS
+            .iter()
+            .zip(&self.regions)
+            .map(|((name, range), counts)| {
+                Json::object()
+                    .with("name", *name)
+                    .with("start", format!("{:04X}", range.start()))
+                    .with("end", format!("{:04X}", range.end()))
+                    .with("reads", counts.reads)
+                    .with("writes", counts.writes)
+                    .with("addresses", self.addresses(range))
+            })
+            .collect();
+        let registers: Vec<Json> = PPU_REGISTERS
+            .iter()
+            .zip(&self.ppu_registers)
+            .map(|((name, addr), counts)| {
+                Json::object()
+                    .with("name", *name)
+                    .with("address", format!("{:04X}", addr))
+                    .with("reads", counts.reads)
+                    .with("writes", counts.writes)
+            })
+            .collect();
+        Json::object().with("regions", regions).with("ppu_registers", registers)
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_counts() {
+        let mut coverage = Coverage::new();
+        coverage.read(0x0010);
+        coverage.write(0x0010);
+        coverage.write(0x0811); // a mirror of $0011
+        coverage.write(0x3456); // $2006, mirrored
+        coverage.read(0xFFFC);
+        coverage.write(0x4014);
+
+        assert_eq!(coverage.region("RAM"), Some(Counts { reads: 1, writes: 1 }));
+        assert_eq!(coverage.region("RAM mirrors"), Some(Counts { reads: 0, writes: 1 }));
+        assert_eq!(coverage.ppu_register(0x2006), Some(Counts { reads: 0, writes: 1 }));
+        assert_eq!(coverage.ppu_register(0x4014), Some(Counts { reads: 0, writes: 1 }));
+        assert_eq!(coverage.region("APU and I/O"), Some(Counts { reads: 0, writes: 1 }));
+        assert_eq!(coverage.region("PRG-RAM"), Some(Counts::default()));
+        assert_eq!(coverage.addresses(&REGIONS[0].1), 1);
+        assert_eq!(coverage.addresses(&REGIONS[7].1), 1);
+
+        let report = coverage.report();
+        assert!(report.contains("RAM            $0000-$07FF             1          1  1/2048\n"));
+        assert!(report.contains("PPUADDR        $2006                   0          1\n"));
+        let json = coverage.to_json().to_string();
+        assert!(json.contains("\"name\":\"PRG-ROM\""), "{}", json);
+    }
+
+    #[test]
+    fn test_run_touches_the_ppu() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        assert!(nes.coverage().is_none());
+        nes.start_coverage();
+        for _ in 0..10 {
+            nes.run_frame();
+        }
+        let coverage = nes.coverage().unwrap();
+        assert!(coverage.region("PRG-ROM").unwrap().reads > 1000);
+        assert!(coverage.ppu_register(0x2002).unwrap().reads > 0); // waiting for vblank
+        assert_eq!(coverage.region("test mode"), Some(Counts::default()));
+    }
+}
@@ -10,6 +10,12 @@ pub struct CPU<'a> { // CPU with..
     pub status: u8, // Status flags [NV_BDIZC]
     pub program_counter: u16, // Program Counter
     pub bus: Bus<'a>,
+
+    // The tutorial test harness uses BRK (0x00) as "stop the program". Games need the real thing:
+    // BRK is a software interrupt that jumps through $FFFE.
+    pub halt_on_brk: bool,
+    last_interrupt: Option<InterruptEvent>,
+    jammed: bool, // executed a KIL/JAM opcode: only a reset gets the CPU going again
 }
 
 #[derive(Debug)]
@@ -72,9 +78,12 @@ fn page_cross(addr1: u16, addr2 : u16) -> bool {
 }
 
 mod interrupt {
-    #[derive(PartialEq, Eq)]
+    #[derive(PartialEq, Eq, Debug, Clone, Copy)]
     pub enum InterruptType {
         NMI,
+        IRQ,
+        BRK,
+        RESET,
     }
 
     #[derive(PartialEq, Eq)]
@@ -94,6 +103,28 @@ mod interrupt {
         b_flag_mask: 0b00100000,
         cpu_cycles: 2,
     };
+    pub(super) const IRQ: Interrupt = Interrupt {
+        itype: InterruptType::IRQ,
+        vector_addr: 0xFFFE,
+        b_flag_mask: 0b00100000,
+        cpu_cycles: 2,
+    };
+    pub(super) const BRK: Interrupt = Interrupt {
+        itype: InterruptType::BRK,
+        vector_addr: 0xFFFE, // shared with IRQ
+        b_flag_mask: 0b00110000, // B is set in the pushed copy: that's how a handler tells BRK from IRQ
+        cpu_cycles: 0, // already counted as the BRK opcode's 7 cycles
+    };
+}
+
+pub use interrupt::InterruptType;
+
+// Recorded every time the CPU jumps through one of the vectors at $FFFA-$FFFF.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterruptEvent {
+    pub itype: InterruptType,
+    pub vector_addr: u16, // where the handler address was read from
+    pub handler: u16,     // the handler address itself
 }
 
 impl<'a> CPU<'a> {
@@ -119,6 +150,9 @@ impl<'a> CPU<'a> {
             status: 0b100100,
             program_counter: 0,
             bus: bus,
+            halt_on_brk: true,
+            last_interrupt: None,
+            jammed: false,
         }
     }
 
@@ -128,8 +162,28 @@ impl<'a> CPU<'a> {
         self.status = 0b100100;
 
         self.stack_pointer = 0xfd;
+        self.jammed = false;
  
         self.program_counter = self.mem_read_u16(0xFFFC);
+        self.last_interrupt = Some(InterruptEvent {
+            itype: InterruptType::RESET,
+            vector_addr: 0xFFFC,
+            handler: self.program_counter,
+        });
+    }
+
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    // Like take_interrupt_event, but leaves it for whoever takes it.
+    pub fn peek_interrupt_event(&self) -> Option<InterruptEvent> {
+        self.last_interrupt
+    }
+
+    // The last interrupt (or reset) taken since the previous call, if any.
+    pub fn take_interrupt_event(&mut self) -> Option<InterruptEvent> {
+        self.last_interrupt.take()
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
@@ -1180,7 +1234,7 @@ impl<'a> CPU<'a> {
         let mut flag = self.status.clone();
 
         flag = flag & 0b1110_1111; // unset B flag
-        flag = flag | 0b0010_0000; // set Unused flag
+        flag = flag | interrupt.b_flag_mask; // set Unused flag (and B, for BRK)
 
         addr = 0x0100 + ((self.stack_pointer) as u16);
 
@@ -1191,6 +1245,12 @@ impl<'a> CPU<'a> {
 
         self.bus.tick(interrupt.cpu_cycles);
         self.program_counter = self.mem_read_u16(interrupt.vector_addr);
+
+        self.last_interrupt = Some(InterruptEvent {
+            itype: interrupt.itype,
+            vector_addr: interrupt.vector_addr,
+            handler: self.program_counter,
+        });
     }
 
     pub fn run(&mut self) {
@@ -1218,258 +1278,314 @@ impl<'a> CPU<'a> {
     // https://doc.rust-lang.org/book/ch13-01-closures.html
         
     {   
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-        // create a reference opdcodes in the cpu of the Hashmap type from u8 to OpCode data, from OPCODES_MAP in 
-        // opcode.rs. OPCODES_MAP is dereferenced as it is a ref, and to get values out of it (instead of pointers) we must
-        // deref with *.
-
         loop {
-            if let Some(_nmi) = self.bus.poll_nmi_status() {
-                self.interrupt(interrupt::NMI);
-            }
+            self.poll_interrupts();
 
             callback(self); // Queue the inputs (orders) and execute them as and when possible...
             
             // ... while the current known inputs can be processed.
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
+            if !self.execute_next() {
+                return;
+            }
+        }
+    }
 
-            let opcode = opcodes.get(&code).expect(&format!("OpCode {:x} is not recognized", code));
-            // gets the value (opcode data) from a reference to the key (code), otherwise throws an exception.
+    // Runs a single instruction, or services a pending interrupt: taking an interrupt is a
+    // step of its own, so a debugger gets to stop before the handler's first instruction.
+    // Returns false once the CPU hits BRK with halt_on_brk set, which is where run() stops.
+    pub fn step(&mut self) -> bool {
+        if self.jammed {
+            return false;
+        }
+        if self.poll_interrupts() {
+            return true;
+        }
+        self.execute_next()
+    }
 
-            match code {
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                    self.lda(&opcode.mode);
-                }
+    // Runs whole instructions until at least `cycles` CPU cycles have elapsed on the bus,
+    // for test harnesses that need to stop at a precise point in time. Returns the number of
+    // cycles actually run, which can overshoot by the length of the last instruction.
+    pub fn run_cycles(&mut self, cycles: usize) -> usize {
+        let start = self.bus.cycles();
+        while self.bus.cycles() - start < cycles {
+            if !self.step() {
+                break;
+            }
+        }
+        self.bus.cycles() - start
+    }
 
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                    self.ldx(&opcode.mode);
-                }
+    fn poll_interrupts(&mut self) -> bool {
+        if let Some(_nmi) = self.bus.poll_nmi_status() {
+            self.interrupt(interrupt::NMI);
+            return true;
+        }
+        if self.bus.irq_line() && self.status & 0b0000_0100 == 0 { // masked by the I flag
+            self.interrupt(interrupt::IRQ);
+            return true;
+        }
+        false
+    }
 
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                    self.ldy(&opcode.mode);
-                }
+    fn execute_next(&mut self) -> bool {
+        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
+        // create a reference opdcodes in the cpu of the Hashmap type from u8 to OpCode data, from OPCODES_MAP in 
+        // opcode.rs. OPCODES_MAP is dereferenced as it is a ref, and to get values out of it (instead of pointers) we must
+        // deref with *.
 
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
+        let code = self.mem_read(self.program_counter);
+        self.program_counter += 1;
+        let program_counter_state = self.program_counter;
 
-                0x86 | 0x96 | 0x8e => {
-                    self.stx(&opcode.mode);
-                }
+        let opcode = opcodes.get(&code).expect(&format!("OpCode {:x} is not recognized", code));
+        // gets the value (opcode data) from a reference to the key (code), otherwise throws an exception.
 
-                0x84 | 0x94 | 0x8c => {
-                    self.sty(&opcode.mode);
-                }
+        match code {
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
+                self.lda(&opcode.mode);
+            }
 
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+                self.ldx(&opcode.mode);
+            }
 
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 /* unofficial -> */ | 0xeb => {
-                    self.sbc(&opcode.mode);
-                }
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+                self.ldy(&opcode.mode);
+            }
 
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
+                self.sta(&opcode.mode);
+            }
+
+            0x86 | 0x96 | 0x8e => {
+                self.stx(&opcode.mode);
+            }
+
+            0x84 | 0x94 | 0x8c => {
+                self.sty(&opcode.mode);
+            }
 
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.mode);
-                }
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+                self.and(&opcode.mode);
+            }
 
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.mode);
-                }
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 /* unofficial -> */ | 0xeb => {
+                self.sbc(&opcode.mode);
+            }
 
-                0x0a | 0x06 | 0x16 | 0x0e | 0x1e => {
-                    self.asl(&opcode.mode);
-                }
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
+                self.adc(&opcode.mode);
+            }
+
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+                self.ora(&opcode.mode);
+            }
 
-                0x2a | 0x26 | 0x36 | 0x2e | 0x3e => {
-                    self.rol(&opcode.mode);
-                }
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+                self.eor(&opcode.mode);
+            }
 
-                0x4a | 0x46 | 0x56 | 0x4e | 0x5e => {
-                    self.lsr(&opcode.mode);
-                }
+            0x0a | 0x06 | 0x16 | 0x0e | 0x1e => {
+                self.asl(&opcode.mode);
+            }
 
-                0x6a | 0x66 | 0x76 | 0x6e | 0x7e => {
-                    self.ror(&opcode.mode);
-                }
+            0x2a | 0x26 | 0x36 | 0x2e | 0x3e => {
+                self.rol(&opcode.mode);
+            }
 
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.cmp(&opcode.mode);
-                }
+            0x4a | 0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&opcode.mode);
+            }
 
-                0xe0 | 0xe4 | 0xec => {
-                    self.cpx(&opcode.mode);
-                }
+            0x6a | 0x66 | 0x76 | 0x6e | 0x7e => {
+                self.ror(&opcode.mode);
+            }
 
-                0xc0 | 0xc4 | 0xcc => {
-                    self.cpy(&opcode.mode);
-                }
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.cmp(&opcode.mode);
+            }
 
-                0x40 => self.rti(),
+            0xe0 | 0xe4 | 0xec => {
+                self.cpx(&opcode.mode);
+            }
 
-                0x20 => self.jsr(&opcode.mode),
+            0xc0 | 0xc4 | 0xcc => {
+                self.cpy(&opcode.mode);
+            }
 
-                0x60 => self.rts(),
+            0x40 => self.rti(),
 
-                0xd0 => self.bne(),
+            0x20 => self.jsr(&opcode.mode),
 
-                0x90 => self.bcc(),
+            0x60 => self.rts(),
 
-                0xb0 => self.bcs(),
+            0xd0 => self.bne(),
 
-                0xf0 => self.beq(),
+            0x90 => self.bcc(),
 
-                0x30 => self.bmi(),
+            0xb0 => self.bcs(),
 
-                0x10 => self.bpl(),
+            0xf0 => self.beq(),
 
-                0x50 => self.bvc(),
+            0x30 => self.bmi(),
 
-                0x70 => self.bvs(),
-                
-                0xaa => self.tax(),
+            0x10 => self.bpl(),
 
-                0xa8 => self.tay(),
+            0x50 => self.bvc(),
 
-                0x8a => self.txa(),
+            0x70 => self.bvs(),
+            
+            0xaa => self.tax(),
 
-                0x98 => self.tya(),
+            0xa8 => self.tay(),
 
-                0xba => self.tsx(),
+            0x8a => self.txa(),
 
-                0x9a => self.txs(),
+            0x98 => self.tya(),
 
-                0xe6 | 0xf6 | 0xee | 0xfe => {
-                    self.inc(&opcode.mode);
-                }
+            0xba => self.tsx(),
 
-                0xc6 | 0xd6 | 0xce | 0xde => {
-                    self.dec(&opcode.mode);
-                }
+            0x9a => self.txs(),
 
-                0x24 | 0x2c => {
-                    self.bit(&opcode.mode);
-                }
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&opcode.mode);
+            }
 
-                0x4c | 0x6c => {
-                    self.jmp(&opcode.mode);
-                }
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(&opcode.mode);
+            }
 
-                0x48 => self.pha(),
+            0x24 | 0x2c => {
+                self.bit(&opcode.mode);
+            }
 
-                0x08 => self.php(),
+            0x4c | 0x6c => {
+                self.jmp(&opcode.mode);
+            }
 
-                0x68 => self.pla(),
+            0x48 => self.pha(),
 
-                0x28 => self.plp(),
+            0x08 => self.php(),
 
-                0xe8 => self.inx(),
+            0x68 => self.pla(),
 
-                0xca => self.dex(),
+            0x28 => self.plp(),
 
-                0xc8 => self.iny(),
+            0xe8 => self.inx(),
 
-                0x88 => self.dey(),
+            0xca => self.dex(),
 
-                0x38 => self.sec(),
+            0xc8 => self.iny(),
 
-                0x18 => self.clc(),
+            0x88 => self.dey(),
 
-                0x78 => self.sei(),
+            0x38 => self.sec(),
 
-                0x58 => self.cli(),
+            0x18 => self.clc(),
 
-                0xf8 => self.sed(),
+            0x78 => self.sei(),
 
-                0xd8 => self.cld(),
+            0x58 => self.cli(),
 
-                0xb8 => self.clv(),
-                
-                0xea /* <- main*/ | 0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa 
-                | 0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2=> {
-                    // NOP basic and KIL
-                },
+            0xf8 => self.sed(),
 
-                // Other NOPs which read memory
-                0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c
-                | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc | 0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
-                    let (addr, _) = self.get_operand_address(&opcode.mode);
-                    #[allow(unused_variables)]
-                    let data = self.mem_read(addr);
-                }
+            0xd8 => self.cld(),
 
-                0xc7 | 0xd7 | 0xCF | 0xdF | 0xdb | 0xd3 | 0xc3 => {
-                    self.dcp(&opcode.mode)
-                },
+            0xb8 => self.clv(),
+            
+            0xea /* <- main*/ | 0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => {
+                // NOP basic
+            },
+
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                // KIL: the real CPU locks up, usually because it ran off into data
+                self.program_counter -= 1;
+                self.jammed = true;
+                return false;
+            }
 
-                0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
-                    self.rla(&opcode.mode)
-                },
+            // Other NOPs which read memory
+            0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c
+            | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc | 0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
+                let (addr, _) = self.get_operand_address(&opcode.mode);
+                #[allow(unused_variables)]
+                let data = self.mem_read(addr);
+            }
 
-                0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 =>  {
-                    self.slo(&opcode.mode)
-                }
+            0xc7 | 0xd7 | 0xCF | 0xdF | 0xdb | 0xd3 | 0xc3 => {
+                self.dcp(&opcode.mode)
+            },
 
-                0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
-                    self.sre(&opcode.mode)
-                }
+            0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
+                self.rla(&opcode.mode)
+            },
 
-                0xcb => {
-                    self.axs(&opcode.mode)
-                }
+            0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 =>  {
+                self.slo(&opcode.mode)
+            }
 
-                0x6b => {
-                    self.arr(&opcode.mode);
-                }
+            0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
+                self.sre(&opcode.mode)
+            }
 
-                0x0b | 0x2b => {
-                    self.anc(&opcode.mode);
-                }
+            0xcb => {
+                self.axs(&opcode.mode)
+            }
 
-                0x4b => {
-                    self.alr(&opcode.mode);
-                }
+            0x6b => {
+                self.arr(&opcode.mode);
+            }
 
-                0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
-                    self.rra(&opcode.mode);
-                }
+            0x0b | 0x2b => {
+                self.anc(&opcode.mode);
+            }
 
-                0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
-                    self.isb(&opcode.mode);
-                }
+            0x4b => {
+                self.alr(&opcode.mode);
+            }
 
-                0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
-                    self.lax(&opcode.mode);
-                }
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
+                self.rra(&opcode.mode);
+            }
 
-                0x87 | 0x97 | 0x8f | 0x83 => {
-                    self.sax(&opcode.mode);
-                }
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
+                self.isb(&opcode.mode);
+            }
 
-                0x00 => { // BRK
-                    self.status = self.status | 0b0001_0000; // set B flag
-                    return; 
-                }
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
+                self.lax(&opcode.mode);
+            }
 
-                _ => todo!(),
+            0x87 | 0x97 | 0x8f | 0x83 => {
+                self.sax(&opcode.mode);
             }
 
-            self.bus.tick(opcode.cycles);
+            0x00 if self.halt_on_brk => { // BRK
+                self.status = self.status | 0b0001_0000; // set B flag
+                return false;
+            }
 
-            if program_counter_state == self.program_counter { 
-                // [-] Why would this ever be false?
-                // [A] Because of CPU and PPU cycles!
-                self.program_counter += (opcode.len - 1) as u16;
-                // Steps to increase program counter by = bytes processed by opcode - 1
-                // -1, because first increase caused by opcode matching is already accounted for. 
+            0x00 => {
+                // BRK is two bytes long (the second is padding), so the
+                // handler returns to the instruction after the padding byte.
+                self.program_counter += 1;
+                self.interrupt(interrupt::BRK);
             }
+
+            _ => todo!(),
+        }
+
+        self.bus.tick(opcode.cycles);
+
+        if program_counter_state == self.program_counter { 
+            // [-] Why would this ever be false?
+            // [A] Because of CPU and PPU cycles!
+            self.program_counter += (opcode.len - 1) as u16;
+            // Steps to increase program counter by = bytes processed by opcode - 1
+            // -1, because first increase caused by opcode matching is already accounted for. 
         }
+        true
     }
 
 
@@ -0,0 +1,393 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::cpu::{InterruptEvent, InterruptType, CPU};
+
+// The debugger core: decides *when* to stop the CPU. The Nes facade asks it before every
+// CPU step; frontends (SDL hotkeys, console, ...) only configure it and show results.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakReason {
+    Breakpoint(u16),             // PC reached a breakpoint
+    Interrupt(InterruptEvent),   // the CPU just jumped through a vector
+    StepOut { from: u16, to: u16 }, // the subroutine at `from` returned to `to`
+    RunTo(u16),                  // PC reached the "run to" address
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint(addr) => write!(f, "breakpoint at ${:04X}", addr),
+            BreakReason::Interrupt(event) => write!(
+                f,
+                "{:?} taken: vector ${:04X} -> handler ${:04X}",
+                event.itype, event.vector_addr, event.handler
+            ),
+            BreakReason::StepOut { from, to } => write!(f, "returned from ${:04X} to ${:04X}", from, to),
+            BreakReason::RunTo(addr) => write!(f, "reached ${:04X}", addr),
+        }
+    }
+}
+
+// One entry of the call stack: a JSR, or an interrupt that hasn't returned yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallFrame {
+    pub target: u16,      // subroutine (or handler) entry point
+    pub return_addr: u16, // where execution continues after the RTS/RTI
+    sp: u8,               // stack pointer before the call pushed anything
+}
+
+// Where to stop: "C000" stops whatever is mapped there, "03:C000" only while PRG bank 3 is.
+// Games that bank switch run unrelated code at the same address, so a plain breakpoint can
+// fire in the wrong bank. Banks are counted in the mapper's bank size (Mapper::prg_bank_size),
+// the same numbers the game writes to the mapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub bank: Option<usize>,
+}
+
+impl Breakpoint {
+    // Hex, "C000", "$C000" or "3:C000"
+    pub fn parse(text: &str) -> Option<Breakpoint> {
+        let hex = |text: &str| usize::from_str_radix(text.trim_start_matches('$').trim_start_matches("0x"), 16).ok();
+        let (bank, addr) = match text.split_once(':') {
+            Some((bank, addr)) => (Some(hex(bank)?), addr),
+            None => (None, text),
+        };
+        Some(Breakpoint { addr: u16::try_from(hex(addr)?).ok()?, bank })
+    }
+}
+
+impl From<u16> for Breakpoint {
+    fn from(addr: u16) -> Self {
+        Breakpoint { addr, bank: None }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:${:04X}", bank, self.addr),
+            None => write!(f, "${:04X}", self.addr),
+        }
+    }
+}
+
+// The PRG bank mapped at `addr`, None outside of ROM.
+pub fn prg_bank(cpu: &CPU, addr: u16) -> Option<usize> {
+    if addr < 0x8000 {
+        return None;
+    }
+    let mapper = cpu.bus.mapper().borrow();
+    Some(mapper.prg_rom_offset(addr)? / mapper.prg_bank_size())
+}
+
+// Which bank is mapped where in $8000-$FFFF, one line per bank-sized window.
+pub fn bank_layout(cpu: &CPU) -> String {
+    let size = cpu.bus.mapper().borrow().prg_bank_size().clamp(0x1000, 0x8000);
+    let mut out = String::new();
+    for start in (0x8000..=0xFFFF).step_by(size) {
+        let end = start + (size - 1);
+        let bank = match prg_bank(cpu, start as u16) {
+            Some(bank) => format!("bank {:02X}", bank),
+            None => "RAM".to_string(),
+        };
+        out.push_str(&format!("${:04X}-${:04X}: {}\n", start, end, bank));
+    }
+    out
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<Breakpoint>,
+    interrupt_breaks: Vec<InterruptType>, // which vectors we stop on
+    skip_once: Option<u16>, // when resuming from a breakpoint, don't stop on it again straight away
+
+    calls: Vec<CallFrame>,
+    step_out: Option<usize>, // stop once the call stack is shallower than this
+    run_to: Option<u16>,     // a one-shot breakpoint
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            interrupt_breaks: vec![],
+            skip_once: None,
+            calls: vec![],
+            step_out: None,
+            run_to: None,
+        }
+    }
+
+    // An address (0xC000) or a Breakpoint with a bank.
+    pub fn add_breakpoint(&mut self, breakpoint: impl Into<Breakpoint>) {
+        self.breakpoints.insert(breakpoint.into());
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: impl Into<Breakpoint>) -> bool {
+        self.breakpoints.remove(&breakpoint.into())
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    pub fn set_break_on_interrupt(&mut self, itype: InterruptType, enabled: bool) {
+        self.interrupt_breaks.retain(|t| *t != itype);
+        if enabled {
+            self.interrupt_breaks.push(itype);
+        }
+    }
+
+    pub fn breaks_on_interrupt(&self, itype: InterruptType) -> bool {
+        self.interrupt_breaks.contains(&itype)
+    }
+
+    // Innermost call last.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.calls
+    }
+
+    // Stop when the current subroutine returns. False if we aren't inside one.
+    pub fn step_out(&mut self) -> bool {
+        if self.calls.is_empty() {
+            return false;
+        }
+        self.step_out = Some(self.calls.len());
+        true
+    }
+
+    pub fn run_to(&mut self, addr: u16) {
+        self.run_to = Some(addr);
+    }
+
+    // Called when execution continues after a break at `pc`.
+    pub fn resume_from(&mut self, pc: u16) {
+        self.skip_once = Some(pc);
+    }
+
+    // Called before every instruction, with PC on the instruction about to run.
+    pub fn check(&mut self, cpu: &mut CPU) -> Option<BreakReason> {
+        let returned = self.unwind(cpu.stack_pointer);
+        let interrupt = cpu.take_interrupt_event();
+        if let Some(event) = interrupt {
+            self.enter_interrupt(event, cpu);
+        }
+
+        // only ask the mapper when a bank matters
+        let pc = cpu.program_counter;
+        let banked = self.breakpoints.iter().any(|bp| bp.addr == pc && bp.bank.is_some());
+        let bank = if banked { prg_bank(cpu, pc) } else { None };
+
+        let reason = self.break_reason(pc, bank, returned, interrupt);
+        if reason.is_some() {
+            // any stop ends a pending step out / run to
+            self.step_out = None;
+            self.run_to = None;
+            if interrupt.is_some() {
+                self.skip_once = Some(cpu.program_counter); // a breakpoint on the handler shouldn't fire twice
+            }
+            return reason;
+        }
+
+        if cpu.bus.peek(pc) == 0x20 {
+            // JSR: it is going to run now, so it's on the call stack from here on
+            self.calls.push(CallFrame {
+                target: cpu.bus.peek_u16(pc.wrapping_add(1)),
+                return_addr: pc.wrapping_add(3),
+                sp: cpu.stack_pointer,
+            });
+        }
+        None
+    }
+
+    fn break_reason(
+        &mut self,
+        pc: u16,
+        bank: Option<usize>,
+        returned: Option<CallFrame>,
+        interrupt: Option<InterruptEvent>,
+    ) -> Option<BreakReason> {
+        if let (Some(depth), Some(frame)) = (self.step_out, returned) {
+            if self.calls.len() < depth {
+                return Some(BreakReason::StepOut { from: frame.target, to: pc });
+            }
+        }
+        if let Some(event) = interrupt {
+            if self.breaks_on_interrupt(event.itype) {
+                return Some(BreakReason::Interrupt(event));
+            }
+        }
+
+        if self.skip_once.take() == Some(pc) {
+            return None;
+        }
+        if self.run_to == Some(pc) {
+            return Some(BreakReason::RunTo(pc));
+        }
+        let hit = |bp: &Breakpoint| bp.addr == pc && (bp.bank.is_none() || bp.bank == bank);
+        if self.breakpoints.iter().any(hit) {
+            return Some(BreakReason::Breakpoint(pc));
+        }
+        None
+    }
+
+    // Drops the frames the last instruction returned from, returns the outermost of them.
+    // Going by the stack pointer instead of counting RTS/RTI keeps us in sync with code that
+    // pushes an address and uses RTS as a jump (a common jump table trick).
+    fn unwind(&mut self, sp: u8) -> Option<CallFrame> {
+        let mut returned = None;
+        while let Some(frame) = self.calls.last() {
+            if sp < frame.sp {
+                break;
+            }
+            returned = self.calls.pop();
+        }
+        returned
+    }
+
+    fn enter_interrupt(&mut self, event: InterruptEvent, cpu: &CPU) {
+        if event.itype == InterruptType::RESET {
+            self.calls.clear();
+            self.step_out = None;
+            return;
+        }
+        // the CPU pushed PC (high byte first) and the status register
+        let sp = cpu.stack_pointer;
+        self.calls.push(CallFrame {
+            target: event.handler,
+            return_addr: cpu.bus.peek_u16(0x0100 + sp.wrapping_add(2) as u16),
+            sp: sp.wrapping_add(3),
+        });
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn parse_interrupt_type(name: &str) -> Option<InterruptType> {
+    match name.to_ascii_lowercase().as_str() {
+        "nmi" => Some(InterruptType::NMI),
+        "irq" => Some(InterruptType::IRQ),
+        "brk" => Some(InterruptType::BRK),
+        "reset" => Some(InterruptType::RESET),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::{Mirroring, Region, Rom};
+    use crate::nes::Nes;
+
+    // 16KiB NROM filled with NOPs, `code` patched in at the given CPU addresses.
+    // Reset starts at $8000, the IRQ/BRK handler is at $9000.
+    fn test_rom(code: &[(u16, &[u8])]) -> Rom {
+        let mut prg = vec![0xEA; 0x4000];
+        for (addr, bytes) in code {
+            let start = (*addr - 0x8000) as usize;
+            prg[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        prg[0x3FFC..0x4000].copy_from_slice(&[0x00, 0x80, 0x00, 0x90]); // reset, IRQ/BRK vectors
+        Rom {
+            prg_rom: prg,
+            chr_rom: vec![0; 0x2000],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+            battery: false,
+            trainer: None,
+            disk_sides: vec![],
+            region: Region::Ntsc,
+            vs_system: false,
+            playchoice: false,
+            mmc3_irq: Default::default(),
+            prg_ram_size: crate::cartridge::PRG_RAM_PAGE_SIZE,
+        }
+    }
+
+    #[test]
+    fn test_bank_breakpoints() {
+        assert_eq!(Breakpoint::parse("$c000"), Some(Breakpoint::from(0xC000)));
+        let banked = Breakpoint::parse("3:C000").unwrap();
+        assert_eq!(banked, Breakpoint { addr: 0xC000, bank: Some(3) });
+        assert_eq!(banked.to_string(), "03:$C000");
+        assert_eq!(Breakpoint::parse("1:10000"), None);
+
+        // AxROM, two 32KiB banks. Bank 0: LDA #1; STA $8000 switches to bank 1 under the
+        // CPU's feet. Both banks end up in the same loop at $8010.
+        let mut prg = vec![0xEA; 0x10000];
+        for bank in prg.chunks_mut(0x8000) {
+            bank[0x10..0x13].copy_from_slice(&[0x4C, 0x10, 0x80]);
+            bank[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+        }
+        prg[..5].copy_from_slice(&[0xA9, 0x01, 0x8D, 0x00, 0x80]);
+        let mut rom = test_rom(&[]);
+        rom.prg_rom = prg;
+        rom.mapper = 7;
+
+        let mut nes = Nes::from_rom(rom).unwrap();
+        nes.debugger().add_breakpoint(Breakpoint { addr: 0x8010, bank: Some(0) });
+        nes.run_frame();
+        assert_eq!(nes.break_reason(), None);
+        assert_eq!(nes.prg_bank_layout(), "$8000-$FFFF: bank 01\n");
+
+        nes.debugger().add_breakpoint(Breakpoint { addr: 0x8010, bank: Some(1) });
+        nes.run_frame();
+        assert_eq!(nes.break_reason(), Some(BreakReason::Breakpoint(0x8010)));
+    }
+
+    #[test]
+    fn test_breaks_before_brk_handler() {
+        // $8000: BRK    $9000: NOP; loop: JMP loop
+        let mut nes = Nes::from_rom(test_rom(&[(0x8000, &[0x00]), (0x9001, &[0x4C, 0x01, 0x90])])).unwrap();
+        nes.debugger().set_break_on_interrupt(InterruptType::BRK, true);
+        nes.debugger().add_breakpoint(0x9000);
+
+        nes.run_frame();
+        let event = match nes.break_reason() {
+            Some(BreakReason::Interrupt(event)) => event,
+            other => panic!("expected an interrupt break, got {:?}", other),
+        };
+        assert_eq!(event.itype, InterruptType::BRK);
+        assert_eq!(event.vector_addr, 0xFFFE);
+        assert_eq!(event.handler, 0x9000);
+        assert_eq!(nes.cpu().program_counter, 0x9000);
+        assert_eq!(nes.debugger().call_stack()[0].return_addr, 0x8002); // BRK skips a padding byte
+
+        // the breakpoint on the handler doesn't stop us a second time
+        nes.resume();
+        nes.run_frame();
+        assert_eq!(nes.break_reason(), None);
+    }
+
+    #[test]
+    fn test_step_out_and_run_to() {
+        let mut nes = Nes::from_rom(test_rom(&[
+            (0x8000, &[0x20, 0x10, 0x80]), // JSR $8010
+            (0x8004, &[0x4C, 0x04, 0x80]), // loop: JMP loop
+            (0x8010, &[0x20, 0x20, 0x80, 0x60]), // JSR $8020; RTS
+            (0x8021, &[0x60]), // $8020: NOP; RTS
+        ]))
+        .unwrap();
+        nes.debugger().add_breakpoint(0x8020);
+
+        nes.run_frame();
+        assert_eq!(nes.break_reason(), Some(BreakReason::Breakpoint(0x8020)));
+        assert_eq!(nes.debugger().call_stack().len(), 2);
+
+        assert!(nes.step_out());
+        nes.run_frame();
+        assert_eq!(nes.break_reason(), Some(BreakReason::StepOut { from: 0x8020, to: 0x8013 }));
+
+        nes.run_to(0x8004);
+        nes.run_frame();
+        assert_eq!(nes.break_reason(), Some(BreakReason::RunTo(0x8004)));
+        assert!(nes.debugger().call_stack().is_empty());
+        assert!(!nes.step_out());
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::nes::Nes;
+
+// Raw memory dumps: one region per file, byte for byte, so they open directly in a hex
+// editor or a script (find where a game keeps the player's health, patch it, load it back).
+// Unlike savestates nothing else is in the file, which is also why importing one can leave
+// the game in a state it never expected - that's the point when poking at it.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ram,    // 2KiB CPU work RAM, $0000-$07FF
+    PrgRam, // cartridge RAM, $6000-$7FFF
+    Vram,   // 2KiB nametable RAM
+    Oam,    // 256 bytes of sprite memory
+}
+
+pub const ALL: [Region; 4] = [Region::Ram, Region::PrgRam, Region::Vram, Region::Oam];
+
+impl Region {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Ram => "ram",
+            Region::PrgRam => "prgram",
+            Region::Vram => "vram",
+            Region::Oam => "oam",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Region> {
+        ALL.iter().copied().find(|region| region.name() == name.to_ascii_lowercase())
+    }
+
+    fn bytes<'a>(&self, nes: &'a Nes) -> &'a [u8] {
+        match self {
+            Region::Ram => nes.ram(),
+            Region::PrgRam => nes.prg_ram(),
+            Region::Vram => nes.vram(),
+            Region::Oam => nes.oam(),
+        }
+    }
+
+    fn bytes_mut<'a>(&self, nes: &'a mut Nes) -> &'a mut [u8] {
+        match self {
+            Region::Ram => nes.ram_mut(),
+            Region::PrgRam => nes.prg_ram_mut(),
+            Region::Vram => nes.vram_mut(),
+            Region::Oam => nes.oam_mut(),
+        }
+    }
+}
+
+// game.nes -> game.ram.bin, game.vram.bin, ...
+pub fn dump_path(rom_path: impl AsRef<Path>, region: Region) -> PathBuf {
+    rom_path.as_ref().with_extension(format!("{}.bin", region.name()))
+}
+
+pub fn export(nes: &Nes, region: Region, path: impl AsRef<Path>) -> io::Result<()> {
+    std::fs::write(path, region.bytes(nes))
+}
+
+// The file has to be exactly the size of the region: a dump of something else is more likely
+// than a deliberate partial one.
+pub fn import(nes: &mut Nes, region: Region, path: impl AsRef<Path>) -> io::Result<()> {
+    let data = std::fs::read(path)?;
+    let memory = region.bytes_mut(nes);
+    if data.len() != memory.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is {} bytes, the file has {}", region.name(), memory.len(), data.len()),
+        ));
+    }
+    memory.copy_from_slice(&data);
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_import() {
+        let dir = std::env::temp_dir();
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let paths: Vec<_> = ALL
+            .iter()
+            .map(|r| dir.join(format!("runesco-dump-test-{}.{}.bin", std::process::id(), r.name())))
+            .collect();
+
+        nes.ram_mut()[0x42] = 1;
+        nes.oam_mut()[0x10] = 2;
+        for (region, path) in ALL.iter().zip(&paths) {
+            export(&nes, *region, path).unwrap();
+        }
+        assert_eq!(std::fs::metadata(&paths[2]).unwrap().len(), 2048);
+
+        nes.ram_mut()[0x42] = 0;
+        nes.oam_mut()[0x10] = 0;
+        for (region, path) in ALL.iter().zip(&paths) {
+            import(&mut nes, *region, path).unwrap();
+        }
+        assert_eq!(nes.ram()[0x42], 1);
+        assert_eq!(nes.oam()[0x10], 2);
+
+        // an OAM dump doesn't fit in RAM
+        assert!(import(&mut nes, Region::Ram, &paths[3]).is_err());
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(Region::parse("VRAM"), Some(Region::Vram));
+        assert_eq!(Region::parse("chr"), None);
+        assert_eq!(dump_path("games/zelda.nes", Region::PrgRam), Path::new("games/zelda.prgram.bin"));
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// Everything that can go wrong getting a game to boot: reading the file, parsing the image,
+// finding a mapper for it. Returned instead of panicking so a frontend can tell the user what
+// is wrong with their file (main.rs prints it and shows a message box) and keep running.
+//
+// Errors that are a bug in the emulator rather than in the input still panic.
+
+#[derive(Debug)]
+pub enum RunescoError {
+    // Not a ROM image, or a damaged one
+    InvalidRom(String),
+    // A ROM image we recognise but can't load, like NES 2.0 headers
+    UnsupportedFormat(String),
+    UnsupportedMapper(u8),
+    // UNIF names its board instead of giving a mapper number
+    UnsupportedBoard(String),
+    // Disk System games need the console's BIOS, which isn't part of the image
+    MissingBios(String),
+    // A .zip or .7z that can't be read or has no ROM in it
+    Archive(String),
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl fmt::Display for RunescoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunescoError::InvalidRom(msg)
+            | RunescoError::UnsupportedFormat(msg)
+            | RunescoError::MissingBios(msg)
+            | RunescoError::Archive(msg) => write!(f, "{}", msg),
+            RunescoError::UnsupportedMapper(id) => write!(f, "Mapper {} is not supported", id),
+            RunescoError::UnsupportedBoard(board) => write!(f, "UNIF board {} is not supported", board),
+            RunescoError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for RunescoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunescoError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// For the places that still report errors as text (the console, savestates).
+impl From<RunescoError> for String {
+    fn from(err: RunescoError) -> String {
+        err.to_string()
+    }
+}
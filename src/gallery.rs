@@ -0,0 +1,157 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::nes::Nes;
+
+// `runesco gallery <dir>`: every game in a directory run without a window for a few seconds,
+// and where each one got to saved as a picture, usually its title screen. Side by side in the
+// index page they make a compatibility gallery at a glance (a black or garbled picture is a
+// game to look at), and running a whole collection exercises every mapper in it in one pass.
+//
+// One game going wrong doesn't stop the rest: a crash (a panic in the emulator) or a jammed
+// CPU is noted with the frame it happened on, and the picture it got to is still kept.
+
+pub const FRAMES: u64 = 300; // 5 seconds: past the publisher logos of most games
+
+// What a game's file can be called; archives are looked into when read (see crate::archive).
+const EXTENSIONS: [&str; 6] = ["nes", "unf", "unif", "fds", "zip", "7z"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Ran,
+    Jammed(u64),  // the frame the CPU jammed on
+    Crashed(u64), // the frame the emulator panicked on
+    NotLoaded(String),
+}
+
+impl Outcome {
+    pub fn ran(&self) -> bool {
+        *self == Outcome::Ran
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Ran => write!(f, "ran"),
+            Outcome::Jammed(frame) => write!(f, "CPU jammed on frame {}", frame),
+            Outcome::Crashed(frame) => write!(f, "crashed on frame {}", frame),
+            Outcome::NotLoaded(err) => write!(f, "didn't load: {}", err),
+        }
+    }
+}
+
+// One game in the gallery.
+pub struct Entry {
+    pub file: String,          // the game's file name
+    pub title: Option<String>, // from the ROM database
+    pub mapper: Option<u8>,
+    pub picture: Option<String>, // the PNG's file name, next to the index page
+    pub outcome: Outcome,
+}
+
+pub fn is_game(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_ascii_lowercase();
+    EXTENSIONS.contains(&extension.as_str())
+}
+
+// Runs `frames` frames, or until the game crashes or jams.
+pub fn run(nes: &mut Nes, frames: u64) -> Outcome {
+    while nes.frame_count() < frames {
+        let frame = nes.frame_count();
+        if panic::catch_unwind(AssertUnwindSafe(|| nes.run_frame())).is_err() {
+            return Outcome::Crashed(frame);
+        }
+        if nes.is_jammed() {
+            return Outcome::Jammed(frame);
+        }
+    }
+    Outcome::Ran
+}
+
+// index.html: the pictures in a grid, each with the game's name, mapper and what happened.
+pub fn index_html(entries: &[Entry]) -> String {
+    let ran = entries.iter().filter(|entry| entry.outcome.ran()).count();
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>runesco gallery</title>\n<style>\n\
+         body { background: #222; color: #ddd; font-family: sans-serif; }\n\
+         figure { display: inline-block; width: 256px; margin: 8px; vertical-align: top; }\n\
+         img { image-rendering: pixelated; background: #000; }\n\
+         .failed { color: #f66; }\n</style></head><body>\n",
+    );
+    html.push_str(&format!("<h1>{} of {} games ran</h1>\n", ran, entries.len()));
+    for entry in entries {
+        let name = entry.title.as_deref().unwrap_or(&entry.file);
+        let picture = match &entry.picture {
+            Some(picture) => format!("<img src=\"{}\" width=\"256\" height=\"240\" alt=\"\">", escape(picture)),
+            None => "<div style=\"width: 256px; height: 240px; background: #000\"></div>".to_string(),
+        };
+        let mapper = entry.mapper.map(|mapper| format!("mapper {}, ", mapper)).unwrap_or_default();
+        let class = if entry.outcome.ran() { "" } else { " class=\"failed\"" };
+        html.push_str(&format!(
+            "<figure>{}<figcaption title=\"{}\">{}<br><span{}>{}{}</span></figcaption></figure>\n",
+            picture,
+            escape(&entry.file),
+            escape(name),
+            class,
+            mapper,
+            escape(&entry.outcome.to_string())
+        ));
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::Rom;
+
+    #[test]
+    fn test_run() {
+        let image = std::fs::read("nestest.nes").unwrap();
+        let mut nes = Nes::new(&image).unwrap();
+        assert_eq!(run(&mut nes, 10), Outcome::Ran);
+        assert_eq!(nes.frame_count(), 10);
+
+        // KIL everywhere the reset vector can point
+        let mut rom = Rom::new(&image).unwrap();
+        let vectors = rom.prg_rom.len() - 6;
+        rom.prg_rom[..vectors].fill(0x02);
+        let mut nes = Nes::from_rom(rom).unwrap();
+        assert_eq!(run(&mut nes, 10), Outcome::Jammed(0));
+    }
+
+    #[test]
+    fn test_index() {
+        assert!(is_game(Path::new("roms/Zelda.NES")) && is_game(Path::new("set.7z")));
+        assert!(!is_game(Path::new("zelda.sav")) && !is_game(Path::new("README")));
+
+        let entries = [
+            Entry {
+                file: "smb.nes".to_string(),
+                title: Some("Super Mario Bros.".to_string()),
+                mapper: Some(0),
+                picture: Some("smb.png".to_string()),
+                outcome: Outcome::Ran,
+            },
+            Entry {
+                file: "<bad>.nes".to_string(),
+                title: None,
+                mapper: None,
+                picture: None,
+                outcome: Outcome::NotLoaded("mapper 255 is not supported".to_string()),
+            },
+        ];
+        let html = index_html(&entries);
+        assert!(html.contains("<h1>1 of 2 games ran</h1>"));
+        assert!(html.contains("<img src=\"smb.png\""));
+        assert!(html.contains("Super Mario Bros.<br><span>mapper 0, ran</span>"));
+        assert!(html.contains("&lt;bad&gt;.nes<br><span class=\"failed\">didn't load: mapper 255 is not supported</span>"));
+    }
+}
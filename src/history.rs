@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+use crate::trace::TraceEntry;
+
+// The last instructions the CPU executed, for working out how it got somewhere it shouldn't
+// be (a jam, a crash, a breakpoint in a handler). Entries are recorded raw and only formatted
+// when a report is printed, see trace::TraceEntry.
+
+pub const HISTORY_LEN: usize = 256;
+
+pub struct History {
+    entries: VecDeque<TraceEntry>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History { entries: VecDeque::with_capacity(HISTORY_LEN) }
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() == HISTORY_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // One trace line per instruction, oldest first.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for entry in self.iter() {
+            report.push_str(&entry.to_string());
+            report.push('\n');
+        }
+        report
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn entry(pc: u16, bytes: [u8; 3]) -> TraceEntry {
+        TraceEntry { pc, bytes, a: 1, x: 2, y: 3, p: 0x24, sp: 0xFD, scanline: 0, dot: 21, cycles: 7 }
+    }
+
+    #[test]
+    fn test_keeps_last_entries() {
+        let mut history = History::new();
+        for pc in 0..(HISTORY_LEN as u16 + 10) {
+            history.push(entry(pc, [0xEA, 0, 0]));
+        }
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history.iter().next().unwrap().pc, 10);
+        assert_eq!(history.iter().last().unwrap().pc, HISTORY_LEN as u16 + 9);
+    }
+
+    #[test]
+    fn test_report_lines() {
+        let mut history = History::new();
+        history.push(entry(0xC000, [0x4C, 0xF5, 0xC5]));
+        history.push(entry(0xC5F5, [0xBD, 0x00, 0x02]));
+        history.push(entry(0xC5F8, [0xD0, 0xFB, 0x00]));
+        assert_eq!(
+            history.report(),
+            "C000  4C F5 C5  JMP $C5F5                       A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7\n\
+             C5F5  BD 00 02  LDA $0200,X                     A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7\n\
+             C5F8  D0 FB     BNE $C5F5                       A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7\n"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::archive;
+use crate::cartridge::Rom;
+use crate::nes::Nes;
+
+// Developer mode for homebrew authors: when the ROM file changes on disk (say ca65/ld65 just
+// rebuilt it), load the new build straight into the running emulator.
+//
+// If only a small part of PRG changed (a tweaked routine, a new table entry) the 2KiB of work
+// RAM is carried over into the new build, so you don't have to play back to the spot you were
+// testing. Bigger changes (or a different mapper / ROM size) get a clean power-on instead,
+// because the old RAM contents probably don't mean anything to the new code.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const WARM_RELOAD_MAX_CHANGED: f32 = 0.10; // at most 10% of PRG bytes changed
+
+#[derive(Debug, PartialEq)]
+pub enum Reload {
+    Warm, // RAM preserved
+    Cold, // powered on from scratch
+}
+
+pub struct RomWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+    last_poll: Instant,
+    loaded: Vec<u8>, // the file as it was when we last loaded it
+}
+
+impl RomWatcher {
+    pub fn new(path: impl Into<PathBuf>, loaded: Vec<u8>) -> Self {
+        let path = path.into();
+        RomWatcher {
+            last_modified: modified(&path),
+            path,
+            last_poll: Instant::now(),
+            loaded,
+        }
+    }
+
+    // Cheap enough to call every frame: the file system is only asked every POLL_INTERVAL.
+    // Reloads `nes` if the file changed and parses as a ROM.
+    pub fn poll(&mut self, nes: &mut Nes) -> Option<Reload> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified == self.last_modified {
+            return None;
+        }
+        self.last_modified = modified;
+
+        // The assembler may still be writing the file: if it doesn't parse yet,
+        // the next write bumps the timestamp again and we'll retry then.
+        let data = archive::read_image(&self.path).ok()?;
+        let rom = match Rom::new(&data) {
+            Ok(rom) => rom,
+            Err(err) => {
+                println!("Hot reload: {} is not loadable yet ({})", self.path.display(), err);
+                return None;
+            }
+        };
+
+        let old = Rom::new(&self.loaded).ok();
+        let warm = match old {
+            Some(old) => {
+                old.mapper == rom.mapper && prg_changed_fraction(&old.prg_rom, &rom.prg_rom) <= WARM_RELOAD_MAX_CHANGED
+            }
+            None => false,
+        };
+
+        let ram = *nes.ram();
+        if let Err(err) = nes.insert_cartridge(rom) {
+            println!("Hot reload: can't run {} ({})", self.path.display(), err);
+            return None;
+        }
+        if warm {
+            nes.ram_mut().copy_from_slice(&ram);
+        }
+        self.loaded = data;
+
+        let reload = if warm { Reload::Warm } else { Reload::Cold };
+        println!("Hot reload: reloaded {} ({:?})", self.path.display(), reload);
+        Some(reload)
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// 0.0: identical, 1.0: every byte differs (or the sizes don't match).
+pub fn prg_changed_fraction(old: &[u8], new: &[u8]) -> f32 {
+    if old.len() != new.len() || old.is_empty() {
+        return 1.0;
+    }
+    let changed = old.iter().zip(new).filter(|(a, b)| a != b).count();
+    changed as f32 / old.len() as f32
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_prg_changed_fraction() {
+        let old = vec![0u8; 100];
+        let mut new = old.clone();
+        assert_eq!(prg_changed_fraction(&old, &new), 0.0);
+
+        new[3] = 1;
+        new[50] = 1;
+        assert_eq!(prg_changed_fraction(&old, &new), 0.02);
+
+        assert_eq!(prg_changed_fraction(&old, &new[..99]), 1.0);
+    }
+}
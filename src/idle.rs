@@ -0,0 +1,103 @@
+use crate::joypads::JoypadButton;
+use crate::pacing::FRAME_RATE;
+
+// For leaving the emulator running on a TV: once nobody has touched the controls for a while,
+// the picture dims (the game's attract mode plays on, like a screensaver over it) and later
+// the game pauses, so a forgotten session doesn't burn a picture into the screen or play its
+// music all night. Both are off unless the frontend asks for them (--idle-dim, --idle-pause).
+//
+// The frontend calls frame() once per picture shown with both joypads' buttons; any change
+// there is someone playing, whichever way it came in (keyboard, pad, a movie playing back, a
+// host being spectated). It also calls activity() on every key and pad button pressed in the
+// window, so hotkeys and keys bound to nothing count too. The console and remote run without
+// a window and never go idle. The first input after going idle wakes it up, and still reaches
+// the game: pressing Start on a dimmed title screen starts the game.
+
+pub struct IdleTimer {
+    dim_after: Option<u64>,   // frames
+    pause_after: Option<u64>, // frames
+    idle_frames: u64,
+    buttons: [JoypadButton; 2], // as of the last frame
+}
+
+impl IdleTimer {
+    // The delays in seconds; None leaves that one off.
+    pub fn new(dim_after: Option<f64>, pause_after: Option<f64>) -> Self {
+        let frames = |seconds: f64| (seconds * FRAME_RATE) as u64;
+        IdleTimer {
+            dim_after: dim_after.map(frames),
+            pause_after: pause_after.map(frames),
+            idle_frames: 0,
+            buttons: [JoypadButton::empty(); 2],
+        }
+    }
+
+    // Once per picture shown, paused or not. Returns true on the picture it wakes up on.
+    pub fn frame(&mut self, buttons: [JoypadButton; 2]) -> bool {
+        if buttons != self.buttons {
+            self.buttons = buttons;
+            return self.activity();
+        }
+        self.idle_frames += 1;
+        false
+    }
+
+    // Something other than the joypads was used. Returns true if that woke it up.
+    pub fn activity(&mut self) -> bool {
+        let woke = self.dimmed() || self.paused();
+        self.idle_frames = 0;
+        woke
+    }
+
+    pub fn dimmed(&self) -> bool {
+        self.dim_after.is_some_and(|after| self.idle_frames >= after)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.pause_after.is_some_and(|after| self.idle_frames >= after)
+    }
+}
+
+// The picture at a quarter of its brightness, for dimmed().
+pub fn dim(rgb: &[u8]) -> Vec<u8> {
+    rgb.iter().map(|&value| value / 4).collect()
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_dims_then_pauses() {
+        let seconds = |frames: u64| frames as f64 / FRAME_RATE;
+        let mut idle = IdleTimer::new(Some(seconds(10) + 0.001), Some(seconds(20) + 0.001));
+        let none = [JoypadButton::empty(); 2];
+        for _ in 0..9 {
+            assert!(!idle.frame(none) && !idle.dimmed());
+        }
+        idle.frame(none);
+        assert!(idle.dimmed() && !idle.paused());
+        for _ in 0..10 {
+            idle.frame(none);
+        }
+        assert!(idle.paused());
+
+        // a press on the second joypad wakes it; holding the button doesn't keep it awake
+        let start = [JoypadButton::empty(), JoypadButton::START];
+        assert!(idle.frame(start));
+        assert!(!idle.dimmed() && !idle.paused());
+        for _ in 0..11 {
+            idle.frame(start);
+        }
+        assert!(idle.dimmed());
+        assert!(idle.activity());
+        assert!(!idle.activity()); // awake already
+
+        let mut off = IdleTimer::new(None, None);
+        for _ in 0..100_000 {
+            off.frame(none);
+        }
+        assert!(!off.dimmed() && !off.paused());
+        assert_eq!(dim(&[0xFF, 0x80, 0x03]), [0x3F, 0x20, 0x00]);
+    }
+}
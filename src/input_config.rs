@@ -0,0 +1,348 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::joypads::JoypadButton;
+use crate::storage::Storage;
+use crate::video_config::VideoConfig;
+
+// Which key or controller button presses which NES button, for both players. Kept in a small
+// TOML file next to the emulator (input.toml unless --input-config says otherwise):
+//
+//   [player1]
+//   up = "key:Up"
+//   a = "key:Z"
+//
+//   [player2]
+//   a = "pad:a"
+//   auto-hold = "b"
+//
+// "key:" bindings use SDL's key names, "pad:" bindings SDL's game controller button names.
+// Nobody has to write the file by hand: the setup wizard (F1, see Wizard) asks for every
+// button in turn and writes it. Without a file the defaults below are used. `auto-hold` lists
+// the buttons (by their names below, separated by spaces or commas) that a press latches on
+// and the next press lets go, instead of being held down (see Joypad::auto_hold); it's only
+// ever written by hand.
+//
+// The same file keeps the window and picture settings in a [video] section, written by the
+// frontend when it closes (see crate::video_config). Only that section is rewritten then, and
+// a file with no [player1] or [player2] section (one the frontend started for the [video]
+// settings alone) leaves the controls at their defaults.
+//
+// Only the subset of TOML the wizard writes is read back: sections, `name = "value"` lines
+// and comments.
+
+pub const PLAYERS: usize = 2;
+
+// In the order the wizard asks for them.
+pub const BUTTONS: [(&str, JoypadButton); 8] = [
+    ("up", JoypadButton::UP),
+    ("down", JoypadButton::DOWN),
+    ("left", JoypadButton::LEFT),
+    ("right", JoypadButton::RIGHT),
+    ("select", JoypadButton::SELECT),
+    ("start", JoypadButton::START),
+    ("b", JoypadButton::BUTTON_B),
+    ("a", JoypadButton::BUTTON_A),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(String), // SDL key name, "Z", "Right Shift"
+    Pad(String), // SDL controller button name, "a", "dpup"
+}
+
+impl Binding {
+    pub fn parse(text: &str) -> Option<Binding> {
+        match text.split_once(':')? {
+            ("key", name) if !name.is_empty() => Some(Binding::Key(name.to_string())),
+            ("pad", name) if !name.is_empty() => Some(Binding::Pad(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(name) => write!(f, "key:{}", name),
+            Binding::Pad(name) => write!(f, "pad:{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputConfig {
+    pub players: [Vec<(JoypadButton, Binding)>; PLAYERS],
+    pub auto_hold: [JoypadButton; PLAYERS],
+    pub video: VideoConfig,
+}
+
+impl InputConfig {
+    pub fn empty() -> Self {
+        InputConfig {
+            players: [vec![], vec![]],
+            auto_hold: [JoypadButton::empty(); PLAYERS],
+            video: VideoConfig::default(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = InputConfig::empty();
+        let mut player = None;
+        let mut video = false;
+        let mut any_player = false;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |what: &str| format!("line {}: {} in '{}'", n + 1, what, line);
+            if let Some(section) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                (player, video) = match section.trim() {
+                    "player1" => (Some(0), false),
+                    "player2" => (Some(1), false),
+                    "video" => (None, true),
+                    _ => return Err(bad("unknown section")),
+                };
+                any_player |= player.is_some();
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or_else(|| bad("expected name = \"value\""))?;
+            let value = value.trim().strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(|| bad("expected a quoted value"))?;
+            if video {
+                config.video.set(name.trim(), value).map_err(|err| bad(&err))?;
+                continue;
+            }
+            let player = player.ok_or_else(|| bad("a binding outside [player1] or [player2]"))?;
+            if name.trim() == "auto-hold" {
+                for name in value.split([' ', ',']).filter(|name| !name.is_empty()) {
+                    let (_, button) = BUTTONS
+                        .iter()
+                        .find(|(button_name, _)| *button_name == name)
+                        .ok_or_else(|| bad("unknown button"))?;
+                    config.auto_hold[player] |= *button;
+                }
+                continue;
+            }
+            let (_, button) = BUTTONS
+                .iter()
+                .find(|(button_name, _)| *button_name == name.trim())
+                .ok_or_else(|| bad("unknown button"))?;
+            let binding = Binding::parse(value).ok_or_else(|| bad("expected \"key:...\" or \"pad:...\""))?;
+            config.players[player].push((*button, binding));
+        }
+        if !any_player {
+            config.players = InputConfig::default().players;
+        }
+        Ok(config)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# runesco controls, written by the setup wizard (F1)\n");
+        for (player, bindings) in self.players.iter().enumerate() {
+            text.push_str(&format!("\n[player{}]\n", player + 1));
+            for (name, button) in BUTTONS {
+                for (_, binding) in bindings.iter().filter(|(b, _)| *b == button) {
+                    text.push_str(&format!("{} = \"{}\"\n", name, binding));
+                }
+            }
+            let auto_hold: Vec<&str> =
+                BUTTONS.iter().filter(|(_, button)| self.auto_hold[player].contains(*button)).map(|(name, _)| *name).collect();
+            if !auto_hold.is_empty() {
+                text.push_str(&format!("auto-hold = \"{}\"\n", auto_hold.join(" ")));
+            }
+        }
+        text.push_str(&self.video.to_text());
+        text
+    }
+
+    // Ok(None) if there is no file yet.
+    pub fn load(storage: &dyn Storage, path: &Path) -> Result<Option<Self>, String> {
+        match storage.read(path) {
+            Ok(Some(data)) => InputConfig::parse(&String::from_utf8_lossy(&data))
+                .map(Some)
+                .map_err(|err| format!("{}: {}", path.display(), err)),
+            Ok(None) => Ok(None),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn save(&self, storage: &dyn Storage, path: &Path) -> Result<(), String> {
+        storage.write(path, self.to_text().as_bytes()).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // Writes `video` over the file's [video] section. Everything else in the file stays as it
+    // is, comments and all; without a file there is only the section.
+    pub fn save_video(storage: &dyn Storage, path: &Path, video: &VideoConfig) -> Result<(), String> {
+        let error = |err: std::io::Error| format!("{}: {}", path.display(), err);
+        let text = storage.read(path).map_err(error)?.map(|data| String::from_utf8_lossy(&data).into_owned());
+        let text = replace_video_section(text.as_deref().unwrap_or(""), video);
+        storage.write(path, text.as_bytes()).map_err(error)
+    }
+
+    // The player (0 or 1) and NES button `binding` presses, if any.
+    pub fn lookup(&self, binding: &Binding) -> Option<(usize, JoypadButton)> {
+        self.players.iter().enumerate().find_map(|(player, bindings)| {
+            bindings.iter().find(|(_, b)| b == binding).map(|(button, _)| (player, *button))
+        })
+    }
+}
+
+// `text` with its [video] section, if it has one, swapped for `video`'s, at the end.
+fn replace_video_section(text: &str, video: &VideoConfig) -> String {
+    let mut kept = String::new();
+    let mut in_video = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            in_video = trimmed[1..trimmed.len() - 1].trim() == "video";
+        }
+        if !in_video {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    let (kept, section) = (kept.trim_end(), video.to_text()); // the section starts with a blank line
+    if kept.is_empty() {
+        return section.trim_start().to_string();
+    }
+    format!("{}\n{}", kept, section)
+}
+
+// Player 1 on the keyboard, player 2 on the first game controller.
+impl Default for InputConfig {
+    fn default() -> Self {
+        let keys = ["Up", "Down", "Left", "Right", "Right Shift", "Return", "X", "Z"];
+        let pad = ["dpup", "dpdown", "dpleft", "dpright", "back", "start", "b", "a"];
+        let bind = |names: [&str; 8], binding: fn(String) -> Binding| {
+            BUTTONS.iter().zip(names).map(|((_, button), name)| (*button, binding(name.to_string()))).collect()
+        };
+        InputConfig {
+            players: [bind(keys, Binding::Key), bind(pad, Binding::Pad)],
+            auto_hold: [JoypadButton::empty(); PLAYERS],
+            video: VideoConfig::default(),
+        }
+    }
+}
+
+// The setup flow: asks for every button of player 1, then player 2, and collects the answers.
+// The frontend shows `prompt` (on the OSD) and hands over every key or button pressed until
+// `finished`; the result then replaces the current config and is saved.
+pub struct Wizard {
+    config: InputConfig,
+    step: usize, // index into the PLAYERS * BUTTONS questions
+}
+
+impl Wizard {
+    pub fn new() -> Self {
+        Wizard { config: InputConfig::empty(), step: 0 }
+    }
+
+    pub fn prompt(&self) -> String {
+        let (name, _) = BUTTONS[self.step % BUTTONS.len()];
+        format!("Player {}: press the key or button for {} (Esc cancels)", self.step / BUTTONS.len() + 1, name)
+    }
+
+    // Err (and the same question again) if `binding` already presses something else.
+    pub fn press(&mut self, binding: Binding) -> Result<(), String> {
+        if self.finished() {
+            return Ok(());
+        }
+        if let Some((player, button)) = self.config.lookup(&binding) {
+            let (name, _) = BUTTONS.iter().find(|(_, b)| *b == button).unwrap();
+            return Err(format!("{} is already player {}'s {}", binding, player + 1, name));
+        }
+        let (_, button) = BUTTONS[self.step % BUTTONS.len()];
+        self.config.players[self.step / BUTTONS.len()].push((button, binding));
+        self.step += 1;
+        Ok(())
+    }
+
+    pub fn finished(&self) -> bool {
+        self.step == PLAYERS * BUTTONS.len()
+    }
+
+    pub fn into_config(self) -> InputConfig {
+        self.config
+    }
+}
+
+impl Default for Wizard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = InputConfig::default();
+        assert_eq!(InputConfig::parse(&config.to_text()).unwrap(), config);
+        assert_eq!(config.lookup(&Binding::Key("Z".to_string())), Some((0, JoypadButton::BUTTON_A)));
+        assert_eq!(config.lookup(&Binding::Pad("dpup".to_string())), Some((1, JoypadButton::UP)));
+        assert_eq!(config.lookup(&Binding::Key("Q".to_string())), None);
+
+        let err = InputConfig::parse("[player1]\njump = \"key:Space\"\n").unwrap_err();
+        assert_eq!(err, "line 2: unknown button in 'jump = \"key:Space\"'");
+        assert!(InputConfig::parse("a = \"key:Z\"").is_err()); // no section
+        assert!(InputConfig::parse("[player1]\na = \"joystick:1\"").is_err());
+    }
+
+    #[test]
+    fn test_video_section() {
+        let text = "[video]\nwindow = \"0,0,512,480\"\n\n[player1]\na = \"key:Z\"\n";
+        let config = InputConfig::parse(text).unwrap();
+        assert_eq!(config.video.window.map(|window| window.width), Some(512));
+        assert_eq!(config.players[0].len(), 1);
+        assert_eq!(InputConfig::parse(&config.to_text()).unwrap(), config);
+        assert!(InputConfig::parse("[video]\nwindow = \"big\"\n").unwrap_err().starts_with("line 2: "));
+        // written for the [video] settings alone: the default controls
+        assert_eq!(InputConfig::parse("[video]\nfullscreen = \"true\"\n").unwrap().players, InputConfig::default().players);
+    }
+
+    #[test]
+    fn test_replace_video_section() {
+        let video = VideoConfig { fullscreen: true, ..VideoConfig::default() };
+        let text = "# mine\n[player1]\na = \"key:Z\" # jump\n\n[video]\nwindow = \"0,0,512,480\"\n\n[player2]\nb = \"pad:x\"\n";
+        assert_eq!(
+            replace_video_section(text, &video),
+            "# mine\n[player1]\na = \"key:Z\" # jump\n\n[player2]\nb = \"pad:x\"\n\n[video]\nfullscreen = \"true\"\n"
+        );
+        assert_eq!(replace_video_section("", &video), "[video]\nfullscreen = \"true\"\n");
+        assert_eq!(replace_video_section("[player1]\n\n[video]\nfullscreen = \"true\"\n", &VideoConfig::default()), "[player1]\n");
+    }
+
+    #[test]
+    fn test_auto_hold() {
+        let config = InputConfig::parse("[player2]\nauto-hold = \"b, a\"\nb = \"pad:x\"\n").unwrap();
+        assert_eq!(config.auto_hold, [JoypadButton::empty(), JoypadButton::BUTTON_A | JoypadButton::BUTTON_B]);
+        assert!(config.to_text().contains("[player2]\nb = \"pad:x\"\nauto-hold = \"b a\"\n"));
+        assert_eq!(InputConfig::parse(&config.to_text()).unwrap(), config);
+        assert!(InputConfig::parse("[player1]\nauto-hold = \"turbo\"").is_err());
+    }
+
+    #[test]
+    fn test_wizard() {
+        let mut wizard = Wizard::new();
+        assert_eq!(wizard.prompt(), "Player 1: press the key or button for up (Esc cancels)");
+        wizard.press(Binding::Key("W".to_string())).unwrap();
+        assert!(wizard.press(Binding::Key("W".to_string())).is_err());
+        assert!(wizard.prompt().contains("for down"));
+
+        for key in ["S", "A", "D", "Tab", "Space", "J", "K"] {
+            wizard.press(Binding::Key(key.to_string())).unwrap();
+        }
+        assert!(wizard.prompt().starts_with("Player 2: "));
+        for (name, _) in BUTTONS {
+            wizard.press(Binding::Pad(name.to_string())).unwrap();
+        }
+        assert!(wizard.finished());
+
+        let config = wizard.into_config();
+        assert_eq!(config.lookup(&Binding::Key("K".to_string())), Some((0, JoypadButton::BUTTON_A)));
+        assert_eq!(config.lookup(&Binding::Pad("select".to_string())), Some((1, JoypadButton::SELECT)));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::joypads::{InputDevice, Joypad, PORT_1, PORT_2};
+use crate::render::frame::Frame;
+use crate::render::osd::{draw_text, fill, LINE_HEIGHT, MARGIN, SCALE};
+
+// The controller test screen (runesco --input-test): no game, just the controller ports,
+// strobed and read once a frame the way a game reads them, with every read drawn bit by bit.
+// For checking a device's protocol without a game that uses it, or finding out why a game
+// doesn't see one: what each data line answers on each read after a strobe is all a game
+// ever gets to know.
+//
+// Each frame writes 1 then 0 to $4016 (OUT0, the strobe) and reads $4016 and $4017 in turns,
+// READS times each. The screen shows the answers as one row per data line, D0-D4, the first
+// read on the left. A standard controller answers its 8 buttons on D0 and 1s after that; a
+// device that counts its reads differently shows it here straight away.
+//
+// The ports are wired as on the Bus (see Bus::read_ports): every device sees every write and
+// read, and the answers are ORed together. The top three bits aren't shown, nothing drives them.
+
+pub const READS: usize = 24; // enough for a Four Score: two controllers and its signature
+pub const LINES: usize = 5; // D0-D4
+const FRAME_CYCLES: u32 = 29781;
+
+const TEXT: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+const UNDRIVEN: (u8, u8, u8) = (0x60, 0x60, 0x60); // a line nothing answered on this frame
+
+pub struct InputTest {
+    joypad1: Joypad,
+    joypad2: Joypad,
+    devices: Vec<Box<dyn InputDevice>>,
+    reads: [[u8; READS]; 2], // the last strobe's answers on $4016 and $4017
+}
+
+impl InputTest {
+    pub fn new() -> Self {
+        InputTest {
+            joypad1: Joypad::new(PORT_1),
+            joypad2: Joypad::new(PORT_2),
+            devices: vec![],
+            reads: [[0; READS]; 2],
+        }
+    }
+
+    pub fn joypads(&mut self) -> (&mut Joypad, &mut Joypad) {
+        (&mut self.joypad1, &mut self.joypad2)
+    }
+
+    // As Bus::plug, but any number of devices of a kind: testing two at once is the point.
+    pub fn plug(&mut self, device: Box<dyn InputDevice>) {
+        self.devices.push(device);
+    }
+
+    // What's plugged in, controllers first.
+    pub fn device_names(&self) -> Vec<&str> {
+        let mut names = vec![self.joypad1.name(), self.joypad2.name()];
+        names.extend(self.devices.iter().map(|device| device.name()));
+        names
+    }
+
+    fn write(&mut self, data: u8) {
+        self.joypad1.write(data);
+        self.joypad2.write(data);
+        for device in &mut self.devices {
+            device.write(data);
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let mut bits = self.joypad1.read(addr) | self.joypad2.read(addr);
+        for device in &mut self.devices {
+            bits |= device.read(addr);
+        }
+        bits & 0x1F
+    }
+
+    // One strobe and its reads; once a frame.
+    pub fn poll(&mut self) {
+        self.write(1);
+        self.write(0);
+        for i in 0..READS {
+            self.reads[0][i] = self.read(PORT_1);
+            self.reads[1][i] = self.read(PORT_2);
+        }
+        // and a frame's worth of time for the devices that keep it (about 29781 CPU cycles)
+        for device in &mut self.devices {
+            for _ in 0..FRAME_CYCLES / u8::MAX as u32 {
+                device.clock(u8::MAX);
+            }
+        }
+    }
+
+    // The last poll's answers on one port, PORT_1 or PORT_2.
+    pub fn reads(&self, addr: u16) -> &[u8; READS] {
+        &self.reads[(addr - PORT_1) as usize]
+    }
+
+    // One data line's bits in read order, in groups of 8: "10000000 11111111 11111111".
+    pub fn line(&self, addr: u16, bit: usize) -> String {
+        let bit = |read: &u8| (b'0' + (read >> bit & 1)) as char;
+        let bytes: Vec<String> = self.reads(addr).chunks(8).map(|reads| reads.iter().map(bit).collect()).collect();
+        bytes.join(" ")
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        fill(frame, 0, 0, Frame::WIDTH, Frame::HIGHT, (0, 0, 0));
+        let mut y = MARGIN;
+        let mut text = |frame: &mut Frame, text: &str, rgb| {
+            draw_text(frame, MARGIN + SCALE, y, text, rgb);
+            y += LINE_HEIGHT;
+        };
+        text(frame, "Input test: reads per strobe", TEXT);
+        for addr in [PORT_1, PORT_2] {
+            text(frame, "", TEXT);
+            text(frame, &format!("${:04X}", addr), TEXT);
+            for bit in 0..LINES {
+                let driven = self.reads(addr).iter().any(|read| read >> bit & 1 == 1);
+                text(frame, &format!("D{} {}", bit, self.line(addr, bit)), if driven { TEXT } else { UNDRIVEN });
+            }
+        }
+        text(frame, "", TEXT);
+        text(frame, &self.device_names().join(", "), UNDRIVEN);
+    }
+}
+
+impl Default for InputTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::joypads::JoypadButton;
+    use crate::turbo_file::TurboFile;
+
+    #[test]
+    fn test_reads_each_line() {
+        let mut input_test = InputTest::new();
+        let (one, two) = input_test.joypads();
+        one.set_button_pressed_status(JoypadButton::BUTTON_A | JoypadButton::START, true);
+        two.set_button_pressed_status(JoypadButton::RIGHT, true);
+        two.set_microphone(true);
+        input_test.poll();
+
+        // 8 buttons, then 1s for as long as the game keeps reading
+        assert_eq!(input_test.line(PORT_1, 0), "10010000 11111111 11111111");
+        assert_eq!(input_test.line(PORT_2, 0), "00000001 11111111 11111111");
+        assert_eq!(input_test.line(PORT_1, 2), "11111111 11111111 11111111"); // the microphone
+        assert_eq!(input_test.line(PORT_2, 2), "00000000 00000000 00000000");
+
+        // the next strobe starts the sequence over
+        input_test.joypads().0.set_button_pressed_status(JoypadButton::START, false);
+        input_test.poll();
+        assert_eq!(input_test.line(PORT_1, 0), "10000000 11111111 11111111");
+    }
+
+    #[test]
+    fn test_devices() {
+        let mut turbo_file = TurboFile::new();
+        turbo_file.load(&[0b0000_0101; crate::turbo_file::SIZE]).unwrap();
+        let mut input_test = InputTest::new();
+        input_test.plug(Box::new(turbo_file));
+        assert_eq!(input_test.device_names(), ["controller", "controller", "Turbo File"]);
+
+        // reading alone doesn't clock it on: it answers its first bit every time
+        input_test.poll();
+        assert!(input_test.reads(PORT_2).iter().all(|&read| read & 0b100 != 0));
+        let mut frame = Frame::new();
+        input_test.draw(&mut frame);
+        assert!(frame.data.contains(&0xFF));
+    }
+}
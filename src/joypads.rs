@@ -1,5 +1,34 @@
+use std::any::Any;
+
 use bitflags::bitflags;
 
+// Anything plugged into the controller ports or the Famicom's expansion port: the standard
+// controllers (Joypad, below) and whatever the Bus has plugged in beside them (see Bus::plug;
+// the Turbo File in crate::turbo_file is one). They all hang off the same few lines:
+//
+//   $4016 write  bit 0 (OUT0) strobes every device; the expansion port gets bits 1-2 too
+//   $4016 read   D0: controller 1, D2: controller 2's microphone, D1-D4: the expansion port
+//   $4017 read   D0: controller 2, D1-D4: the expansion port
+//
+// Every device sees every write and every read of both registers, and answers a read with the
+// bits it drives, in place, 0 for the rest; the Bus ORs the answers together, which is how the
+// lines are wired. Devices that keep time of their own (a light gun's sensor, a tape) also
+// get the CPU cycles as they pass.
+// See: https://www.nesdev.org/wiki/Input_devices and https://www.nesdev.org/wiki/Expansion_port
+pub trait InputDevice: Any {
+    fn write(&mut self, data: u8);
+    // `addr` is $4016 or $4017.
+    fn read(&mut self, addr: u16) -> u8;
+    fn clock(&mut self, _cycles: u8) {}
+    // For the user, on the controller test screen (see crate::input_test).
+    fn name(&self) -> &str {
+        "device"
+    }
+}
+
+pub const PORT_1: u16 = 0x4016;
+pub const PORT_2: u16 = 0x4017;
+
 bitflags! {
     // https://wiki.nesdev.com/w/index.php/Controller_reading_code
     pub struct JoypadButton: u8 {
@@ -15,36 +44,49 @@ bitflags! {
 }
 
 pub struct Joypad {
+    port: u16,        // PORT_1 or PORT_2, where its buttons are read
+    microphone: bool, // loud; only the Famicom's second controller has one, see Bus::set_microphone
     strobe: bool,     // is it in read mode or write mode
     button_index: u8, // pointer to a button
     pub button_status: JoypadButton,
+
+    // Turbo (auto-fire) buttons read as pressed and released in turns while they're held,
+    // switching every TURBO_FRAMES frames; the Nes facade moves the phase along.
+    pub turbo: JoypadButton,
+    turbo_released: bool,
+
+    // Auto-hold buttons, for players who can't keep a button down: a press latches the button
+    // on and the next one lets it go, so the game sees it held in between (B to run in Super
+    // Mario Bros.). Set from the input config (see crate::input_config).
+    pub auto_hold: JoypadButton,
 }
 
+pub const TURBO_FRAMES: u64 = 2; // pressed for 2 frames, released for 2: 15 presses a second
+
 impl Joypad {
-    pub fn new() -> Self {
+    pub fn new(port: u16) -> Self {
         Joypad {
+            port,
+            microphone: false,
             strobe: false,
             button_index: 0,
             button_status: JoypadButton::from_bits_truncate(0),
+            turbo: JoypadButton::empty(),
+            turbo_released: false,
+            auto_hold: JoypadButton::empty(),
         }
     }
 
-    pub fn write(&mut self, data: u8) {
-        self.strobe = data & 1 == 1; // set strobe mode to on if bit 1 of data is set
-        if self.strobe { // if it is to be on,
-            self.button_index = 0 // initialise the button pointer for reads.
-        }
-    }
-
-    pub fn read(&mut self) -> u8 {
+    // The next button, in bit 0.
+    fn read_button(&mut self) -> u8 {
         if self.button_index > 7 { // if button pointer exceeds, a read on an NES will always keep returning 1
             return 1;
         }
 
         // otherwise...
-        let response = (self.button_status.bits & (1 << self.button_index)) >> self.button_index;
+        let response = (self.pressed().bits & (1 << self.button_index)) >> self.button_index;
 
-        // self.button_status.bits & (1 << self.button_index) isolates the bit corresponding to the current button 
+        // self.pressed().bits & (1 << self.button_index) isolates the bit corresponding to the current button 
         // (A = index 0, B = index 1, and so on) from button_status.bits.
         // (1 << self.button_index) creates a mask with a 1 in the position of self.button_index: 
         
@@ -63,6 +105,72 @@ impl Joypad {
     }
 
     pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
-        self.button_status.set(button, pressed);
+        let latched = button & self.auto_hold;
+        if pressed {
+            self.button_status.toggle(latched);
+        }
+        self.button_status.set(button - latched, pressed);
+    }
+
+    // The buttons as the game sees them: held ones, minus turbo buttons in their off phase.
+    pub fn pressed(&self) -> JoypadButton {
+        if self.turbo_released {
+            self.button_status - self.turbo
+        } else {
+            self.button_status
+        }
+    }
+
+    pub fn set_turbo_phase(&mut self, frame: u64) {
+        self.turbo_released = (frame / TURBO_FRAMES) % 2 == 1;
+    }
+
+    pub fn set_microphone(&mut self, loud: bool) {
+        self.microphone = loud;
+    }
+
+    pub fn microphone(&self) -> bool {
+        self.microphone
+    }
+}
+
+impl InputDevice for Joypad {
+    fn name(&self) -> &str {
+        "controller"
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1; // set strobe mode to on if bit 1 of data is set
+        if self.strobe { // if it is to be on,
+            self.button_index = 0 // initialise the button pointer for reads.
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        // the microphone is wired to $4016, whichever controller it's on
+        let microphone = if addr == PORT_1 { (self.microphone as u8) << 2 } else { 0 };
+        if addr == self.port {
+            microphone | self.read_button()
+        } else {
+            microphone
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_auto_hold() {
+        let mut joypad = Joypad::new(PORT_1);
+        joypad.auto_hold = JoypadButton::BUTTON_B;
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, false);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        assert_eq!(joypad.pressed(), JoypadButton::BUTTON_A | JoypadButton::BUTTON_B); // still held
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, false);
+        assert_eq!(joypad.pressed(), JoypadButton::empty());
     }
 }
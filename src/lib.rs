@@ -0,0 +1,57 @@
+//! ruNESco: a NES emulator core.
+//!
+//! The [`nes::Nes`] facade is the easiest way in: load a ROM, run frames, read the picture
+//! and memory. The SDL2 frontend in `main.rs` is built on top of it.
+
+pub mod action;
+pub mod apu;
+pub mod archive;
+pub mod audio;
+pub mod audio_recorder;
+pub mod bus;
+pub mod cartridge;
+pub mod cheats;
+pub mod compat;
+pub mod console;
+pub mod coverage;
+pub mod cpu;
+pub mod debugger;
+pub mod dumps;
+pub mod error;
+pub mod gallery;
+pub mod history;
+pub mod hot_reload;
+pub mod idle;
+pub mod input_config;
+pub mod input_test;
+pub mod joypads;
+pub mod json;
+pub mod mapper;
+pub mod movie;
+pub mod nes;
+pub mod opcodes;
+pub mod pacing;
+pub mod ram_watch;
+pub mod rng;
+pub mod romdb;
+pub mod savestate;
+pub mod spectate;
+pub mod speed;
+pub mod speedrun;
+pub mod sram;
+pub mod step_view;
+pub mod storage;
+pub mod test_status;
+pub mod trace;
+pub mod turbo_file;
+pub mod volume;
+pub mod video_config;
+pub mod vs_system;
+pub mod watchdog;
+
+pub mod ppu;
+pub mod remote;
+pub mod render;
+
+#[macro_use]
+extern crate lazy_static;
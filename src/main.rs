@@ -1,35 +1,102 @@
-pub mod bus;
-pub mod cartridge;
-pub mod cpu;
-pub mod joypads;
-pub mod opcodes;
-pub mod trace;
-
-pub mod ppu;
-pub mod render;
-
-use std::collections::HashMap;
-
-use bus::Bus;
-//use cpu::Mem;
-use cpu::CPU;
-//use rand::Rng;
-use crate::ppu::NesPPU;
-use cartridge::Rom;
-use render::frame::Frame;
-use render::palette;
-//use trace::trace;
+use std::io::{self, BufRead};
+use std::panic::{self, AssertUnwindSafe};
+use std::ops::Range;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::mpsc::{self, TryRecvError};
+use std::time::{Duration, Instant};
 
+use runesco::action::{self, Action};
+use runesco::console::{Console, Outcome};
+use runesco::cpu::InterruptType;
+use runesco::debugger::{self, Breakpoint};
+use runesco::dumps;
+use runesco::error::RunescoError;
+use runesco::gallery::{self, Entry};
+use runesco::hot_reload::RomWatcher;
+use runesco::idle::{self, IdleTimer};
+use runesco::input_config::{Binding, InputConfig, Wizard};
+use runesco::input_test::InputTest;
+use runesco::joypads::Joypad;
+use runesco::json::Json;
+use runesco::movie::Movie;
+use runesco::apu::mixer::{Channel, Source};
+use runesco::apu::stretch::StretchMode;
+use runesco::archive;
+use runesco::audio;
+use runesco::audio_recorder::AudioRecorder;
+use runesco::cartridge::{self, Region, Rom};
+use runesco::cheats::{self, Cheats};
+use runesco::compat::{self, Support};
+use runesco::nes::Nes;
+use runesco::render::frame::Frame;
+use runesco::render::filters::{self, FlashFilter};
+use runesco::render::overlays::{ApuVisualizer, RamHeatmap, ScanlineRuler, SpriteZeroOverlay};
+use runesco::mapper::barcode::{self, Card};
+use runesco::mapper::{self, fds};
+use runesco::render::osd::{Level, Osd, OsdMessages};
+use runesco::render::palette;
+use runesco::remote::RemoteServer;
+use runesco::romdb::{self, Database};
+use runesco::pacing::FramePacer;
+use runesco::savestate::{self, SaveState};
+use runesco::spectate::{Session, SpectatorClient, SpectatorServer};
+use runesco::speed::{self, SpeedControl};
+use runesco::speedrun::{self, SpeedrunTimer, TimerOverlay};
+use runesco::step_view::StepView;
+use runesco::sram;
+use runesco::storage::{FileStorage, Storage};
+use runesco::test_status::TestStatus;
+use runesco::trace::TraceEntry;
+use runesco::turbo_file::TurboFile;
+use runesco::video_config::{Pacing, WindowGeometry};
+use runesco::volume::{self, Volume, VolumeControl};
+use runesco::watchdog::{Stall, Watchdog};
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::controller::Button;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
 //use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::video::{FullscreenType, SwapInterval, Window};
 //use sdl2::EventPump;
 // use std::time::Duration;
 
-#[macro_use]
-extern crate lazy_static;
+// With --json, stdout carries nothing but the JSON result, for scripts to parse; progress
+// and diagnostics printed with status! go to stderr instead.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// Saves, savestates and settings live in files next to the game (see runesco::storage).
+static STORAGE: FileStorage = FileStorage;
+
+// How long the window may stop responding before the watchdog steps in (see runesco::watchdog),
+// and how often a state is kept to go back to when it does.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(5);
+const AUTOSAVE_FRAMES: u64 = 600;
+
+// Samples SDL asks for at a time, and how much sound may be waiting in its queue before more
+// is dropped rather than queued. The pacer (see runesco::pacing) keeps the queue well below
+// that; the limit is for when it can't, like after the window was dragged.
+const AUDIO_BLOCK: u16 = 1024;
+const MAX_QUEUED_AUDIO: u32 = audio::SAMPLE_RATE / 10; // 100ms
+
+// How loud the recording device has to get (of full scale) before the game hears the Famicom
+// microphone, with --mic and no threshold.
+const DEFAULT_MIC_THRESHOLD: f32 = 0.25;
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if JSON_OUTPUT.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 #[allow(dead_code)]
 fn show_tile(chr_rom: &Vec<u8>, bank: usize, tile_n: usize) -> Frame {
@@ -121,10 +188,1344 @@ fn show_tile_bank(chr_rom: &Vec<u8>, bank: usize) -> Frame {
     frame
 }
 
+struct Args {
+    rom_path: String,
+    headless_frames: Option<u64>, // run this many frames without a window, then exit
+    json: bool, // --json: print the results of --headless (or check) as JSON
+    coverage: bool, // --coverage: after --headless, report which memory and PPU registers were used
+    console: bool, // no window, commands from stdin
+    remote: Option<String>, // --remote 127.0.0.1:6502: no window, commands from a debugger over TCP
+    watch: bool, // reload the ROM whenever the file changes (for homebrew development)
+    breakpoints: Vec<Breakpoint>, // --break C000, or 03:C000 for one PRG bank
+    break_on: Vec<InterruptType>, // --break-on nmi,irq,brk,reset
+    run_to: Option<u16>, // --run-to C000: stop once, the first time PC gets there
+    diff_states: Option<(String, String)>, // --diff-states a.state b.state: compare and exit
+    dump_frames: Option<(Range<u64>, PathBuf)>, // --dump-frames 100..200 shots/
+    record: Option<PathBuf>, // --record run.rnm: write a movie of the session on quit
+    subframe: bool, // --subframe: record every controller read, not one sample per frame
+    play: Option<PathBuf>, // --play run.rnm
+    spectators: Option<String>, // --spectators 0.0.0.0:6503: stream our inputs to people watching
+    spectate: Option<String>, // --spectate host:6503: watch someone else's session
+    romdb: Option<PathBuf>, // --romdb extra.txt: more ROM database entries, on top of the bundled ones
+    mapper_fallback: bool, // --mapper-fallback: run unsupported mappers as a close supported one
+    dmc_conflict: bool, // off with --no-dmc-conflict, see Bus::dmc_dma
+    open_bus: bool, // off with --no-open-bus: unmapped reads return 0, see Bus::read_open_bus
+    bus_conflicts: bool, // off with --no-bus-conflicts, see Bus::bus_conflict
+    audio_filters: bool, // off with --no-audio-filters, see runesco::apu::filter
+    fast_forward_audio: StretchMode, // --fast-forward-audio skip: silent fast-forward, see runesco::apu::stretch
+    cartridge_volume: Option<u32>, // --cartridge-volume <percent>: the cartridge's sound chip
+    mic: Option<f32>, // --mic [25]: the Famicom microphone from the recording device, loud above this percent
+    fds_bios: Option<PathBuf>, // --fds-bios disksys.rom, for Disk System games
+    turbo_file: Option<PathBuf>, // --turbo-file saves.tf: a Turbo File on the expansion port, kept in this file
+    barcodes: Option<PathBuf>, // --barcodes cards.txt: the Datach's cards, swiped in turn with Home
+    splits: Option<PathBuf>, // --splits smb.txt: a speedrun timer, split by these RAM rules
+    input_config: PathBuf, // --input-config pads.toml: the controls, written by the F1 wizard
+    speed: Option<u32>, // --speed 75: percent of full speed, instead of the game's saved one
+    flash_filter: Option<f32>, // --flash-filter [10]: soften flashes brighter than this percent
+    idle_dim: Option<f64>, // --idle-dim 5: dim the picture after this many minutes without input
+    idle_pause: Option<f64>, // --idle-pause 30: pause the game after this many minutes without input
+    dip_switches: u8, // --dip 3C: a Vs. System cabinet's DIP switches, switch 1 in bit 0
+    palette: Option<PathBuf>, // --palette rp2c04.pal: show the game in these colours
+    watchdog_kill: Option<u64>, // --watchdog-kill 60: quit if the window stops responding this many seconds
+    repair_header: bool, // --repair-header: clear the junk old tools left in bytes 7-15 of iNES headers
+    input_test: bool, // --input-test: no game, show what the controller ports answer (see runesco::input_test)
+    step_view: bool, // --step-view: start stopped in the step view, at the first instruction (see runesco::step_view)
+}
+
+impl Args {
+    // Whether the game is shown in a window, rather than run for a report, a console or a remote.
+    fn windowed(&self) -> bool {
+        !self.console && self.remote.is_none() && self.headless_frames.is_none() && self.dump_frames.is_none()
+    }
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        rom_path: "nestest.nes".to_string(),
+        headless_frames: None,
+        json: false,
+        coverage: false,
+        console: false,
+        remote: None,
+        watch: false,
+        breakpoints: vec![],
+        break_on: vec![],
+        run_to: None,
+        diff_states: None,
+        dump_frames: None,
+        record: None,
+        subframe: false,
+        play: None,
+        spectators: None,
+        spectate: None,
+        romdb: None,
+        mapper_fallback: false,
+        dmc_conflict: true,
+        open_bus: true,
+        bus_conflicts: true,
+        audio_filters: true,
+        fast_forward_audio: StretchMode::Stretch,
+        cartridge_volume: None,
+        mic: None,
+        fds_bios: None,
+        turbo_file: None,
+        barcodes: None,
+        splits: None,
+        input_config: PathBuf::from("input.toml"),
+        speed: None,
+        flash_filter: None,
+        idle_dim: None,
+        idle_pause: None,
+        dip_switches: 0,
+        palette: None,
+        watchdog_kill: None,
+        repair_header: false,
+        input_test: false,
+        step_view: false,
+    };
+
+    let mut iter = std::env::args().skip(1).peekable();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--headless" => {
+                let frames = iter.next().and_then(|n| n.parse().ok());
+                args.headless_frames = Some(frames.expect("--headless expects a number of frames"));
+            }
+            "--watch" => args.watch = true,
+            "--json" => args.json = true,
+            "--coverage" => args.coverage = true,
+            "--console" => args.console = true,
+            "--remote" => args.remote = Some(iter.next().expect("--remote expects an address like 127.0.0.1:6502")),
+            "--break" => {
+                let breakpoint = iter.next().and_then(|a| Breakpoint::parse(&a));
+                args.breakpoints.push(breakpoint.expect("--break expects a hex address, or bank:address"));
+            }
+            "--run-to" => {
+                let addr = iter.next().and_then(|a| u16::from_str_radix(a.trim_start_matches('$'), 16).ok());
+                args.run_to = Some(addr.expect("--run-to expects a hex address"));
+            }
+            "--break-on" => {
+                let names = iter.next().expect("--break-on expects a list like nmi,brk,reset");
+                for name in names.split(',') {
+                    let itype = debugger::parse_interrupt_type(name);
+                    args.break_on.push(itype.unwrap_or_else(|| panic!("unknown interrupt '{}'", name)));
+                }
+            }
+            "--diff-states" => {
+                let usage = "--diff-states expects two savestate files";
+                let a = iter.next().expect(usage);
+                args.diff_states = Some((a, iter.next().expect(usage)));
+            }
+            "--dump-frames" => {
+                let usage = "--dump-frames expects a range of frames and a directory, like 100..200 shots/";
+                let range = iter.next().and_then(|r| parse_frame_range(&r)).expect(usage);
+                args.dump_frames = Some((range, PathBuf::from(iter.next().expect(usage))));
+            }
+            "--record" => args.record = Some(PathBuf::from(iter.next().expect("--record expects a file name"))),
+            "--subframe" => args.subframe = true,
+            "--mapper-fallback" => args.mapper_fallback = true,
+            "--repair-header" => args.repair_header = true,
+            "--input-test" => args.input_test = true,
+            "--step-view" => args.step_view = true,
+            "--no-dmc-conflict" => args.dmc_conflict = false,
+            "--no-open-bus" => args.open_bus = false,
+            "--no-bus-conflicts" => args.bus_conflicts = false,
+            "--no-audio-filters" => args.audio_filters = false,
+            "--cartridge-volume" => {
+                let percent = iter.next().and_then(|p| p.trim_end_matches('%').parse().ok());
+                args.cartridge_volume = Some(percent.expect("--cartridge-volume expects a percentage"));
+            }
+            "--mic" => {
+                // the threshold is optional: the next argument may be the ROM
+                let percent = iter.peek().and_then(|t| t.trim_end_matches('%').parse::<f32>().ok());
+                if percent.is_some() {
+                    iter.next();
+                }
+                args.mic = Some(percent.map_or(DEFAULT_MIC_THRESHOLD, |p| p / 100.0));
+            }
+            "--fds-bios" => args.fds_bios = Some(PathBuf::from(iter.next().expect("--fds-bios expects a file"))),
+            "--turbo-file" => args.turbo_file = Some(PathBuf::from(iter.next().expect("--turbo-file expects a file"))),
+            "--barcodes" => args.barcodes = Some(PathBuf::from(iter.next().expect("--barcodes expects a file"))),
+            "--splits" => args.splits = Some(PathBuf::from(iter.next().expect("--splits expects a file"))),
+            "--speed" => {
+                let percent = iter.next().and_then(|p| p.trim_end_matches('%').parse().ok());
+                args.speed = Some(percent.expect("--speed expects a percentage, like 75"));
+            }
+            "--fast-forward-audio" => {
+                let mode = iter.next().and_then(|name| StretchMode::from_name(&name));
+                args.fast_forward_audio = mode.expect("--fast-forward-audio expects stretch or skip");
+            }
+            "--idle-dim" => {
+                let minutes = iter.next().and_then(|m| m.parse().ok());
+                args.idle_dim = Some(minutes.expect("--idle-dim expects a number of minutes"));
+            }
+            "--idle-pause" => {
+                let minutes = iter.next().and_then(|m| m.parse().ok());
+                args.idle_pause = Some(minutes.expect("--idle-pause expects a number of minutes"));
+            }
+            "--flash-filter" => {
+                // the threshold is optional: the next argument may be the ROM
+                let percent = iter.peek().and_then(|t| t.trim_end_matches('%').parse::<f32>().ok());
+                if percent.is_some() {
+                    iter.next();
+                }
+                args.flash_filter = Some(percent.map_or(filters::DEFAULT_FLASH_THRESHOLD, |p| p / 100.0));
+            }
+            "--dip" => {
+                let dip = iter.next().and_then(|d| u8::from_str_radix(d.trim_start_matches('$'), 16).ok());
+                args.dip_switches = dip.expect("--dip expects the 8 switches as a hex byte, switch 1 in bit 0");
+            }
+            "--watchdog-kill" => {
+                let seconds = iter.next().and_then(|s| s.parse().ok());
+                args.watchdog_kill = Some(seconds.expect("--watchdog-kill expects a number of seconds"));
+            }
+            "--palette" => args.palette = Some(PathBuf::from(iter.next().expect("--palette expects a .pal file"))),
+            "--input-config" => args.input_config = PathBuf::from(iter.next().expect("--input-config expects a file")),
+            "--romdb" => args.romdb = Some(PathBuf::from(iter.next().expect("--romdb expects a file"))),
+            "--play" => args.play = Some(PathBuf::from(iter.next().expect("--play expects a movie file"))),
+            "--spectators" => args.spectators = Some(iter.next().expect("--spectators expects an address like 0.0.0.0:6503")),
+            "--spectate" => args.spectate = Some(iter.next().expect("--spectate expects the host's address, like host:6503")),
+            _ => args.rom_path = arg,
+        }
+    }
+    args
+}
+
+// "100..200" (frames 100 to 199) or "100..=200"
+fn parse_frame_range(text: &str) -> Option<Range<u64>> {
+    let (start, end) = text.split_once("..")?;
+    let start = start.parse().ok()?;
+    let end = match end.strip_prefix('=') {
+        Some(last) => last.parse::<u64>().ok()? + 1,
+        None => end.parse().ok()?,
+    };
+    Some(start..end)
+}
+
+// Like nes.run_frame(), but if the emulator panics the last instructions executed are
+// printed first, to show how the CPU got there.
+fn run_frame_reporting_crashes(nes: &mut Nes) {
+    if let Err(cause) = panic::catch_unwind(AssertUnwindSafe(|| nes.run_frame())) {
+        eprintln!("Emulator crashed. Last {} instructions:", nes.history().len());
+        eprint!("{}", nes.history().report());
+        panic::resume_unwind(cause);
+    }
+}
+
+fn report_jam(nes: &Nes) {
+    status!("CPU jammed (KIL opcode). Last {} instructions:", nes.history().len());
+    status!("{}", nes.history().report().trim_end());
+}
+
+// Battery-backed RAM is written when the emulator closes, like a real cartridge being switched off.
+// So is the movie, if one is being recorded.
+fn save_and_quit(nes: &mut Nes, sav_path: &Path, args: &Args) -> ! {
+    if let Err(err) = sram::save(&STORAGE, nes, sav_path) {
+        status!("Could not write {}: {}", sav_path.display(), err);
+    }
+    if let (Some(path), Some(turbo_file)) = (&args.turbo_file, nes.turbo_file()) {
+        if let Err(err) = STORAGE.write(path, turbo_file.data()) {
+            status!("Could not write {}: {}", path.display(), err);
+        }
+    }
+    if let Some(recorder) = nes.stop_audio_recording() {
+        if let Err(err) = recorder.finish() {
+            status!("Could not finish the sound recording: {}", err);
+        }
+    }
+    if let (Some(path), Some(movie)) = (args.record.as_deref(), nes.recording()) {
+        match std::fs::write(path, movie.to_text()) {
+            Ok(()) => status!("Wrote movie to {} ({} input changes)", path.display(), movie.events().len()),
+            Err(err) => status!("Could not write {}: {}", path.display(), err),
+        }
+    }
+    std::process::exit(0)
+}
+
+// The loop stopped responding for a while and got going again (see runesco::watchdog): logs
+// where the game was, then holds emulation and asks what to do.
+fn report_stall(nes: &Nes, stall: &Stall, autosave: Option<&(SaveState, u64)>, osd: &OsdMessages) {
+    let seconds = stall.duration.as_secs_f32();
+    status!("Stalled for {:.1}s {}, at frame {}. Last {} instructions:", seconds, stall.activity, nes.frame_count(), nes.history().len());
+    status!("{}", nes.history().report().trim_end());
+    let restore = match autosave {
+        Some((_, frame)) => format!(", R: back to the autosave of frame {}", frame),
+        None => String::new(),
+    };
+    osd.clear();
+    osd.show(
+        &format!("Stalled for {:.0}s {}. Enter: carry on{}, Esc: quit", seconds, stall.activity, restore),
+        Level::Warning,
+        u32::MAX,
+    );
+}
+
+fn read_state(path: &str) -> SaveState {
+    savestate::read_file(&STORAGE, Path::new(path)).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// The frontend's state for the game being played that actions read and change, the same
+// whichever control surface the action came from.
+struct Controls {
+    rom_path: String,
+    speed: SpeedControl,
+    speed_path: PathBuf,
+    cards: Vec<Card>, // --barcodes
+    next_card: usize,
+    volume: VolumeControl,
+    volume_path: PathBuf, // the game's; the master volume's is volume::MASTER_PATH
+    timer: Option<Rc<RefCell<SpeedrunTimer>>>, // --splits
+}
+
+impl Controls {
+    // `speed` is --speed, which wins over the speed saved for the game.
+    fn new(rom_path: &str, speed: Option<u32>) -> Self {
+        let speed_path = speed::speed_path(rom_path);
+        let speed = SpeedControl::new(load_speed(&speed_path, speed));
+        let volume_path = volume::game_path(rom_path);
+        let volume = VolumeControl::new(load_volume(Path::new(volume::MASTER_PATH)), load_volume(&volume_path));
+        Controls {
+            rom_path: rom_path.to_string(),
+            speed,
+            speed_path,
+            cards: vec![],
+            next_card: 0,
+            volume,
+            volume_path,
+            timer: None,
+        }
+    }
+}
+
+// Performs an action, whether it came from a hotkey, the console or the remote (see
+// runesco::action). Ok is a message for the player, empty if there's nothing to say.
+fn perform(nes: &mut Nes, controls: &mut Controls, action: &Action) -> Result<String, String> {
+    match action {
+        Action::SaveState(slot) => {
+            let path = action::state_path(&controls.rom_path, *slot);
+            savestate::write_file(&STORAGE, &path, &nes.save_state())
+                .map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+            Ok(format!("Saved state {} to {}", slot, path.display()))
+        }
+        Action::LoadState(slot) => {
+            let path = action::state_path(&controls.rom_path, *slot);
+            let state = savestate::read_file(&STORAGE, &path).map_err(|err| format!("Could not load {}", err))?;
+            nes.load_state(&state).map_err(|err| format!("Could not load {}: {}", path.display(), err))?;
+            Ok(format!("Loaded state {} from {}", slot, path.display()))
+        }
+        Action::ToggleTurbo(player, button) => {
+            let on = nes.toggle_turbo(*player, *button);
+            Ok(format!("{}: {}", action, if on { "on" } else { "off" }))
+        }
+        Action::Screenshot => {
+            let path = Path::new(&controls.rom_path).with_extension(format!("{}.png", nes.frame_count()));
+            nes.frame().write_png(&path).map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+            Ok(format!("Wrote {}", path.display()))
+        }
+        Action::ToggleAudioRecording => match nes.stop_audio_recording() {
+            Some(recorder) => {
+                let seconds = recorder.seconds();
+                recorder.finish().map_err(|err| format!("Could not finish the recording: {}", err))?;
+                Ok(format!("Recorded {:.1}s of sound", seconds))
+            }
+            None => {
+                let path = Path::new(&controls.rom_path).with_extension(format!("{}.wav", nes.frame_count()));
+                let recorder = AudioRecorder::create(&path).map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+                nes.start_audio_recording(recorder);
+                Ok(format!("Recording sound to {}", path.display()))
+            }
+        },
+        Action::SetSpeed(percent) => {
+            let percent = controls.speed.set(*percent);
+            Ok(save_speed(controls, percent))
+        }
+        Action::AdjustSpeed(steps) => {
+            let percent = controls.speed.adjust(*steps);
+            Ok(save_speed(controls, percent))
+        }
+        Action::SetVolume(which, percent) => {
+            let percent = controls.volume.set(*which, *percent);
+            Ok(save_volume(nes, controls, *which, percent))
+        }
+        Action::AdjustVolume(which, steps) => {
+            let percent = controls.volume.adjust(*which, *steps);
+            Ok(save_volume(nes, controls, *which, percent))
+        }
+        Action::ToggleMasterMute => {
+            let muted = controls.volume.toggle_mute();
+            nes.set_master_volume(controls.volume.gain());
+            Ok(if muted { "Sound muted" } else { "Sound on" }.to_string())
+        }
+        Action::ToggleOverlay(name) => {
+            let on = !nes.compositor().is_enabled(name);
+            if !nes.compositor().set_enabled(name, on) {
+                return Err(format!("No overlay called {}", name));
+            }
+            Ok(String::new())
+        }
+        Action::ToggleMute(channel) => {
+            let muted = !nes.channel_muted(*channel);
+            nes.set_channel_muted(*channel, muted);
+            Ok(format!("{}: {}", channel.name(), if muted { "muted" } else { "on" }))
+        }
+        Action::Solo(channel) => {
+            nes.solo_channel(*channel);
+            if Channel::ALL.iter().any(|other| nes.channel_muted(*other)) {
+                Ok(format!("Only {}", channel.name()))
+            } else {
+                Ok("All channels on".to_string())
+            }
+        }
+        Action::UnmuteAll => {
+            for channel in Channel::ALL {
+                nes.set_channel_muted(channel, false);
+            }
+            Ok("All channels on".to_string())
+        }
+        Action::Barcode(digits) => {
+            nes.scan_barcode(digits)?;
+            Ok(format!("Swiped {}", digits))
+        }
+        Action::NextBarcode => {
+            if controls.cards.is_empty() {
+                return Err("No cards to swipe: give a list with --barcodes".to_string());
+            }
+            // next card, back to the first after the last
+            let card = &controls.cards[controls.next_card % controls.cards.len()];
+            nes.scan_barcode(&card.code)?;
+            controls.next_card = (controls.next_card + 1) % controls.cards.len();
+            Ok(format!("Swiped {}", card.name))
+        }
+        Action::ResetTimer => {
+            let timer = controls.timer.as_ref().ok_or("No timer: give the splits with --splits")?;
+            timer.borrow_mut().reset();
+            Ok("Timer reset".to_string())
+        }
+        Action::ExportSplits => {
+            let timer = controls.timer.as_ref().ok_or("No timer: give the splits with --splits")?;
+            let path = Path::new(&controls.rom_path).with_extension("lss");
+            let game = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+            STORAGE
+                .write(&path, timer.borrow().to_livesplit(&game).as_bytes())
+                .map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+            Ok(format!("Wrote {}", path.display()))
+        }
+        Action::Reset => {
+            nes.reset();
+            Ok(String::new())
+        }
+        Action::Power => {
+            nes.power_cycle();
+            Ok("Switched off and on again".to_string())
+        }
+        Action::NextDisk => {
+            if nes.disk_sides() == 0 {
+                return Err("This game has no disks".to_string());
+            }
+            // next side, back to the first after the last
+            let side = nes.inserted_disk().map_or(0, |side| (side + 1) % nes.disk_sides());
+            nes.insert_disk(Some(side));
+            Ok(format!("Inserted {}", disk_side_name(side)))
+        }
+        Action::ExportDumps => {
+            export_dumps(nes, &controls.rom_path);
+            Ok(String::new())
+        }
+        Action::ImportDumps => {
+            import_dumps(nes, &controls.rom_path);
+            Ok(String::new())
+        }
+    }
+}
+
+// Remembered for the game, see runesco::speed.
+fn save_speed(controls: &Controls, percent: u32) -> String {
+    if let Err(err) = speed::save(&STORAGE, &controls.speed_path, percent) {
+        println!("Could not write {}: {}", controls.speed_path.display(), err);
+    }
+    format!("Speed {}%", percent)
+}
+
+// The master volume for every game, the game's for this one; see runesco::volume.
+fn save_volume(nes: &mut Nes, controls: &Controls, which: Volume, percent: u32) -> String {
+    nes.set_master_volume(controls.volume.gain());
+    let path = match which {
+        Volume::Master => Path::new(volume::MASTER_PATH),
+        Volume::Game => controls.volume_path.as_path(),
+    };
+    if let Err(err) = volume::save(&STORAGE, path, percent) {
+        println!("Could not write {}: {}", path.display(), err);
+    }
+    match which {
+        Volume::Master => format!("Volume {}%", percent),
+        Volume::Game => format!("Game volume {}%", percent),
+    }
+}
+
+// The window's hotkeys, apart from the debugger's and the setup wizard's: each one is an
+// action, so everything they do can be done from the console and remote too.
+fn hotkey(keycode: Keycode) -> Option<Action> {
+    let action = match keycode {
+        Keycode::F2 => Action::ToggleOverlay("sprite0".to_string()),
+        Keycode::F3 => Action::ToggleOverlay("ruler".to_string()),
+        Keycode::H => Action::ToggleOverlay("heatmap".to_string()),
+        Keycode::V => Action::ToggleOverlay("apu".to_string()),
+        Keycode::F4 => Action::ExportDumps,
+        Keycode::F7 => Action::ImportDumps,
+        Keycode::F8 => Action::Reset,
+        Keycode::F10 => Action::NextDisk,
+        Keycode::Home => Action::NextBarcode,
+        Keycode::End => Action::ResetTimer,
+        Keycode::F11 => Action::SaveState(0),
+        Keycode::F12 => Action::LoadState(0),
+        Keycode::PrintScreen => Action::Screenshot,
+        Keycode::Insert => Action::ToggleAudioRecording,
+        // game speed, for players who need more time; see speed.rs
+        Keycode::Minus => Action::AdjustSpeed(-1),
+        Keycode::Equals => Action::AdjustSpeed(1),
+        // the sound channels on the keypad, in the order of Channel::ALL; 0 brings them all back
+        Keycode::Kp1 => Action::ToggleMute(Channel::Pulse1),
+        Keycode::Kp2 => Action::ToggleMute(Channel::Pulse2),
+        Keycode::Kp3 => Action::ToggleMute(Channel::Triangle),
+        Keycode::Kp4 => Action::ToggleMute(Channel::Noise),
+        Keycode::Kp5 => Action::ToggleMute(Channel::Dmc),
+        Keycode::Kp6 => Action::ToggleMute(Channel::Cartridge),
+        Keycode::Kp0 => Action::UnmuteAll,
+        // the volume, around the keypad's digits: +/- for all games, * and / for this one
+        Keycode::KpPlus => Action::AdjustVolume(Volume::Master, 1),
+        Keycode::KpMinus => Action::AdjustVolume(Volume::Master, -1),
+        Keycode::KpMultiply => Action::AdjustVolume(Volume::Game, 1),
+        Keycode::KpDivide => Action::AdjustVolume(Volume::Game, -1),
+        Keycode::KpPeriod => Action::ToggleMasterMute,
+        _ => return None,
+    };
+    Some(action)
+}
+
+// F4/F7: every region to/from game.ram.bin, game.vram.bin, ... Missing files are skipped on import.
+fn export_dumps(nes: &Nes, rom_path: &str) {
+    for region in dumps::ALL {
+        let path = dumps::dump_path(rom_path, region);
+        match dumps::export(nes, region, &path) {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(err) => println!("Could not write {}: {}", path.display(), err),
+        }
+    }
+}
+
+fn import_dumps(nes: &mut Nes, rom_path: &str) {
+    for region in dumps::ALL {
+        let path = dumps::dump_path(rom_path, region);
+        if !path.exists() {
+            continue;
+        }
+        match dumps::import(nes, region, &path) {
+            Ok(()) => println!("Loaded {}", path.display()),
+            Err(err) => println!("Could not load {}: {}", path.display(), err),
+        }
+    }
+}
+
+fn run_headless(nes: &mut Nes, frames: u64, args: &Args, title: Option<&str>) {
+    let started = Instant::now();
+    if args.coverage {
+        nes.start_coverage();
+    }
+    let mut frame_hashes = vec![];
+    for _ in 0..frames {
+        run_frame_reporting_crashes(nes);
+        if args.json {
+            frame_hashes.push(format!("{:08X}", crc32fast::hash(&nes.frame().data)));
+        }
+        if nes.is_jammed() {
+            report_jam(nes);
+            break;
+        }
+    }
+    if !args.json {
+        println!("Ran {} frames ({} CPU cycles)", nes.frame_count(), nes.cpu().bus.cycles());
+        if let Some(coverage) = nes.coverage() {
+            print!("{}", coverage.report());
+        }
+        return;
+    }
+
+    // blargg-style test ROMs report their result in PRG-RAM, see runesco::test_status
+    let test_status = TestStatus::read(nes.prg_ram()).map(|status| {
+        Json::object()
+            .with("code", status.code)
+            .with("running", status.running())
+            .with("passed", status.passed())
+            .with("text", status.text)
+    });
+    let seconds = started.elapsed().as_secs_f64();
+    let result = Json::object()
+        .with("rom", args.rom_path.as_str())
+        .with("sha1", nes.rom_sha1())
+        .with("title", title)
+        .with("frames", nes.frame_count())
+        .with("cpu_cycles", nes.cpu().bus.cycles())
+        .with("jammed", nes.is_jammed())
+        .with("frame_hashes", frame_hashes) // CRC32 of each frame's RGB24 picture
+        .with("test_status", test_status)
+        .with("coverage", nes.coverage().map(|coverage| coverage.to_json()))
+        .with("timing", Json::object().with("seconds", seconds).with("fps", nes.frame_count() as f64 / seconds));
+    println!("{}", result);
+}
+
+// The console hands actions back to be performed here, like the window's hotkeys.
+fn perform_outcome(nes: &mut Nes, controls: &mut Controls, outcome: Outcome) -> Result<Outcome, String> {
+    match outcome {
+        Outcome::Action(action) => perform(nes, controls, &action).map(Outcome::Output),
+        other => Ok(other),
+    }
+}
+
+// Headless, driven by commands on stdin (see runesco::console). stdin is read on its own
+// thread, so emulation keeps running between commands after a 'resume'. Closing stdin quits.
+fn run_console(nes: &mut Nes, controls: &mut Controls) {
+    let (lines, commands) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut console = Console::new();
+    println!("Paused at power-on, type 'help' for commands");
+    loop {
+        let line = if console.paused() {
+            match commands.recv() {
+                Ok(line) => line,
+                Err(_) => return,
+            }
+        } else {
+            match commands.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => {
+                    run_frame_reporting_crashes(nes);
+                    if nes.is_jammed() {
+                        report_jam(nes);
+                        console.set_paused(true);
+                    } else if let Some(reason) = nes.break_reason() {
+                        println!("Paused: {}", reason);
+                        console.set_paused(true);
+                    }
+                    continue;
+                }
+            }
+        };
+
+        match console.execute(nes, &line).and_then(|outcome| perform_outcome(nes, controls, outcome)) {
+            Ok(Outcome::Output(text)) if text.is_empty() => {}
+            Ok(Outcome::Output(text)) => println!("{}", text),
+            Ok(Outcome::Action(_)) => {}
+            Ok(Outcome::Quit) => return,
+            Err(err) => println!("error: {}", err),
+        }
+    }
+}
+
+// Headless, driven by a debugger connected over TCP (see runesco::remote). Same commands as
+// --console; when the connection closes the emulator pauses and waits for the next one.
+fn run_remote(nes: &mut Nes, controls: &mut Controls, addr: &str) {
+    let mut server = RemoteServer::bind(addr).unwrap_or_else(|err| panic!("could not listen on {}: {}", addr, err));
+    println!("Waiting for a debugger on {}, paused at power-on", server.local_addr().unwrap());
+    let mut console = Console::new();
+    loop {
+        let was_connected = server.connected();
+        if let Some(line) = server.poll() {
+            let result = console.execute(nes, &line).and_then(|outcome| perform_outcome(nes, controls, outcome));
+            server.reply(&result);
+            if let Ok(Outcome::Quit) = result {
+                return;
+            }
+            continue;
+        }
+        if was_connected && !server.connected() {
+            console.set_paused(true);
+        }
+        if console.paused() {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            continue;
+        }
+
+        run_frame_reporting_crashes(nes);
+        if nes.is_jammed() {
+            report_jam(nes);
+            server.notify("stopped: CPU jammed");
+            console.set_paused(true);
+        } else if let Some(reason) = nes.break_reason() {
+            server.notify(&format!("stopped {}", reason));
+            console.set_paused(true);
+        }
+    }
+}
+
+// Runs without a window up to the end of the range, writing each frame in it as
+// dir/frame_00042.png. Frames are counted from 0, the first one after power-on.
+fn dump_frames(nes: &mut Nes, frames: Range<u64>, dir: &Path) {
+    std::fs::create_dir_all(dir).unwrap_or_else(|err| panic!("could not create {}: {}", dir.display(), err));
+    while nes.frame_count() < frames.end {
+        let index = nes.frame_count();
+        run_frame_reporting_crashes(nes);
+        if nes.is_jammed() {
+            report_jam(nes);
+            break;
+        }
+        if frames.contains(&index) {
+            let path = dir.join(format!("frame_{:05}.png", index));
+            nes.frame().write_png(&path).unwrap_or_else(|err| panic!("could not write {}: {}", path.display(), err));
+        }
+    }
+    println!("Wrote frames {}..{} to {}", frames.start, nes.frame_count().min(frames.end), dir.display());
+}
+
+// The game's saved speed, unless `requested` overrides it; full speed if neither is there.
+fn load_speed(path: &Path, requested: Option<u32>) -> u32 {
+    let saved = speed::load(&STORAGE, path).unwrap_or_else(|err| {
+        println!("Could not read {}: {}", path.display(), err);
+        None
+    });
+    requested.or(saved).unwrap_or(speed::MAX_PERCENT)
+}
+
+fn load_volume(path: &Path) -> u32 {
+    let saved = volume::load(&STORAGE, path).unwrap_or_else(|err| {
+        println!("Could not read {}: {}", path.display(), err);
+        None
+    });
+    saved.unwrap_or(volume::MAX_PERCENT)
+}
+
+// Presses or releases whatever NES button `binding` is mapped to.
+fn press(joypads: (&mut Joypad, &mut Joypad), config: &InputConfig, binding: Binding, pressed: bool) {
+    if let Some((player, button)) = config.lookup(&binding) {
+        let joypad = if player == 0 { joypads.0 } else { joypads.1 };
+        joypad.auto_hold = config.auto_hold[player];
+        joypad.set_button_pressed_status(button, pressed);
+    }
+}
+
+// Remembers how the window and the picture were left, for the next run (see
+// runesco::video_config). Only the file's [video] section is rewritten, and only if something
+// in it changed: the controls are left as they are, or to their defaults without a file.
+fn save_video(config: &mut InputConfig, window: &Window, nes: &mut Nes, args: &Args) {
+    let mut video = config.video.clone();
+    video.fullscreen = window.fullscreen_state() != FullscreenType::Off;
+    if !video.fullscreen {
+        let ((x, y), (width, height)) = (window.position(), window.size());
+        video.window = Some(WindowGeometry { x, y, width, height });
+    }
+    let filters = nes.compositor().filters();
+    video.filters = Some(filters.iter().filter(|(_, on)| *on).map(|(name, _)| name.to_string()).collect());
+    if args.palette.is_some() {
+        video.palette = args.palette.clone();
+    }
+    if video == config.video {
+        return;
+    }
+    if let Err(err) = InputConfig::save_video(&STORAGE, &args.input_config, &video) {
+        status!("Could not save the window settings: {}", err);
+    }
+    config.video = video;
+}
+
+// Hands a key or button press to the setup wizard; once it has every button, the new
+// controls take over and are saved.
+fn answer_wizard(wizard: &mut Option<Wizard>, binding: Binding, config: &mut InputConfig, path: &Path, osd: &OsdMessages) {
+    let Some(asking) = wizard.as_mut() else { return };
+    osd.clear();
+    if let Err(err) = asking.press(binding) {
+        osd.warning(&err);
+    }
+    if !asking.finished() {
+        osd.show(&asking.prompt(), Level::Info, u32::MAX);
+        return;
+    }
+    // the wizard only asks for bindings; auto-hold and the video settings stay as they were
+    let (auto_hold, video) = (config.auto_hold, config.video.clone());
+    *config = wizard.take().unwrap().into_config();
+    config.auto_hold = auto_hold;
+    config.video = video;
+    match config.save(&STORAGE, path) {
+        Ok(()) => osd.info(&format!("Controls saved to {}", path.display())),
+        Err(err) => osd.warning(&format!("Controls not saved: {}", err)),
+    }
+}
+
+// Disk System games run on the BIOS from --fds-bios, or disksys.rom next to the game or in the
+// current directory.
+fn load_fds_bios(rom: &mut Rom, rom_path: &Path, bios_path: Option<&Path>) -> Result<(), RunescoError> {
+    let candidates = match bios_path {
+        Some(path) => vec![path.to_path_buf()],
+        None => vec![rom_path.with_file_name("disksys.rom"), PathBuf::from("disksys.rom")],
+    };
+    for path in &candidates {
+        if let Ok(bios) = std::fs::read(path) {
+            if bios.len() != fds::BIOS_SIZE {
+                let msg = format!("{} is not the Disk System BIOS (it should be 8KiB)", path.display());
+                return Err(RunescoError::MissingBios(msg));
+            }
+            rom.prg_rom = bios;
+            return Ok(());
+        }
+    }
+    Err(RunescoError::MissingBios(
+        "Disk System games need the Disk System BIOS: put disksys.rom next to the game or use --fds-bios".to_string(),
+    ))
+}
+
+// "disk 1 side A" for side 0
+fn disk_side_name(side: usize) -> String {
+    format!("disk {} side {}", side / 2 + 1, if side & 1 == 0 { 'A' } else { 'B' })
+}
+
+// Looks the ROM up in the database: fixes its header if it's a known bad one, and returns the
+// game's title if there is one.
+fn identify(rom: &mut Rom, extra_db: Option<&Path>) -> Option<String> {
+    let mut db = Database::bundled();
+    if let Some(path) = extra_db {
+        let text = std::fs::read_to_string(path).unwrap_or_else(|err| panic!("could not read {}: {}", path.display(), err));
+        db.extend(Database::parse(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err)));
+    }
+
+    status!("ROM CRC32 {:08X}, SHA-1 {}", rom.crc32(), rom.sha1());
+    match rom.region {
+        Region::Ntsc => {}
+        Region::Pal => status!("PAL cartridge: it will run too fast, only NTSC timing is emulated"),
+        Region::Dual => status!("NTSC/PAL cartridge"),
+    }
+    let entry = db.lookup(rom)?.clone();
+    status!("Identified as {}", entry.title);
+    for change in romdb::apply(&entry, rom) {
+        status!("  header corrected: {}", change);
+    }
+    Some(entry.title)
+}
+
+// Gets the game image loaded from `rom_path` ready to run. Returns the cartridge, the game's
+// title if the database knows it, and the warning to show if it runs on a fallback mapper.
+fn prepare_rom(args: &Args, rom_path: &str, image: &[u8]) -> Result<(Rom, Option<String>, Option<String>), RunescoError> {
+    let image = &mut image.to_vec();
+    for warning in cartridge::check_header(image) {
+        status!("Header: {}", warning);
+    }
+    if args.repair_header && cartridge::repair_header(image) {
+        status!("Header repaired: bytes 7-15 cleared");
+    }
+    let mut rom = Rom::new(image)?;
+    let title = identify(&mut rom, args.romdb.as_deref());
+    if rom.mapper == fds::MAPPER && rom.prg_rom.is_empty() {
+        load_fds_bios(&mut rom, Path::new(rom_path), args.fds_bios.as_deref())?;
+    }
+    let fallback_warning = if args.mapper_fallback { mapper::substitute(&mut rom) } else { None };
+    Ok((rom, title, fallback_warning))
+}
+
+// A game file dropped on the window replaces the running one, like swapping cartridges with
+// the power off. Returns the image (for --watch) and the game's title; on error the old game
+// keeps running.
+fn switch_game(
+    nes: &mut Nes,
+    args: &Args,
+    rom_path: &str,
+    saved_palette: Option<&Path>,
+    osd: &OsdMessages,
+) -> Result<(Vec<u8>, Option<String>), RunescoError> {
+    let image = archive::read_image(rom_path)?;
+    let (rom, title, fallback_warning) = prepare_rom(args, rom_path, &image)?;
+    nes.insert_cartridge(rom)?;
+    osd.clear();
+    set_up_cabinet(nes, args, rom_path, saved_palette, osd);
+    load_cheats(nes, rom_path, osd);
+    osd.info(&format!("Loaded {}", title.as_deref().unwrap_or(rom_path)));
+    if let Some(warning) = &fallback_warning {
+        println!("Warning: {}", warning);
+        osd.warning(warning);
+    }
+    Ok((image, title))
+}
+
+// Vs. System games: sets the cabinet's DIP switches and looks for the game's palette, which
+// --palette names or sits next to the ROM (game.nes -> game.pal). Other games get --palette,
+// the palette saved from the last run, or the console's colours.
+fn set_up_cabinet(nes: &mut Nes, args: &Args, rom_path: &str, saved_palette: Option<&Path>, osd: &OsdMessages) {
+    let vs_system = match nes.vs_panel() {
+        Some(panel) => {
+            panel.dip_switches = args.dip_switches;
+            status!("Vs. System game: 5 and 6 insert coins, 9 is the service button");
+            true
+        }
+        None => false,
+    };
+    let beside_rom = Path::new(rom_path).with_extension("pal");
+    let path = match &args.palette {
+        Some(path) => Some(path.clone()),
+        None if vs_system && beside_rom.exists() => Some(beside_rom),
+        None => saved_palette.map(Path::to_path_buf),
+    };
+    let colors = match path {
+        Some(path) => match std::fs::read(&path).map_err(|err| err.to_string()).and_then(|raw| palette::from_pal_file(&raw)) {
+            Ok(colors) => colors,
+            Err(err) => {
+                status!("Could not read {}: {}", path.display(), err);
+                osd.warning(&format!("Could not read the palette {}", path.display()));
+                palette::SYSTEM_PALLETE
+            }
+        },
+        None => {
+            if vs_system {
+                osd.warning("Vs. System game: colours may be wrong without its palette (--palette)");
+            }
+            palette::SYSTEM_PALLETE
+        }
+    };
+    nes.compositor().set_colors(colors);
+}
+
+// The game's cheat codes, from game.cht next to the ROM if there is one (see runesco::cheats).
+fn load_cheats(nes: &mut Nes, rom_path: &str, osd: &OsdMessages) {
+    let path = cheats::cht_path(rom_path);
+    let text = match STORAGE.read(&path) {
+        Ok(Some(data)) => String::from_utf8_lossy(&data).into_owned(),
+        Ok(None) => return,
+        Err(err) => {
+            status!("Could not read {}: {}", path.display(), err);
+            return;
+        }
+    };
+    match Cheats::parse(&text) {
+        Ok(cheats) => {
+            let on = cheats.list().iter().filter(|cheat| cheat.enabled).count();
+            status!("Loaded {} cheats from {}, {} on", cheats.list().len(), path.display(), on);
+            if on > 0 {
+                osd.info(&format!("{} cheats on", on));
+            }
+            for (a, b) in cheats.conflicts() {
+                status!("Cheats {} and {} both change {:04X}, only one of them will work", a.code, b.code, a.addr);
+                osd.warning(&format!("Cheats {} and {} conflict", a.code, b.code));
+            }
+            *nes.cheats_mut() = cheats;
+        }
+        Err(err) => {
+            status!("{}: {}", path.display(), err);
+            osd.warning(&format!("Could not load the cheats in {}", path.display()));
+        }
+    }
+}
+
+// The Turbo File kept in `path`; a new one starts out empty. None if the file can't be read.
+fn load_turbo_file(path: &Path) -> Option<TurboFile> {
+    let mut turbo_file = TurboFile::new();
+    let loaded = STORAGE.read(path).map_err(|err| err.to_string()).and_then(|data| match data {
+        Some(data) => turbo_file.load(&data),
+        None => Ok(()),
+    });
+    match loaded {
+        Ok(()) => Some(turbo_file),
+        Err(err) => {
+            status!("Could not read {}: {}, no Turbo File plugged in", path.display(), err);
+            None
+        }
+    }
+}
+
+// `--input-test`: the controller test screen (see runesco::input_test) instead of a game, with
+// the controls, the microphone and the Turbo File set up as they would be for one. No ROM is
+// needed, and nothing is saved: the Turbo File is only read. Escape quits.
+fn run_input_test(args: &Args) -> ! {
+    let mut input_test = InputTest::new();
+    if let Some(turbo_file) = args.turbo_file.as_deref().and_then(load_turbo_file) {
+        input_test.plug(Box::new(turbo_file));
+    }
+    let input_config = InputConfig::load(&STORAGE, &args.input_config)
+        .unwrap_or_else(|err| {
+            status!("Using the default controls: {}", err);
+            None
+        })
+        .unwrap_or_default();
+
+    let sdl_context = sdl2::init().unwrap();
+    let mic = args.mic.and_then(|threshold| {
+        open_mic(&sdl_context, threshold).map_err(|err| status!("No microphone: {}", err)).ok()
+    });
+    let mut mic_key = false;
+    // a controller only sends events once it's open
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    let _controllers: Vec<_> = (0..controller_subsystem.num_joysticks().unwrap())
+        .filter(|&i| controller_subsystem.is_game_controller(i))
+        .filter_map(|i| controller_subsystem.open(i).ok())
+        .collect();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem.window("runesco: input test", 256 * 3, 240 * 3).position_centered().build().unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    let creator = canvas.texture_creator();
+    let mut texture = creator.create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let mut frame = Frame::new();
+    let mut pacer = FramePacer::new();
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => std::process::exit(0),
+                Event::KeyDown { keycode: Some(Keycode::M), .. } => mic_key = true,
+                Event::KeyUp { keycode: Some(Keycode::M), .. } => mic_key = false,
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    press(input_test.joypads(), &input_config, Binding::Key(keycode.name()), true);
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    press(input_test.joypads(), &input_config, Binding::Key(keycode.name()), false);
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    press(input_test.joypads(), &input_config, Binding::Pad(button.string()), true);
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    press(input_test.joypads(), &input_config, Binding::Pad(button.string()), false);
+                }
+                _ => {}
+            }
+        }
+        let loud = mic_key || mic.as_ref().is_some_and(|(_, loud)| loud.load(Ordering::Relaxed));
+        input_test.joypads().1.set_microphone(loud);
+        input_test.poll();
+        input_test.draw(&mut frame);
+        texture.update(None, &frame.data, 256 * 3).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+        pacer.wait(None);
+    }
+}
+
+fn open_audio(sdl_context: &sdl2::Sdl) -> Result<AudioQueue<f32>, String> {
+    let spec = AudioSpecDesired { freq: Some(audio::SAMPLE_RATE as i32), channels: Some(1), samples: Some(AUDIO_BLOCK) };
+    let queue = sdl_context.audio()?.open_queue::<f32, _>(None, &spec)?;
+    queue.resume();
+    Ok(queue)
+}
+
+// Moves what the APU produced since the last call to the audio device.
+fn play_audio(nes: &mut Nes, queue: &AudioQueue<f32>) {
+    let mut samples = vec![0.0; nes.audio().len()];
+    nes.audio().pop_into(&mut samples);
+    if queued_samples(queue) < MAX_QUEUED_AUDIO && !queue.queue(&samples) {
+        status!("Could not queue audio: {}", sdl2::get_error());
+    }
+}
+
+fn queued_samples(queue: &AudioQueue<f32>) -> u32 {
+    queue.size() / std::mem::size_of::<f32>() as u32
+}
+
+// The Famicom microphone from a real one. The console only has a comparator on it, so all the
+// game gets is whether the input's peak is over the threshold. SDL calls this on its audio
+// thread; the answer crosses over to the game loop in an atomic.
+struct MicCapture {
+    threshold: f32,
+    loud: Arc<AtomicBool>,
+}
+
+impl AudioCallback for MicCapture {
+    type Channel = f32;
+
+    fn callback(&mut self, input: &mut [f32]) {
+        let peak = input.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        self.loud.store(peak > self.threshold, Ordering::Relaxed);
+    }
+}
+
+fn open_mic(sdl_context: &sdl2::Sdl, threshold: f32) -> Result<(AudioDevice<MicCapture>, Arc<AtomicBool>), String> {
+    let spec = AudioSpecDesired { freq: Some(audio::SAMPLE_RATE as i32), channels: Some(1), samples: Some(AUDIO_BLOCK) };
+    let loud = Arc::new(AtomicBool::new(false));
+    let capture = MicCapture { threshold, loud: loud.clone() };
+    let device = sdl_context.audio()?.open_capture(None, &spec, |_| capture)?;
+    device.resume();
+    Ok((device, loud))
+}
+
+fn window_title(title: &Option<String>) -> String {
+    match title {
+        Some(title) => format!("runesco: {}", title),
+        None => "runesco: Rust NES Co-Op".to_string(),
+    }
+}
+
+// The game can't be started: says why on stderr, and in a message box too when the user
+// expects a window (and may not be looking at a terminal).
+fn exit_with_error(err: &RunescoError, args: &Args) -> ! {
+    let mut msg = err.to_string();
+    if let RunescoError::UnsupportedMapper(id) = err {
+        if let Some((to, _)) = mapper::fallback(*id) {
+            msg.push_str(&format!(" (--mapper-fallback would try it as mapper {})", to));
+        }
+    }
+    eprintln!("{}", msg);
+
+    if args.windowed() {
+        let text = format!("Could not start {}:\n\n{}", args.rom_path, msg);
+        let _ = show_simple_message_box(MessageBoxFlag::ERROR, "runesco", &text, None);
+    }
+    std::process::exit(1);
+}
+
+// `runesco check game.nes [more.nes ...]`: prints what each game needs and what's missing
+// (see runesco::compat) instead of running it, as JSON with --json; --repair-header checks
+// them as the emulator would run them with it. Exits with 1 if any of them won't run.
+fn check_roms(args: &[String]) -> ! {
+    let json = args.iter().any(|arg| arg == "--json");
+    let repair = args.iter().any(|arg| arg == "--repair-header");
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+    let mut all_run = true;
+    let mut results = vec![];
+    for path in args.iter().filter(|arg| !arg.starts_with("--")) {
+        let checked = archive::read_image(path).and_then(|image| {
+            let mut repaired = image.clone();
+            if repair {
+                cartridge::repair_header(&mut repaired);
+            }
+            let mut rom = Rom::new(&repaired)?;
+            let title = identify(&mut rom, None);
+            Ok((compat::check(&image, &rom), rom, title))
+        });
+        all_run &= matches!(&checked, Ok((report, _, _)) if report.verdict() != Support::Missing);
+        match checked {
+            Ok((report, rom, title)) if json => results.push(
+                report
+                    .to_json()
+                    .with("path", path.as_str())
+                    .with("crc32", format!("{:08X}", rom.crc32()))
+                    .with("sha1", rom.sha1())
+                    .with("title", title),
+            ),
+            Err(err) if json => results.push(Json::object().with("path", path.as_str()).with("error", err.to_string())),
+            Ok((report, _, _)) => println!("{}\n{}", path, report),
+            Err(err) => println!("{}\n  {}\n", path, err),
+        }
+    }
+    if json {
+        println!("{}", Json::Array(results));
+    }
+    std::process::exit(if all_run { 0 } else { 1 });
+}
+
+// `runesco gallery <dir> [--frames 300] [--out <dir>]`: a picture of every game in the
+// directory after a few seconds' run, and an index.html of them all (see runesco::gallery),
+// in <dir>/gallery unless --out says otherwise. Exits with 1 if any of them didn't run.
+fn make_gallery(args: &[String]) -> ! {
+    let mut frames = gallery::FRAMES;
+    let mut dir = None;
+    let mut out = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--frames" => frames = iter.next().and_then(|n| n.parse().ok()).expect("--frames expects a number"),
+            "--out" => out = Some(PathBuf::from(iter.next().expect("--out expects a directory"))),
+            _ => dir = Some(PathBuf::from(arg)),
+        }
+    }
+    let Some(dir) = dir else {
+        eprintln!("usage: runesco gallery <dir> [--frames 300] [--out <dir>]");
+        std::process::exit(2);
+    };
+    let out = out.unwrap_or_else(|| dir.join("gallery"));
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("could not read {}: {}", dir.display(), err))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && gallery::is_game(path))
+        .collect();
+    paths.sort();
+    std::fs::create_dir_all(&out).unwrap_or_else(|err| panic!("could not create {}: {}", out.display(), err));
+
+    let mut entries = vec![];
+    for path in &paths {
+        let file = path.file_name().unwrap().to_string_lossy().into_owned();
+        println!("{}", file);
+        let mut entry = Entry { file, title: None, mapper: None, picture: None, outcome: gallery::Outcome::Ran };
+        let loaded = archive::read_image(path).and_then(|image| {
+            let mut rom = Rom::new(&image)?;
+            entry.title = identify(&mut rom, None);
+            entry.mapper = Some(rom.mapper);
+            if rom.mapper == fds::MAPPER && rom.prg_rom.is_empty() {
+                load_fds_bios(&mut rom, path, None)?;
+            }
+            Nes::from_rom(rom)
+        });
+        match loaded {
+            Ok(mut nes) => {
+                entry.outcome = gallery::run(&mut nes, frames);
+                let picture = Path::new(&entry.file).with_extension("png").to_string_lossy().into_owned();
+                match nes.frame().write_png(out.join(&picture)) {
+                    Ok(()) => entry.picture = Some(picture),
+                    Err(err) => println!("  could not write {}: {}", picture, err),
+                }
+            }
+            Err(err) => entry.outcome = gallery::Outcome::NotLoaded(err.to_string()),
+        }
+        println!("  {}", entry.outcome);
+        entries.push(entry);
+    }
+
+    let index = out.join("index.html");
+    std::fs::write(&index, gallery::index_html(&entries))
+        .unwrap_or_else(|err| panic!("could not write {}: {}", index.display(), err));
+    let ran = entries.iter().filter(|entry| entry.outcome.ran()).count();
+    println!("{} of {} games ran, gallery in {}", ran, entries.len(), index.display());
+    std::process::exit(if ran == entries.len() { 0 } else { 1 });
+}
+
 fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    match argv.get(1).map(String::as_str) {
+        Some("check") => check_roms(&argv[2..]),
+        Some("gallery") => make_gallery(&argv[2..]),
+        _ => {}
+    }
+    let args = parse_args();
+    JSON_OUTPUT.store(args.json, Ordering::Relaxed);
+    if args.input_test {
+        run_input_test(&args);
+    }
+    if let Some((a, b)) = &args.diff_states {
+        print!("{}", savestate::diff_report(&read_state(a), &read_state(b)));
+        return;
+    }
+
+    //load the game
+    let nes_file_data: Vec<u8> = archive::read_image(&args.rom_path).unwrap_or_else(|err| exit_with_error(&err, &args));
+    let (rom, title, fallback_warning) =
+        prepare_rom(&args, &args.rom_path, &nes_file_data).unwrap_or_else(|err| exit_with_error(&err, &args));
+    let mut nes = Nes::from_rom(rom).unwrap_or_else(|err| exit_with_error(&err, &args));
+    nes.set_dmc_read_conflict(args.dmc_conflict);
+    nes.set_open_bus(args.open_bus);
+    nes.set_bus_conflicts(args.bus_conflicts);
+    nes.set_audio_filters(args.audio_filters);
+    nes.set_fast_forward_audio(args.fast_forward_audio);
+    if let Some(percent) = args.cartridge_volume {
+        nes.set_source_volume(Source::Cartridge, percent as f32 / 100.0);
+    }
+    if let Some(turbo_file) = args.turbo_file.as_deref().and_then(load_turbo_file) {
+        nes.attach_turbo_file(Some(turbo_file));
+    }
+    for addr in &args.breakpoints {
+        nes.debugger().add_breakpoint(*addr);
+    }
+    for itype in &args.break_on {
+        nes.debugger().set_break_on_interrupt(*itype, true);
+    }
+    if let Some(addr) = args.run_to {
+        nes.run_to(addr);
+    }
+    if let Some(path) = &args.play {
+        let text = std::fs::read_to_string(path).unwrap_or_else(|err| panic!("could not read {}: {}", path.display(), err));
+        nes.play_movie(Movie::parse(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err)));
+    } else if args.record.is_some() || args.spectators.is_some() {
+        nes.record_movie(args.subframe); // spectators are sent the recording as it grows
+    }
+
+    let osd = Osd::new();
+    let osd_messages = osd.messages();
+    nes.compositor().add_overlay(Box::new(osd), true);
+    if let Some(warning) = &fallback_warning {
+        status!("Warning: {}", warning);
+        osd_messages.warning(warning);
+    }
+    // the controls (player 1 on the keyboard and player 2 on the controller unless the F1
+    // wizard wrote something else), and how the window was left last time; reports, the
+    // console and remotes leave the picture settings alone
+    let (mut input_config, config_readable) = match InputConfig::load(&STORAGE, &args.input_config) {
+        Ok(config) => (config.unwrap_or_default(), true),
+        Err(err) => {
+            osd_messages.warning(&format!("Using the default controls: {}", err));
+            (InputConfig::default(), false) // and not writing over the file
+        }
+    };
+    let saved_palette = input_config.video.palette.clone().filter(|_| args.windowed());
+    set_up_cabinet(&mut nes, &args, &args.rom_path, saved_palette.as_deref(), &osd_messages);
+    load_cheats(&mut nes, &args.rom_path, &osd_messages);
+
+    // photosensitivity safety, see render::filters
+    let flash = FlashFilter::new(args.flash_filter.unwrap_or(filters::DEFAULT_FLASH_THRESHOLD));
+    nes.compositor().add_filter(Box::new(flash), args.flash_filter.is_some());
+    if let Some(saved) = input_config.video.filters.as_ref().filter(|_| args.windowed()) {
+        let names: Vec<String> = nes.compositor().filters().iter().map(|(name, _)| name.to_string()).collect();
+        for name in names {
+            let asked = name == "flash" && args.flash_filter.is_some();
+            nes.compositor().set_enabled(&name, asked || saved.contains(&name));
+        }
+    }
+
+    // debug overlays, toggled with hotkeys in the window (F2, F3, H, V)
+    nes.compositor().add_overlay(Box::new(SpriteZeroOverlay), false);
+    nes.compositor().add_overlay(Box::new(ScanlineRuler), false);
+    nes.compositor().add_overlay(Box::new(RamHeatmap::new()), false);
+    let visualizer = ApuVisualizer::new();
+    nes.set_apu_scope(Some(visualizer.scope()));
+    nes.compositor().add_overlay(Box::new(visualizer), false);
+
+    // the ROM path changes when another game is dropped on the window
+    let mut controls = Controls::new(&args.rom_path, args.speed);
+    nes.set_master_volume(controls.volume.gain());
+    if let Some(path) = &args.barcodes {
+        let cards = std::fs::read_to_string(path).map_err(|err| err.to_string()).and_then(|text| barcode::parse_cards(&text));
+        match cards {
+            Ok(cards) => controls.cards = cards,
+            Err(err) => status!("Could not read {}: {}", path.display(), err),
+        }
+    }
+    if let Some(path) = &args.splits {
+        let splits =
+            std::fs::read_to_string(path).map_err(|err| err.to_string()).and_then(|text| speedrun::parse_splits(&text));
+        match splits {
+            Ok(splits) => {
+                let overlay = TimerOverlay::new(SpeedrunTimer::new(splits));
+                controls.timer = Some(overlay.timer());
+                nes.compositor().add_overlay(Box::new(overlay), true);
+            }
+            Err(err) => status!("Could not read {}: {}", path.display(), err),
+        }
+    }
+    let mut sav_path = sram::save_path(&nes, &controls.rom_path);
+    match sram::load(&STORAGE, &mut nes, &sav_path) {
+        Ok(true) => status!("Loaded save data from {}", sav_path.display()),
+        Ok(false) => {}
+        Err(err) => status!("Could not read {}: {}", sav_path.display(), err),
+    }
+
+    if let Some((frames, dir)) = &args.dump_frames {
+        dump_frames(&mut nes, frames.clone(), dir);
+        save_and_quit(&mut nes, &sav_path, &args);
+    }
+    if args.console {
+        run_console(&mut nes, &mut controls);
+        save_and_quit(&mut nes, &sav_path, &args);
+    }
+    if let Some(addr) = &args.remote {
+        run_remote(&mut nes, &mut controls, addr);
+        save_and_quit(&mut nes, &sav_path, &args);
+    }
+    if let Some(frames) = args.headless_frames {
+        run_headless(&mut nes, frames, &args, title.as_deref());
+        save_and_quit(&mut nes, &sav_path, &args);
+    }
+
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    // no sound is no reason not to play
+    let audio_queue = open_audio(&sdl_context)
+        .map_err(|err| osd_messages.warning(&format!("No sound: {}", err)))
+        .ok();
+    // the device has to stay open for as long as it's listened to
+    let mic = args.mic.and_then(|threshold| {
+        open_mic(&sdl_context, threshold)
+            .map_err(|err| osd_messages.warning(&format!("No microphone: {}", err)))
+            .ok()
+    });
+    let mut mic_key = false; // M, for the microphone without one
 
     let controller_subsystem = sdl_context.game_controller().unwrap();
     let controller = (0..controller_subsystem.num_joysticks().unwrap())
@@ -139,19 +1540,35 @@ fn main() {
 
     println!("Controller detected: {}", controller.name());
 
-    let window = video_subsystem
-        .window(
-            "runesco: Rust NES Co-Op",
-            (256.0 * 3.0) as u32,
-            (240.0 * 3.0) as u32,
-        )
-        // 256 x 240 screen, scaled by a factor of 3 ([?] for RGB)
-        .position_centered()
-        .build()
-        .unwrap();
+    // 256 x 240 screen, scaled by a factor of 3 ([?] for RGB), unless it was left otherwise
+    let saved_window = input_config.video.window;
+    let (width, height) = saved_window.map_or((256 * 3, 240 * 3), |window| (window.width, window.height));
+    let mut window = video_subsystem.window(&window_title(&title), width, height);
+    window.resizable();
+    match saved_window {
+        Some(saved) => window.position(saved.x, saved.y),
+        None => window.position_centered(),
+    };
+    let mut window = window.build().unwrap();
+    if input_config.video.fullscreen {
+        if let Err(err) = window.set_fullscreen(FullscreenType::Desktop) {
+            osd_messages.warning(&format!("Could not go fullscreen: {}", err));
+        }
+    }
 
     // A 'canvas': something which can be 'drawn' on is put over the window
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    // the pictures are paced by the audio device, see runesco::pacing; vsync only for a
+    // variable refresh display, which then refreshes when each one comes (see Pacing)
+    let mut canvas = match input_config.video.pacing {
+        Pacing::Timer => window.into_canvas().build().unwrap(),
+        Pacing::Vrr => {
+            let canvas = window.into_canvas().present_vsync().build().unwrap();
+            // adaptive vsync, so a picture that misses the display's range is shown at once
+            // rather than held a whole refresh; only the OpenGL renderer has it
+            let _ = video_subsystem.gl_set_swap_interval(SwapInterval::LateSwapTearing);
+            canvas
+        }
+    };
     let mut event_pump = sdl_context.event_pump().unwrap();
     canvas.set_scale(10.0, 10.0).unwrap();
 
@@ -165,31 +1582,11 @@ fn main() {
         .unwrap();
     // We specify that the visuals are in the form of 256 x 240 pixel grid
 
-    //load the game
-    let nes_file_data: Vec<u8> = std::fs::read("nestest.nes").unwrap();
-    let rom = Rom::new(&nes_file_data).unwrap();
-
-    let mut frame = Frame::new();
+    if controls.speed.percent() < speed::MAX_PERCENT {
+        osd_messages.info(&format!("Speed {}%", controls.speed.percent()));
+    }
 
-    let mut p1 = HashMap::new();
-    p1.insert(Keycode::Down, joypads::JoypadButton::DOWN);
-    p1.insert(Keycode::Up, joypads::JoypadButton::UP);
-    p1.insert(Keycode::Right, joypads::JoypadButton::RIGHT);
-    p1.insert(Keycode::Left, joypads::JoypadButton::LEFT);
-    p1.insert(Keycode::RShift, joypads::JoypadButton::SELECT);
-    p1.insert(Keycode::Return, joypads::JoypadButton::START);
-    p1.insert(Keycode::Z, joypads::JoypadButton::BUTTON_A);
-    p1.insert(Keycode::X, joypads::JoypadButton::BUTTON_B);
-
-    let mut p2 = HashMap::new();
-    p2.insert(Button::DPadDown, joypads::JoypadButton::DOWN);
-    p2.insert(Button::DPadUp, joypads::JoypadButton::UP);
-    p2.insert(Button::DPadRight, joypads::JoypadButton::RIGHT);
-    p2.insert(Button::DPadLeft, joypads::JoypadButton::LEFT);
-    p2.insert(Button::Back, joypads::JoypadButton::SELECT);
-    p2.insert(Button::Start, joypads::JoypadButton::START);
-    p2.insert(Button::A, joypads::JoypadButton::BUTTON_A);
-    p2.insert(Button::B, joypads::JoypadButton::BUTTON_B);
+    let mut wizard: Option<Wizard> = None;
 
     //let bank = show_tile_bank(&rom.chr_rom, 1);
 
@@ -197,57 +1594,350 @@ fn main() {
     //canvas.copy(&texture, None, None).unwrap();
     //canvas.present();
 
+    let mut watcher = if args.watch {
+        Some(RomWatcher::new(&controls.rom_path, nes_file_data))
+    } else {
+        None
+    };
+
+    let mut spectator_server = args.spectators.as_ref().map(|addr| {
+        let server = SpectatorServer::bind(addr, Session::of(&nes)).unwrap_or_else(|err| panic!("could not listen on {}: {}", addr, err));
+        println!("Spectators can join on {}", server.local_addr().unwrap());
+        server
+    });
+    let mut spectator_client = args.spectate.as_ref().map(|addr| {
+        let client = SpectatorClient::connect(addr, &nes).unwrap_or_else(|err| panic!("could not connect to {}: {}", addr, err));
+        osd_messages.info(&format!("Watching {}", addr));
+        client
+    });
+    let mut spectators = 0;
+
     // the game cycle
-    let bus = Bus::new(rom, move 
-        |ppu: &NesPPU, joypad1: &mut joypads::Joypad, joypad2: &mut joypads::Joypad| {
-        render::render(ppu, &mut frame);
-        // renders the current data from PPU and draws the current frame
+    let mut paused = false; // stopped by the debugger; F5 continues
+    let mut stepping = args.step_view; // in the step view: Space runs an instruction, Pause leaves
+    let mut jam_reported = false;
+    let watchdog = Watchdog::start(WATCHDOG_TIMEOUT, args.watchdog_kill.map(Duration::from_secs));
+    let mut autosave: Option<(SaveState, u64)> = None; // and its frame
+    let mut stalled = false; // emulation held until the player answers report_stall
+    let mut pacer = FramePacer::new();
+    let mut idle = IdleTimer::new(args.idle_dim.map(|m| m * 60.0), args.idle_pause.map(|m| m * 60.0));
+    loop {
+        if let Some(stall) = watchdog.take_stall() {
+            report_stall(&nes, &stall, autosave.as_ref(), &osd_messages);
+            stalled = true;
+        }
+        watchdog.beat("running the game");
+        if let Some(watcher) = watcher.as_mut() {
+            watcher.poll(&mut nes);
+        }
 
-        texture.update(None, &frame.data, 256 * 3).unwrap();
+        // a spectator runs as many frames as the host's inputs allow, see spectate::Pacer
+        let frames = match spectator_client.as_mut() {
+            Some(client) => {
+                let was_connected = client.connected();
+                client.poll(&mut nes).unwrap_or_else(|err| panic!("{}", err));
+                if was_connected && !client.connected() {
+                    osd_messages.info("The host ended the session");
+                }
+                client.frames_to_run(&nes)
+            }
+            None => controls.speed.frames_to_run(),
+        };
+        nes.set_microphone(mic_key || mic.as_ref().is_some_and(|(_, loud)| loud.load(Ordering::Relaxed)));
+        nes.set_audio_speed(frames.max(1) as f32);
+        for _ in 0..if stalled || stepping || idle.paused() { 0 } else { frames } {
+            run_frame_reporting_crashes(&mut nes);
+            if let Some(message) = controls.timer.as_ref().and_then(|timer| timer.borrow_mut().update(&nes)) {
+                osd_messages.info(&message);
+            }
+            let frame = nes.frame_count();
+            if frame.is_multiple_of(AUTOSAVE_FRAMES) && autosave.as_ref().is_none_or(|(_, saved)| *saved != frame) {
+                autosave = Some((nes.save_state(), frame));
+            }
+        }
+        let (one, two) = nes.joypads();
+        idle.frame([one.button_status, two.button_status]);
+        if let Some(queue) = &audio_queue {
+            play_audio(&mut nes, queue);
+        }
+        if let (Some(server), Some(movie)) = (spectator_server.as_mut(), nes.recording()) {
+            server.broadcast(movie, nes.frame_count());
+            if server.spectators() != spectators {
+                spectators = server.spectators();
+                osd_messages.info(&format!("{} watching", spectators));
+            }
+        }
+        // runs the CPU until the PPU finishes a picture, and draws the current frame
+
+        if nes.is_jammed() && !jam_reported {
+            report_jam(&nes);
+            jam_reported = true; // F8 (reset) gets it going again
+        }
+
+        if !paused {
+            if let Some(reason) = nes.break_reason() {
+                println!("Break: {} (F5 to continue)", reason);
+                print!("{}", nes.prg_bank_layout());
+                paused = true;
+            }
+        }
+
+        watchdog.beat("drawing the picture");
+        if stepping {
+            let past: Vec<TraceEntry> = nes.history().iter().copied().collect();
+            let mut shown = Frame { data: nes.frame().data.clone() };
+            StepView::capture(nes.cpu(), &past).draw(&mut shown);
+            texture.update(None, &shown.data, 256 * 3).unwrap();
+        } else if idle.dimmed() {
+            texture.update(None, &idle::dim(&nes.frame().data), 256 * 3).unwrap();
+        } else {
+            texture.update(None, &nes.frame().data, 256 * 3).unwrap();
+        }
         // sdl updates pixels accordingly
 
         canvas.copy(&texture, None, None).unwrap();
 
+        watchdog.beat("waiting for the next frame");
+        pacer.wait(audio_queue.as_ref().map(queued_samples));
         canvas.present();
 
+        watchdog.beat("handling input");
         for event in event_pump.poll_iter() {
+            if matches!(event, Event::KeyDown { .. } | Event::ControllerButtonDown { .. }) {
+                idle.activity();
+            }
             match event {
+                // while the setup wizard runs, every key and button press answers it
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } if wizard.is_some() => {
+                    wizard = None;
+                    osd_messages.clear();
+                    osd_messages.info("Controls unchanged");
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } if wizard.is_some() => {
+                    answer_wizard(&mut wizard, Binding::Key(keycode.name()), &mut input_config, &args.input_config, &osd_messages);
+                }
+                Event::ControllerButtonDown { button, .. } if wizard.is_some() => {
+                    answer_wizard(&mut wizard, Binding::Pad(button.string()), &mut input_config, &args.input_config, &osd_messages);
+                }
+                Event::KeyDown { .. } | Event::KeyUp { .. } | Event::ControllerButtonUp { .. } if wizard.is_some() => {}
+
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => std::process::exit(0),
- 
- 
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = p1.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        joypad1.set_button_pressed_status(*key, true);
+                } => {
+                    if config_readable {
+                        save_video(&mut input_config, canvas.window(), &mut nes, &args);
                     }
+                    save_and_quit(&mut nes, &sav_path, &args)
                 }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = p1.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        joypad1.set_button_pressed_status(*key, false);
+
+                // after a stall, nothing runs until the player picks what happens next
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::Return | Keycode::R)),
+                    repeat: false,
+                    ..
+                } if stalled => {
+                    osd_messages.clear();
+                    if let (Keycode::R, Some((state, frame))) = (keycode, &autosave) {
+                        match nes.load_state(state) {
+                            Ok(()) => osd_messages.info(&format!("Back at frame {}", frame)),
+                            Err(err) => osd_messages.warning(&format!("Could not restore the autosave: {}", err)),
+                        }
                     }
+                    stalled = false;
                 }
+                Event::KeyDown { .. } | Event::ControllerButtonDown { .. } if stalled => {}
 
-                Event::ControllerButtonDown { button, .. } => {
-                    if let Some(button) = p2.get(&button) {
-                        joypad2.set_button_pressed_status(*button, true);
+                // Alt+Enter: fullscreen and back
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    let window = canvas.window_mut();
+                    let to = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    if let Err(err) = window.set_fullscreen(to) {
+                        osd_messages.warning(&format!("Could not change to or from fullscreen: {}", err));
                     }
                 }
-                Event::ControllerButtonUp { button, .. } => {
-                    if let Some(button) = p2.get(&button) {
-                        joypad2.set_button_pressed_status(*button, false);
+                // the step view, see runesco::step_view; held down, Space keeps stepping
+                Event::KeyDown {
+                    keycode: Some(Keycode::Pause),
+                    repeat: false,
+                    ..
+                } => stepping = !stepping,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    ..
+                } if stepping => nes.step_instruction(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    let started = Wizard::new();
+                    osd_messages.clear();
+                    osd_messages.show(&started.prompt(), Level::Info, u32::MAX);
+                    wizard = Some(started);
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if hotkey(keycode).is_some() => {
+                    let action = hotkey(keycode).unwrap();
+                    osd_messages.clear();
+                    match perform(&mut nes, &mut controls, &action) {
+                        Ok(message) if message.is_empty() => {}
+                        Ok(message) => {
+                            println!("{}", message);
+                            osd_messages.info(&message);
+                        }
+                        Err(err) => {
+                            println!("{}", err);
+                            osd_messages.warning(&err);
+                        }
+                    }
+                    if action == Action::Reset || action == Action::Power {
+                        jam_reported = false;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    nes.resume();
+                    paused = false;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    if nes.step_out() {
+                        paused = false;
+                    } else {
+                        println!("Step out: not inside a subroutine");
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => print!("{}", nes.history().report()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => controls.speed.hold_slow(true),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => controls.speed.hold_slow(false),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backquote),
+                    repeat: false,
+                    ..
+                } => controls.speed.hold_fast(true),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backquote),
+                    ..
+                } => controls.speed.hold_fast(false),
+
+                // shouting into the Famicom's microphone, for players without one to shout into
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => mic_key = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => mic_key = false,
+
+                // a Vs. System cabinet's coin slots and service button, held like the real ones
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::Num5 | Keycode::Num6 | Keycode::Num9)),
+                    ..
+                } | Event::KeyUp {
+                    keycode: Some(keycode @ (Keycode::Num5 | Keycode::Num6 | Keycode::Num9)),
+                    ..
+                } if nes.vs_panel().is_some() => {
+                    let down = matches!(event, Event::KeyDown { .. });
+                    let panel = nes.vs_panel().unwrap();
+                    match keycode {
+                        Keycode::Num5 => panel.set_coin(0, down),
+                        Keycode::Num6 => panel.set_coin(1, down),
+                        _ => panel.set_service(down),
                     }
                 }
+
+                // a movie or a spectator session belongs to one game
+                Event::DropFile { .. }
+                    if nes.recording().is_some() || nes.is_playing_movie() || spectator_client.is_some() =>
+                {
+                    osd_messages.warning("Can't change games while recording, playing or watching a session");
+                }
+                Event::DropFile { filename, .. } => {
+                    if let Err(err) = sram::save(&STORAGE, &nes, &sav_path) {
+                        println!("Could not write {}: {}", sav_path.display(), err);
+                    }
+                    match switch_game(&mut nes, &args, &filename, saved_palette.as_deref(), &osd_messages) {
+                        Ok((image, title)) => {
+                            // the mute stays on: the player silenced the emulator, not the game
+                            let muted = controls.volume.is_muted();
+                            controls = Controls::new(&filename, None);
+                            if muted {
+                                controls.volume.toggle_mute();
+                            }
+                            nes.set_master_volume(controls.volume.gain());
+                            // the splits watch the RAM of the game they were written for
+                            nes.compositor().set_enabled("timer", false);
+                            sav_path = sram::save_path(&nes, &controls.rom_path);
+                            match sram::load(&STORAGE, &mut nes, &sav_path) {
+                                Ok(true) => println!("Loaded save data from {}", sav_path.display()),
+                                Ok(false) => {}
+                                Err(err) => println!("Could not read {}: {}", sav_path.display(), err),
+                            }
+                            if args.watch {
+                                watcher = Some(RomWatcher::new(&controls.rom_path, image));
+                            }
+                            let _ = canvas.window_mut().set_title(&window_title(&title));
+                            paused = false;
+                            jam_reported = false;
+                        }
+                        Err(err) => osd_messages.warning(&format!("Could not load {}: {}", filename, err)),
+                    }
+                }
+ 
+ 
+                // while a movie plays, it has the controllers (so do the host's inputs when spectating)
+                Event::KeyDown { .. } | Event::KeyUp { .. } | Event::ControllerButtonDown { .. } | Event::ControllerButtonUp { .. }
+                    if spectator_client.is_some() => {}
+                // held keys repeat, which would flip auto-hold buttons on and off
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } if !nes.is_playing_movie() => {
+                    press(nes.joypads(), &input_config, Binding::Key(keycode.name()), true);
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } if !nes.is_playing_movie() => {
+                    press(nes.joypads(), &input_config, Binding::Key(keycode.name()), false);
+                }
+
+                Event::ControllerButtonDown { button, .. } if !nes.is_playing_movie() => {
+                    press(nes.joypads(), &input_config, Binding::Pad(button.string()), true);
+                }
+                Event::ControllerButtonUp { button, .. } if !nes.is_playing_movie() => {
+                    press(nes.joypads(), &input_config, Binding::Pad(button.string()), false);
+                }
  
                 _ => { /* do nothing */ }
             }
         }
-    });
-
-    let mut cpu = CPU::new(bus);
-
-    cpu.reset();
-    cpu.run();
+    }
 }
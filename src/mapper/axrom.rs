@@ -0,0 +1,92 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x8000; // 32KiB
+
+// Mapper 7 (AxROM): Battletoads, Marble Madness, Wizards & Warriors and other Rare games.
+// See: https://www.nesdev.org/wiki/AxROM
+//
+// A single register anywhere in $8000-$FFFF:
+//
+//   ...M.PPP
+//      |  +++- 32KiB PRG bank at $8000
+//      +------ which 1KiB of VRAM all four nametables show (single-screen mirroring)
+//
+// CHR is 8KiB that is never banked. Only some AxROM boards have bus conflicts (see
+// Mapper::bus_conflicts), and games made for the others don't avoid them, so none are
+// emulated.
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    register: u8,
+}
+
+impl Axrom {
+    pub fn new(prg_rom: Vec<u8>) -> Self {
+        Axrom { prg_rom, register: 0 }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = (self.register & 0b111) as usize;
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Axrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        self.register = data;
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0b1_0000 == 0 {
+            Mirroring::SINGLE_SCREEN_A
+        } else {
+            Mirroring::SINGLE_SCREEN_B
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new().u8(self.register).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("AxROM", data);
+        self.register = state.u8()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_bank_and_mirroring() {
+        let prg = (0..4).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut axrom = Axrom::new(prg);
+        assert_eq!(axrom.read_prg(0xFFFF), 0);
+        assert_eq!(axrom.mirroring(), Mirroring::SINGLE_SCREEN_A);
+
+        axrom.write_prg(0x8000, 0b1_0010);
+        assert_eq!(axrom.read_prg(0x8000), 2);
+        assert_eq!(axrom.read_prg(0xC123), 2);
+        assert_eq!(axrom.mirroring(), Mirroring::SINGLE_SCREEN_B);
+    }
+}
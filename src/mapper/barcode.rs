@@ -0,0 +1,210 @@
+use crate::mapper::state::{StateReader, StateWriter};
+
+// The barcode reader in Bandai's Datach Joint ROM System (see crate::mapper::datach): the
+// player swipes a card and the game watches the reader's output bit, one bar or space at a
+// time. Cards carry ordinary EAN-13 or EAN-8 codes, drawn as modules (thin bars or spaces):
+//
+//   start guard 101, the left digits (7 modules each), middle guard 01010, the right digits,
+//   end guard 101
+//
+// Each digit has three encodings: L and G on the left, R on the right. EAN-13's first digit
+// isn't drawn at all; it picks which of the next six use L and which G. EAN-8 uses L for all
+// four on the left. The reader outputs 0 over a bar and 1 over a space, with plenty of space
+// before and after the code, and moves on a module every MODULE_CYCLES CPU cycles, about the
+// speed of a swipe by hand.
+// See: https://www.nesdev.org/wiki/Datach_Joint_ROM_System
+
+const MODULE_CYCLES: u32 = 1000;
+const QUIET_ZONE: usize = 32; // modules of space around the code
+
+// The L codes, one per digit; R is their complement and G is R backwards.
+const L_CODES: [u8; 10] =
+    [0b0001101, 0b0011001, 0b0010011, 0b0111101, 0b0100011, 0b0110001, 0b0101111, 0b0111011, 0b0110111, 0b0001011];
+
+// For EAN-13, which of digits 2-7 use G (a set bit, digit 2 in bit 5), by the first digit.
+const G_PARITY: [u8; 10] =
+    [0b000000, 0b001011, 0b001101, 0b001110, 0b010011, 0b011001, 0b011100, 0b010101, 0b010110, 0b011010];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    L,
+    G,
+    R,
+}
+
+fn code(digit: u8, encoding: Encoding) -> u8 {
+    let l = L_CODES[digit as usize];
+    match encoding {
+        Encoding::L => l,
+        Encoding::R => !l & 0x7F,
+        Encoding::G => (!l & 0x7F).reverse_bits() >> 1,
+    }
+}
+
+// The modules of a card's code, true for a bar. Checks the digits and the check digit, so a
+// typo is caught here rather than by the game's "read error".
+pub fn modules(text: &str) -> Result<Vec<bool>, String> {
+    let digits: Vec<u8> = text.trim().bytes().map(|b| b.wrapping_sub(b'0')).collect();
+    if !(digits.len() == 13 || digits.len() == 8) || digits.iter().any(|&d| d > 9) {
+        return Err(format!("'{}' is not a barcode: expected 13 or 8 digits (EAN-13 or EAN-8)", text.trim()));
+    }
+    // weights 3 and 1, alternating from the right, with the check digit weighing 1
+    let sum: u32 = digits.iter().rev().enumerate().map(|(i, &d)| d as u32 * if i % 2 == 1 { 3 } else { 1 }).sum();
+    if !sum.is_multiple_of(10) {
+        return Err(format!("'{}' is not a barcode: the check digit is wrong", text.trim()));
+    }
+
+    let (left, right, parity) = match digits.len() {
+        13 => (&digits[1..7], &digits[7..], G_PARITY[digits[0] as usize]),
+        _ => (&digits[..4], &digits[4..], 0),
+    };
+    let mut modules = vec![];
+    let mut push = |bits: u8, count: u32| {
+        modules.extend((0..count).rev().map(|bit| bits >> bit & 1 != 0));
+    };
+    push(0b101, 3);
+    for (i, &digit) in left.iter().enumerate() {
+        let g = parity >> (left.len() - 1 - i) & 1 != 0;
+        push(code(digit, if g { Encoding::G } else { Encoding::L }), 7);
+    }
+    push(0b01010, 5);
+    for &digit in right {
+        push(code(digit, Encoding::R), 7);
+    }
+    push(0b101, 3);
+    Ok(modules)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BarcodeReader {
+    modules: Vec<bool>, // the card being swiped, quiet zones included; empty between swipes
+    cycles: u32,        // since the swipe started
+}
+
+impl BarcodeReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts swiping the card with code `text`.
+    pub fn scan(&mut self, text: &str) -> Result<(), String> {
+        let code = modules(text)?;
+        self.modules = vec![false; QUIET_ZONE];
+        self.modules.extend(code);
+        self.modules.extend([false; QUIET_ZONE]);
+        self.cycles = 0;
+        Ok(())
+    }
+
+    pub fn clock(&mut self, cycles: u8) {
+        if !self.modules.is_empty() {
+            self.cycles += cycles as u32;
+            if self.cycles / MODULE_CYCLES >= self.modules.len() as u32 {
+                self.modules.clear(); // the card is through
+            }
+        }
+    }
+
+    // The reader's output: 1 over a space (and with no card), 0 over a bar.
+    pub fn output(&self) -> bool {
+        let module = (self.cycles / MODULE_CYCLES) as usize;
+        !self.modules.get(module).copied().unwrap_or(false)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let packed: Vec<u8> = self.modules.iter().map(|&bar| bar as u8).collect();
+        state.u16(packed.len() as u16).bytes(&packed).u32(self.cycles);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let len = state.u16()? as usize;
+        self.modules = state.bytes(len)?.iter().map(|&bar| bar != 0).collect();
+        self.cycles = state.u32()?;
+        Ok(())
+    }
+}
+
+// The cards the frontend can swipe (--barcodes), from a text file: one card per line, its
+// code then optionally a name to show, with # starting a comment.
+//
+//   4902425123455  Goku     # from the Dragon Ball Z set
+//   96385074
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub code: String,
+    pub name: String, // the code again, if the line didn't give one
+}
+
+pub fn parse_cards(text: &str) -> Result<Vec<Card>, String> {
+    let mut cards = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        modules(code).map_err(|err| format!("line {}: {}", number + 1, err))?;
+        let name = if name.trim().is_empty() { code } else { name.trim() };
+        cards.push(Card { code: code.to_string(), name: name.to_string() });
+    }
+    Ok(cards)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn bars(text: &str) -> String {
+        modules(text).unwrap().iter().map(|&bar| if bar { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn test_ean_encodings() {
+        // 4 902425 123455: first digit 4 is LGLLGG
+        let code = bars("4902425123455");
+        assert_eq!(code.len(), 95);
+        assert_eq!(&code[0..3], "101");
+        assert_eq!(&code[3..10], "0001011"); // 9 in L
+        assert_eq!(&code[10..17], "0100111"); // 0 in G
+        assert_eq!(&code[45..50], "01010");
+        assert_eq!(&code[50..57], "1100110"); // 1 in R
+        assert_eq!(bars("96385074").len(), 67);
+
+        assert!(modules("4902425123450").is_err()); // check digit
+        assert!(modules("12345").is_err());
+        assert!(modules("490242512345x").is_err());
+    }
+
+    #[test]
+    fn test_parse_cards() {
+        let cards = parse_cards("# Dragon Ball Z\n4902425123455  Goku  # the first\n\n96385074\n").unwrap();
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0], Card { code: "4902425123455".to_string(), name: "Goku".to_string() });
+        assert_eq!(cards[1].name, "96385074");
+        assert_eq!(
+            parse_cards("96385074\n96385075").unwrap_err(),
+            "line 2: '96385075' is not a barcode: the check digit is wrong"
+        );
+    }
+
+    #[test]
+    fn test_swipe() {
+        let mut reader = BarcodeReader::new();
+        assert!(reader.output());
+        reader.scan("96385074").unwrap();
+        reader.clock(250);
+        assert!(reader.output()); // the quiet zone
+        for _ in 1..QUIET_ZONE as u32 * MODULE_CYCLES / 250 {
+            reader.clock(250);
+        }
+        assert!(!reader.output()); // the first bar of the start guard
+        for _ in 0..MODULE_CYCLES / 250 {
+            reader.clock(250);
+        }
+        assert!(reader.output());
+        for _ in 0..1000 {
+            reader.clock(255);
+        }
+        assert!(reader.modules.is_empty() && reader.output());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x8000; // 32KiB
+const CHR_BANK_SIZE: usize = 0x1000; // 4KiB (NINA-001)
+
+// Mapper 34 covers two unrelated boards that ended up with the same number:
+//
+// BNROM (Deadly Towers): any write to $8000-$FFFF selects the 32KiB PRG bank, CHR is 8KiB
+// of RAM.
+//
+// NINA-001 (AVE's Impossible Mission II): registers at the top of PRG-RAM,
+//   $7FFD: 32KiB PRG bank   $7FFE: 4KiB CHR bank at $0000   $7FFF: 4KiB CHR bank at $1000
+//
+// The header can't tell them apart, so like other emulators we assume NINA-001 whenever the
+// cartridge has more than 8KiB of CHR ROM (BNROM has none).
+// See: https://www.nesdev.org/wiki/INES_Mapper_034
+pub struct Bnrom {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 4KiB CHR banks on the cartridge
+    mirroring: Mirroring,
+    nina: bool,
+
+    prg_bank: u8,
+    chr: [u8; 2],
+}
+
+impl Bnrom {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring) -> Self {
+        Bnrom {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            mirroring,
+            nina: chr_size > 0x2000,
+            prg_bank: 0,
+            chr: [0, 1],
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        mapper::prg_offset(self.prg_rom.len(), self.prg_bank as usize, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Bnrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        if !self.nina {
+            self.prg_bank = data;
+        }
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        !self.nina
+    }
+
+    fn write_low(&mut self, addr: u16, data: u8) {
+        if !self.nina {
+            return;
+        }
+        match addr {
+            0x7FFD => self.prg_bank = data & 1,
+            0x7FFE => self.chr[0] = data & 0b1111,
+            0x7FFF => self.chr[1] = data & 0b1111,
+            _ => {}
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        if !self.nina {
+            return addr as usize;
+        }
+        let bank = self.chr[addr as usize / CHR_BANK_SIZE] as usize % self.chr_banks;
+        bank * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new().u8(self.prg_bank).bytes(&self.chr).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("BNROM", data);
+        self.prg_bank = state.u8()?;
+        self.chr = state.array()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_both_boards() {
+        let prg: Vec<u8> = (0..4).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut bnrom = Bnrom::new(prg.clone(), 0, Mirroring::VERTICAL);
+        bnrom.write_prg(0x8000, 3);
+        assert_eq!(bnrom.read_prg(0x9000), 3);
+        assert_eq!(bnrom.map_chr(0x1234), 0x1234);
+
+        let mut nina = Bnrom::new(prg, 0x10000, Mirroring::VERTICAL);
+        nina.write_prg(0x8000, 3); // not a register on this board
+        assert_eq!(nina.read_prg(0x9000), 0);
+        nina.write_low(0x7FFD, 1);
+        nina.write_low(0x7FFF, 9);
+        assert_eq!(nina.read_prg(0x9000), 1);
+        assert_eq!(nina.map_chr(0x1004), 9 * CHR_BANK_SIZE + 4);
+    }
+}
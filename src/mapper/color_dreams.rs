@@ -0,0 +1,96 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x8000; // 32KiB
+const CHR_BANK_SIZE: usize = 0x2000; // 8KiB
+
+// Mapper 11 (Color Dreams): the unlicensed Color Dreams and Wisdom Tree games (Crystal Mines,
+// Bible Adventures, ...).
+// See: https://www.nesdev.org/wiki/Color_Dreams
+//
+// A single register anywhere in $8000-$FFFF:
+//
+//   CCCC..PP
+//   ||||  ++- 32KiB PRG bank
+//   ++++----- 8KiB CHR bank
+//
+// The same bits as GxROM, just the other way round.
+pub struct ColorDreams {
+    prg_rom: Vec<u8>,
+    chr_banks: usize,
+    mirroring: Mirroring,
+    register: u8,
+}
+
+impl ColorDreams {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring) -> Self {
+        ColorDreams {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            mirroring,
+            register: 0,
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = (self.register & 0b11) as usize;
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        self.register = data;
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        true
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = (self.register >> 4) as usize % self.chr_banks;
+        bank * CHR_BANK_SIZE + addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new().u8(self.register).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("Color Dreams", data);
+        self.register = state.u8()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_banks() {
+        let prg = (0..4).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut mapper = ColorDreams::new(prg, 16 * CHR_BANK_SIZE, Mirroring::VERTICAL);
+        mapper.write_prg(0xC000, 0b1010_0001);
+        assert_eq!(mapper.read_prg(0x8000), 1);
+        assert_eq!(mapper.map_chr(0x1FFF), 10 * CHR_BANK_SIZE + 0x1FFF);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::barcode::BarcodeReader;
+use crate::mapper::eeprom::{Chip, Eeprom};
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper, SaveDevice};
+
+const PRG_BANK_SIZE: usize = 0x4000; // 16KiB
+
+// Mapper 157 (Bandai Datach Joint ROM System): a base unit with a barcode reader that plugs
+// into the Famicom and takes the game cartridges (Dragon Ball Z Gaiden, Ultraman Club,
+// SD Gundam Wars...). The player swipes the cards that came with the game to call up
+// characters and items. The board is Bandai's FCG with the LZ93D50 chip, registers at
+// $8000-$FFFF picked by the low 4 bits of the address:
+//
+//   $x0-$x7: 1KiB CHR banks, unused: the Datach has 8KiB of CHR-RAM
+//   $x8:     16KiB PRG bank at $8000 ($C000 is fixed to the last bank)
+//   $x9:     mirroring: vertical, horizontal, single screen A, single screen B
+//   $xA:     IRQ control: bit 0 enables; writing acknowledges and loads the counter
+//   $xB,$xC: IRQ latch low, high byte
+//   $xD:     .DC. ....  the EEPROM's lines: C: SCL, D: SDA
+//
+// The 16 bit IRQ counter counts down every CPU cycle and fires when it reaches 0.
+//
+// $6000-$7FFF reads the base unit's inputs:
+//
+//   ...E B...  B: the barcode reader, 0 over a bar (see crate::mapper::barcode)
+//              E: the EEPROM's SDA line (see crate::mapper::eeprom)
+//
+// The saves live in a 24C02 in the base unit; a few cartridges add a 24C01 of their own,
+// which isn't emulated.
+// See: https://www.nesdev.org/wiki/INES_Mapper_157
+pub struct Datach {
+    prg_rom: Vec<u8>,
+    prg_bank: u8,
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    latch: u16,
+    counter: u16,
+    irq_pending: bool,
+
+    eeprom: Eeprom,
+    reader: BarcodeReader,
+}
+
+impl Datach {
+    pub fn new(prg_rom: Vec<u8>) -> Self {
+        Datach {
+            prg_rom,
+            prg_bank: 0,
+            mirroring: Mirroring::VERTICAL,
+            irq_enabled: false,
+            latch: 0,
+            counter: 0,
+            irq_pending: false,
+            eeprom: Eeprom::new(Chip::C24C02),
+            reader: BarcodeReader::new(),
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000..=0xBFFF => (self.prg_bank & 0x0F) as usize,
+            _ => self.prg_rom.len() / PRG_BANK_SIZE - 1,
+        };
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Datach {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    // The other bits aren't driven; the games mask them off, so they read as 0 here.
+    fn read_low(&self, _addr: u16) -> Option<u8> {
+        Some((self.eeprom.read_sda() as u8) << 4 | (self.reader.output() as u8) << 3)
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr & 0x0F {
+            0x0..=0x7 => {}
+            0x8 => self.prg_bank = data,
+            0x9 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_A,
+                    _ => Mirroring::SINGLE_SCREEN_B,
+                };
+            }
+            0xA => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter = self.latch;
+                self.irq_pending = false;
+            }
+            0xB => self.latch = (self.latch & 0xFF00) | data as u16,
+            0xC => self.latch = (self.latch & 0x00FF) | (data as u16) << 8,
+            0xD => self.eeprom.write_lines(data & 0x20 != 0, data & 0x40 != 0),
+            _ => {}
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self, cycles: u8) {
+        if self.irq_enabled {
+            for _ in 0..cycles {
+                if self.counter == 0 {
+                    self.irq_pending = true;
+                }
+                self.counter = self.counter.wrapping_sub(1);
+            }
+        }
+        self.reader.clock(cycles);
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn scan_barcode(&mut self, code: &str) -> Result<(), String> {
+        self.reader.scan(code)
+    }
+
+    fn save_device(&self) -> Option<SaveDevice> {
+        Some(SaveDevice::Eeprom(self.eeprom.chip()))
+    }
+
+    fn save_device_data(&self) -> Vec<u8> {
+        self.eeprom.data().to_vec()
+    }
+
+    fn load_save_device(&mut self, data: &[u8]) -> Result<(), String> {
+        self.eeprom.load(data)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state
+            .u8(self.prg_bank)
+            .mirroring(self.mirroring)
+            .bool(self.irq_enabled)
+            .u16(self.latch)
+            .u16(self.counter)
+            .bool(self.irq_pending);
+        self.eeprom.save_state(&mut state);
+        self.reader.save_state(&mut state);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("Datach", data);
+        self.prg_bank = state.u8()?;
+        self.mirroring = state.mirroring()?;
+        self.irq_enabled = state.bool()?;
+        self.latch = state.u16()?;
+        self.counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+        self.eeprom.load_state(&mut state)?;
+        self.reader.load_state(&mut state)?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_registers_and_inputs() {
+        let prg = (0..16).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut datach = Datach::new(prg);
+        assert_eq!(datach.read_prg(0xC000), 15);
+        datach.write_prg(0x8008, 3);
+        assert_eq!(datach.read_prg(0x8000), 3);
+        datach.write_prg(0xFFF9, 1); // any address, by the low bits
+        assert_eq!(datach.mirroring(), Mirroring::HORIZONTAL);
+
+        datach.write_prg(0x800B, 100);
+        datach.write_prg(0x800C, 0);
+        datach.write_prg(0x800A, 1);
+        datach.clock_cpu(100);
+        assert!(!datach.irq_pending());
+        datach.clock_cpu(1);
+        assert!(datach.irq_pending());
+        datach.write_prg(0x800A, 0);
+        assert!(!datach.irq_pending());
+
+        // no card: the reader sees space; SDA reads high once the game releases it too
+        assert_eq!(datach.read_low(0x6000), Some(0b0000_1000));
+        datach.write_prg(0x800D, 0x40);
+        assert_eq!(datach.read_low(0x6000), Some(0b0001_1000));
+        datach.scan_barcode("4902425123455").unwrap();
+        assert!(datach.scan_barcode("123").is_err());
+        let mut bars = 0;
+        for _ in 0..200_000 / 200 {
+            datach.clock_cpu(200);
+            bars += (datach.read_low(0x6000).unwrap() & 0b1000 == 0) as usize;
+        }
+        assert!(bars > 0);
+        assert_eq!(datach.read_low(0x7FFF), Some(0b0001_1000)); // the card is through
+    }
+}
@@ -0,0 +1,336 @@
+use crate::mapper::state::{StateReader, StateWriter};
+
+// The serial EEPROMs Bandai's boards keep save games in instead of battery-backed RAM: a
+// 24C01 (128 bytes) or 24C02 (256 bytes), talked to over I2C. The game wiggles the clock
+// (SCL) and data (SDA) lines through a mapper register and reads SDA back through another;
+// the board only passes the lines on, so all the protocol is here.
+//
+// A transfer starts with SDA falling while SCL is high and ends with SDA rising while SCL is
+// high. In between, every byte is 8 data bits (sampled on SCL rising) and a 9th clock in which
+// the receiver pulls SDA low to acknowledge:
+//
+//   24C02  start, 1010xxx0, word address, data... stop          write, 8 byte pages
+//          start, 1010xxx1, data... (the master acks each) stop  read from the current address
+//   24C01  start, 7 bit address + read bit, data... stop      everything LSB first, 4 byte pages
+//
+// The contents persist in a .eeprom file next to the ROM, raw, like other emulators keep them
+// (see crate::sram).
+// See: https://www.nesdev.org/wiki/Bandai_FCG_board#Serial_EEPROM
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip {
+    C24C01,
+    C24C02,
+}
+
+impl Chip {
+    pub fn size(&self) -> usize {
+        match self {
+            Chip::C24C01 => 128,
+            Chip::C24C02 => 256,
+        }
+    }
+
+    fn page_size(&self) -> u8 {
+        match self {
+            Chip::C24C01 => 4,
+            Chip::C24C02 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    Device,  // 24C02: the device address byte
+    Address, // the word address (24C01: with the read bit)
+    Write,
+    Read,
+}
+
+pub struct Eeprom {
+    chip: Chip,
+    data: Vec<u8>,
+    scl: bool,
+    sda: bool,
+    phase: Phase,
+    bit: u8, // clocks into the current byte: 8 once the data is in, 9 after the acknowledge
+    shift: u8,
+    addr: u8,
+    out: bool,        // what the chip drives on SDA; high is released
+    acking: bool,     // the acknowledge clock is the chip's, not the master's
+    master_ack: bool, // whether the master wants another byte, while reading
+}
+
+impl Eeprom {
+    pub fn new(chip: Chip) -> Self {
+        Eeprom {
+            chip,
+            data: vec![0xFF; chip.size()], // erased
+            scl: false,
+            sda: false,
+            phase: Phase::Idle,
+            bit: 0,
+            shift: 0,
+            addr: 0,
+            out: true,
+            acking: false,
+            master_ack: false,
+        }
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.data.len() {
+            return Err(format!("is {} bytes, expected a {} byte EEPROM image", data.len(), self.data.len()));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    // The lines as the game sets them through the mapper.
+    pub fn write_lines(&mut self, scl: bool, sda: bool) {
+        let (old_scl, old_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+
+        if old_scl && scl && old_sda != sda {
+            if !sda {
+                self.start();
+            } else {
+                self.phase = Phase::Idle;
+                self.out = true;
+            }
+        } else if !old_scl && scl {
+            self.clock_rising();
+        } else if old_scl && !scl {
+            self.clock_falling();
+        }
+    }
+
+    // What the game reads back on SDA: low when either side pulls it low.
+    pub fn read_sda(&self) -> bool {
+        self.out && self.sda
+    }
+
+    fn start(&mut self) {
+        self.phase = match self.chip {
+            Chip::C24C01 => Phase::Address,
+            Chip::C24C02 => Phase::Device,
+        };
+        self.bit = 0;
+        self.out = true;
+        self.acking = false;
+    }
+
+    fn clock_rising(&mut self) {
+        match (self.phase, self.bit) {
+            (Phase::Idle, _) => return,
+            (Phase::Read, 8) if !self.acking => self.master_ack = !self.sda,
+            (Phase::Read, _) => {}
+            (_, 0..=7) => self.shift = self.shift << 1 | self.sda as u8,
+            _ => {}
+        }
+        self.bit += 1;
+    }
+
+    // The chip changes what it drives while SCL is low.
+    fn clock_falling(&mut self) {
+        if self.phase == Phase::Idle {
+            return;
+        }
+        match self.bit {
+            8 if self.phase == Phase::Read => self.out = true, // released for the master's ack
+            8 => {
+                let byte = match self.chip {
+                    Chip::C24C01 => self.shift.reverse_bits(),
+                    Chip::C24C02 => self.shift,
+                };
+                self.out = !self.receive(byte);
+                self.acking = true;
+            }
+            9 => {
+                self.bit = 0;
+                let acked = std::mem::take(&mut self.acking);
+                if self.phase == Phase::Read {
+                    // a read starts with a byte right after the command, then goes on for as
+                    // long as the master acknowledges them
+                    if !acked && !self.master_ack {
+                        self.phase = Phase::Idle;
+                        self.out = true;
+                        return;
+                    }
+                    self.out = self.data_bit();
+                } else {
+                    self.out = true;
+                }
+            }
+            1..=7 if self.phase == Phase::Read => self.out = self.data_bit(),
+            _ => {}
+        }
+    }
+
+    // The byte just clocked in; returns whether the chip acknowledges it.
+    fn receive(&mut self, byte: u8) -> bool {
+        match self.phase {
+            Phase::Device => {
+                if byte >> 4 != 0b1010 {
+                    self.phase = Phase::Idle; // another device on the bus
+                    return false;
+                }
+                self.phase = if byte & 1 != 0 { self.begin_read() } else { Phase::Address };
+            }
+            Phase::Address if self.chip == Chip::C24C01 => {
+                // sent LSB first, so the read bit came in last
+                self.addr = byte & 0x7F;
+                self.phase = if byte & 0x80 != 0 { self.begin_read() } else { Phase::Write };
+            }
+            Phase::Address => {
+                self.addr = byte;
+                self.phase = Phase::Write;
+            }
+            Phase::Write => {
+                let len = self.data.len();
+                self.data[self.addr as usize % len] = byte;
+                let page = self.chip.page_size();
+                self.addr = (self.addr & !(page - 1)) | (self.addr.wrapping_add(1) & (page - 1));
+            }
+            Phase::Read | Phase::Idle => {}
+        }
+        true
+    }
+
+    // The first byte goes out from the current address; every byte acked moves on to the next.
+    fn begin_read(&mut self) -> Phase {
+        self.addr = self.addr.wrapping_sub(1);
+        Phase::Read
+    }
+
+    fn data_bit(&mut self) -> bool {
+        if self.bit == 0 {
+            self.addr = ((self.addr as usize + 1) % self.data.len()) as u8;
+        }
+        let byte = self.data[self.addr as usize];
+        match self.chip {
+            Chip::C24C01 => byte >> self.bit & 1 != 0,
+            Chip::C24C02 => byte >> (7 - self.bit) & 1 != 0,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.data).bool(self.scl).bool(self.sda);
+        state.u8(self.phase as u8).u8(self.bit).u8(self.shift).u8(self.addr);
+        state.bool(self.out).bool(self.acking).bool(self.master_ack);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let data = state.bytes(self.data.len())?;
+        self.data.copy_from_slice(data);
+        self.scl = state.bool()?;
+        self.sda = state.bool()?;
+        self.phase = match state.u8()? {
+            0 => Phase::Idle,
+            1 => Phase::Device,
+            2 => Phase::Address,
+            3 => Phase::Write,
+            _ => Phase::Read,
+        };
+        self.bit = state.u8()?;
+        self.shift = state.u8()?;
+        self.addr = state.u8()?;
+        self.out = state.bool()?;
+        self.acking = state.bool()?;
+        self.master_ack = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // The game's side of the bus.
+    struct Master<'a>(&'a mut Eeprom);
+
+    impl Master<'_> {
+        fn start(&mut self) {
+            self.0.write_lines(true, true);
+            self.0.write_lines(true, false);
+            self.0.write_lines(false, false);
+        }
+
+        fn stop(&mut self) {
+            self.0.write_lines(false, false);
+            self.0.write_lines(true, false);
+            self.0.write_lines(true, true);
+        }
+
+        fn clock(&mut self, sda: bool) -> bool {
+            self.0.write_lines(false, sda);
+            self.0.write_lines(true, sda);
+            let read = self.0.read_sda();
+            self.0.write_lines(false, sda);
+            read
+        }
+
+        // MSB first; returns whether the chip acked
+        fn send(&mut self, byte: u8) -> bool {
+            for bit in (0..8).rev() {
+                self.clock(byte >> bit & 1 != 0);
+            }
+            !self.clock(true)
+        }
+
+        fn receive(&mut self, ack: bool) -> u8 {
+            let byte = (0..8).fold(0, |byte, _| byte << 1 | self.clock(true) as u8);
+            self.clock(!ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn test_24c02_write_then_read() {
+        let mut eeprom = Eeprom::new(Chip::C24C02);
+        let mut bus = Master(&mut eeprom);
+        bus.start();
+        assert!(bus.send(0xA0));
+        assert!(bus.send(0x10));
+        assert!(bus.send(0x12));
+        assert!(bus.send(0x34));
+        bus.stop();
+
+        // set the address with a write that has no data, then read from it
+        bus.start();
+        assert!(bus.send(0xA0));
+        assert!(bus.send(0x10));
+        bus.start();
+        assert!(bus.send(0xA1));
+        assert_eq!(bus.receive(true), 0x12);
+        assert_eq!(bus.receive(false), 0x34);
+        bus.stop();
+
+        bus.start();
+        assert!(!bus.send(0x50)); // not an EEPROM: no ack
+        bus.stop();
+
+        assert_eq!(&eeprom.data()[0x10..0x13], [0x12, 0x34, 0xFF]);
+    }
+
+    #[test]
+    fn test_24c01_is_lsb_first() {
+        let mut eeprom = Eeprom::new(Chip::C24C01);
+        let mut bus = Master(&mut eeprom);
+        bus.start();
+        assert!(bus.send(0x05u8.reverse_bits())); // address 5, then the write bit
+        assert!(bus.send(0xC3u8.reverse_bits()));
+        bus.stop();
+        assert_eq!(eeprom.data()[0x05], 0xC3);
+    }
+}
@@ -0,0 +1,1009 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::Mapper;
+
+pub const MAPPER: u8 = 20; // the iNES number set aside for the Disk System
+pub const BIOS_SIZE: usize = 0x2000;
+pub const SIDE_SIZE: usize = 65500; // one disk side in a .fds image
+
+const RAM_START: u16 = 0x8000; // $6000-$7FFF is the Bus's PRG-RAM, the rest of the 32KiB is here
+const BIOS_START: u16 = 0xE000;
+
+const FIRST_BYTE_DELAY: u32 = 50_000; // CPU cycles from the motor starting to the first byte
+const BYTE_CYCLES: u32 = 150; // ~96kbit/s
+const SWAP_CYCLES: u32 = 900_000; // half a second with no disk, so the BIOS notices the swap
+
+const SOUND_START: u16 = 0x4040;
+const SOUND_END: u16 = 0x4097;
+
+// The Famicom Disk System: a RAM adapter in the cartridge slot plus a disk drive. Games load
+// from disk into 32KiB of RAM ($6000-$DFFF), the 8KiB BIOS at $E000 does the loading, CHR is
+// 8KiB of RAM. The BIOS isn't part of a game image, the user supplies it (disksys.rom).
+// See: https://www.nesdev.org/wiki/Family_Computer_Disk_System
+//
+// Registers ($4020-$4033):
+//
+//   $4020/$4021  timer IRQ reload, low/high      $4022  timer IRQ control: .... ..ER (enable, repeat)
+//   $4023        master I/O enable (bit 0 disk, bit 1 sound, see FdsAudio for $4040-$4092)
+//   $4024        byte to write to disk
+//   $4025        drive control: IS.C HWRM (IRQ on each byte, ready to transfer, CRC,
+//                horizontal mirroring, read mode, transfer reset, motor on)
+//   $4030        status: timer IRQ (bit 0), byte transferred (bit 1). Reading acknowledges both IRQs
+//   $4031        byte read from disk     $4032  drive status: not inserted, not ready, write protected
+//   $4033        external port, bit 7 is the battery-good flag
+//
+// The drive is a loop of magnetic tape: once the motor runs, the head reads (or writes) one
+// byte every ~150 CPU cycles from the start of the side to its end. On the real disk, blocks
+// are separated by gaps of zeros and each starts with a $80 mark and ends with a CRC; .fds
+// images leave all that out, so disk_side() puts it back in when a side is loaded.
+pub struct Fds {
+    bios: Vec<u8>,
+    ram: Vec<u8>, // $8000-$DFFF
+    sides: Vec<Vec<u8>>, // with gaps, as the head sees them
+    inserted: Option<usize>,
+    swap_to: Option<(usize, u32)>, // side going in once the countdown runs out
+    mirroring: Mirroring,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+
+    disk_enabled: bool,
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    ready: bool, // the BIOS is waiting for a block
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    transferred: bool,
+    read_data: u8,
+    write_data: u8,
+
+    sound_enabled: bool,
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(bios: Vec<u8>, sides: &[Vec<u8>]) -> Self {
+        Fds {
+            bios,
+            ram: vec![0; (BIOS_START - RAM_START) as usize],
+            sides: sides.iter().map(|side| disk_side(side)).collect(),
+            inserted: if sides.is_empty() { None } else { Some(0) },
+            swap_to: None,
+            mirroring: Mirroring::HORIZONTAL,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            disk_enabled: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            transferred: false,
+            read_data: 0,
+            write_data: 0,
+            sound_enabled: false,
+            audio: FdsAudio::new(),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        if let Some((side, countdown)) = self.swap_to {
+            if countdown == 0 {
+                self.inserted = Some(side);
+                self.swap_to = None;
+            } else {
+                self.swap_to = Some((side, countdown - 1));
+            }
+        }
+
+        let side = match self.inserted {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            // back to the start of the side
+            self.delay = FIRST_BYTE_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let data = self.sides[side][self.position];
+            if !self.ready {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // the $80 block mark: the next byte is the block itself
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transferred = true;
+                self.read_data = data;
+                self.disk_irq |= irq;
+            }
+        } else {
+            // CRCs aren't checked on read, so any value does when writing one
+            let data = if self.crc_control {
+                0x00
+            } else {
+                self.transferred = true;
+                self.disk_irq |= irq;
+                if self.ready { self.write_data } else { 0x00 }
+            };
+            self.sides[side][self.position] = data;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        if addr >= BIOS_START {
+            self.bios[(addr - BIOS_START) as usize]
+        } else {
+            self.ram[(addr - RAM_START) as usize]
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < BIOS_START {
+            self.ram[(addr - RAM_START) as usize] = data;
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        // the BIOS is the only ROM
+        addr.checked_sub(BIOS_START).map(usize::from)
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        BIOS_SIZE
+    }
+
+    fn read_expansion(&mut self, addr: u16, open_bus: u8) -> u8 {
+        if let SOUND_START..=SOUND_END = addr {
+            return if self.sound_enabled { self.audio.read(addr, open_bus) } else { open_bus };
+        }
+        if !self.disk_enabled {
+            return open_bus;
+        }
+        match addr {
+            0x4030 => {
+                let status = self.timer_irq as u8 | (self.transferred as u8) << 1;
+                self.transferred = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                status
+            }
+            0x4031 => {
+                self.transferred = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+            0x4032 => {
+                // only the low three bits are wired
+                let empty = self.inserted.is_none();
+                open_bus & 0xF8 | empty as u8 | ((empty || !self.scanning) as u8) << 1 | (empty as u8) << 2
+            }
+            0x4033 => 0x80,
+            _ => open_bus,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | data as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = data & 0b01 != 0;
+                self.irq_enabled = data & 0b10 != 0 && self.disk_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_enabled = data & 1 != 0;
+                self.sound_enabled = data & 2 != 0;
+                if !self.disk_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_enabled => {
+                self.write_data = data;
+                self.transferred = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_enabled => {
+                self.motor_on = data & 0x01 != 0;
+                self.reset_transfer = data & 0x02 != 0;
+                self.read_mode = data & 0x04 != 0;
+                self.mirroring = if data & 0x08 != 0 { Mirroring::HORIZONTAL } else { Mirroring::VERTICAL };
+                self.crc_control = data & 0x10 != 0;
+                self.ready = data & 0x40 != 0;
+                self.disk_irq_enabled = data & 0x80 != 0;
+                self.disk_irq = false;
+            }
+            SOUND_START..=SOUND_END if self.sound_enabled => self.audio.write(addr, data),
+            _ => {}
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.clock_timer();
+            self.clock_drive();
+            self.audio.clock();
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn expansion_audio_sample(&self) -> f32 {
+        self.audio.output()
+    }
+
+    // At full volume the wave is about 2.4 times as loud as an APU pulse (0.15).
+    fn expansion_audio_gain(&self) -> f32 {
+        0.36
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn inserted_disk(&self) -> Option<usize> {
+        self.inserted.or(self.swap_to.map(|(side, _)| side))
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        match side {
+            Some(side) if side < self.sides.len() && self.inserted.is_some() => {
+                // the BIOS only looks for a new disk after seeing the old one go
+                self.inserted = None;
+                self.swap_to = Some((side, SWAP_CYCLES));
+            }
+            Some(side) if side < self.sides.len() => self.inserted = Some(side),
+            Some(_) => {}
+            None => {
+                self.inserted = None;
+                self.swap_to = None;
+            }
+        }
+    }
+
+    // The disks as written, and the side going in if one is being swapped.
+    fn media(&self) -> Option<Vec<u8>> {
+        let mut media = StateWriter::new();
+        media.u8(self.inserted_disk().map_or(0xFF, |side| side as u8));
+        for side in &self.sides {
+            media.bytes(side);
+        }
+        Some(media.finish())
+    }
+
+    fn load_media(&mut self, media: &[u8]) {
+        if media.len() != 1 + self.sides.iter().map(Vec::len).sum::<usize>() {
+            return; // another game's
+        }
+        self.inserted = (media[0] != 0xFF).then_some(media[0] as usize);
+        self.swap_to = None;
+        let mut rest = &media[1..];
+        for side in &mut self.sides {
+            let (saved, more) = rest.split_at(side.len());
+            side.copy_from_slice(saved);
+            rest = more;
+        }
+    }
+
+    // The disks go in too: games save on them, and the BIOS expects the blocks it wrote.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes(&self.ram).u8(self.sides.len() as u8);
+        for side in &self.sides {
+            state.bytes(side);
+        }
+        state.u8(self.inserted.map_or(0xFF, |side| side as u8));
+        match self.swap_to {
+            Some((side, countdown)) => state.u8(side as u8).u32(countdown),
+            None => state.u8(0xFF).u32(0),
+        };
+        state
+            .mirroring(self.mirroring)
+            .u16(self.irq_reload)
+            .u16(self.irq_counter)
+            .bool(self.irq_repeat)
+            .bool(self.irq_enabled)
+            .bool(self.timer_irq)
+            .bool(self.disk_enabled)
+            .bool(self.motor_on)
+            .bool(self.reset_transfer)
+            .bool(self.read_mode)
+            .bool(self.crc_control)
+            .bool(self.ready)
+            .bool(self.disk_irq_enabled)
+            .bool(self.disk_irq)
+            .u32(self.position as u32)
+            .u32(self.delay)
+            .bool(self.end_of_head)
+            .bool(self.scanning)
+            .bool(self.gap_ended)
+            .bool(self.transferred)
+            .u8(self.read_data)
+            .u8(self.write_data)
+            .bool(self.sound_enabled);
+        self.audio.save(&mut state);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("Disk System", data);
+        let ram = state.bytes(self.ram.len())?;
+        if state.u8()? as usize != self.sides.len() {
+            return Err("the savestate is for a game with a different number of disk sides".to_string());
+        }
+        let sides: Vec<&[u8]> = self.sides.iter().map(|side| state.bytes(side.len())).collect::<Result<_, _>>()?;
+        let side = |n: u8| (n != 0xFF).then_some(n as usize);
+        let inserted = side(state.u8()?);
+        let swap_to = side(state.u8()?);
+        let countdown = state.u32()?;
+        let mirroring = state.mirroring()?;
+        let irq_reload = state.u16()?;
+        let irq_counter = state.u16()?;
+        let flags: [u8; 11] = state.array()?;
+        let position = state.u32()? as usize;
+        let delay = state.u32()?;
+        let [end_of_head, scanning, gap_ended, transferred]: [u8; 4] = state.array()?;
+        let read_data = state.u8()?;
+        let write_data = state.u8()?;
+        let sound_enabled = state.bool()?;
+        let mut audio = FdsAudio::new();
+        audio.load(&mut state)?;
+        state.finish()?;
+
+        // all read: only now change the drive
+        self.ram.copy_from_slice(ram);
+        for (side, saved) in self.sides.iter_mut().zip(sides) {
+            side.copy_from_slice(saved);
+        }
+        self.inserted = inserted;
+        self.swap_to = swap_to.map(|side| (side, countdown));
+        self.mirroring = mirroring;
+        self.irq_reload = irq_reload;
+        self.irq_counter = irq_counter;
+        let flag = |n: usize| flags[n] != 0;
+        self.irq_repeat = flag(0);
+        self.irq_enabled = flag(1);
+        self.timer_irq = flag(2);
+        self.disk_enabled = flag(3);
+        self.motor_on = flag(4);
+        self.reset_transfer = flag(5);
+        self.read_mode = flag(6);
+        self.crc_control = flag(7);
+        self.ready = flag(8);
+        self.disk_irq_enabled = flag(9);
+        self.disk_irq = flag(10);
+        self.position = position;
+        self.delay = delay;
+        self.end_of_head = end_of_head != 0;
+        self.scanning = scanning != 0;
+        self.gap_ended = gap_ended != 0;
+        self.transferred = transferred != 0;
+        self.read_data = read_data;
+        self.write_data = write_data;
+        self.sound_enabled = sound_enabled;
+        self.audio = audio;
+        Ok(())
+    }
+}
+
+// The RAM adapter's sound: one channel playing a 64-step waveform the game writes (6 bits a
+// step), with a volume envelope, and a second 64-step table that bends its pitch for vibrato
+// and the warbly FDS bass (the modulation unit). Registers, all write-only but $4040-$407F and
+// the gains at $4090/$4092:
+//
+//   $4040-$407F  the waveform, writable while $4089 bit 7 is set (the output holds meanwhile)
+//   $4080        MDVV VVVV volume envelope: M: off (the gain is V), D: up, V: speed
+//   $4082/$4083  frequency low / EH.. FFFF high 4 bits, E: halts the wave at its start,
+//                H: stops both envelopes
+//   $4084        modulation envelope, as $4080      $4085  modulation counter, 7 bits signed
+//   $4086/$4087  modulation frequency low / H... FFFF, H: halts it so the table can be written
+//   $4088        3 bits into the modulation table, at two entries per write
+//   $4089        W... ..VV  W: waveform writable, V: master volume 2/2, 2/3, 2/4, 2/5
+//   $408A        envelope speed multiplier for both envelopes ($E8 from the BIOS)
+//
+// The wave moves a step each time a 16-bit accumulator overflows with the frequency plus the
+// modulation's pitch offset, so 64 steps take 65536 * 64 / frequency cycles. The modulation
+// table steps the same way with its own frequency, each entry nudging a counter (0, +1, +2,
+// +4, reset, -4, -2, -1) which, scaled by the modulation gain, makes the offset. The real
+// adapter also runs the output through a ~2kHz low-pass filter; that isn't emulated.
+// See: https://www.nesdev.org/wiki/FDS_audio
+#[derive(Debug, Clone)]
+struct FdsAudio {
+    wave: [u8; 64],
+    wave_writable: bool,
+    wave_halted: bool,
+    envelopes_halted: bool,
+    frequency: u16, // 12 bits
+    position: u8,   // of the wave, 0-63
+    accumulator: u16,
+    volume: Envelope,
+    master_volume: u8,
+    envelope_speed: u8,
+
+    mod_table: [u8; 64],
+    mod_halted: bool,
+    mod_frequency: u16,
+    mod_position: u8,
+    mod_accumulator: u16,
+    mod_counter: i8, // -64 to 63
+    modulation: Envelope,
+    pitch_offset: i32, // what the modulation adds to the frequency
+}
+
+// One of the two envelopes: a gain moving up or down by one every 8 * (speed + 1) * the
+// master speed cycles, or set directly.
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+    speed: u8,
+    increase: bool,
+    off: bool,
+    gain: u8, // 0-63; more than 32 is taken as 32 by the volume
+    timer: u32,
+}
+
+// The master volume's steps out of 36, after nesdev's table.
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 18, 14]; // 2/2, 2/3, 2/4, 2/5
+// What each modulation table entry does to the counter; None resets it.
+const MOD_STEPS: [Option<i8>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+
+impl Envelope {
+    fn write(&mut self, data: u8, master_speed: u8) {
+        self.speed = data & 0x3F;
+        self.increase = data & 0x40 != 0;
+        self.off = data & 0x80 != 0;
+        if self.off {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.off || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.speed).bool(self.increase).bool(self.off).u8(self.gain).u32(self.timer);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.speed = state.u8()?;
+        self.increase = state.bool()?;
+        self.off = state.bool()?;
+        self.gain = state.u8()?;
+        self.timer = state.u32()?;
+        Ok(())
+    }
+}
+
+impl FdsAudio {
+    fn new() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_writable: false,
+            wave_halted: true,
+            envelopes_halted: false,
+            frequency: 0,
+            position: 0,
+            accumulator: 0,
+            volume: Envelope::default(),
+            master_volume: 0,
+            envelope_speed: 0xE8,
+            mod_table: [0; 64],
+            mod_halted: true,
+            mod_frequency: 0,
+            mod_position: 0,
+            mod_accumulator: 0,
+            mod_counter: 0,
+            modulation: Envelope::default(),
+            pitch_offset: 0,
+        }
+    }
+
+    fn read(&mut self, addr: u16, open_bus: u8) -> u8 {
+        match addr {
+            0x4040..=0x407F => open_bus & 0xC0 | self.wave[(addr - 0x4040) as usize],
+            0x4090 => open_bus & 0xC0 | self.volume.gain,
+            0x4092 => open_bus & 0xC0 | self.modulation.gain,
+            _ => open_bus,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_writable => self.wave[(addr - 0x4040) as usize] = data & 0x3F,
+            0x4080 => self.volume.write(data, self.envelope_speed),
+            0x4082 => self.frequency = self.frequency & 0x0F00 | data as u16,
+            0x4083 => {
+                self.frequency = self.frequency & 0x00FF | (data as u16 & 0x0F) << 8;
+                self.wave_halted = data & 0x80 != 0;
+                self.envelopes_halted = data & 0x40 != 0;
+                if self.wave_halted {
+                    self.position = 0;
+                    self.accumulator = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.modulation.reset_timer(self.envelope_speed);
+                }
+            }
+            0x4084 => self.modulation.write(data, self.envelope_speed),
+            0x4085 => {
+                self.mod_counter = ((data << 1) as i8) >> 1; // 7 bits, sign extended
+                self.update_pitch();
+            }
+            0x4086 => self.mod_frequency = self.mod_frequency & 0x0F00 | data as u16,
+            0x4087 => {
+                self.mod_frequency = self.mod_frequency & 0x00FF | (data as u16 & 0x0F) << 8;
+                self.mod_halted = data & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+                self.update_pitch();
+            }
+            0x4088 if self.mod_halted => {
+                for _ in 0..2 {
+                    self.mod_table[self.mod_position as usize] = data & 0x07;
+                    self.mod_position = (self.mod_position + 1) & 0x3F;
+                }
+            }
+            0x4089 => {
+                self.wave_writable = data & 0x80 != 0;
+                self.master_volume = data & 0x03;
+            }
+            0x408A => self.envelope_speed = data,
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.clock(self.envelope_speed);
+            self.modulation.clock(self.envelope_speed);
+        }
+
+        if !self.mod_halted && self.mod_frequency > 0 {
+            let (sum, overflowed) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+            self.mod_accumulator = sum;
+            if overflowed {
+                self.mod_counter = match MOD_STEPS[self.mod_table[self.mod_position as usize] as usize] {
+                    Some(step) => (((self.mod_counter + step) as u8) << 1) as i8 >> 1, // wraps at 7 bits
+                    None => 0,
+                };
+                self.mod_position = (self.mod_position + 1) & 0x3F;
+            }
+        }
+        self.update_pitch();
+
+        let frequency = self.frequency as i32 + self.pitch_offset;
+        if !self.wave_halted && !self.wave_writable && frequency > 0 {
+            let (sum, overflowed) = self.accumulator.overflowing_add(frequency as u16);
+            self.accumulator = sum;
+            if overflowed {
+                self.position = (self.position + 1) & 0x3F;
+            }
+        }
+    }
+
+    // The modulation's pitch offset from its counter and gain, rounded the way the chip does
+    // (nesdev's reference code). None while the modulation is halted.
+    fn update_pitch(&mut self) {
+        if self.mod_halted || self.mod_frequency == 0 {
+            self.pitch_offset = 0;
+            return;
+        }
+        let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        let product = self.frequency as i32 * temp;
+        self.pitch_offset = (product >> 6) + (product & 0x3F >= 32) as i32;
+    }
+
+    // 0.0-1.0
+    fn output(&self) -> f32 {
+        let gain = self.volume.gain.min(32) as u32 * MASTER_VOLUMES[self.master_volume as usize];
+        let level = self.wave[self.position as usize] as u32 * gain / 1152; // 0-63
+        level as f32 / 63.0
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state
+            .bytes(&self.wave)
+            .bool(self.wave_writable)
+            .bool(self.wave_halted)
+            .bool(self.envelopes_halted)
+            .u16(self.frequency)
+            .u8(self.position)
+            .u16(self.accumulator)
+            .u8(self.master_volume)
+            .u8(self.envelope_speed);
+        self.volume.save(state);
+        state
+            .bytes(&self.mod_table)
+            .bool(self.mod_halted)
+            .u16(self.mod_frequency)
+            .u8(self.mod_position)
+            .u16(self.mod_accumulator)
+            .u8(self.mod_counter as u8);
+        self.modulation.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.wave = state.array()?;
+        self.wave_writable = state.bool()?;
+        self.wave_halted = state.bool()?;
+        self.envelopes_halted = state.bool()?;
+        self.frequency = state.u16()?;
+        self.position = state.u8()? & 0x3F;
+        self.accumulator = state.u16()?;
+        self.master_volume = state.u8()? & 0x03;
+        self.envelope_speed = state.u8()?;
+        self.volume.load(state)?;
+        self.mod_table = state.array()?;
+        self.mod_halted = state.bool()?;
+        self.mod_frequency = state.u16()?;
+        self.mod_position = state.u8()? & 0x3F;
+        self.mod_accumulator = state.u16()?;
+        self.mod_counter = state.u8()? as i8;
+        self.modulation.load(state)?;
+        self.update_pitch();
+        Ok(())
+    }
+}
+
+// A side from a .fds image, the way the head sees it: a lead-in gap, then each block as the $80
+// mark, the block, two CRC bytes and a gap. The block lengths come from the blocks themselves;
+// the first byte that isn't a block type is where the data on the side ends.
+pub fn disk_side(image: &[u8]) -> Vec<u8> {
+    const LEAD_IN: usize = 28300 / 8;
+    const GAP: usize = 976 / 8;
+
+    let mut side = vec![0; LEAD_IN];
+    let mut pos = 0;
+    while pos < image.len() {
+        let len = match image[pos] {
+            1 => 56, // disk info
+            2 => 2,  // file count
+            3 => 16, // file header
+            4 => {
+                // file data: its size is in the header just before it
+                if pos < 3 {
+                    break;
+                }
+                1 + u16::from_le_bytes([image[pos - 3], image[pos - 2]]) as usize
+            }
+            _ => break,
+        };
+        let block = &image[pos..(pos + len).min(image.len())];
+        side.push(0x80);
+        side.extend_from_slice(block);
+        side.extend([0x4D, 0x62]); // CRC, not checked
+        side.resize(side.len() + GAP, 0);
+        pos += len;
+    }
+    side.resize(side.len().max(SIDE_SIZE + LEAD_IN), 0);
+    side
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // One file, "HI", on side A.
+    fn image() -> Vec<u8> {
+        let mut side = vec![0x01];
+        side.extend(b"*NINTENDO-HVC*");
+        side.resize(56, 0);
+        side.extend([0x02, 0x01]);
+        let mut header = vec![0x03, 0, 0];
+        header.extend(b"FILE0001");
+        header.extend([0x00, 0x60, 2, 0, 0]); // load at $6000, 2 bytes, PRG
+        side.extend(header);
+        side.extend([0x04, b'H', b'I']);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    fn test_gaps_are_added() {
+        let side = disk_side(&image());
+        let first = side.iter().position(|&b| b != 0).unwrap();
+        assert_eq!(&side[first..first + 16], b"\x80\x01*NINTENDO-HVC*");
+        let data = side.windows(4).position(|w| w == b"\x80\x04HI").unwrap();
+        assert!(data > first + 56 + 2 + 16);
+    }
+
+    #[test]
+    fn test_reading_the_disk() {
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], &[image()]);
+        fds.write_expansion(0x4023, 0x01);
+        assert_eq!(fds.read_expansion(0x4032, 0) & 0b101, 0); // inserted, writable
+
+        // motor on, read mode; then wait for the block like the BIOS does
+        fds.write_expansion(0x4025, 0b0010_0101);
+        fds.clock_cpu(255);
+        fds.write_expansion(0x4025, 0b1110_0101);
+        let mut read = vec![];
+        for _ in 0..1_000_000 {
+            fds.clock_cpu(1);
+            if fds.irq_pending() {
+                read.push(fds.read_expansion(0x4031, 0));
+                if read.len() == 15 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(read, b"\x01*NINTENDO-HVC*");
+        assert_eq!(fds.read_expansion(0x4032, 0) & 0b10, 0); // ready
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], &[]);
+        fds.write_expansion(0x4023, 0x01);
+        fds.write_expansion(0x4020, 10);
+        fds.write_expansion(0x4021, 0);
+        fds.write_expansion(0x4022, 0b10); // enabled, one shot
+        fds.clock_cpu(10);
+        assert!(!fds.irq_pending());
+        fds.clock_cpu(1);
+        assert!(fds.irq_pending());
+        assert_eq!(fds.read_expansion(0x4030, 0) & 1, 1);
+        assert!(!fds.irq_pending());
+        fds.clock_cpu(100);
+        assert!(!fds.irq_pending());
+    }
+
+    #[test]
+    fn test_sound_unit() {
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], &[]);
+        fds.write_expansion(0x4023, 0x83);
+        // a square wave: 32 steps high, 32 low
+        fds.write_expansion(0x4089, 0x80);
+        for i in 0..64 {
+            fds.write_expansion(0x4040 + i, if i < 32 { 0xFF } else { 0 });
+        }
+        fds.write_expansion(0x4089, 0x00); // master volume 2/2
+        assert_eq!(fds.read_expansion(0x4040, 0xFF), 0xFF);
+        assert_eq!(fds.read_expansion(0x4040, 0x00), 0x3F);
+
+        fds.write_expansion(0x4080, 0x80 | 32); // no envelope, full gain
+        fds.write_expansion(0x4082, 0x00);
+        fds.write_expansion(0x4083, 0x04); // frequency $400: a step every 64 cycles
+        assert_eq!(fds.expansion_audio_sample(), 1.0);
+        for _ in 0..32 {
+            fds.clock_cpu(64);
+        }
+        assert_eq!(fds.expansion_audio_sample(), 0.0);
+        for _ in 0..32 {
+            fds.clock_cpu(64);
+        }
+        assert_eq!(fds.expansion_audio_sample(), 1.0);
+
+        fds.write_expansion(0x4089, 0x02); // master volume 2/4: half, 31.5 rounded down
+        assert_eq!(fds.expansion_audio_sample(), 31.0 / 63.0);
+        fds.write_expansion(0x4089, 0x03); // master volume 2/5
+        assert_eq!(fds.expansion_audio_sample(), (63 * 32 * 14 / 1152) as f32 / 63.0);
+
+        // the volume envelope, from 0: up a step every 8 * 1 * $E8 cycles
+        fds.write_expansion(0x4080, 0x80);
+        fds.write_expansion(0x4080, 0x40);
+        for _ in 0..3 * 8 * 0xE8 {
+            fds.clock_cpu(1);
+        }
+        assert_eq!(fds.read_expansion(0x4090, 0) & 0x3F, 3);
+
+        // sound off in $4023: the registers don't answer
+        fds.write_expansion(0x4023, 0x01);
+        fds.write_expansion(0x4080, 0x80);
+        fds.write_expansion(0x4023, 0x83);
+        assert_eq!(fds.read_expansion(0x4090, 0) & 0x3F, 3);
+    }
+
+    #[test]
+    fn test_modulation() {
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], &[]);
+        fds.write_expansion(0x4023, 0x83);
+        fds.write_expansion(0x4082, 0x00);
+        fds.write_expansion(0x4083, 0x04);
+        fds.write_expansion(0x4084, 0x80 | 16); // modulation gain 16
+        fds.write_expansion(0x4085, 1);
+        fds.write_expansion(0x4087, 0x80); // halted: the table can be written
+        for _ in 0..32 {
+            fds.write_expansion(0x4088, 0); // entries that leave the counter alone
+        }
+        assert_eq!(fds.audio.pitch_offset, 0);
+
+        // counter 1 * gain 16 / 16 = 1, times the frequency / 64
+        fds.write_expansion(0x4086, 0x01);
+        fds.write_expansion(0x4087, 0x00);
+        fds.clock_cpu(1);
+        assert_eq!(fds.audio.pitch_offset, 0x400 / 64);
+        fds.write_expansion(0x4085, 0x7F); // -1
+        assert_eq!(fds.audio.pitch_offset, -(0x400 / 64));
+
+        // a +1 table entry steps the counter each time the table moves on
+        fds.write_expansion(0x4087, 0x80);
+        for _ in 0..32 {
+            fds.write_expansion(0x4088, 1);
+        }
+        fds.write_expansion(0x4085, 0);
+        fds.write_expansion(0x4086, 0x00);
+        fds.write_expansion(0x4087, 0x08); // $800: a step every 32 cycles
+        fds.clock_cpu(32 * 3);
+        assert_eq!(fds.audio.mod_counter, 3);
+
+        let state = fds.save_state();
+        let mut loaded = Fds::new(vec![0; BIOS_SIZE], &[]);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.audio.mod_counter, 3);
+        assert_eq!(loaded.audio.pitch_offset, fds.audio.pitch_offset);
+    }
+
+    #[test]
+    fn test_disk_writes_survive_a_power_cycle() {
+        use crate::cartridge::Rom;
+        use crate::nes::Nes;
+
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[..3].copy_from_slice(&[0x4C, 0x00, 0xE0]); // JMP $E000
+        bios[0x1FFC..0x1FFE].copy_from_slice(&[0x00, 0xE0]);
+        let mut raw = image();
+        raw.extend(image());
+        let mut rom = Rom::new(&raw).unwrap();
+        rom.prg_rom = bios;
+        let mut nes = Nes::from_rom(rom).unwrap();
+
+        // side B in, then $AB written from the start of it
+        let mapper = nes.cpu().bus.mapper().clone();
+        mapper.borrow_mut().write_expansion(0x4023, 0x01);
+        mapper.borrow_mut().insert_disk(Some(1));
+        for _ in 0..SWAP_CYCLES / 200 + 1 {
+            mapper.borrow_mut().clock_cpu(200);
+        }
+        mapper.borrow_mut().write_expansion(0x4024, 0xAB);
+        mapper.borrow_mut().write_expansion(0x4025, 0b0110_0001); // motor on, write mode, ready
+        for _ in 0..(FIRST_BYTE_DELAY + 100 * BYTE_CYCLES) / 10 {
+            mapper.borrow_mut().clock_cpu(10);
+        }
+        mapper.borrow_mut().write_expansion(0x4025, 0b0010_0100); // motor off
+
+        nes.power_cycle();
+        assert!(!std::rc::Rc::ptr_eq(&mapper, nes.cpu().bus.mapper())); // a new board
+        assert_eq!(nes.inserted_disk(), Some(1));
+        let mapper = nes.cpu().bus.mapper().clone();
+        mapper.borrow_mut().write_expansion(0x4023, 0x01);
+        mapper.borrow_mut().write_expansion(0x4025, 0b0010_0101);
+        mapper.borrow_mut().clock_cpu(255);
+        mapper.borrow_mut().write_expansion(0x4025, 0b1110_0101);
+        let mut read = vec![];
+        for _ in 0..1_000_000 {
+            mapper.borrow_mut().clock_cpu(1);
+            if mapper.borrow().irq_pending() {
+                read.push(mapper.borrow_mut().read_expansion(0x4031, 0));
+                if read.len() == 10 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(read, [0xAB; 10]); // the first $AB was taken for a block mark
+    }
+
+    #[test]
+    fn test_swapping_sides_ejects_first() {
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], &[image(), image()]);
+        fds.write_expansion(0x4023, 0x01);
+        fds.insert_disk(Some(1));
+        assert_eq!(fds.read_expansion(0x4032, 0) & 1, 1);
+        for _ in 0..SWAP_CYCLES / 200 + 1 {
+            fds.clock_cpu(200);
+        }
+        assert_eq!(fds.read_expansion(0x4032, 0) & 1, 0);
+        assert_eq!(fds.inserted_disk(), Some(1));
+    }
+}
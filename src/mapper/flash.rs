@@ -0,0 +1,162 @@
+use crate::mapper::state::{StateReader, StateWriter};
+
+// PRG flash on self-flashing boards (UNROM-512 and other homebrew carts): the PRG ROM chip is
+// an SST39SF0x0 the game can rewrite, to keep save games or downloaded levels. The chip
+// ignores plain writes; a command is a sequence of writes to magic addresses (only the low 15
+// bits count), and the last write of it does the work:
+//
+//   $5555=AA $2AAA=55 $5555=A0, addr=data          program a byte (bits can only go 1 -> 0)
+//   $5555=AA $2AAA=55 $5555=80 $5555=AA $2AAA=55, sector=30
+//                                                  erase a 4KiB sector back to $FF
+//   ... $5555=80 $5555=AA $2AAA=55 $5555=10        erase the whole chip
+//   $5555=AA $2AAA=55 $5555=90                     read the chip's ID, until an F0 write
+//
+// Addresses here are offsets into the chip; the mapper works them out from its bank register.
+// A flashed game persists as a .flash file next to the ROM, the whole chip image, so the
+// original ROM file stays untouched (see crate::sram).
+// See: https://www.nesdev.org/wiki/UNROM_512#Flash_ROM
+
+const SECTOR_SIZE: usize = 0x1000;
+const MANUFACTURER_ID: u8 = 0xBF; // SST
+const DEVICE_ID: u8 = 0xB7; // 39SF040
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Read,
+    Program, // the next write is the byte to program
+    Id,
+}
+
+pub struct Flash {
+    data: Vec<u8>,
+    unlock: u8, // how far into a command sequence the writes are, 0-5
+    erase: bool, // the sequence so far had the erase command in it
+    mode: Mode,
+}
+
+impl Flash {
+    pub fn new(data: Vec<u8>) -> Self {
+        Flash { data, unlock: 0, erase: false, mode: Mode::Read }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.data.len() {
+            return Err(format!("is {} bytes, expected a {} byte flash image", data.len(), self.data.len()));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        match self.mode {
+            Mode::Id => [MANUFACTURER_ID, DEVICE_ID][offset & 1],
+            _ => self.data[offset % self.data.len()],
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.mode == Mode::Program {
+            let len = self.data.len();
+            self.data[offset % len] &= data;
+            self.mode = Mode::Read;
+            return;
+        }
+        if data == 0xF0 {
+            self.reset();
+            return;
+        }
+
+        let command = offset & 0x7FFF;
+        self.unlock = match (self.unlock, command, data) {
+            (0 | 3, 0x5555, 0xAA) => self.unlock + 1,
+            (1 | 4, 0x2AAA, 0x55) => self.unlock + 1,
+            (2, 0x5555, 0xA0) => {
+                self.mode = Mode::Program;
+                0
+            }
+            (2, 0x5555, 0x90) => {
+                self.mode = Mode::Id;
+                0
+            }
+            (2, 0x5555, 0x80) => {
+                self.erase = true;
+                3
+            }
+            (5, 0x5555, 0x10) if self.erase => {
+                self.data.fill(0xFF);
+                0
+            }
+            (5, _, 0x30) if self.erase => {
+                let sector = offset % self.data.len() / SECTOR_SIZE * SECTOR_SIZE;
+                let end = (sector + SECTOR_SIZE).min(self.data.len());
+                self.data[sector..end].fill(0xFF);
+                0
+            }
+            _ => 0, // anything out of sequence cancels it
+        };
+        if self.unlock == 0 {
+            self.erase = false;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.unlock = 0;
+        self.erase = false;
+        self.mode = Mode::Read;
+    }
+
+    // The whole chip goes in: what the game flashed is part of the state it's in.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.data).u8(self.unlock).bool(self.erase).u8(self.mode as u8);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let data = state.bytes(self.data.len())?;
+        self.data.copy_from_slice(data);
+        self.unlock = state.u8()?;
+        self.erase = state.bool()?;
+        self.mode = match state.u8()? {
+            0 => Mode::Read,
+            1 => Mode::Program,
+            _ => Mode::Id,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn command(flash: &mut Flash, writes: &[(usize, u8)]) {
+        for &(offset, data) in writes {
+            flash.write(offset, data);
+        }
+    }
+
+    #[test]
+    fn test_program_and_erase() {
+        let mut flash = Flash::new(vec![0xFF; 0x8000]);
+        flash.write(0x1234, 0x00); // a plain write does nothing
+        assert_eq!(flash.read(0x1234), 0xFF);
+
+        command(&mut flash, &[(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0xA0), (0x1234, 0x5A)]);
+        assert_eq!(flash.read(0x1234), 0x5A);
+        command(&mut flash, &[(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0xA0), (0x1234, 0xF0)]);
+        assert_eq!(flash.read(0x1234), 0x50); // only clears bits
+
+        command(&mut flash, &[(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0x90)]);
+        assert_eq!((flash.read(0), flash.read(1)), (MANUFACTURER_ID, DEVICE_ID));
+        flash.write(0, 0xF0);
+        assert_eq!(flash.read(0x1234), 0x50);
+
+        let erase = [(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0x80), (0x5555, 0xAA), (0x2AAA, 0x55)];
+        command(&mut flash, &erase);
+        flash.write(0x1000, 0x30);
+        assert_eq!(flash.read(0x1234), 0xFF);
+    }
+}
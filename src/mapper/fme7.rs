@@ -0,0 +1,344 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8KiB
+const CHR_BANK_SIZE: usize = 0x0400; // 1KiB
+
+// Mapper 69 (Sunsoft FME-7, and the 5A/5B with sound): Gimmick!, Batman: Return of the Joker,
+// Hebereke. See: https://www.nesdev.org/wiki/Sunsoft_FME-7
+//
+// Two registers: $8000-$9FFF picks a command, $A000-$BFFF writes its parameter.
+//
+//   0-7: 1KiB CHR banks
+//   8:   $6000-$7FFF: E R BBBBBB (E: RAM enabled, R: RAM instead of ROM, ROM bank); E isn't
+//        emulated, RAM selected always answers
+//   9-B: 8KiB PRG banks at $8000, $A000, $C000 ($E000 is fixed to the last bank)
+//   C:   mirroring: vertical, horizontal, single screen A, single screen B
+//   D:   IRQ control: C...... I (C: counter counts, I: IRQ enabled); writing acknowledges
+//   E,F: IRQ counter low, high byte
+//
+// The IRQ counter is 16 bits and counts down every CPU cycle; wrapping from $0000 to $FFFF
+// fires the IRQ.
+//
+// The 5B adds a Yamaha/AY-3-8910-style sound chip at $C000 (register select) and $E000
+// (data). Its three square channels are emulated; the noise generator and envelope are not
+// (Gimmick!, the one game that uses the 5B, sticks to plain squares).
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 1KiB CHR banks on the cartridge
+
+    command: u8,
+    chr: [u8; 8],
+    prg: [u8; 3],
+    low_bank: u8, // command 8
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize) -> Self {
+        Fme7 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            command: 0,
+            chr: [0; 8],
+            prg: [0; 3],
+            low_bank: 0,
+            mirroring: Mirroring::VERTICAL,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::default(),
+        }
+    }
+
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    fn bank_offset(&self, bank: usize, addr: u16) -> usize {
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000..=0xDFFF => (self.prg[(addr as usize - 0x8000) / PRG_BANK_SIZE] & 0x3F) as usize,
+            _ => self.prg_banks() - 1,
+        };
+        self.bank_offset(bank, addr)
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0..=7 => self.chr[self.command as usize] = data,
+            8 => self.low_bank = data,
+            9..=0xB => self.prg[(self.command - 9) as usize] = data,
+            0xC => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_A,
+                    _ => Mirroring::SINGLE_SCREEN_B,
+                };
+            }
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.counter = (self.counter & 0xFF00) | data as u16,
+            _ => self.counter = (self.counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn read_low(&self, addr: u16) -> Option<u8> {
+        if self.low_bank & 0x40 != 0 {
+            return None; // PRG-RAM
+        }
+        Some(self.prg_rom[self.bank_offset((self.low_bank & 0x3F) as usize, addr)])
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.select(data),
+            _ => self.audio.write(data),
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = self.chr[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank % self.chr_banks) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.counter_enabled {
+                self.counter = self.counter.wrapping_sub(1);
+                if self.counter == 0xFFFF && self.irq_enabled {
+                    self.irq_pending = true;
+                }
+            }
+            self.audio.clock();
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn expansion_audio_sample(&self) -> f32 {
+        self.audio.output()
+    }
+
+    // Each square at full volume about as loud as an APU pulse (0.15).
+    fn expansion_audio_gain(&self) -> f32 {
+        0.45
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state
+            .u8(self.command)
+            .bytes(&self.chr)
+            .bytes(&self.prg)
+            .u8(self.low_bank)
+            .mirroring(self.mirroring)
+            .bool(self.irq_enabled)
+            .bool(self.counter_enabled)
+            .u16(self.counter)
+            .bool(self.irq_pending);
+        self.audio.save(&mut state);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("FME-7", data);
+        self.command = state.u8()?;
+        self.chr = state.array()?;
+        self.prg = state.array()?;
+        self.low_bank = state.u8()?;
+        self.mirroring = state.mirroring()?;
+        self.irq_enabled = state.bool()?;
+        self.counter_enabled = state.bool()?;
+        self.counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+        self.audio.load(&mut state)?;
+        state.finish()
+    }
+}
+
+// The 5B's square channels. Registers (picked through $C000, written through $E000):
+//   0-5: 12 bit tone period of channels A, B, C (low byte, then high nibble)
+//   7:   mixer, ..NNNTTT, a set T bit silences that channel's tone
+//   8-A: channel volume, ...EVVVV (E: use the envelope instead, not emulated)
+// A channel toggles its square every `period` steps, and steps once every 16 CPU cycles.
+// Volume is logarithmic, 3dB per step.
+#[derive(Default)]
+struct Sunsoft5b {
+    register: u8,
+    regs: [u8; 16],
+    timers: [u16; 3],
+    high: [bool; 3],
+    divider: u8,
+}
+
+impl Sunsoft5b {
+    fn select(&mut self, data: u8) {
+        // the top nibble must be 0, or the write is ignored (the chip's "chip select")
+        self.register = if data & 0xF0 == 0 { data } else { 0xFF };
+    }
+
+    fn write(&mut self, data: u8) {
+        if let Some(reg) = self.regs.get_mut(self.register as usize) {
+            *reg = data;
+        }
+    }
+
+    fn period(&self, channel: usize) -> u16 {
+        (self.regs[channel * 2] as u16 | (self.regs[channel * 2 + 1] as u16 & 0x0F) << 8).max(1)
+    }
+
+    fn clock(&mut self) {
+        self.divider = (self.divider + 1) & 0x0F;
+        if self.divider != 0 {
+            return;
+        }
+        for channel in 0..3 {
+            self.timers[channel] += 1;
+            if self.timers[channel] >= self.period(channel) {
+                self.timers[channel] = 0;
+                self.high[channel] = !self.high[channel];
+            }
+        }
+    }
+
+    // 0.0-1.0 with all three channels high at full volume.
+    fn output(&self) -> f32 {
+        let mut sum = 0.0;
+        for channel in 0..3 {
+            let tone_on = self.regs[7] & (1 << channel) == 0;
+            let volume = self.regs[8 + channel] & 0x0F;
+            if tone_on && self.high[channel] && volume > 0 {
+                sum += 10f32.powf((volume as f32 - 15.0) * 3.0 / 20.0);
+            }
+        }
+        sum / 3.0
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.register).bytes(&self.regs);
+        for (timer, high) in self.timers.iter().zip(self.high) {
+            state.u16(*timer).bool(high);
+        }
+        state.u8(self.divider);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.register = state.u8()?;
+        self.regs = state.array()?;
+        for channel in 0..3 {
+            self.timers[channel] = state.u16()?;
+            self.high[channel] = state.bool()?;
+        }
+        self.divider = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mapper::test::banked_prg;
+
+    fn command(fme7: &mut Fme7, command: u8, parameter: u8) {
+        fme7.write_prg(0x8000, command);
+        fme7.write_prg(0xA000, parameter);
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut fme7 = Fme7::new(banked_prg(32, PRG_BANK_SIZE), 0x40000);
+        command(&mut fme7, 9, 4);
+        command(&mut fme7, 0xA, 5);
+        command(&mut fme7, 0xB, 6);
+        command(&mut fme7, 5, 200);
+        command(&mut fme7, 0xC, 1);
+        assert_eq!(fme7.read_prg(0x8000), 4);
+        assert_eq!(fme7.read_prg(0xA000), 5);
+        assert_eq!(fme7.read_prg(0xC000), 6);
+        assert_eq!(fme7.read_prg(0xE000), 31);
+        assert_eq!(fme7.map_chr(0x1410), 200 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(fme7.mirroring(), Mirroring::HORIZONTAL);
+
+        // $6000: ROM bank 7, then RAM
+        command(&mut fme7, 8, 7);
+        assert_eq!(fme7.read_low(0x6000), Some(7));
+        command(&mut fme7, 8, 0xC0);
+        assert_eq!(fme7.read_low(0x6000), None);
+    }
+
+    #[test]
+    fn test_irq_fires_on_wrap() {
+        let mut fme7 = Fme7::new(banked_prg(8, PRG_BANK_SIZE), 0x2000);
+        command(&mut fme7, 0xE, 2);
+        command(&mut fme7, 0xF, 0);
+        command(&mut fme7, 0xD, 0x81);
+
+        fme7.clock_cpu(2); // 1, 0
+        assert!(!fme7.irq_pending());
+        fme7.clock_cpu(1); // wraps to $FFFF
+        assert!(fme7.irq_pending());
+
+        command(&mut fme7, 0xD, 0x81); // acknowledge
+        assert!(!fme7.irq_pending());
+        fme7.clock_cpu(100);
+        assert!(!fme7.irq_pending());
+    }
+
+    #[test]
+    fn test_5b_square() {
+        let mut fme7 = Fme7::new(banked_prg(8, PRG_BANK_SIZE), 0x2000);
+        let mut audio = |reg: u8, data: u8| {
+            fme7.write_prg(0xC000, reg);
+            fme7.write_prg(0xE000, data);
+        };
+        audio(0, 2); // channel A: period 2
+        audio(7, 0b110); // only A's tone
+        audio(8, 15);
+
+        assert_eq!(fme7.expansion_audio_sample(), 0.0);
+        fme7.clock_cpu(32); // two steps: goes high
+        assert_eq!(fme7.expansion_audio_sample(), 1.0 / 3.0);
+        fme7.clock_cpu(32);
+        assert_eq!(fme7.expansion_audio_sample(), 0.0);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::flash::Flash;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper, SaveDevice};
+
+const PRG_BANK_SIZE: usize = 0x8000; // 32KiB
+const CHR_BANK_SIZE: usize = 0x2000; // 8KiB
+const NAMETABLES: usize = 0x4000; // where the nametable pages start in the board's RAM
+pub const CHR_RAM_SIZE: usize = 0x8000;
+
+// Mapper 111 (GTROM, sold as Cheapocabra): Membler Industries' homebrew board. 512KiB of PRG
+// flash, and 32KiB of RAM on the PPU side: two 8KiB CHR pages, then two 8KiB nametable
+// pages. The nametables all come from the board's RAM, so it's four-screen without the
+// console's VRAM. One register, at $5000-$5FFF and again at $7000-$7FFF:
+//
+//   GRNC PPPP
+//   |||| ++++- 32KiB PRG bank at $8000
+//   |||+------ CHR-RAM page
+//   ||+------- nametable page
+//   ++-------- the red and green LEDs on the cartridge (not shown)
+//
+// The PRG is always flash; writes to $8000-$FFFF go to the chip (see crate::mapper::flash),
+// and games that save that way have the battery bit set.
+// See: https://www.nesdev.org/wiki/GTROM
+pub struct Gtrom {
+    flash: Flash,
+    saves: bool,
+    register: u8,
+}
+
+impl Gtrom {
+    pub fn new(prg_rom: Vec<u8>, saves: bool) -> Self {
+        Gtrom { flash: Flash::new(prg_rom), saves, register: 0 }
+    }
+
+    // Offset in PRG of a CPU access at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = (self.register & 0x0F) as usize;
+        mapper::prg_offset(self.flash.data().len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Gtrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.flash.read(self.prg_offset(addr))
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let offset = self.prg_offset(addr);
+        self.flash.write(offset, data);
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        if let 0x5000..=0x5FFF = addr {
+            self.register = data;
+        }
+    }
+
+    fn write_low(&mut self, addr: u16, data: u8) {
+        if let 0x7000..=0x7FFF = addr {
+            self.register = data;
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        (self.register >> 4 & 1) as usize * CHR_BANK_SIZE + addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FOUR_SCREEN
+    }
+
+    fn chr_nametable(&self, index: usize) -> Option<usize> {
+        let page = (self.register >> 5 & 1) as usize;
+        Some(NAMETABLES + page * 0x2000 + index * 0x400)
+    }
+
+    fn save_device(&self) -> Option<SaveDevice> {
+        self.saves.then_some(SaveDevice::Flash)
+    }
+
+    fn save_device_data(&self) -> Vec<u8> {
+        self.flash.data().to_vec()
+    }
+
+    fn load_save_device(&mut self, data: &[u8]) -> Result<(), String> {
+        self.flash.load(data)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.register);
+        self.flash.save_state(&mut state);
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("GTROM", data);
+        self.register = state.u8()?;
+        self.flash.load_state(&mut state)?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        let prg = (0..16).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut gtrom = Gtrom::new(prg, false);
+        assert_eq!(gtrom.read_prg(0xFFFC), 0);
+
+        gtrom.write_expansion(0x5000, 0b0011_0101);
+        assert_eq!(gtrom.read_prg(0x8000), 5);
+        assert_eq!(gtrom.map_chr(0x0123), CHR_BANK_SIZE + 0x123);
+        assert_eq!(gtrom.chr_nametable(3), Some(0x6000 + 0xC00));
+
+        gtrom.write_low(0x7FFF, 0b0000_0010); // the mirror at $7000
+        assert_eq!(gtrom.read_prg(0x8000), 2);
+        assert_eq!(gtrom.chr_nametable(0), Some(0x4000));
+        gtrom.write_low(0x6000, 0x0F); // PRG-RAM, not the register
+        assert_eq!(gtrom.read_prg(0x8000), 2);
+    }
+}
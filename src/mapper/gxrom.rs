@@ -0,0 +1,113 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x8000; // 32KiB
+const CHR_BANK_SIZE: usize = 0x2000; // 8KiB
+
+// Mapper 66 (GxROM): Super Mario Bros. + Duck Hunt, Dragon Power, Gumshoe.
+// Mapper 140 (Jaleco JF-11/JF-14): Bio Senshi Dan, Mississippi Satsujin Jiken.
+// See: https://www.nesdev.org/wiki/GxROM and https://www.nesdev.org/wiki/INES_Mapper_140
+//
+// One register, the same on both boards:
+//
+//   ..PP..CC
+//     ||  ++- 8KiB CHR bank
+//     ++----- 32KiB PRG bank
+//
+// GxROM decodes it anywhere in $8000-$FFFF, the Jaleco boards in $6000-$7FFF.
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr_banks: usize,
+    mirroring: Mirroring,
+    register_at_6000: bool,
+    register: u8,
+}
+
+impl Gxrom {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring, register_at_6000: bool) -> Self {
+        Gxrom {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            mirroring,
+            register_at_6000,
+            register: 0,
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = ((self.register >> 4) & 0b11) as usize;
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Gxrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        if !self.register_at_6000 {
+            self.register = data;
+        }
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        !self.register_at_6000
+    }
+
+    fn write_low(&mut self, _addr: u16, data: u8) {
+        if self.register_at_6000 {
+            self.register = data;
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = (self.register & 0b11) as usize % self.chr_banks;
+        bank * CHR_BANK_SIZE + addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new().u8(self.register).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("GxROM", data);
+        self.register = state.u8()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_register_location() {
+        let prg = (0..4).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect::<Vec<_>>();
+        let mut gxrom = Gxrom::new(prg.clone(), 0x8000, Mirroring::VERTICAL, false);
+        gxrom.write_prg(0x8000, 0b0010_0011);
+        assert_eq!(gxrom.read_prg(0x8000), 2);
+        assert_eq!(gxrom.map_chr(0x0010), 3 * CHR_BANK_SIZE + 0x10);
+
+        let mut jaleco = Gxrom::new(prg, 0x8000, Mirroring::VERTICAL, true);
+        jaleco.write_prg(0x8000, 0b0011_0000); // no effect here
+        assert_eq!(jaleco.read_prg(0xFFFF), 0);
+        jaleco.write_low(0x6000, 0b0001_0001);
+        assert_eq!(jaleco.read_prg(0xFFFF), 1);
+        assert_eq!(jaleco.map_chr(0x0000), CHR_BANK_SIZE);
+    }
+}
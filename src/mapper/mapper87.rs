@@ -0,0 +1,89 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::Mapper;
+
+const CHR_BANK_SIZE: usize = 0x2000; // 8KiB
+
+// Mapper 87: Jaleco, Konami and Taito boards with NROM-style PRG and a CHR bank register
+// in $6000-$7FFF (City Connection, Goonies, Argus).
+// See: https://www.nesdev.org/wiki/INES_Mapper_087
+//
+//   ......LH
+//         |+- high bit of the 8KiB CHR bank
+//         +-- low bit (the two are wired backwards)
+pub struct Mapper87 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize,
+    mirroring: Mirroring,
+    chr_bank: u8,
+}
+
+impl Mapper87 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring) -> Self {
+        Mapper87 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            mirroring,
+            chr_bank: 0,
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        // 16KiB games see their PRG twice, as on NROM
+        (addr as usize - 0x8000) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Mapper87 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        0x8000 // no banks
+    }
+
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+
+    fn write_low(&mut self, _addr: u16, data: u8) {
+        self.chr_bank = ((data & 1) << 1) | ((data >> 1) & 1);
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        (self.chr_bank as usize % self.chr_banks) * CHR_BANK_SIZE + addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new().u8(self.chr_bank).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("Mapper 87", data);
+        self.chr_bank = state.u8()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_swapped_chr_bits() {
+        let mut mapper = Mapper87::new(vec![7; 0x4000], 4 * CHR_BANK_SIZE, Mirroring::HORIZONTAL);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+        mapper.write_low(0x6000, 0b01);
+        assert_eq!(mapper.map_chr(0), 2 * CHR_BANK_SIZE);
+        mapper.write_low(0x7FFF, 0b10);
+        assert_eq!(mapper.map_chr(0), CHR_BANK_SIZE);
+    }
+}
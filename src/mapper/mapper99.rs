@@ -0,0 +1,105 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::Mapper;
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8KiB
+const CHR_BANK_SIZE: usize = 0x2000; // 8KiB
+
+// Mapper 99: the Nintendo Vs. System's own banking (Vs. Super Mario Bros., Vs. Excitebike,
+// Vs. Tennis). See: https://www.nesdev.org/wiki/INES_Mapper_099
+//
+// There are no registers on the cartridge: bit 2 of every write to $4016 (the controller
+// strobe port, see crate::vs_system) picks the 8KiB CHR bank, and on 40KiB games also the
+// 8KiB PRG bank at $8000 (bank 0 or 4). The rest of PRG is fixed.
+//
+// The Vs. System has 4KiB of nametable RAM, so the boards are four-screen. Four-screen VRAM
+// isn't emulated; vertical mirroring stands in for it, which covers the horizontally scrolling
+// games.
+pub struct Mapper99 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize,
+    mirroring: Mirroring,
+    bank: u8, // $4016 bit 2
+}
+
+impl Mapper99 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring) -> Self {
+        Mapper99 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            mirroring: if mirroring == Mirroring::FOUR_SCREEN { Mirroring::VERTICAL } else { mirroring },
+            bank: 0,
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let offset = addr as usize - 0x8000;
+        if self.prg_rom.len() > 0x8000 {
+            // 40KiB: banks 1-3 are fixed at $A000-$FFFF, $8000 shows bank 0 or bank 4
+            return match offset {
+                0..=0x1FFF => self.bank as usize * 4 * PRG_BANK_SIZE + offset,
+                _ => offset,
+            };
+        }
+        offset % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Mapper99 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+
+    fn write_strobe(&mut self, data: u8) {
+        self.bank = (data >> 2) & 1;
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        (self.bank as usize % self.chr_banks) * CHR_BANK_SIZE + addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new().u8(self.bank).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("Mapper 99", data);
+        self.bank = state.u8()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_bank_from_strobe_writes() {
+        let prg: Vec<u8> = (0..5).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut mapper = Mapper99::new(prg, 2 * CHR_BANK_SIZE, Mirroring::FOUR_SCREEN);
+        assert_eq!(mapper.mirroring(), Mirroring::VERTICAL);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert_eq!(mapper.read_prg(0xA000), 1);
+        assert_eq!(mapper.read_prg(0xE000), 3);
+
+        mapper.write_strobe(0b101); // bit 2 set, with the strobe bit
+        assert_eq!(mapper.read_prg(0x8000), 4);
+        assert_eq!(mapper.read_prg(0xA000), 1);
+        assert_eq!(mapper.map_chr(0x10), CHR_BANK_SIZE + 0x10);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x4000; // 16KiB
+const CHR_BANK_SIZE: usize = 0x1000; // 4KiB
+
+// Mapper 1 (MMC1): Zelda, Metroid, Mega Man 2, Final Fantasy and many more.
+// See: https://www.nesdev.org/wiki/MMC1
+//
+// The CPU can't write a whole register at once. Instead, every write to $8000-$FFFF feeds
+// bit 0 into a 5-bit shift register, and the fifth write copies the collected value into
+// one of four internal registers, chosen by bits 13-14 of the *fifth* write's address:
+//
+//   $8000-$9FFF: control      $A000-$BFFF: CHR bank 0
+//   $C000-$DFFF: CHR bank 1   $E000-$FFFF: PRG bank
+//
+// Writing a value with bit 7 set resets the shift register instead.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 4KiB CHR banks on the cartridge
+
+    shift: u8,       // shift register, with a marker bit telling us when five bits are in
+    control: u8,     // 43210: C (CHR mode), PP (PRG mode), MM (mirroring)
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+const SHIFT_RESET: u8 = 0b1_0000; // the marker reaches bit 0 after four shifts
+
+impl Mmc1 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize) -> Self {
+        Mmc1 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            shift: SHIFT_RESET,
+            control: 0x0C, // power-on: PRG mode 3, last bank fixed at $C000
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value & 0b1111, // bit 4 is the PRG-RAM enable
+        }
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = self.prg_bank as usize;
+        let last = self.prg_banks() - 1;
+
+        let (bank, offset) = match ((self.control >> 2) & 0b11, addr) {
+            // modes 0 and 1: one 32KiB bank, the low bit of the bank number is ignored
+            (0 | 1, _) => ((bank & !1) + (addr as usize - 0x8000) / PRG_BANK_SIZE, addr as usize % PRG_BANK_SIZE),
+            // mode 2: first bank fixed at $8000, switchable bank at $C000
+            (2, 0x8000..=0xBFFF) => (0, addr as usize - 0x8000),
+            (2, _) => (bank, addr as usize - 0xC000),
+            // mode 3: switchable bank at $8000, last bank fixed at $C000
+            (_, 0x8000..=0xBFFF) => (bank, addr as usize - 0x8000),
+            (_, _) => (last, addr as usize - 0xC000),
+        };
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, offset)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if data & 0b1000_0000 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= 0x0C;
+            return;
+        }
+
+        let full = self.shift & 1 == 1; // marker bit arrived: this is the fifth write
+        self.shift = (self.shift >> 1) | ((data & 1) << 4);
+
+        if full {
+            let value = self.shift;
+            self.write_register(addr, value);
+            self.shift = SHIFT_RESET;
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+        let bank = if self.control & 0b1_0000 == 0 {
+            // 8KiB mode: one bank pair, the low bit is ignored
+            (self.chr_bank_0 as usize & !1) + addr / CHR_BANK_SIZE
+        } else if addr < CHR_BANK_SIZE {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+        (bank % self.chr_banks) * CHR_BANK_SIZE + addr % CHR_BANK_SIZE
+    }
+
+    // Boards with more than 8KiB of PRG-RAM bank it with spare bits of the CHR bank register,
+    // since their CHR is 8KiB of RAM that doesn't need them: SOROM's 16KiB with bit 3, SXROM's
+    // 32KiB with bits 2-3.
+    fn prg_ram_offset(&self, addr: u16, size: usize) -> usize {
+        let bank = match size {
+            0x4000 => self.chr_bank_0 as usize >> 3 & 1,
+            0x8000 => self.chr_bank_0 as usize >> 2 & 0b11,
+            _ => 0,
+        };
+        bank * 0x2000 + (addr - 0x6000) as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SINGLE_SCREEN_A,
+            1 => Mirroring::SINGLE_SCREEN_B,
+            2 => Mirroring::VERTICAL,
+            _ => Mirroring::HORIZONTAL,
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new()
+            .u8(self.shift)
+            .u8(self.control)
+            .u8(self.chr_bank_0)
+            .u8(self.chr_bank_1)
+            .u8(self.prg_bank)
+            .finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("MMC1", data);
+        self.shift = state.u8()?;
+        self.control = state.u8()?;
+        self.chr_bank_0 = state.u8()?;
+        self.chr_bank_1 = state.u8()?;
+        self.prg_bank = state.u8()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mapper::test::banked_prg;
+
+    fn write_serial(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.write_prg(addr, (value >> i) & 1);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mut mmc1 = Mmc1::new(banked_prg(8, PRG_BANK_SIZE), 0x2000);
+        assert_eq!(mmc1.read_prg(0x8000), 0);
+        assert_eq!(mmc1.read_prg(0xC000), 7);
+    }
+
+    #[test]
+    fn test_serial_prg_bank_switch() {
+        let mut mmc1 = Mmc1::new(banked_prg(8, PRG_BANK_SIZE), 0x2000);
+        write_serial(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.read_prg(0x8000), 5);
+        assert_eq!(mmc1.read_prg(0xFFFF), 7);
+
+        // mode 2: first bank fixed, switching at $C000
+        write_serial(&mut mmc1, 0x8000, 0b0_10_00);
+        assert_eq!(mmc1.read_prg(0x8000), 0);
+        assert_eq!(mmc1.read_prg(0xC000), 5);
+    }
+
+    #[test]
+    fn test_reset_bit_restarts_shift_register() {
+        let mut mmc1 = Mmc1::new(banked_prg(8, PRG_BANK_SIZE), 0x2000);
+        mmc1.write_prg(0xE000, 1);
+        mmc1.write_prg(0xE000, 1);
+        mmc1.write_prg(0xE000, 0x80); // throw away the two bits above
+        write_serial(&mut mmc1, 0xE000, 2);
+        assert_eq!(mmc1.read_prg(0x8000), 2);
+    }
+
+    #[test]
+    fn test_mirroring_and_chr_banks() {
+        let mut mmc1 = Mmc1::new(banked_prg(2, PRG_BANK_SIZE), 0x8000);
+        write_serial(&mut mmc1, 0x8000, 0b1_11_10); // 4KiB CHR, vertical
+        assert_eq!(mmc1.mirroring(), Mirroring::VERTICAL);
+
+        write_serial(&mut mmc1, 0xA000, 3);
+        write_serial(&mut mmc1, 0xC000, 6);
+        assert_eq!(mmc1.map_chr(0x0010), 3 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(mmc1.map_chr(0x1010), 6 * CHR_BANK_SIZE + 0x10);
+
+        write_serial(&mut mmc1, 0x8000, 0b0_11_01); // 8KiB CHR, one-screen upper
+        assert_eq!(mmc1.mirroring(), Mirroring::SINGLE_SCREEN_B);
+        assert_eq!(mmc1.map_chr(0x1010), 3 * CHR_BANK_SIZE + 0x10); // bank 3 & !1 = 2, +1
+    }
+
+    #[test]
+    fn test_prg_ram_banks() {
+        let mut mmc1 = Mmc1::new(banked_prg(2, PRG_BANK_SIZE), 0x2000);
+        write_serial(&mut mmc1, 0xA000, 0b0_1100);
+        assert_eq!(mmc1.prg_ram_offset(0x6010, 0x8000), 3 * 0x2000 + 0x10); // SXROM
+        assert_eq!(mmc1.prg_ram_offset(0x6010, 0x4000), 0x2000 + 0x10); // SOROM
+        assert_eq!(mmc1.prg_ram_offset(0x6010, 0x2000), 0x10);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::Mapper;
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8KiB
+const CHR_BANK_SIZE: usize = 0x1000; // 4KiB
+
+// Mapper 9 (MMC2): Mike Tyson's Punch-Out!! / Punch-Out!!
+// See: https://www.nesdev.org/wiki/MMC2
+//
+//   $A000-$AFFF: 8KiB PRG bank at $8000 ($A000-$FFFF is fixed to the last three banks)
+//   $B000-$BFFF: 4KiB CHR bank at $0000 while latch 0 is $FD
+//   $C000-$CFFF: 4KiB CHR bank at $0000 while latch 0 is $FE
+//   $D000-$DFFF: 4KiB CHR bank at $1000 while latch 1 is $FD
+//   $E000-$EFFF: 4KiB CHR bank at $1000 while latch 1 is $FE
+//   $F000-$FFFF: mirroring
+//
+// The latches flip by themselves when the PPU fetches tile $FD or $FE from a pattern table,
+// so a game can switch CHR banks halfway across a scanline just by placing those tiles in the
+// nametable (Punch-Out!! uses that to draw the boxers bigger than one bank allows). The switch
+// happens after the marker tile is fetched: the marker itself still comes from the old bank.
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 4KiB CHR banks on the cartridge
+
+    prg_bank: u8,
+    chr_fd: [u8; 2], // per pattern table: bank used while the latch holds $FD
+    chr_fe: [u8; 2], // ... and while it holds $FE
+    latch: [u8; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring) -> Self {
+        Mmc2 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            prg_bank: 0,
+            chr_fd: [0, 0],
+            chr_fe: [0, 0],
+            latch: [0xFE, 0xFE],
+            mirroring,
+        }
+    }
+
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let banks = self.prg_banks();
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_bank as usize % banks,
+            // the last three banks, in order
+            _ => banks - 4 + (addr as usize - 0x8000) / PRG_BANK_SIZE,
+        };
+        bank * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0b1111,
+            0xB000..=0xBFFF => self.chr_fd[0] = data & 0b1_1111,
+            0xC000..=0xCFFF => self.chr_fe[0] = data & 0b1_1111,
+            0xD000..=0xDFFF => self.chr_fd[1] = data & 0b1_1111,
+            0xE000..=0xEFFF => self.chr_fe[1] = data & 0b1_1111,
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 1 == 0 { Mirroring::VERTICAL } else { Mirroring::HORIZONTAL };
+            }
+            _ => { /* $8000-$9FFF: no registers */ }
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let table = (addr as usize / CHR_BANK_SIZE) & 1;
+        let bank = if self.latch[table] == 0xFD { self.chr_fd[table] } else { self.chr_fe[table] } as usize;
+        (bank % self.chr_banks) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn notify_chr_fetch(&mut self, addr: u16) {
+        // latch 0 only reacts to the first row of the marker tiles, latch 1 to any row
+        match addr {
+            0x0FD8 => self.latch[0] = 0xFD,
+            0x0FE8 => self.latch[0] = 0xFE,
+            0x1FD8..=0x1FDF => self.latch[1] = 0xFD,
+            0x1FE8..=0x1FEF => self.latch[1] = 0xFE,
+            _ => {}
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new()
+            .u8(self.prg_bank)
+            .bytes(&self.chr_fd)
+            .bytes(&self.chr_fe)
+            .bytes(&self.latch)
+            .mirroring(self.mirroring)
+            .finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("MMC2", data);
+        self.prg_bank = state.u8()?;
+        self.chr_fd = state.array()?;
+        self.chr_fe = state.array()?;
+        self.latch = state.array()?;
+        self.mirroring = state.mirroring()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_prg_banks() {
+        let prg = (0..16).flat_map(|b| vec![b as u8; PRG_BANK_SIZE]).collect();
+        let mut mmc2 = Mmc2::new(prg, 0x20000, Mirroring::VERTICAL);
+        mmc2.write_prg(0xA000, 5);
+        assert_eq!(mmc2.read_prg(0x8000), 5);
+        assert_eq!(mmc2.read_prg(0xA000), 13);
+        assert_eq!(mmc2.read_prg(0xE000), 15);
+    }
+
+    #[test]
+    fn test_latches_switch_after_marker_tiles() {
+        let mut mmc2 = Mmc2::new(vec![0; 0x20000], 0x20000, Mirroring::VERTICAL);
+        mmc2.write_prg(0xB000, 1);
+        mmc2.write_prg(0xC000, 2);
+        mmc2.write_prg(0xD000, 3);
+        mmc2.write_prg(0xE000, 4);
+        assert_eq!(mmc2.map_chr(0x0000), 2 * CHR_BANK_SIZE);
+        assert_eq!(mmc2.map_chr(0x1000), 4 * CHR_BANK_SIZE);
+
+        mmc2.notify_chr_fetch(0x0FD8);
+        mmc2.notify_chr_fetch(0x1FDB);
+        assert_eq!(mmc2.map_chr(0x0010), CHR_BANK_SIZE + 0x10);
+        assert_eq!(mmc2.map_chr(0x1010), 3 * CHR_BANK_SIZE + 0x10);
+
+        mmc2.notify_chr_fetch(0x0FE9); // not the first row: latch 0 ignores it
+        assert_eq!(mmc2.map_chr(0x0000), CHR_BANK_SIZE);
+        mmc2.notify_chr_fetch(0x0FE8);
+        assert_eq!(mmc2.map_chr(0x0000), 2 * CHR_BANK_SIZE);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8KiB
+const CHR_BANK_SIZE: usize = 0x0400; // 1KiB
+
+// Mapper 4 (MMC3): Super Mario Bros. 3, Kirby's Adventure, Mega Man 3-6 and many more.
+// See: https://www.nesdev.org/wiki/MMC3
+//
+// Registers come in even/odd pairs, decoded by bit 0 of the address and which 8KiB the write
+// lands in:
+//
+//   $8000 even: bank select (which of R0-R7 the next $8001 write updates, PRG/CHR modes)
+//   $8001 odd:  bank data
+//   $A000 even: mirroring         $A001 odd: PRG-RAM protect
+//   $C000 even: IRQ latch         $C001 odd: IRQ reload
+//   $E000 even: IRQ disable/ack   $E001 odd: IRQ enable
+//
+// The scanline counter is clocked by rising edges of PPU address line A12. With the usual
+// setup (background tiles from $0000, sprites from $1000) that happens once per scanline,
+// when the PPU starts fetching sprite tiles - see NesPPU::tick.
+//
+// The counter behaves differently on the two makes of the chip. Sharp's MMC3B/C, on most
+// boards, raise the IRQ whenever the counter is 0 after a clock, so a latch of 0 fires on
+// every scanline. NEC's MMC3A (the "old" behavior) only fires when the counter gets to 0 by
+// counting down or by a reload the game asked for with $C001. A handful of games were only
+// ever tested on one of them and glitch on the other; the ROM database says which (see
+// crate::romdb), and Sharp is the default.
+// See: https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum IrqRevision {
+    #[default]
+    Sharp,
+    Nec,
+}
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 1KiB CHR banks on the cartridge
+
+    bank_select: u8, // 76...210: C (CHR inversion), P (PRG mode), RRR (target register)
+    registers: [u8; 8], // R0-R5: CHR banks, R6-R7: PRG banks
+    mirroring: Mirroring,
+    four_screen: bool, // wired on the board, $A000 has no effect
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    revision: IrqRevision,
+}
+
+impl Mmc3 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring, revision: IrqRevision) -> Self {
+        Mmc3 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            four_screen: mirroring == Mirroring::FOUR_SCREEN,
+            mirroring,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            revision,
+        }
+    }
+
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let second_last = self.prg_banks() - 2;
+        let swap_mode = self.bank_select & 0b0100_0000 != 0;
+
+        let bank = match (addr, swap_mode) {
+            (0x8000..=0x9FFF, false) => self.registers[6] as usize,
+            (0x8000..=0x9FFF, true) => second_last,
+            (0xA000..=0xBFFF, _) => self.registers[7] as usize,
+            (0xC000..=0xDFFF, false) => second_last,
+            (0xC000..=0xDFFF, true) => self.registers[6] as usize,
+            _ => self.prg_banks() - 1,
+        };
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (0x8000..=0x9FFF, true) => self.bank_select = data,
+            (0x8000..=0x9FFF, false) => {
+                let target = (self.bank_select & 0b111) as usize;
+                self.registers[target] = match target {
+                    0 | 1 => data & !1, // the 2KiB banks ignore the low bit
+                    6 | 7 => data & 0b11_1111,
+                    _ => data,
+                };
+            }
+            (0xA000..=0xBFFF, true) => {
+                if !self.four_screen {
+                    self.mirroring = if data & 1 == 0 { Mirroring::VERTICAL } else { Mirroring::HORIZONTAL };
+                }
+            }
+            (0xA000..=0xBFFF, false) => { /* PRG-RAM protect: not emulated, the RAM is always enabled */ }
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false; // disabling also acknowledges
+            }
+            (_, false) => self.irq_enabled = true,
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        // CHR inversion swaps the two pattern tables: the 2KiB banks move to $1000
+        let addr = if self.bank_select & 0b1000_0000 != 0 { addr ^ 0x1000 } else { addr } as usize;
+        let bank = match addr {
+            0x0000..=0x07FF => self.registers[0] as usize + addr / CHR_BANK_SIZE,
+            0x0800..=0x0FFF => self.registers[1] as usize + (addr - 0x0800) / CHR_BANK_SIZE,
+            _ => self.registers[2 + (addr - 0x1000) / CHR_BANK_SIZE] as usize,
+        };
+        (bank % self.chr_banks) * CHR_BANK_SIZE + addr % CHR_BANK_SIZE
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_scanline(&mut self) {
+        let (before, reloading) = (self.irq_counter, self.irq_reload);
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fires = match self.revision {
+            IrqRevision::Sharp => self.irq_counter == 0,
+            IrqRevision::Nec => self.irq_counter == 0 && (before > 0 || reloading),
+        };
+        if fires && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        StateWriter::new()
+            .u8(self.bank_select)
+            .bytes(&self.registers)
+            .mirroring(self.mirroring)
+            .u8(self.irq_latch)
+            .u8(self.irq_counter)
+            .bool(self.irq_reload)
+            .bool(self.irq_enabled)
+            .bool(self.irq_pending)
+            .finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("MMC3", data);
+        self.bank_select = state.u8()?;
+        self.registers = state.array()?;
+        self.mirroring = state.mirroring()?;
+        self.irq_latch = state.u8()?;
+        self.irq_counter = state.u8()?;
+        self.irq_reload = state.bool()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mapper::test::banked_prg;
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc3 = Mmc3::new(banked_prg(16, PRG_BANK_SIZE), 0x2000, Mirroring::VERTICAL, IrqRevision::Sharp);
+        mmc3.write_prg(0x8000, 6);
+        mmc3.write_prg(0x8001, 3);
+        mmc3.write_prg(0x8000, 7);
+        mmc3.write_prg(0x8001, 4);
+        assert_eq!(mmc3.read_prg(0x8000), 3);
+        assert_eq!(mmc3.read_prg(0xA000), 4);
+        assert_eq!(mmc3.read_prg(0xC000), 14);
+        assert_eq!(mmc3.read_prg(0xE000), 15);
+
+        mmc3.write_prg(0x8000, 0b0100_0000); // swap $8000 and $C000
+        assert_eq!(mmc3.read_prg(0x8000), 14);
+        assert_eq!(mmc3.read_prg(0xC000), 3);
+    }
+
+    #[test]
+    fn test_chr_inversion() {
+        let mut mmc3 = Mmc3::new(banked_prg(4, PRG_BANK_SIZE), 0x40000, Mirroring::VERTICAL, IrqRevision::Sharp);
+        mmc3.write_prg(0x8000, 0);
+        mmc3.write_prg(0x8001, 9); // low bit dropped: 2KiB bank starting at 1KiB bank 8
+        mmc3.write_prg(0x8000, 2);
+        mmc3.write_prg(0x8001, 20);
+        assert_eq!(mmc3.map_chr(0x0400), 9 * CHR_BANK_SIZE);
+        assert_eq!(mmc3.map_chr(0x1000), 20 * CHR_BANK_SIZE);
+
+        mmc3.write_prg(0x8000, 0b1000_0000);
+        assert_eq!(mmc3.map_chr(0x0000), 20 * CHR_BANK_SIZE);
+        assert_eq!(mmc3.map_chr(0x1400), 9 * CHR_BANK_SIZE);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = Mmc3::new(banked_prg(4, PRG_BANK_SIZE), 0x2000, Mirroring::VERTICAL, IrqRevision::Sharp);
+        mmc3.write_prg(0xC000, 2); // fire every third scanline
+        mmc3.write_prg(0xC001, 0);
+        mmc3.write_prg(0xE001, 0);
+
+        mmc3.clock_scanline(); // reload: 2
+        mmc3.clock_scanline(); // 1
+        assert!(!mmc3.irq_pending());
+        mmc3.clock_scanline(); // 0
+        assert!(mmc3.irq_pending());
+
+        mmc3.write_prg(0xE000, 0);
+        assert!(!mmc3.irq_pending());
+        mmc3.clock_scanline(); // reload, but disabled
+        mmc3.clock_scanline();
+        mmc3.clock_scanline();
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_irq_revisions_with_latch_zero() {
+        for (revision, fires) in [(IrqRevision::Sharp, [true, true, true]), (IrqRevision::Nec, [true, false, false])] {
+            let mut mmc3 = Mmc3::new(banked_prg(4, PRG_BANK_SIZE), 0x2000, Mirroring::VERTICAL, revision);
+            mmc3.write_prg(0xC000, 0);
+            mmc3.write_prg(0xC001, 0);
+            mmc3.write_prg(0xE001, 0);
+            for fire in fires {
+                mmc3.clock_scanline();
+                assert_eq!(mmc3.irq_pending(), fire, "{:?}", revision);
+                mmc3.write_prg(0xE000, 0); // acknowledge
+                mmc3.write_prg(0xE001, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut mmc3 = Mmc3::new(banked_prg(16, PRG_BANK_SIZE), 0x2000, Mirroring::VERTICAL, IrqRevision::Sharp);
+        mmc3.write_prg(0x8000, 6);
+        mmc3.write_prg(0x8001, 5);
+        mmc3.write_prg(0xC000, 1);
+        mmc3.write_prg(0xE001, 0);
+        mmc3.clock_scanline();
+        let saved = mmc3.save_state();
+
+        let mut loaded = Mmc3::new(banked_prg(16, PRG_BANK_SIZE), 0x2000, Mirroring::VERTICAL, IrqRevision::Sharp);
+        loaded.load_state(&saved).unwrap();
+        assert_eq!(loaded.read_prg(0x8000), 5);
+        loaded.clock_scanline(); // the counter carried over: 1 -> 0 fires
+        assert!(loaded.irq_pending());
+
+        assert!(loaded.load_state(&saved[1..]).is_err());
+    }
+}
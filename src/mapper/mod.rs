@@ -0,0 +1,430 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::{Mirroring, Rom, CHR_RAM_SIZE};
+use crate::error::RunescoError;
+
+pub mod axrom;
+pub mod barcode;
+pub mod bnrom;
+pub mod color_dreams;
+pub mod datach;
+pub mod eeprom;
+pub mod fds;
+pub mod flash;
+pub mod fme7;
+pub mod gtrom;
+pub mod gxrom;
+pub mod mapper87;
+pub mod mapper99;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod n163;
+pub mod nrom;
+pub mod state;
+pub mod unrom512;
+pub mod vrc6;
+
+// A mapper is the extra hardware on a cartridge that sits between the consoles and the ROM chips.
+// Games bigger than the 32KiB of PRG / 8KiB of CHR the NES can see at once write to "registers"
+// in ROM space ($8000-$FFFF), and the mapper swaps which part (bank) of the ROM is visible.
+// Many mappers can also change the nametable mirroring at runtime.
+// See: https://www.nesdev.org/wiki/Mapper
+
+pub trait Mapper {
+    // CPU side: $8000-$FFFF
+    fn read_prg(&mut self, addr: u16) -> u8;
+    fn write_prg(&mut self, addr: u16, data: u8); // ROM can't be written, so writes here configure the mapper
+
+    // Boards built from discrete logic chips don't stop the ROM from answering a write: ROM
+    // and CPU drive the data bus at the same time, and where they disagree 0 wins, so the
+    // register gets the written value ANDed with the ROM byte at that address. Games avoid
+    // that by writing to a table holding the value they write; the Bus does the AND (see
+    // Bus::bus_conflict) for the boards that say so here.
+    fn bus_conflicts(&self) -> bool {
+        false
+    }
+
+    // Where in PRG ROM a read of `addr` ($8000-$FFFF) lands with the banks as they are now,
+    // and the size of the banks the mapper switches. The debugger uses them for bank-aware
+    // breakpoints. None: no ROM there (the Disk System's RAM).
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize>;
+    fn prg_bank_size(&self) -> usize;
+
+    // CPU writes to $6000-$7FFF. They still land in PRG-RAM as well, but a few boards
+    // without RAM decode their registers there.
+    fn write_low(&mut self, _addr: u16, _data: u8) {}
+
+    // Where in the cartridge's `size` bytes of PRG-RAM a CPU access to $6000-$7FFF lands. The
+    // Bus wraps the result, so RAM smaller than the window shows up mirrored across it; boards
+    // with more than 8KiB bank it here.
+    fn prg_ram_offset(&self, addr: u16, _size: usize) -> usize {
+        (addr - 0x6000) as usize
+    }
+
+    // CPU reads of $6000-$7FFF, for boards that can bank ROM there. None: PRG-RAM answers.
+    fn read_low(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    // CPU reads and writes in $4020-$5FFF, where a few boards (the Disk System, MMC5) have
+    // registers. Where nothing answers the bus floats and the read sees `open_bus`, the last
+    // value on the data bus (see Bus::open_bus); registers that only drive some bits keep
+    // its other bits.
+    fn read_expansion(&mut self, _addr: u16, open_bus: u8) -> u8 {
+        open_bus
+    }
+    fn write_expansion(&mut self, _addr: u16, _data: u8) {}
+
+    // CPU writes to $4016, the controller strobe. Vs. System boards take their bank from it.
+    fn write_strobe(&mut self, _data: u8) {}
+
+    // PPU side: translates a pattern table address ($0000-$1FFF) into an offset in CHR memory.
+    // The CHR memory itself stays with the PPU (see NesPPU::chr_rom), the mapper only picks the bank.
+    fn map_chr(&self, addr: u16) -> usize;
+
+    fn mirroring(&self) -> Mirroring;
+
+    // Boards that wire the nametables to their own RAM (four-screen out of CHR-RAM) return
+    // where nametable `index` (0-3: $2000, $2400, $2800, $2C00) is in CHR memory. None: the
+    // console's 2KiB of VRAM, mirrored as mirroring() says.
+    fn chr_nametable(&self, _index: usize) -> Option<usize> {
+        None
+    }
+
+    // Called after the PPU fetched pattern data at `addr` ($0000-$1FFF), for mappers that
+    // watch what is being drawn.
+    fn notify_chr_fetch(&mut self, _addr: u16) {}
+
+    // Called by the PPU once per rendered scanline, for mappers that count scanlines.
+    fn clock_scanline(&mut self) {}
+
+    // Called by the Bus as the CPU runs, for mappers with cycle counters or sound channels.
+    fn clock_cpu(&mut self, _cycles: u8) {}
+
+    // Level of the cartridge's IRQ line: the CPU keeps taking the IRQ while this is true
+    // (and interrupts are enabled), so mappers clear it when the game acknowledges it.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    // Current output of the cartridge's own sound channels (VRC6, N163, FDS, ...) as a
+    // fraction of their full volume, 0.0 for mappers without any. The Bus hands it to the
+    // APU's mixer as it runs (see Mixer::mix).
+    fn expansion_audio_sample(&self) -> f32 {
+        0.0
+    }
+
+    // How loud those channels are at full volume, next to the console's own channels all at
+    // full volume (1.0). The cartridge mixed them in through its own resistors, so this is
+    // per chip, and a rough match at that: boards differ.
+    fn expansion_audio_gain(&self) -> f32 {
+        1.0
+    }
+
+    // Disk drives (the Disk System): how many disk sides the game has, which one is in the
+    // drive (None: ejected), and swapping them.
+    fn disk_sides(&self) -> usize {
+        0
+    }
+    fn inserted_disk(&self) -> Option<usize> {
+        None
+    }
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    // Media that outlives the power: the disks as the game left them and the side in the
+    // drive. A power cycle hands them from the old board to the new one (see Nes::power_cycle).
+    fn media(&self) -> Option<Vec<u8>> {
+        None
+    }
+    fn load_media(&mut self, _media: &[u8]) {}
+
+    // Barcode readers (the Datach): swipes the card with the EAN code `code` (digits).
+    fn scan_barcode(&mut self, _code: &str) -> Result<(), String> {
+        Err("this cartridge has no barcode reader".to_string())
+    }
+
+    // Save memory on the board other than battery-backed PRG-RAM (which the Bus keeps): a
+    // serial EEPROM, or the PRG flash of a self-flashing board. crate::sram keeps its
+    // contents, as the device's own file, between sessions.
+    fn save_device(&self) -> Option<SaveDevice> {
+        None
+    }
+    fn save_device_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_save_device(&mut self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    // Savestates: everything the mapper holds besides ROM (bank registers, IRQ counters, sound
+    // channels, writable disks) so that loading puts the cartridge back exactly as it was.
+    // load_state gets what save_state returned, possibly from an older session; see state.rs.
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveDevice {
+    Eeprom(eeprom::Chip),
+    Flash,
+}
+
+impl SaveDevice {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SaveDevice::Eeprom(eeprom::Chip::C24C01) => "24C01 EEPROM",
+            SaveDevice::Eeprom(eeprom::Chip::C24C02) => "24C02 EEPROM",
+            SaveDevice::Flash => "self-flashing PRG",
+        }
+    }
+
+    // game.nes -> game.eeprom, game.flash
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SaveDevice::Eeprom(_) => "eeprom",
+            SaveDevice::Flash => "flash",
+        }
+    }
+}
+
+// Both the Bus (PRG) and the PPU (CHR, mirroring) need to talk to the same mapper.
+// Rc: shared ownership, RefCell: mutation checked at runtime instead of compile time.
+// See: https://doc.rust-lang.org/book/ch15-05-interior-mutability.html
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;
+
+// Mappers we don't implement yet that a supported one can stand in for, at least well enough to
+// boot most games: (requested, used instead, what's missing). Only used with --mapper-fallback,
+// since a game that half works is confusing unless you know why.
+const FALLBACKS: &[(u8, u8, &str)] = &[
+    (3, 66, "CNROM as GxROM: same CHR register, PRG never switches"),
+    (118, 4, "TxSROM as MMC3: CHR-controlled mirroring is missing"),
+    (119, 4, "TQROM as MMC3: the CHR-RAM banks are missing"),
+    (155, 1, "MMC1A as MMC1: PRG-RAM can't be disabled"),
+    (185, 66, "CNROM with copy protection as GxROM: the CHR disable check is missing"),
+    (206, 4, "Namco 108 as MMC3: a subset of it, without IRQs"),
+    (241, 34, "BxROM with PRG-RAM as BNROM"),
+];
+
+pub fn fallback(id: u8) -> Option<(u8, &'static str)> {
+    FALLBACKS.iter().find(|(from, _, _)| *from == id).map(|(_, to, note)| (*to, *note))
+}
+
+// Switches a ROM whose mapper isn't supported to its fallback, if it has one. Returns the
+// warning to show; None if nothing changed.
+pub fn substitute(rom: &mut Rom) -> Option<String> {
+    if for_rom(rom).is_ok() {
+        return None;
+    }
+    let (to, note) = fallback(rom.mapper)?;
+    let warning = format!("Mapper {} is not supported, running it as mapper {} ({})", rom.mapper, to, note);
+    rom.mapper = to;
+    Some(warning)
+}
+
+// What every supported mapper is and how much of it is emulated, for `runesco check` (see
+// crate::compat). Keep in step with for_rom.
+pub struct MapperInfo {
+    pub id: u8,
+    pub name: &'static str,
+    pub audio: Option<&'static str>,      // the cartridge's own sound channels
+    pub missing: &'static [&'static str], // parts of the board that aren't emulated
+}
+
+pub const MAPPERS: &[MapperInfo] = &[
+    MapperInfo { id: 0, name: "NROM", audio: None, missing: &[] },
+    MapperInfo { id: 1, name: "MMC1", audio: None, missing: &[] },
+    MapperInfo { id: 4, name: "MMC3", audio: None, missing: &["PRG-RAM write protection"] },
+    MapperInfo { id: 7, name: "AxROM", audio: None, missing: &[] },
+    MapperInfo { id: 9, name: "MMC2", audio: None, missing: &[] },
+    MapperInfo { id: 11, name: "Color Dreams", audio: None, missing: &[] },
+    MapperInfo { id: 19, name: "Namco 163", audio: Some("8 wavetable channels"), missing: &[] },
+    MapperInfo {
+        id: fds::MAPPER,
+        name: "Famicom Disk System",
+        audio: Some("a wavetable channel with pitch modulation"),
+        missing: &[],
+    },
+    MapperInfo { id: 24, name: "Konami VRC6", audio: Some("2 pulse channels and a sawtooth"), missing: &[] },
+    MapperInfo { id: 26, name: "Konami VRC6 (swapped address lines)", audio: Some("2 pulse channels and a sawtooth"), missing: &[] },
+    MapperInfo { id: 30, name: "UNROM-512", audio: None, missing: &[] },
+    MapperInfo { id: 34, name: "BNROM / NINA-001", audio: None, missing: &[] },
+    MapperInfo { id: 66, name: "GxROM", audio: None, missing: &[] },
+    MapperInfo {
+        id: 69,
+        name: "Sunsoft FME-7 / 5B",
+        audio: Some("3 square channels (5B)"),
+        missing: &["the 5B's envelope and noise"],
+    },
+    MapperInfo { id: 87, name: "Jaleco/Konami/Taito CHR banking", audio: None, missing: &[] },
+    MapperInfo { id: 99, name: "Vs. System", audio: None, missing: &["four-screen VRAM (vertical mirroring stands in)"] },
+    MapperInfo { id: 111, name: "GTROM (Cheapocabra)", audio: None, missing: &[] },
+    MapperInfo { id: 140, name: "Jaleco JF-11/JF-14", audio: None, missing: &[] },
+    MapperInfo {
+        id: 157,
+        name: "Bandai Datach Joint ROM System",
+        audio: None,
+        missing: &["the 24C01 EEPROM some Datach cartridges add"],
+    },
+];
+
+pub fn info(id: u8) -> Option<&'static MapperInfo> {
+    MAPPERS.iter().find(|info| info.id == id)
+}
+
+// How much CHR-RAM a board without CHR ROM carries.
+pub fn chr_ram_size(id: u8) -> usize {
+    match id {
+        30 => unrom512::CHR_RAM_SIZE,
+        111 => gtrom::CHR_RAM_SIZE,
+        _ => CHR_RAM_SIZE,
+    }
+}
+
+// Offset in a PRG ROM of `rom_len` bytes of a read at `addr` with `bank` (of `bank_size`
+// bytes) switched in. Bank numbers past the end of the ROM wrap around, since the board's
+// extra bank bits aren't connected to anything, and a ROM smaller than a bank (16KiB on a
+// board that switches 32KiB) repeats inside it.
+pub fn prg_offset(rom_len: usize, bank: usize, bank_size: usize, addr: usize) -> usize {
+    let banks = (rom_len / bank_size).max(1);
+    ((bank % banks) * bank_size + addr % bank_size) % rom_len
+}
+
+// Rejects PRG ROM the board couldn't have: a size that isn't made of whole banks, too few
+// banks for the ones the mapper keeps fixed at the end, or more ROM than a board without
+// PRG banking can address. Those are bad dumps or headers naming the wrong mapper, better
+// reported when the game loads than found as a crash on the first read of a missing bank.
+fn check_prg_size(id: u8, len: usize, bank_size: usize) -> Result<(), RunescoError> {
+    let kib = |bytes: usize| bytes / 1024;
+    let largest = match id {
+        0 | 87 => Some(0x8000),
+        99 => Some(0xA000), // the 40KiB Vs. layout
+        _ => None,
+    };
+    if let Some(largest) = largest.filter(|&largest| len > largest) {
+        return Err(RunescoError::InvalidRom(format!(
+            "{}KiB of PRG ROM is more than mapper {} can address ({}KiB); the header probably names the wrong mapper",
+            kib(len),
+            id,
+            kib(largest)
+        )));
+    }
+    let smallest = match id {
+        4 => 2 * bank_size, // the last two banks are fixed
+        9 => 4 * bank_size, // the last three
+        _ => bank_size.min(0x4000),
+    };
+    let whole_banks = if len < bank_size { bank_size.is_multiple_of(len) } else { len.is_multiple_of(bank_size) };
+    if len < smallest || !whole_banks {
+        return Err(RunescoError::InvalidRom(format!(
+            "{}KiB of PRG ROM doesn't fit mapper {}, which switches {}KiB banks",
+            kib(len),
+            id,
+            kib(bank_size)
+        )));
+    }
+    Ok(())
+}
+
+pub fn for_rom(rom: &Rom) -> Result<SharedMapper, RunescoError> {
+    let prg_rom = rom.prg_rom.clone();
+    let chr_size = rom.chr_rom.len();
+    let mirroring = rom.screen_mirroring;
+
+    let mapper: SharedMapper = match rom.mapper {
+        0 => Rc::new(RefCell::new(nrom::Nrom::new(prg_rom, mirroring))),
+        1 => Rc::new(RefCell::new(mmc1::Mmc1::new(prg_rom, chr_size))),
+        4 => Rc::new(RefCell::new(mmc3::Mmc3::new(prg_rom, chr_size, mirroring, rom.mmc3_irq))),
+        7 => Rc::new(RefCell::new(axrom::Axrom::new(prg_rom))),
+        9 => Rc::new(RefCell::new(mmc2::Mmc2::new(prg_rom, chr_size, mirroring))),
+        11 => Rc::new(RefCell::new(color_dreams::ColorDreams::new(prg_rom, chr_size, mirroring))),
+        fds::MAPPER => {
+            // the game image doesn't include the BIOS, the frontend puts it in as PRG ROM
+            if prg_rom.len() != fds::BIOS_SIZE {
+                return Err(RunescoError::MissingBios(
+                    "Disk System games need the Disk System BIOS (disksys.rom, 8KiB)".to_string(),
+                ));
+            }
+            Rc::new(RefCell::new(fds::Fds::new(prg_rom, &rom.disk_sides)))
+        }
+        19 => Rc::new(RefCell::new(n163::N163::new(prg_rom, chr_size))),
+        24 => Rc::new(RefCell::new(vrc6::Vrc6::new(prg_rom, chr_size, false))),
+        26 => Rc::new(RefCell::new(vrc6::Vrc6::new(prg_rom, chr_size, true))),
+        30 => Rc::new(RefCell::new(unrom512::Unrom512::new(prg_rom, chr_size, mirroring, rom.battery))),
+        34 => Rc::new(RefCell::new(bnrom::Bnrom::new(prg_rom, chr_size, mirroring))),
+        66 => Rc::new(RefCell::new(gxrom::Gxrom::new(prg_rom, chr_size, mirroring, false))),
+        69 => Rc::new(RefCell::new(fme7::Fme7::new(prg_rom, chr_size))),
+        87 => Rc::new(RefCell::new(mapper87::Mapper87::new(prg_rom, chr_size, mirroring))),
+        99 => Rc::new(RefCell::new(mapper99::Mapper99::new(prg_rom, chr_size, mirroring))),
+        111 => Rc::new(RefCell::new(gtrom::Gtrom::new(prg_rom, rom.battery))),
+        140 => Rc::new(RefCell::new(gxrom::Gxrom::new(prg_rom, chr_size, mirroring, true))),
+        157 => Rc::new(RefCell::new(datach::Datach::new(prg_rom))),
+        id => return Err(RunescoError::UnsupportedMapper(id)),
+    };
+    if rom.mapper != fds::MAPPER {
+        check_prg_size(rom.mapper, rom.prg_rom.len(), mapper.borrow().prg_bank_size())?;
+    }
+    Ok(mapper)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // PRG-ROM for mapper tests: every byte of a bank holds the bank number, so reads tell
+    // which bank is mapped.
+    pub fn banked_prg(banks: usize, bank_size: usize) -> Vec<u8> {
+        (0..banks).flat_map(|b| vec![b as u8; bank_size]).collect()
+    }
+
+    #[test]
+    fn test_substitute_unsupported_mappers() {
+        let mut rom = crate::cartridge::test::test_rom();
+        rom.mapper = 0;
+        assert!(substitute(&mut rom).is_none()); // NROM is supported
+
+        rom.mapper = 206;
+        assert!(matches!(for_rom(&rom), Err(RunescoError::UnsupportedMapper(206))));
+        let warning = substitute(&mut rom).unwrap();
+        assert!(warning.starts_with("Mapper 206 is not supported, running it as mapper 4"));
+        assert_eq!(rom.mapper, 4);
+        assert!(for_rom(&rom).is_ok());
+
+        rom.mapper = 255; // nothing close to it
+        assert!(substitute(&mut rom).is_none());
+        assert_eq!(rom.mapper, 255);
+    }
+
+    #[test]
+    fn test_prg_sizes() {
+        let mut rom = crate::cartridge::test::test_rom();
+        rom.mapper = 0;
+        rom.prg_rom = vec![0; 0x4000]; // NROM-128
+        assert!(for_rom(&rom).is_ok());
+        rom.prg_rom = vec![0; 0x10000];
+        assert!(for_rom(&rom).err().unwrap().to_string().contains("more than mapper 0 can address"));
+
+        rom.mapper = 1;
+        assert!(for_rom(&rom).is_ok()); // 64KiB is 4 MMC1 banks
+        rom.prg_rom = vec![0; 0x5000];
+        assert!(for_rom(&rom).err().unwrap().to_string().contains("switches 16KiB banks"));
+        rom.mapper = 4;
+        rom.prg_rom = vec![0; 0x2000]; // MMC3 needs its two fixed banks
+        assert!(for_rom(&rom).is_err());
+
+        assert_eq!(prg_offset(0x4000, 0, 0x8000, 0xC123), 0x0123);
+        assert_eq!(prg_offset(0x10000, 5, 0x4000, 0x8123), 0x4123);
+    }
+
+    #[test]
+    fn test_registry_matches_for_rom() {
+        let mut rom = crate::cartridge::test::test_rom();
+        for id in 0..=255 {
+            rom.mapper = id;
+            // the Disk System fails on the missing BIOS, but is supported
+            let supported = !matches!(for_rom(&rom), Err(RunescoError::UnsupportedMapper(_)));
+            assert_eq!(supported, info(id).is_some(), "mapper {}", id);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8KiB
+const CHR_BANK_SIZE: usize = 0x0400; // 1KiB
+
+// Mapper 19 (Namco 163): Megami Tensei II, Rolling Thunder, King of Kings, Erika to Satoru no
+// Yume Bouken. See: https://www.nesdev.org/wiki/Namco_163
+//
+//   $4800-$4FFF: sound RAM data port (address set through $F800)
+//   $5000-$57FF: IRQ counter low byte          $5800-$5FFF: E HHHHHHH (E: counting, high bits)
+//   $8000-$BFFF: 1KiB CHR banks 0-7, one per $800
+//   $C000-$DFFF: nametables 0-3, one per $800 ($E0-$FF: console VRAM page, bit 0)
+//   $E000-$E7FF: .S PPPPPP 8KiB PRG bank at $8000 (S: sound disabled)
+//   $E800-$EFFF: 8KiB PRG bank at $A000        $F000-$F7FF: 8KiB PRG bank at $C000
+//   $F800-$FFFF: I AAAAAAA sound RAM address (I: increment after each access)
+//
+// $E000-$FFFF is fixed to the last bank. The IRQ counter is 15 bits and counts up every CPU
+// cycle while enabled, until it reaches $7FFF and fires; writing either half acknowledges.
+// Both halves can be read back.
+//
+// The chip can also show console VRAM as pattern tables and CHR ROM as nametables (bank
+// values $E0 and up). Only the nametable registers pointing at console VRAM are emulated,
+// which is what games use them for: they become the mirroring.
+pub struct N163 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 1KiB CHR banks on the cartridge
+
+    chr: [u8; 8],
+    nametables: [u8; 4],
+    prg: [u8; 3],
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio,
+    audio_disabled: bool,
+}
+
+impl N163 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize) -> Self {
+        N163 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            chr: [0; 8],
+            nametables: [0xE0, 0xE1, 0xE0, 0xE1],
+            prg: [0; 3],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::new(),
+            audio_disabled: false,
+        }
+    }
+
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000..=0xDFFF => (self.prg[(addr as usize - 0x8000) / PRG_BANK_SIZE] & 0x3F) as usize,
+            _ => self.prg_banks() - 1,
+        };
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for N163 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let register = (addr as usize & 0x7FFF) / 0x800;
+        match addr {
+            0x8000..=0xBFFF => self.chr[register] = data,
+            0xC000..=0xDFFF => self.nametables[register - 8] = data,
+            0xE000..=0xE7FF => {
+                self.prg[0] = data;
+                self.audio_disabled = data & 0x40 != 0;
+            }
+            0xE800..=0xF7FF => self.prg[register - 12] = data,
+            _ => self.audio.set_address(data),
+        }
+    }
+
+    fn read_expansion(&mut self, addr: u16, open_bus: u8) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.audio.read(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            _ => open_bus,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write(data),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16 & 0x7F) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = self.chr[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank % self.chr_banks) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    // Each of the four nametables picks one of the two pages of console VRAM on its own;
+    // the layouts games actually set up are the usual mirrorings.
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables.map(|page| page & 1) {
+            [0, 0, 1, 1] => Mirroring::HORIZONTAL,
+            [0, 0, 0, 0] => Mirroring::SINGLE_SCREEN_A,
+            [1, 1, 1, 1] => Mirroring::SINGLE_SCREEN_B,
+            _ => Mirroring::VERTICAL,
+        }
+    }
+
+    fn clock_cpu(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.irq_enabled && self.irq_counter < 0x7FFF {
+                self.irq_counter += 1;
+                if self.irq_counter == 0x7FFF {
+                    self.irq_pending = true;
+                }
+            }
+            self.audio.clock();
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn expansion_audio_sample(&self) -> f32 {
+        if self.audio_disabled {
+            0.0
+        } else {
+            self.audio.output()
+        }
+    }
+
+    // The resistor on the board varies a lot from game to game; this is a middle value.
+    fn expansion_audio_gain(&self) -> f32 {
+        0.3
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state
+            .bytes(&self.chr)
+            .bytes(&self.nametables)
+            .bytes(&self.prg)
+            .u16(self.irq_counter)
+            .bool(self.irq_enabled)
+            .bool(self.irq_pending);
+        self.audio.save(&mut state);
+        state.bool(self.audio_disabled).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("Namco 163", data);
+        self.chr = state.array()?;
+        self.nametables = state.array()?;
+        self.prg = state.array()?;
+        self.irq_counter = state.u16()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.audio.load(&mut state)?;
+        self.audio_disabled = state.bool()?;
+        state.finish()
+    }
+}
+
+// The 163's wavetable channels. They live in 128 bytes of internal RAM, which also holds the
+// waveforms as 4 bit samples, two per byte (low nibble first). Each channel has 8 bytes of
+// registers at the top of it, channel 7 at $78-$7F, channel 6 at $70-$77 and so on down:
+//
+//   +0, +2, +4 bits 0-1: 18 bit frequency     +1, +3, +5: 24 bit phase (the top 8 bits: sample)
+//   +4 bits 2-7: waveform length, 256 - (value & $FC) samples
+//   +6: waveform start, in samples            +7: volume (bits 0-3)
+//
+// $7F bits 4-6 hold how many channels are enabled, minus one, counted down from channel 7.
+// The chip updates one channel every 15 CPU cycles, taking turns, and outputs them one after
+// the other; mixed here by averaging, which is what the ear hears when the turns are fast.
+struct Namco163Audio {
+    ram: [u8; 128],
+    address: u8,
+    auto_increment: bool,
+    divider: u8,
+    current: usize,   // channel updated next
+    levels: [f32; 8], // each channel's last output, 0.0-1.0
+}
+
+impl Namco163Audio {
+    fn new() -> Self {
+        Namco163Audio {
+            ram: [0; 128],
+            address: 0,
+            auto_increment: false,
+            divider: 0,
+            current: 7,
+            levels: [0.0; 8],
+        }
+    }
+
+    fn set_address(&mut self, data: u8) {
+        self.address = data & 0x7F;
+        self.auto_increment = data & 0x80 != 0;
+    }
+
+    fn read(&mut self) -> u8 {
+        let data = self.ram[self.address as usize];
+        self.step_address();
+        data
+    }
+
+    fn write(&mut self, data: u8) {
+        self.ram[self.address as usize] = data;
+        self.step_address();
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < 15 {
+            return;
+        }
+        self.divider = 0;
+
+        self.update_channel(self.current);
+        let first = 8 - self.enabled_channels();
+        self.current = if self.current <= first { 7 } else { self.current - 1 };
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let reg: [u32; 8] = std::array::from_fn(|offset| self.ram[base + offset] as u32);
+
+        let frequency = reg[0] | reg[2] << 8 | (reg[4] & 0x03) << 16;
+        let length = (256 - (reg[4] & 0xFC)) << 16;
+        let mut phase = reg[1] | reg[3] << 8 | reg[5] << 16;
+        phase = (phase + frequency) % length;
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let index = ((phase >> 16) + reg[6]) & 0xFF;
+        let sample = (self.ram[index as usize / 2] >> ((index & 1) * 4)) & 0x0F;
+        let volume = reg[7] & 0x0F;
+        self.levels[channel] = (sample as u32 * volume) as f32 / (15.0 * 15.0);
+    }
+
+    // 0.0-1.0, averaged over the enabled channels.
+    fn output(&self) -> f32 {
+        let enabled = self.enabled_channels();
+        self.levels[8 - enabled..].iter().sum::<f32>() / enabled as f32
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram).u8(self.address).bool(self.auto_increment).u8(self.divider).u8(self.current as u8);
+        for level in self.levels {
+            state.u32(level.to_bits());
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.ram = state.array()?;
+        self.address = state.u8()?;
+        self.auto_increment = state.bool()?;
+        self.divider = state.u8()?;
+        self.current = state.u8()? as usize % 8;
+        for level in &mut self.levels {
+            *level = f32::from_bits(state.u32()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mapper::test::banked_prg;
+
+    #[test]
+    fn test_banking() {
+        let mut n163 = N163::new(banked_prg(16, PRG_BANK_SIZE), 0x40000);
+        n163.write_prg(0xE000, 3);
+        n163.write_prg(0xE800, 4);
+        n163.write_prg(0xF000, 5);
+        n163.write_prg(0x9800, 100); // CHR bank 3
+        assert_eq!(n163.read_prg(0x8000), 3);
+        assert_eq!(n163.read_prg(0xA000), 4);
+        assert_eq!(n163.read_prg(0xC000), 5);
+        assert_eq!(n163.read_prg(0xE000), 15);
+        assert_eq!(n163.map_chr(0x0C10), 100 * CHR_BANK_SIZE + 0x10);
+
+        assert_eq!(n163.mirroring(), Mirroring::VERTICAL);
+        for (i, page) in [0xE0, 0xE0, 0xE1, 0xE1].into_iter().enumerate() {
+            n163.write_prg(0xC000 + i as u16 * 0x800, page);
+        }
+        assert_eq!(n163.mirroring(), Mirroring::HORIZONTAL);
+    }
+
+    #[test]
+    fn test_irq_counts_up_to_7fff() {
+        let mut n163 = N163::new(banked_prg(4, PRG_BANK_SIZE), 0x2000);
+        n163.write_expansion(0x5000, 0xFD);
+        n163.write_expansion(0x5800, 0x80 | 0x7F);
+        assert_eq!(n163.read_expansion(0x5800, 0), 0xFF);
+
+        n163.clock_cpu(1);
+        assert!(!n163.irq_pending());
+        n163.clock_cpu(1);
+        assert!(n163.irq_pending());
+        n163.clock_cpu(10); // stops there
+        assert_eq!(n163.read_expansion(0x5000, 0), 0xFF);
+
+        n163.write_expansion(0x5000, 0); // acknowledge
+        assert!(!n163.irq_pending());
+    }
+
+    #[test]
+    fn test_wavetable_channel() {
+        let mut n163 = N163::new(banked_prg(4, PRG_BANK_SIZE), 0x2000);
+        // a 4 sample waveform at the start of RAM: F, F, 0, 0
+        n163.write_prg(0xF800, 0x80);
+        n163.write_expansion(0x4800, 0xFF);
+        n163.write_expansion(0x4800, 0x00);
+        n163.write_expansion(0x4800, 0x55);
+        n163.write_prg(0xF800, 0x00);
+        assert_eq!(n163.read_expansion(0x4800, 0), 0xFF); // no auto-increment: stays
+        assert_eq!(n163.read_expansion(0x4800, 0), 0xFF);
+
+        // channel 7 only: frequency $10000 (a sample per update), length 4, full volume
+        n163.write_prg(0xF800, 0x80 | 0x78);
+        for data in [0x00, 0, 0x00, 0, 0xFC | 0x01, 0, 0, 0x0F] {
+            n163.write_expansion(0x4800, data);
+        }
+
+        let mut outputs = vec![];
+        for _ in 0..4 {
+            n163.clock_cpu(15);
+            outputs.push(n163.expansion_audio_sample());
+        }
+        assert_eq!(outputs, [1.0, 0.0, 0.0, 1.0]);
+
+        n163.write_prg(0xE000, 0x40); // sound disabled
+        assert_eq!(n163.expansion_audio_sample(), 0.0);
+    }
+}
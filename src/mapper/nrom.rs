@@ -0,0 +1,52 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::StateReader;
+use crate::mapper::{self, Mapper};
+
+// Mapper 0: no bank switching at all. 16KiB or 32KiB of PRG and 8KiB of CHR,
+// mirroring hardwired on the board. This is what nestest.nes, Super Mario Bros. etc. use.
+// 16KiB games (NROM-128) see their PRG twice, at $8000 and at $C000.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(prg_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        Nrom { prg_rom, mirroring }
+    }
+}
+
+impl Mapper for Nrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[mapper::prg_offset(self.prg_rom.len(), 0, 0x8000, addr as usize)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(mapper::prg_offset(self.prg_rom.len(), 0, 0x8000, addr as usize))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        0x8000 // no banks, all of it is one
+    }
+
+    fn write_prg(&mut self, addr: u16, _data: u8) {
+        eprintln!("Ignoring write to PRG ROM at {:x}", addr);
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // nothing to save, NROM has no registers
+    fn save_state(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        StateReader::new("NROM", data).finish()
+    }
+}
@@ -0,0 +1,139 @@
+use crate::cartridge::Mirroring;
+
+// Mapper savestates: every mapper writes its registers, counters and sound channels (never
+// its ROM) with a StateWriter in save_state, and reads them back in the same order with a
+// StateReader in load_state. The bytes end up in the savestate's "MAPR" chunk (see
+// crate::savestate). Numbers are little-endian, like the rest of the savestate. The APU saves
+// its channels the same way, into a chunk of its own (see Apu::save_state).
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn bytes(&mut self, values: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(values);
+        self
+    }
+
+    pub fn mirroring(&mut self, mirroring: Mirroring) -> &mut Self {
+        self.u8(match mirroring {
+            Mirroring::VERTICAL => 0,
+            Mirroring::HORIZONTAL => 1,
+            Mirroring::FOUR_SCREEN => 2,
+            Mirroring::SINGLE_SCREEN_A => 3,
+            Mirroring::SINGLE_SCREEN_B => 4,
+        })
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+}
+
+// Errors name the mapper, so a state from another game (or a corrupt one) says what's wrong.
+pub struct StateReader<'a> {
+    mapper: &'static str,
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(mapper: &'static str, data: &'a [u8]) -> Self {
+        StateReader { mapper, data }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err(format!("{} mapper state is truncated", self.mapper));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn mirroring(&mut self) -> Result<Mirroring, String> {
+        match self.u8()? {
+            0 => Ok(Mirroring::VERTICAL),
+            1 => Ok(Mirroring::HORIZONTAL),
+            2 => Ok(Mirroring::FOUR_SCREEN),
+            3 => Ok(Mirroring::SINGLE_SCREEN_A),
+            4 => Ok(Mirroring::SINGLE_SCREEN_B),
+            other => Err(format!("{} mapper state: bad mirroring {}", self.mapper, other)),
+        }
+    }
+
+    // Everything was read: leftovers mean the state was written by a different mapper.
+    pub fn finish(self) -> Result<(), String> {
+        match self.data.len() {
+            0 => Ok(()),
+            extra => Err(format!("{} mapper state has {} unexpected bytes", self.mapper, extra)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = StateWriter::new().u8(7).bool(true).u16(0x1234).u32(0xDEADBEEF).mirroring(Mirroring::SINGLE_SCREEN_B).bytes(&[1, 2]).finish();
+        assert_eq!(data.len(), 1 + 1 + 2 + 4 + 1 + 2);
+
+        let mut reader = StateReader::new("test", &data);
+        assert_eq!(reader.u8().unwrap(), 7);
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.u16().unwrap(), 0x1234);
+        assert_eq!(reader.u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(reader.mirroring().unwrap(), Mirroring::SINGLE_SCREEN_B);
+        assert_eq!(reader.array::<2>().unwrap(), [1, 2]);
+        reader.finish().unwrap();
+
+        let mut reader = StateReader::new("test", &data[..2]);
+        reader.u8().unwrap();
+        assert_eq!(reader.u16().unwrap_err(), "test mapper state is truncated");
+        assert!(StateReader::new("test", &data).finish().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::flash::Flash;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper, SaveDevice};
+
+const PRG_BANK_SIZE: usize = 0x4000; // 16KiB
+const CHR_BANK_SIZE: usize = 0x2000; // 8KiB
+pub const CHR_RAM_SIZE: usize = 4 * CHR_BANK_SIZE;
+
+// Mapper 30 (UNROM-512): RetroUSB's board for homebrew, and what NESmaker builds for. UxROM
+// grown to 512KiB of PRG, with 32KiB of CHR-RAM in four banks. One register:
+//
+//   MCCP PPPP
+//   |||+-++++- 16KiB PRG bank at $8000 ($C000 is fixed to the last bank)
+//   |++------- 8KiB CHR-RAM bank
+//   +--------- which 1KiB of VRAM all four nametables show, on one-screen boards
+//
+// The header's mirroring bits pick how the nametables are wired: horizontal or vertical as
+// usual, the four-screen bit alone for one-screen under the M bit (the ROM loader turns it
+// into SINGLE_SCREEN_A), both bits for four-screen, which takes the four nametables from the
+// last 8KiB of the CHR-RAM.
+//
+// With the battery bit set the PRG is flash the game can rewrite (see crate::mapper::flash):
+// the register moves to $C000-$FFFF and writes to $8000-$BFFF go to the chip, at the
+// address the PRG bank in the register selects. Without it the PRG is plain ROM, and the
+// register sees bus conflicts like UxROM's.
+// See: https://www.nesdev.org/wiki/UNROM_512
+pub struct Unrom512 {
+    flash: Flash,
+    flashable: bool,
+    chr_banks: usize,
+    one_screen: bool,
+    mirroring: Mirroring, // the header's, for boards that aren't one-screen
+    register: u8,
+}
+
+impl Unrom512 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring, flashable: bool) -> Self {
+        Unrom512 {
+            flash: Flash::new(prg_rom),
+            flashable,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            one_screen: matches!(mirroring, Mirroring::SINGLE_SCREEN_A | Mirroring::SINGLE_SCREEN_B),
+            mirroring,
+            register: 0,
+        }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x8000..=0xBFFF => (self.register & 0x1F) as usize,
+            _ => self.flash.data().len() / PRG_BANK_SIZE - 1,
+        }
+    }
+
+    // Offset in PRG of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        mapper::prg_offset(self.flash.data().len(), self.prg_bank(addr), PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.flash.read(self.prg_offset(addr))
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        !self.flashable
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if self.flashable && addr < 0xC000 {
+            let offset = self.prg_offset(addr);
+            self.flash.write(offset, data);
+        } else {
+            self.register = data;
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = (self.register >> 5 & 0b11) as usize % self.chr_banks;
+        bank * CHR_BANK_SIZE + addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.one_screen, self.register & 0x80 != 0) {
+            (false, _) => self.mirroring,
+            (true, false) => Mirroring::SINGLE_SCREEN_A,
+            (true, true) => Mirroring::SINGLE_SCREEN_B,
+        }
+    }
+
+    fn chr_nametable(&self, index: usize) -> Option<usize> {
+        let last_bank = (self.chr_banks - 1) * CHR_BANK_SIZE;
+        (self.mirroring == Mirroring::FOUR_SCREEN).then_some(last_bank + index * 0x400)
+    }
+
+    fn save_device(&self) -> Option<SaveDevice> {
+        self.flashable.then_some(SaveDevice::Flash)
+    }
+
+    fn save_device_data(&self) -> Vec<u8> {
+        self.flash.data().to_vec()
+    }
+
+    fn load_save_device(&mut self, data: &[u8]) -> Result<(), String> {
+        self.flash.load(data)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.register);
+        if self.flashable {
+            self.flash.save_state(&mut state);
+        }
+        state.finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("UNROM-512", data);
+        self.register = state.u8()?;
+        if self.flashable {
+            self.flash.load_state(&mut state)?;
+        }
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mapper::test::banked_prg;
+
+    fn prg() -> Vec<u8> {
+        banked_prg(32, PRG_BANK_SIZE)
+    }
+
+    #[test]
+    fn test_banks_and_one_screen() {
+        let mut mapper = Unrom512::new(prg(), CHR_RAM_SIZE, Mirroring::SINGLE_SCREEN_A, false);
+        assert_eq!(mapper.read_prg(0xC000), 31);
+        mapper.write_prg(0x8000, 0b1101_0011);
+        assert_eq!(mapper.read_prg(0x8000), 0x13);
+        assert_eq!(mapper.read_prg(0xFFFF), 31);
+        assert_eq!(mapper.map_chr(0x0010), 2 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::SINGLE_SCREEN_B);
+
+        let mapper = Unrom512::new(prg(), CHR_RAM_SIZE, Mirroring::HORIZONTAL, false);
+        assert_eq!(mapper.mirroring(), Mirroring::HORIZONTAL);
+        assert_eq!(mapper.chr_nametable(0), None);
+        assert_eq!(mapper.save_device(), None);
+        assert!(mapper.bus_conflicts());
+    }
+
+    #[test]
+    fn test_four_screen() {
+        let mut mapper = Unrom512::new(prg(), CHR_RAM_SIZE, Mirroring::FOUR_SCREEN, true);
+        assert_eq!(mapper.mirroring(), Mirroring::FOUR_SCREEN);
+        assert_eq!(mapper.chr_nametable(0), Some(3 * CHR_BANK_SIZE));
+        assert_eq!(mapper.chr_nametable(3), Some(3 * CHR_BANK_SIZE + 0xC00));
+        mapper.write_prg(0xC000, 0x20); // switching CHR banks doesn't move them
+        assert_eq!(mapper.chr_nametable(1), Some(3 * CHR_BANK_SIZE + 0x400));
+        assert!(!mapper.bus_conflicts()); // flash boards don't have them
+    }
+
+    #[test]
+    fn test_self_flashing() {
+        let mut mapper = Unrom512::new(prg(), CHR_RAM_SIZE, Mirroring::VERTICAL, true);
+        assert_eq!(mapper.save_device(), Some(SaveDevice::Flash));
+
+        // program $00 into bank 3, at $8123: the game switches banks between the writes to
+        // put the command addresses ($5555 is in bank 1, $2AAA in bank 0) under $8000-$BFFF
+        let mut write = |bank: u8, addr: u16, data: u8| {
+            mapper.write_prg(0xC000, bank);
+            mapper.write_prg(addr, data);
+        };
+        write(1, 0x9555, 0xAA);
+        write(0, 0xAAAA, 0x55);
+        write(1, 0x9555, 0xA0);
+        write(3, 0x8123, 0x00);
+
+        assert_eq!(mapper.read_prg(0x8123), 0x00);
+        assert_eq!(mapper.read_prg(0x8124), 3);
+        assert_eq!(mapper.save_device_data()[3 * PRG_BANK_SIZE + 0x123], 0x00);
+        assert_eq!(mapper.register, 3); // the writes to $8000-$BFFF didn't touch it
+    }
+}
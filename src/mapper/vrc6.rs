@@ -0,0 +1,461 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateReader, StateWriter};
+use crate::mapper::{self, Mapper};
+
+const PRG_BANK_SIZE: usize = 0x2000; // 8KiB
+const CHR_BANK_SIZE: usize = 0x0400; // 1KiB
+
+// Mappers 24 and 26 (Konami VRC6): Akumajou Densetsu (24), Madara and Esper Dream 2 (26).
+// See: https://www.nesdev.org/wiki/VRC6
+//
+// Registers are picked by the top nibble of the address and its two low bits:
+//
+//   $8000-$8003: 16KiB PRG bank at $8000      $C000-$C003: 8KiB PRG bank at $C000
+//   $9000-$9002: pulse 1   $A000-$A002: pulse 2   $B000-$B002: sawtooth   $9003: audio control
+//   $B003: PPU banking mode, mirroring
+//   $D000-$D003, $E000-$E003: 1KiB CHR banks 0-7
+//   $F000: IRQ latch   $F001: IRQ control   $F002: IRQ acknowledge
+//
+// $E000-$FFFF is fixed to the last 8KiB bank. The two boards only differ in wiring: mapper 26
+// (VRC6b) has address lines A0 and A1 swapped, so $x001 and $x002 trade places.
+//
+// Only banking mode 0 (eight 1KiB CHR banks, mirroring from $B003 bits 2-3) is emulated;
+// the modes that use CHR ROM as nametables aren't needed by the released games.
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    chr_banks: usize, // number of 1KiB CHR banks on the cartridge
+    swapped_lines: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr: [u8; 8],
+    mirroring: Mirroring,
+
+    irq: VrcIrq,
+
+    pulses: [Pulse; 2],
+    saw: Sawtooth,
+    audio_halted: bool,
+}
+
+impl Vrc6 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, swapped_lines: bool) -> Self {
+        Vrc6 {
+            prg_rom,
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            swapped_lines,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0; 8],
+            mirroring: Mirroring::VERTICAL,
+            irq: VrcIrq::default(),
+            pulses: [Pulse::default(), Pulse::default()],
+            saw: Sawtooth::default(),
+            audio_halted: false,
+        }
+    }
+
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    // Offset in PRG ROM of a CPU read at `addr`.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000..=0xBFFF => (self.prg_16k as usize & 0x0F) * 2 + (addr as usize - 0x8000) / PRG_BANK_SIZE,
+            0xC000..=0xDFFF => self.prg_8k as usize & 0x1F,
+            _ => self.prg_banks() - 1,
+        };
+        mapper::prg_offset(self.prg_rom.len(), bank, PRG_BANK_SIZE, addr as usize)
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.prg_offset(addr))
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        PRG_BANK_SIZE
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let mut reg = addr & 0xF003;
+        if self.swapped_lines {
+            reg = (reg & 0xF000) | ((reg & 1) << 1) | ((reg & 2) >> 1);
+        }
+        match reg {
+            0x8000..=0x8003 => self.prg_16k = data,
+            0x9000..=0x9002 => self.pulses[0].write(reg & 3, data),
+            0x9003 => self.audio_halted = data & 1 != 0, // bits 1-2 (frequency scaling) are a test mode
+            0xA000..=0xA002 => self.pulses[1].write(reg & 3, data),
+            0xB000..=0xB002 => self.saw.write(reg & 3, data),
+            0xB003 => {
+                self.mirroring = match (data >> 2) & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLE_SCREEN_A,
+                    _ => Mirroring::SINGLE_SCREEN_B,
+                };
+            }
+            0xC000..=0xC003 => self.prg_8k = data,
+            0xD000..=0xD003 => self.chr[(reg & 3) as usize] = data,
+            0xE000..=0xE003 => self.chr[4 + (reg & 3) as usize] = data,
+            0xF000 => self.irq.latch = data,
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = self.chr[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank % self.chr_banks) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            if !self.audio_halted {
+                self.pulses[0].clock();
+                self.pulses[1].clock();
+                self.saw.clock();
+            }
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn expansion_audio_sample(&self) -> f32 {
+        // two 4 bit pulses and a 5 bit saw, summed linearly like the chip's DAC does
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        sum as f32 / 61.0
+    }
+
+    // Its pulses at full volume are about as loud as the APU's: 61 steps of 0.15 / 15.
+    fn expansion_audio_gain(&self) -> f32 {
+        0.6
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(self.prg_16k).u8(self.prg_8k).bytes(&self.chr).mirroring(self.mirroring);
+        self.irq.save(&mut state);
+        for pulse in &self.pulses {
+            pulse.save(&mut state);
+        }
+        self.saw.save(&mut state);
+        state.bool(self.audio_halted).finish()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new("VRC6", data);
+        self.prg_16k = state.u8()?;
+        self.prg_8k = state.u8()?;
+        self.chr = state.array()?;
+        self.mirroring = state.mirroring()?;
+        self.irq.load(&mut state)?;
+        for pulse in &mut self.pulses {
+            pulse.load(&mut state)?;
+        }
+        self.saw.load(&mut state)?;
+        self.audio_halted = state.bool()?;
+        state.finish()
+    }
+}
+
+// The IRQ counter Konami put in the VRC4, VRC6 and VRC7. It counts up from the latch and
+// fires when it wraps past $FF, either every CPU cycle or (through a prescaler that divides
+// by 113.667, i.e. 341/3) roughly once per scanline. Unlike MMC3 it doesn't watch the PPU,
+// so it keeps counting with rendering off.
+#[derive(Default)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += 341;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state
+            .u8(self.latch)
+            .u8(self.counter)
+            .u16(self.prescaler as u16)
+            .bool(self.enabled)
+            .bool(self.enable_after_ack)
+            .bool(self.cycle_mode)
+            .bool(self.pending);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.latch = state.u8()?;
+        self.counter = state.u8()?;
+        self.prescaler = state.u16()? as i16;
+        self.enabled = state.bool()?;
+        self.enable_after_ack = state.bool()?;
+        self.cycle_mode = state.bool()?;
+        self.pending = state.bool()?;
+        Ok(())
+    }
+}
+
+// $x000: MDDD VVVV (ignore duty, duty 0-7, volume)   $x001: period low   $x002: E... PPPP (enable, period high)
+// The output is high for duty+1 of every 16 steps, or always with M set (a crude DAC for samples).
+#[derive(Default)]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.volume = data & 0x0F;
+                self.duty = (data >> 4) & 0b111;
+                self.ignore_duty = data & 0x80 != 0;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state
+            .u8(self.volume)
+            .u8(self.duty)
+            .bool(self.ignore_duty)
+            .u16(self.period)
+            .bool(self.enabled)
+            .u16(self.timer)
+            .u8(self.step);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.volume = state.u8()?;
+        self.duty = state.u8()?;
+        self.ignore_duty = state.bool()?;
+        self.period = state.u16()?;
+        self.enabled = state.bool()?;
+        self.timer = state.u16()?;
+        self.step = state.u8()?;
+        Ok(())
+    }
+}
+
+// $B000: ..AA AAAA (accumulator rate)   $B001: period low   $B002: E... PPPP
+// Every second step adds the rate to an accumulator, and the 14th step resets it: a 7 level
+// staircase whose top 5 bits go to the DAC.
+#[derive(Default)]
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state
+            .u8(self.rate)
+            .u16(self.period)
+            .bool(self.enabled)
+            .u16(self.timer)
+            .u8(self.step)
+            .u8(self.accumulator);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.rate = state.u8()?;
+        self.period = state.u16()?;
+        self.enabled = state.bool()?;
+        self.timer = state.u16()?;
+        self.step = state.u8()?;
+        self.accumulator = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mapper::test::banked_prg;
+
+    #[test]
+    fn test_banking_and_swapped_lines() {
+        let mut vrc6 = Vrc6::new(banked_prg(16, PRG_BANK_SIZE), 0x20000, false);
+        vrc6.write_prg(0x8000, 3);
+        vrc6.write_prg(0xC000, 9);
+        vrc6.write_prg(0xE002, 21); // CHR bank 6
+        vrc6.write_prg(0xB003, 0b0100); // horizontal
+        assert_eq!(vrc6.read_prg(0x8000), 6);
+        assert_eq!(vrc6.read_prg(0xA000), 7);
+        assert_eq!(vrc6.read_prg(0xC000), 9);
+        assert_eq!(vrc6.read_prg(0xE000), 15);
+        assert_eq!(vrc6.map_chr(0x1810), 21 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(vrc6.mirroring(), Mirroring::HORIZONTAL);
+
+        // VRC6b: $E001 is CHR bank 6, $E002 is bank 5
+        let mut vrc6b = Vrc6::new(banked_prg(16, PRG_BANK_SIZE), 0x20000, true);
+        vrc6b.write_prg(0xE001, 21);
+        assert_eq!(vrc6b.map_chr(0x1800), 21 * CHR_BANK_SIZE);
+        assert_eq!(vrc6b.map_chr(0x1400), 0);
+    }
+
+    #[test]
+    fn test_irq_counts_up_to_wrap() {
+        let mut vrc6 = Vrc6::new(banked_prg(16, PRG_BANK_SIZE), 0x2000, false);
+        vrc6.write_prg(0xF000, 0xFD);
+        vrc6.write_prg(0xF001, 0b111); // cycle mode, enabled, re-enable after ack
+
+        vrc6.clock_cpu(2); // $FE, $FF
+        assert!(!vrc6.irq_pending());
+        vrc6.clock_cpu(1); // wraps: reload and fire
+        assert!(vrc6.irq_pending());
+
+        vrc6.write_prg(0xF002, 0);
+        assert!(!vrc6.irq_pending());
+        vrc6.clock_cpu(3);
+        assert!(vrc6.irq_pending());
+    }
+
+    #[test]
+    fn test_audio_channels() {
+        let mut vrc6 = Vrc6::new(banked_prg(16, PRG_BANK_SIZE), 0x2000, false);
+        assert_eq!(vrc6.expansion_audio_sample(), 0.0);
+
+        vrc6.write_prg(0x9000, 0b1000_1111); // pulse 1: constant, full volume
+        vrc6.write_prg(0x9002, 0x80);
+        assert_eq!(vrc6.expansion_audio_sample(), 15.0 / 61.0);
+
+        vrc6.write_prg(0xB000, 0x10);
+        vrc6.write_prg(0xB002, 0x80); // period 0: a step every cycle
+        vrc6.clock_cpu(12); // six additions
+        assert_eq!(vrc6.saw.output(), 0x60 >> 3);
+        vrc6.clock_cpu(2);
+        assert_eq!(vrc6.saw.output(), 0);
+
+        vrc6.write_prg(0x9003, 1); // halt
+        vrc6.clock_cpu(12);
+        assert_eq!(vrc6.saw.output(), 0);
+    }
+}
@@ -0,0 +1,373 @@
+use std::fmt::Write;
+
+// Input movies: the joypad changes of a session, replayed from power-on to reproduce it
+// exactly (bug reports, regression tests, TAS). The emulation is deterministic, so the inputs
+// and the RNG seed are all a movie needs.
+//
+// Changes are normally stamped with the frame they happened in and applied when that frame
+// starts. That is what a player can do anyway, and it survives changes to CPU timing. Games
+// that read the controller several times per frame (to poll faster, or to work around the
+// DMC corrupting reads) can see different buttons in each read, though, and only a subframe
+// movie can reproduce that: there each change is stamped with the CPU cycle of the joypad
+// strobe it was sampled at, and played back at the first strobe on or after that cycle.
+//
+// The file is text, one change per line:
+//
+//   runesco-movie 1
+//   seed 0
+//   subframe                  (only in subframe movies)
+//   120 - 1 08                frame, CPU cycle ("-": at the start of the frame), port, buttons (hex)
+//   121 3615590 1 00
+//   300 9022145 reset         the console's own buttons: reset, or "power" to switch it off and on
+//
+// Buttons are the JoypadButton bits: A is $01, B $02, Select $04, Start $08, Up $10, Down $20,
+// Left $40, Right $80.
+//
+// Resets and power cycles go in on the exact cycle (or at the start of the frame, with "-"),
+// before the instruction that would run on it. Inserted into a movie by hand (Movie::insert),
+// they test what a game does when the power goes at the worst moment: whether a save routine
+// interrupted halfway leaves the save intact, or how a TAS reset trick lands. Cycles count on
+// across power cycles, from the movie's start.
+
+const HEADER: &str = "runesco-movie 1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub cycle: Option<u64>, // None: applied at the start of the frame
+    pub input: Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Joypad { port: usize, buttons: u8 }, // port 0 or 1
+    Reset,
+    Power, // off and on again, see Nes::power_cycle
+}
+
+impl InputEvent {
+    // One line of the movie file, without the newline.
+    pub fn to_line(&self) -> String {
+        let cycle = self.cycle.map_or("-".to_string(), |c| c.to_string());
+        let input = match self.input {
+            Input::Joypad { port, buttons } => format!("{} {:02X}", port + 1, buttons),
+            Input::Reset => "reset".to_string(),
+            Input::Power => "power".to_string(),
+        };
+        format!("{} {} {}", self.frame, cycle, input)
+    }
+
+    pub fn parse_line(line: &str) -> Option<InputEvent> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (frame, cycle, input) = match fields.as_slice() {
+            [frame, cycle, "reset"] => (frame, cycle, Input::Reset),
+            [frame, cycle, "power"] => (frame, cycle, Input::Power),
+            [frame, cycle, port, buttons] => {
+                let port: usize = port.parse().ok()?;
+                if port != 1 && port != 2 {
+                    return None;
+                }
+                (frame, cycle, Input::Joypad { port: port - 1, buttons: u8::from_str_radix(buttons, 16).ok()? })
+            }
+            _ => return None,
+        };
+        Some(InputEvent {
+            frame: frame.parse().ok()?,
+            cycle: if *cycle == "-" { None } else { Some(cycle.parse().ok()?) },
+            input,
+        })
+    }
+
+    fn is_console(&self) -> bool {
+        !matches!(self.input, Input::Joypad { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub seed: u64, // see crate::rng
+    pub subframe: bool,
+    events: Vec<InputEvent>,
+}
+
+impl Movie {
+    pub fn new(seed: u64, subframe: bool) -> Self {
+        Movie { seed, subframe, events: vec![] }
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    // Puts an event where it belongs in time, after any already there for the same moment.
+    // Events at the start of a frame come before the ones stamped with a cycle in it.
+    pub fn insert(&mut self, event: InputEvent) {
+        let when = |e: &InputEvent| (e.frame, e.cycle.is_some(), e.cycle);
+        let at = self.events.iter().position(|e| when(e) > when(&event)).unwrap_or(self.events.len());
+        self.events.insert(at, event);
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\nseed {}\n", HEADER, self.seed);
+        if self.subframe {
+            out.push_str("subframe\n");
+        }
+        for event in &self.events {
+            let _ = writeln!(out, "{}", event.to_line());
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err("not a runesco movie".to_string());
+        }
+
+        let mut movie = Movie::new(0, false);
+        for (n, line) in lines {
+            let bad = || format!("line {}: can't read '{}'", n + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| bad())?,
+                ["subframe"] => movie.subframe = true,
+                _ => movie.events.push(InputEvent::parse_line(line).ok_or_else(bad)?),
+            }
+        }
+        Ok(movie)
+    }
+}
+
+// Builds a movie from the inputs the emulator sees, keeping only the changes.
+pub struct Recorder {
+    movie: Movie,
+    last: [u8; 2],
+}
+
+impl Recorder {
+    pub fn new(seed: u64, subframe: bool) -> Self {
+        Recorder { movie: Movie::new(seed, subframe), last: [0, 0] }
+    }
+
+    pub fn subframe(&self) -> bool {
+        self.movie.subframe
+    }
+
+    pub fn sample(&mut self, frame: u64, cycle: Option<u64>, buttons: [u8; 2]) {
+        for (port, (now, last)) in buttons.into_iter().zip(self.last.iter_mut()).enumerate() {
+            if now != *last {
+                let input = Input::Joypad { port, buttons: now };
+                self.movie.events.push(InputEvent { frame, cycle, input });
+                *last = now;
+            }
+        }
+    }
+
+    // The reset or power button, pressed before the instruction on `cycle`.
+    pub fn console(&mut self, frame: u64, cycle: u64, input: Input) {
+        self.movie.events.push(InputEvent { frame, cycle: Some(cycle), input });
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// The joypad changes and the console's buttons are played from separate places in the
+// movie: the buttons are checked before every instruction, the joypads only when a frame
+// starts or the game strobes them.
+pub struct Player {
+    movie: Movie,
+    next: usize,         // first joypad event not applied yet
+    next_console: usize, // first reset or power event not performed yet, or the end
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Self {
+        let mut player = Player { movie, next: 0, next_console: 0 };
+        player.skip_to_console_event();
+        player
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.movie.events.len() && self.next_console >= self.movie.events.len()
+    }
+
+    // For movies that arrive while they play (see crate::spectate). Events must come in order.
+    pub fn push(&mut self, event: InputEvent) {
+        self.movie.events.push(event);
+        self.skip_to_console_event();
+    }
+
+    // Frame events due when `frame` starts.
+    pub fn frame_start(&mut self, frame: u64, buttons: &mut [u8; 2]) {
+        self.apply_while(buttons, |event| event.cycle.is_none() && event.frame <= frame);
+    }
+
+    // Subframe events due at a strobe on `cycle`.
+    pub fn strobe(&mut self, cycle: u64, buttons: &mut [u8; 2]) {
+        self.apply_while(buttons, |event| matches!(event.cycle, Some(c) if c <= cycle));
+    }
+
+    // A reset or power cycle due by the instruction about to run on `cycle`, one at a time.
+    pub fn console_due(&mut self, frame: u64, cycle: u64) -> Option<Input> {
+        let event = *self.movie.events.get(self.next_console)?;
+        let due = match event.cycle {
+            None => event.frame <= frame,
+            Some(c) => c <= cycle,
+        };
+        if !due {
+            return None;
+        }
+        self.next_console += 1;
+        self.skip_to_console_event();
+        Some(event.input)
+    }
+
+    fn skip_to_console_event(&mut self) {
+        while self.movie.events.get(self.next_console).is_some_and(|event| !event.is_console()) {
+            self.next_console += 1;
+        }
+    }
+
+    fn apply_while(&mut self, buttons: &mut [u8; 2], due: impl Fn(&InputEvent) -> bool) {
+        while let Some(event) = self.movie.events.get(self.next) {
+            match event.input {
+                Input::Joypad { .. } if !due(event) => break,
+                Input::Joypad { port, buttons: now } => buttons[port] = now,
+                _ => {} // see console_due
+            }
+            self.next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_roundtrip() {
+        let mut recorder = Recorder::new(42, true);
+        recorder.sample(3, Some(100), [0x08, 0]);
+        recorder.sample(3, Some(200), [0x08, 0]); // no change, nothing recorded
+        recorder.sample(4, Some(300), [0, 0x81]);
+        let movie = recorder.finish();
+        assert_eq!(movie.events().len(), 3);
+
+        let text = movie.to_text();
+        assert!(text.contains("\n4 300 1 00\n4 300 2 81\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie));
+        assert!(Movie::parse("runesco-movie 1\n1 - 3 00\n").is_err());
+        assert!(Movie::parse("hello").is_err());
+    }
+
+    #[test]
+    fn test_player_applies_subframe_events_at_strobes() {
+        let mut recorder = Recorder::new(0, true);
+        recorder.sample(0, Some(1000), [0x01, 0]);
+        recorder.sample(0, Some(2000), [0x02, 0]); // same frame, second read
+        let mut player = Player::new(recorder.finish());
+
+        let mut buttons = [0, 0];
+        player.frame_start(0, &mut buttons);
+        assert_eq!(buttons, [0, 0]);
+        player.strobe(1500, &mut buttons);
+        assert_eq!(buttons, [0x01, 0]);
+        player.strobe(2000, &mut buttons);
+        assert_eq!(buttons, [0x02, 0]);
+        assert!(player.finished());
+    }
+
+    #[test]
+    fn test_console_events() {
+        let mut movie = Movie::parse("runesco-movie 1\n5 - 1 08\n9 - 1 00\n").unwrap();
+        movie.insert(InputEvent { frame: 5, cycle: Some(90000), input: Input::Reset });
+        movie.insert(InputEvent { frame: 7, cycle: None, input: Input::Power });
+        let text = movie.to_text();
+        assert!(text.ends_with("5 - 1 08\n5 90000 reset\n7 - power\n9 - 1 00\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie.clone()));
+        assert!(Movie::parse("runesco-movie 1\n5 - restart\n").is_err());
+
+        let mut player = Player::new(movie);
+        let mut buttons = [0, 0];
+        player.frame_start(5, &mut buttons);
+        assert_eq!(buttons, [0x08, 0]);
+        assert_eq!(player.console_due(5, 89999), None);
+        assert_eq!(player.console_due(5, 90010), Some(Input::Reset));
+        assert_eq!(player.console_due(6, 100000), None);
+        assert_eq!(player.console_due(7, 120000), Some(Input::Power));
+        assert_eq!(player.console_due(7, 120000), None);
+        assert!(!player.finished()); // frame 9's release
+        player.frame_start(9, &mut buttons);
+        assert!(player.finished());
+    }
+
+    #[test]
+    fn test_replay_with_reset_and_power_cycle() {
+        use crate::joypads::JoypadButton;
+        use crate::nes::Nes;
+
+        let rom = std::fs::read("nestest.nes").unwrap();
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.record_movie(false);
+        for frame in 0..40 {
+            nes.joypads().0.set_button_pressed_status(JoypadButton::DOWN, frame % 6 < 3);
+            match frame {
+                10 => nes.reset(),
+                25 => nes.power_cycle(),
+                _ => {}
+            }
+            for _ in 0..frame % 4 {
+                nes.step_instruction(); // off the frame boundary
+            }
+            nes.run_frame();
+        }
+        let movie = nes.stop_recording().unwrap();
+        assert_eq!(movie.events().iter().filter(|e| e.input == Input::Reset).count(), 1);
+        assert_eq!(movie.events().iter().filter(|e| e.input == Input::Power).count(), 1);
+
+        let mut replay = Nes::new(&rom).unwrap();
+        replay.play_movie(Movie::parse(&movie.to_text()).unwrap());
+        while replay.frame_count() < 40 {
+            replay.run_frame();
+        }
+        assert_eq!(replay.ram(), nes.ram());
+        assert_eq!(replay.cpu().program_counter, nes.cpu().program_counter);
+    }
+
+    #[test]
+    fn test_replay_reproduces_session() {
+        use crate::joypads::JoypadButton;
+        use crate::nes::Nes;
+
+        let rom = std::fs::read("nestest.nes").unwrap();
+        for subframe in [false, true] {
+            let mut nes = Nes::new(&rom).unwrap();
+            nes.record_movie(subframe);
+            for frame in 0..30 {
+                // nestest's menu: move the cursor down a few times
+                nes.joypads().0.set_button_pressed_status(JoypadButton::DOWN, frame % 6 < 3);
+                nes.run_frame();
+            }
+            let movie = nes.stop_recording().unwrap();
+            assert!(!movie.events().is_empty());
+            assert_eq!(movie.events().iter().all(|e| e.cycle.is_some()), subframe);
+
+            let mut replay = Nes::new(&rom).unwrap();
+            replay.play_movie(Movie::parse(&movie.to_text()).unwrap());
+            for _ in 0..30 {
+                replay.run_frame();
+            }
+            assert_eq!(replay.ram(), nes.ram());
+        }
+    }
+}
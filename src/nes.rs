@@ -0,0 +1,1062 @@
+use crate::apu::mixer::{Channel, Source};
+use crate::apu::scope::SharedScope;
+use crate::apu::stretch::StretchMode;
+use crate::audio::AudioBuffer;
+use crate::audio_recorder::AudioRecorder;
+use crate::bus::Bus;
+use crate::cartridge::{Region, Rom};
+use crate::cheats::Cheats;
+use crate::coverage::Coverage;
+use crate::cpu::{InterruptType, CPU};
+use crate::debugger::{self, BreakReason, Debugger};
+use crate::error::RunescoError;
+use crate::history::History;
+use crate::mapper::SaveDevice;
+use crate::joypads::JoypadButton;
+use crate::movie::{Input, InputEvent, Movie, Player, Recorder};
+use crate::joypads::Joypad;
+use crate::ppu::write_log::WriteLog;
+use crate::ppu::NesPPU;
+use crate::render::compositor::Compositor;
+use crate::render::frame::Frame;
+use crate::rng::Rng;
+use crate::savestate::SaveState;
+use crate::trace::{CpuSnapshot, TraceEntry};
+use crate::turbo_file::TurboFile;
+use crate::vs_system::VsPanel;
+
+type ScanlineHook = Box<dyn FnMut(&NesPPU)>;
+type AudioCallback = Box<dyn FnMut(&[f32])>;
+type InstructionHook = Box<dyn FnMut(&CpuSnapshot)>;
+
+/// A whole console: CPU, PPU, RAM and cartridge, driven one frame at a time.
+///
+/// It doesn't need a window, so it works the same in the SDL frontend, in tests and in
+/// headless runs.
+///
+/// ```
+/// use runesco::nes::Nes;
+///
+/// let rom = std::fs::read("nestest.nes").unwrap();
+/// let mut nes = Nes::new(&rom).unwrap();
+///
+/// nes.run_frame();
+/// assert_eq!(nes.frame_count(), 1);
+///
+/// // 256 x 240 pixels, 3 bytes (RGB) each
+/// assert_eq!(nes.frame().data.len(), 256 * 240 * 3);
+/// ```
+pub struct Nes {
+    cpu: CPU<'static>,
+    compositor: Compositor,
+    frame: Frame,
+    frames: u64,
+    jumps: u64, // times the console left its timeline: see jumps()
+    debugger: Debugger,
+    break_reason: Option<BreakReason>, // set while the debugger has stopped emulation
+    history: History,
+    scanline_hooks: Vec<(u16, ScanlineHook)>,
+    instruction_hook: Option<InstructionHook>,
+    last_scanline: u16,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    input_frame: Option<u64>, // the frame whose start-of-frame movie input was handled
+    last_strobes: u64,
+    cycle_base: u64, // CPU cycles run before the last power cycle: movie cycles count on from them
+    audio: AudioBuffer,
+    audio_recorder: Option<AudioRecorder>,
+    audio_callback: Option<AudioCallback>,
+    frame_samples: Vec<f32>, // the last frame's sound, gathered for the audio callback
+    rom_sha1: String,
+    region: Region,
+    rom: Rom, // kept to power on again from
+    rng: Rng, // see rng(); its streams go by `frames`, which a power cycle doesn't restart
+}
+
+impl Nes {
+    /// Parses an iNES image and powers the console on.
+    ///
+    /// ```
+    /// use runesco::nes::Nes;
+    ///
+    /// assert!(Nes::new(b"not a rom, just some bytes").is_err());
+    /// ```
+    pub fn new(rom: &[u8]) -> Result<Nes, RunescoError> {
+        Nes::from_rom(Rom::new(rom)?)
+    }
+
+    /// Fails if the cartridge's mapper isn't supported.
+    pub fn from_rom(rom: Rom) -> Result<Nes, RunescoError> {
+        // Without a window there is nothing to do at the end of a frame: the facade
+        // reads the picture out of the PPU itself in run_frame.
+        let rom_sha1 = rom.sha1();
+        let region = rom.region;
+        let bus = Bus::new(rom.clone(), |_, _, _| {})?;
+        let mut cpu = CPU::new(bus);
+        cpu.halt_on_brk = false;
+        cpu.reset();
+
+        Ok(Nes {
+            cpu,
+            compositor: Compositor::new(),
+            frame: Frame::new(),
+            frames: 0,
+            jumps: 0,
+            debugger: Debugger::new(),
+            break_reason: None,
+            history: History::new(),
+            scanline_hooks: vec![],
+            instruction_hook: None,
+            last_scanline: 0,
+            recorder: None,
+            player: None,
+            input_frame: None,
+            last_strobes: 0,
+            cycle_base: 0,
+            audio: AudioBuffer::new(),
+            audio_recorder: None,
+            audio_callback: None,
+            frame_samples: vec![],
+            rom_sha1,
+            region,
+            rom,
+            rng: Rng::new(0),
+        })
+    }
+
+    /// Swaps in another cartridge and powers the console on again. Frontend state
+    /// (compositor stages, debugger settings, emulation options) is kept. On error the old
+    /// cartridge stays in.
+    ///
+    /// ```
+    /// use runesco::cartridge::Rom;
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.set_dmc_read_conflict(false);
+    /// nes.run_frame();
+    ///
+    /// nes.insert_cartridge(Rom::new(&rom).unwrap()).unwrap();
+    /// assert_eq!(nes.frame_count(), 0);
+    /// assert!(!nes.dmc_read_conflict());
+    /// ```
+    pub fn insert_cartridge(&mut self, rom: Rom) -> Result<(), RunescoError> {
+        let mut nes = Nes::from_rom(rom)?;
+        nes.jumps = self.jumps + 1;
+        self.carry_settings(&mut nes);
+        *self = nes;
+        Ok(())
+    }
+
+    // What stays when the console is powered on again: the frontend's, not the cartridge's.
+    fn carry_settings(&mut self, nes: &mut Nes) {
+        nes.set_dmc_read_conflict(self.dmc_read_conflict());
+        nes.set_open_bus(self.open_bus());
+        nes.set_bus_conflicts(self.bus_conflicts());
+        nes.set_audio_filters(self.audio_filters());
+        nes.set_fast_forward_audio(self.fast_forward_audio());
+        for source in [Source::Console, Source::Cartridge] {
+            nes.set_source_volume(source, self.source_volume(source));
+        }
+        nes.set_master_volume(self.master_volume());
+        nes.set_apu_scope(self.cpu.bus.apu().scope());
+        for channel in Channel::ALL {
+            nes.set_channel_muted(channel, self.channel_muted(channel));
+        }
+        nes.cpu.bus.plug_devices(self.cpu.bus.take_devices());
+        nes.audio_recorder = self.audio_recorder.take();
+        nes.audio_callback = self.audio_callback.take();
+        nes.compositor = std::mem::take(&mut self.compositor);
+        nes.debugger = std::mem::take(&mut self.debugger);
+        nes.scanline_hooks = std::mem::take(&mut self.scanline_hooks);
+        nes.instruction_hook = self.instruction_hook.take();
+    }
+
+    /// Presses the reset button. While recording a movie, the press goes into it.
+    pub fn reset(&mut self) {
+        self.record_console(Input::Reset);
+        self.press_reset();
+    }
+
+    /// Switches the console off and on again, on the same cartridge. Unlike
+    /// [`Nes::insert_cartridge`] this carries on the same session: the frame count, a movie
+    /// being recorded or played, what's plugged in and the random seed stay, and so do
+    /// battery-backed RAM and the Disk System's disks (as written, with the same side in).
+    /// Everything else starts over.
+    ///
+    /// ```
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.run_frame();
+    /// nes.ram_mut()[0x300] = 0x55;
+    ///
+    /// nes.power_cycle();
+    /// assert_eq!(nes.frame_count(), 1);
+    /// assert_ne!(nes.ram()[0x300], 0x55);
+    /// ```
+    pub fn power_cycle(&mut self) {
+        self.record_console(Input::Power);
+        self.power_on_again();
+    }
+
+    fn press_reset(&mut self) {
+        self.jumps += 1;
+        self.cpu.bus.apu_mut().reset();
+        self.cpu.reset();
+    }
+
+    fn power_on_again(&mut self) {
+        let mut nes = Nes::from_rom(self.rom.clone()).expect("the cartridge was running a moment ago");
+        if self.has_battery() {
+            nes.prg_ram_mut().copy_from_slice(self.prg_ram());
+        }
+        if self.save_device().is_some() {
+            let _ = nes.load_save_device(&self.save_device_data());
+        }
+        if let Some(media) = self.cpu.bus.mapper().borrow().media() {
+            nes.cpu.bus.mapper().borrow_mut().load_media(&media);
+        }
+        let (pad1, pad2) = self.cpu.bus.joypads();
+        let (new1, new2) = nes.cpu.bus.joypads();
+        std::mem::swap(pad1, new1);
+        std::mem::swap(pad2, new2);
+        *nes.cheats_mut() = std::mem::take(self.cheats_mut());
+        *nes.cpu.bus.coverage_mut() = self.cpu.bus.coverage_mut().take();
+        self.carry_settings(&mut nes);
+
+        nes.frames = self.frames;
+        nes.rng = std::mem::replace(&mut self.rng, Rng::new(0));
+        nes.jumps = self.jumps + 1;
+        nes.frame = std::mem::replace(&mut self.frame, Frame::new());
+        nes.history = std::mem::take(&mut self.history);
+        nes.recorder = self.recorder.take();
+        nes.player = self.player.take();
+        nes.input_frame = self.input_frame;
+        nes.cycle_base = self.movie_cycle();
+        nes.audio = std::mem::take(&mut self.audio);
+        *self = nes;
+    }
+
+    // Where the console is, as movies count cycles.
+    fn movie_cycle(&self) -> u64 {
+        self.cycle_base + self.cpu.bus.cycles() as u64
+    }
+
+    fn record_console(&mut self, input: Input) {
+        let (frame, cycle) = (self.frames, self.movie_cycle());
+        if let Some(recorder) = &mut self.recorder {
+            recorder.console(frame, cycle, input);
+        }
+    }
+
+    /// Runs the CPU until the PPU reaches vblank, then draws the finished picture.
+    /// Stops early (without drawing) if the debugger breaks; while stopped this does nothing
+    /// until [`Nes::resume`] is called. A jammed CPU also stops it until [`Nes::reset`].
+    ///
+    /// ```
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// for _ in 0..10 {
+    ///     nes.run_frame();
+    /// }
+    /// assert_eq!(nes.frame_count(), 10);
+    /// ```
+    pub fn run_frame(&mut self) {
+        if self.break_reason.is_some() || self.cpu.is_jammed() {
+            return;
+        }
+        loop {
+            let reason = self.debugger.check(&mut self.cpu);
+            if reason.is_some() {
+                self.break_reason = reason;
+                return;
+            }
+            if self.execute() {
+                return;
+            }
+        }
+    }
+
+    /// Runs a single CPU instruction, even while the debugger has stopped emulation (it stays
+    /// stopped). Breakpoints don't fire on it. If the instruction finishes the frame, the
+    /// picture is drawn as in [`Nes::run_frame`].
+    pub fn step_instruction(&mut self) {
+        if self.cpu.is_jammed() {
+            return;
+        }
+        let _ = self.debugger.check(&mut self.cpu); // keeps its call stack up to date
+        self.execute();
+    }
+
+    // One instruction and everything around it. True once the frame is finished (and drawn)
+    // or the CPU jammed.
+    fn execute(&mut self) -> bool {
+        if self.input_frame != Some(self.frames) {
+            self.input_frame = Some(self.frames);
+            self.movie_frame_start();
+        }
+        let (frame, cycle) = (self.frames, self.movie_cycle());
+        while let Some(input) = self.player.as_mut().and_then(|player| player.console_due(frame, cycle)) {
+            match input {
+                Input::Reset => self.press_reset(),
+                Input::Power => self.power_on_again(),
+                Input::Joypad { .. } => {}
+            }
+        }
+
+        let entry = TraceEntry::capture(&self.cpu);
+        let running = self.cpu.step();
+        // a step that only took an interrupt didn't run the instruction we captured
+        match self.cpu.peek_interrupt_event() {
+            Some(event) if event.itype != InterruptType::BRK => {}
+            _ => {
+                self.history.push(entry);
+                if let Some(hook) = &mut self.instruction_hook {
+                    hook(&entry);
+                }
+            }
+        }
+
+        let mut frame_done = !running; // jammed: the CPU has stopped
+        if running {
+            if self.cpu.bus.strobe_count() != self.last_strobes {
+                self.last_strobes = self.cpu.bus.strobe_count();
+                self.movie_strobe();
+            }
+
+            let scanline = self.cpu.bus.ppu().scanline();
+            if scanline != self.last_scanline {
+                self.last_scanline = scanline;
+                self.run_scanline_hooks(scanline);
+            }
+            frame_done = self.cpu.bus.take_frame_ready();
+        }
+        if frame_done {
+            let (audio, recorder) = (&mut self.audio, &mut self.audio_recorder);
+            let (frame_samples, gather) = (&mut self.frame_samples, self.audio_callback.is_some());
+            frame_samples.clear();
+            self.cpu.bus.apu_mut().end_frame(|sample| {
+                audio.push(sample);
+                if let Some(recorder) = recorder {
+                    recorder.push(sample);
+                }
+                if gather {
+                    frame_samples.push(sample);
+                }
+            });
+            if let Some(callback) = &mut self.audio_callback {
+                callback(&self.frame_samples);
+            }
+            self.compositor.compose(self.cpu.bus.ppu(), &mut self.frame);
+            // the next period's RAM writes are only worth keeping if the heatmap will draw them
+            self.cpu.bus.ppu_mut().record_ram_writes = self.compositor.is_enabled("heatmap");
+            self.frames += 1;
+            self.rng.begin_frame(self.frames);
+        }
+        frame_done
+    }
+
+    /// Starts recording the joypads into a movie. Movies play back from power-on, so this is
+    /// meant to be called right after [`Nes::new`]. With `subframe`, every controller read
+    /// is recorded instead of one sample per frame (see [`crate::movie`]).
+    pub fn record_movie(&mut self, subframe: bool) {
+        let seed = self.rng.seed();
+        self.recorder = Some(Recorder::new(seed, subframe));
+        self.player = None;
+    }
+
+    /// The movie recorded so far.
+    pub fn recording(&self) -> Option<&Movie> {
+        self.recorder.as_ref().map(|recorder| recorder.movie())
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    /// Replays a movie, overriding the joypads. Like recording, it starts from power-on.
+    ///
+    /// ```
+    /// use runesco::movie::Movie;
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let movie = Movie::parse("runesco-movie 1\nseed 0\n2 - 1 08\n").unwrap(); // Start on frame 2
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.play_movie(movie);
+    /// for _ in 0..3 {
+    ///     nes.run_frame();
+    /// }
+    /// assert!(!nes.is_playing_movie());
+    /// ```
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.rng.reseed(movie.seed, self.frames);
+        self.player = Some(Player::new(movie));
+        self.recorder = None;
+    }
+
+    /// Adds an input at the end of the movie being played, for movies streamed in while they
+    /// play (see [`crate::spectate`]). Does nothing if no movie is playing.
+    pub fn queue_movie_input(&mut self, event: InputEvent) {
+        if let Some(player) = &mut self.player {
+            player.push(event);
+        }
+    }
+
+    /// True while a movie still has inputs to apply.
+    pub fn is_playing_movie(&self) -> bool {
+        self.player.as_ref().is_some_and(|player| !player.finished())
+    }
+
+    // As the game sees them, turbo included: that's what a movie records.
+    fn buttons(&mut self) -> [u8; 2] {
+        let (pad1, pad2) = self.cpu.bus.joypads();
+        [pad1.pressed().bits(), pad2.pressed().bits()]
+    }
+
+    fn set_buttons(&mut self, buttons: [u8; 2]) {
+        let (pad1, pad2) = self.cpu.bus.joypads();
+        pad1.button_status = JoypadButton::from_bits_truncate(buttons[0]);
+        pad2.button_status = JoypadButton::from_bits_truncate(buttons[1]);
+    }
+
+    fn movie_frame_start(&mut self) {
+        let frame = self.frames;
+        // a movie's inputs have the turbo presses in them already
+        let playing = self.is_playing_movie();
+        let (pad1, pad2) = self.cpu.bus.joypads();
+        for pad in [pad1, pad2] {
+            pad.set_turbo_phase(if playing { 0 } else { frame });
+        }
+
+        let mut buttons = self.buttons();
+        if let Some(recorder) = self.recorder.as_mut().filter(|recorder| !recorder.subframe()) {
+            recorder.sample(frame, None, buttons);
+        }
+        // only a movie changes the buttons; writing back what the game sees would let go of
+        // turbo buttons the player is still holding
+        if let Some(player) = self.player.as_mut().filter(|_| playing) {
+            player.frame_start(frame, &mut buttons);
+            self.set_buttons(buttons);
+        }
+    }
+
+    // Called after the instruction that strobed the controllers, before the game reads them.
+    fn movie_strobe(&mut self) {
+        let mut buttons = self.buttons();
+        let (frame, cycle) = (self.frames, self.movie_cycle());
+        if let Some(recorder) = self.recorder.as_mut().filter(|recorder| recorder.subframe()) {
+            recorder.sample(frame, Some(cycle), buttons);
+        }
+        if let Some(player) = self.player.as_mut().filter(|player| !player.finished()) {
+            player.strobe(cycle, &mut buttons);
+            self.set_buttons(buttons);
+        }
+    }
+
+    /// Switches auto-fire on or off for `button` of `player` (0 or 1): while it's held, the
+    /// game sees it pressed and released in turns. Returns whether it's on now.
+    ///
+    /// ```
+    /// use runesco::joypads::JoypadButton;
+    /// # use runesco::nes::Nes;
+    /// # let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// assert!(nes.toggle_turbo(0, JoypadButton::BUTTON_B));
+    /// assert!(!nes.toggle_turbo(0, JoypadButton::BUTTON_B));
+    /// ```
+    pub fn toggle_turbo(&mut self, player: usize, button: JoypadButton) -> bool {
+        let (pad1, pad2) = self.cpu.bus.joypads();
+        let pad = if player == 0 { pad1 } else { pad2 };
+        pad.turbo.toggle(button);
+        pad.turbo.contains(button)
+    }
+
+    /// Calls `hook` every time the PPU starts `scanline` (0-261; 241 is the start of vblank),
+    /// at the first instruction boundary on that line. Useful to look at mid-frame state, like
+    /// the scroll registers of a split screen.
+    pub fn add_scanline_hook(&mut self, scanline: u16, hook: impl FnMut(&NesPPU) + 'static) {
+        self.scanline_hooks.push((scanline, Box::new(hook)));
+    }
+
+    /// Calls `hook` after every instruction the CPU runs, with the registers, opcode bytes,
+    /// PPU position and cycle count as they were when it started; the difference in `cycles`
+    /// between two calls is how long the first one took. Interrupts aren't instructions and
+    /// don't get a call of their own, the handler's first instruction does. Meant for tools
+    /// built on top of the facade: profilers, coverage maps, visualizers. Only one hook is
+    /// kept (setting another replaces it) and it stays set across [`Nes::insert_cartridge`].
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// // a profile: how many times each address ran
+    /// let executed = Rc::new(RefCell::new(vec![0u32; 0x10000]));
+    /// let counts = executed.clone();
+    /// nes.set_instruction_hook(move |cpu| counts.borrow_mut()[cpu.pc as usize] += 1);
+    /// nes.run_frame();
+    /// nes.clear_instruction_hook();
+    ///
+    /// let total: u32 = executed.borrow().iter().sum();
+    /// assert!(total > 5000); // a frame is 29781 cycles, 2 to 7 per instruction
+    /// let last = nes.history().iter().last().unwrap().pc;
+    /// assert!(executed.borrow()[last as usize] > 0);
+    /// ```
+    pub fn set_instruction_hook(&mut self, hook: impl FnMut(&CpuSnapshot) + 'static) {
+        self.instruction_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_instruction_hook(&mut self) {
+        self.instruction_hook = None;
+    }
+
+    fn run_scanline_hooks(&mut self, scanline: u16) {
+        let mut ran = false;
+        for (line, hook) in self.scanline_hooks.iter_mut() {
+            if *line == scanline {
+                hook(self.cpu.bus.ppu());
+                ran = true;
+            }
+        }
+        if ran {
+            self.cpu.bus.ppu_mut().record_hook(scanline);
+        }
+    }
+
+    /// The last instructions executed, oldest first.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Writes to the PPU registers during the last few frames, oldest first, stamped with the
+    /// frame, scanline and dot they happened on.
+    ///
+    /// ```
+    /// # use runesco::nes::Nes;
+    /// # let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.ppu_write_log_mut().set_frames(1); // only the frame in progress
+    /// nes.run_frame();
+    /// for write in nes.ppu_write_log().iter().filter(|write| write.addr == 0x2005) {
+    ///     println!("{}", write); // frame 1 line 241 dot  30  $2005 PPUSCROLL = 00
+    /// }
+    /// ```
+    pub fn ppu_write_log(&self) -> &WriteLog {
+        self.cpu.bus.ppu().write_log()
+    }
+
+    pub fn ppu_write_log_mut(&mut self) -> &mut WriteLog {
+        self.cpu.bus.ppu_mut().write_log_mut()
+    }
+
+    /// True once the CPU has executed a KIL/JAM opcode. Only [`Nes::reset`] recovers.
+    pub fn is_jammed(&self) -> bool {
+        self.cpu.is_jammed()
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Why emulation is stopped, if the debugger stopped it.
+    pub fn break_reason(&self) -> Option<BreakReason> {
+        self.break_reason
+    }
+
+    pub fn resume(&mut self) {
+        if self.break_reason.take().is_some() {
+            self.debugger.resume_from(self.cpu.program_counter);
+        }
+    }
+
+    /// Continues until the current subroutine returns. Returns false (and does nothing)
+    /// outside of a subroutine.
+    pub fn step_out(&mut self) -> bool {
+        if !self.debugger.step_out() {
+            return false;
+        }
+        self.resume();
+        true
+    }
+
+    /// Continues until the CPU reaches `addr`.
+    pub fn run_to(&mut self, addr: u16) {
+        self.debugger.run_to(addr);
+        self.resume();
+    }
+
+    /// Which PRG ROM bank is mapped in each part of $8000-$FFFF, one line per window the
+    /// mapper switches, like `$8000-$BFFF: bank 03`. Bank numbers are the ones bank-aware
+    /// breakpoints use (see [`crate::debugger::Breakpoint`]).
+    pub fn prg_bank_layout(&self) -> String {
+        debugger::bank_layout(&self.cpu)
+    }
+
+    /// How many disk sides the game has: 0 for cartridges, 2 or more for Disk System games.
+    pub fn disk_sides(&self) -> usize {
+        self.cpu.bus.mapper().borrow().disk_sides()
+    }
+
+    /// The disk side in the drive, counted from 0 (disk 1 side A), or None while it's ejected.
+    pub fn inserted_disk(&self) -> Option<usize> {
+        self.cpu.bus.mapper().borrow().inserted_disk()
+    }
+
+    /// Takes the disk out and puts `side` in (None: leave the drive empty). The drive stays
+    /// empty for a moment first, as the BIOS waits to see the old disk go.
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.cpu.bus.mapper().borrow_mut().insert_disk(side);
+    }
+
+    /// Swipes a card through the cartridge's barcode reader (the Datach): `code` is the EAN-13
+    /// or EAN-8 number printed under the bars. Fails on a mistyped code (the check digit
+    /// catches most) and on cartridges without a reader.
+    pub fn scan_barcode(&mut self, code: &str) -> Result<(), String> {
+        self.cpu.bus.mapper().borrow_mut().scan_barcode(code)
+    }
+
+    /// The last picture drawn by [`Nes::run_frame`], as 256x240 RGB24 pixels.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Counts the times the console didn't go on from the frame before: a state loaded, the
+    /// reset button, a power cycle or another cartridge. Whatever compares memory from frame
+    /// to frame starts over when it changes.
+    pub fn jumps(&self) -> u64 {
+        self.jumps
+    }
+
+    /// Randomness that replays: seeded from the movie being recorded or played (see
+    /// [`crate::rng`]), with a stream of its own for every frame. Frames are counted as
+    /// [`Nes::frame_count`] does, so a power cycle doesn't bring back a stream already used.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// The 2KiB of CPU work RAM ($0000-$07FF).
+    ///
+    /// ```
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.run_frame();
+    ///
+    /// let ram = nes.ram();
+    /// assert_eq!(ram.len(), 2048);
+    /// ```
+    pub fn ram(&self) -> &[u8; 2048] {
+        self.cpu.bus.ram()
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8; 2048] {
+        self.cpu.bus.ram_mut()
+    }
+
+    /// The 8KiB of cartridge RAM at $6000-$7FFF.
+    pub fn prg_ram(&self) -> &[u8] {
+        self.cpu.bus.prg_ram()
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.cpu.bus.prg_ram_mut()
+    }
+
+    /// A byte of the CPU's address space, read without side effects. See [`Bus::peek`].
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.bus.peek(addr)
+    }
+
+    /// The 2KiB of nametable RAM inside the console.
+    pub fn vram(&self) -> &[u8; 2048] {
+        &self.cpu.bus.ppu().vram
+    }
+
+    pub fn vram_mut(&mut self) -> &mut [u8; 2048] {
+        &mut self.cpu.bus.ppu_mut().vram
+    }
+
+    /// Sprite memory: 64 sprites of 4 bytes (Y, tile, attributes, X).
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.cpu.bus.ppu().oam_data
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8; 256] {
+        &mut self.cpu.bus.ppu_mut().oam_data
+    }
+
+    /// Whether the cartridge keeps its PRG-RAM with the power off (see [`crate::sram`]).
+    pub fn has_battery(&self) -> bool {
+        self.cpu.bus.has_battery()
+    }
+
+    /// The board's own save memory besides battery-backed PRG-RAM (an EEPROM, or flash the
+    /// game rewrites), if it has any. See [`crate::sram`].
+    pub fn save_device(&self) -> Option<SaveDevice> {
+        self.cpu.bus.mapper().borrow().save_device()
+    }
+
+    /// The save device's contents, in its file format.
+    pub fn save_device_data(&self) -> Vec<u8> {
+        self.cpu.bus.mapper().borrow().save_device_data()
+    }
+
+    pub fn load_save_device(&mut self, data: &[u8]) -> Result<(), String> {
+        self.cpu.bus.mapper().borrow_mut().load_save_device(data)
+    }
+
+    /// Snapshots the console; see [`crate::savestate`] for what is (and isn't yet) included.
+    ///
+    /// ```
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// let state = nes.save_state();
+    ///
+    /// nes.run_frame();
+    /// nes.load_state(&state).unwrap();
+    /// assert_eq!(nes.frame_count(), 1);
+    /// assert_eq!(nes.cpu().bus.cycles(), 0); // but the console is back at power-on
+    /// ```
+    pub fn save_state(&self) -> SaveState {
+        SaveState::capture(&self.cpu)
+    }
+
+    /// Fails, leaving the console untouched, if the state doesn't fit this cartridge.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        state.restore(&mut self.cpu)?;
+        self.jumps += 1;
+        self.history.clear(); // the instructions before the load didn't lead here
+        self.cpu.bus.ppu_mut().write_log_mut().clear();
+        self.break_reason = None;
+        self.audio.discontinuity(); // queued samples belong to the timeline we just left
+        self.last_scanline = self.cpu.bus.ppu().scanline();
+        Ok(())
+    }
+
+    /// Whether DMC sample fetches disturb controller reads like on the console (the default).
+    /// See [`Bus::dmc_dma`].
+    pub fn set_dmc_read_conflict(&mut self, enabled: bool) {
+        self.cpu.bus.set_dmc_read_conflict(enabled);
+    }
+
+    pub fn dmc_read_conflict(&self) -> bool {
+        self.cpu.bus.dmc_read_conflict()
+    }
+
+    /// Whether reads nothing answers see the last value on the data bus, like on the console
+    /// (the default), instead of 0. See [`Bus::set_open_bus`].
+    pub fn set_open_bus(&mut self, enabled: bool) {
+        self.cpu.bus.set_open_bus(enabled);
+    }
+
+    pub fn open_bus(&self) -> bool {
+        self.cpu.bus.open_bus_enabled()
+    }
+
+    /// Whether writes to ROM space are ANDed with the ROM byte there on boards built from
+    /// discrete logic, like on the console (the default). See [`Bus::set_bus_conflicts`].
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.cpu.bus.set_bus_conflicts(enabled);
+    }
+
+    pub fn bus_conflicts(&self) -> bool {
+        self.cpu.bus.bus_conflicts()
+    }
+
+    /// Tells the sound how many frames the frontend runs for each one it shows (fast-forward
+    /// at 4.0, 1.0 at the console's speed), so what comes out still fits the time it has to
+    /// play in; the mode is what fast-forward sounds like. See [`crate::apu::stretch`].
+    ///
+    /// ```
+    /// use runesco::apu::stretch::StretchMode;
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// nes.set_fast_forward_audio(StretchMode::Skip);
+    /// nes.set_audio_speed(4.0);
+    /// for _ in 0..4 {
+    ///     nes.run_frame();
+    /// }
+    /// assert!(nes.audio().len() < 1000); // about one frame's worth
+    /// ```
+    pub fn set_audio_speed(&mut self, speed: f32) {
+        self.cpu.bus.apu_mut().set_speed(speed);
+    }
+
+    pub fn set_fast_forward_audio(&mut self, mode: StretchMode) {
+        self.cpu.bus.apu_mut().set_stretch_mode(mode);
+    }
+
+    pub fn fast_forward_audio(&self) -> StretchMode {
+        self.cpu.bus.apu().stretch_mode()
+    }
+
+    /// Whether the sound goes through the console's output filters (the default): high-pass
+    /// at 90Hz and 440Hz, low-pass at 14kHz. Off, it's the APU's unfiltered output.
+    pub fn set_audio_filters(&mut self, enabled: bool) {
+        self.cpu.bus.apu_mut().set_filters(enabled);
+    }
+
+    pub fn audio_filters(&self) -> bool {
+        self.cpu.bus.apu().filters()
+    }
+
+    /// Shares the APU's channel levels with the visualizer overlay that owns `scope` (see
+    /// [`crate::apu::scope`]). It only records while the overlay is drawn.
+    pub fn set_apu_scope(&mut self, scope: Option<SharedScope>) {
+        self.cpu.bus.apu_mut().set_scope(scope);
+    }
+
+    /// How loud the console's own channels or the cartridge's sound chip play, 1.0 being as
+    /// on the console. See [`crate::apu::mixer`].
+    ///
+    /// ```
+    /// use runesco::apu::mixer::Source;
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// nes.set_source_volume(Source::Cartridge, 0.5);
+    /// assert_eq!(nes.source_volume(Source::Cartridge), 0.5);
+    /// assert_eq!(nes.source_volume(Source::Console), 1.0);
+    /// ```
+    pub fn set_source_volume(&mut self, source: Source, volume: f32) {
+        self.cpu.bus.apu_mut().set_volume(source, volume);
+    }
+
+    pub fn source_volume(&self, source: Source) -> f32 {
+        self.cpu.bus.apu().volume(source)
+    }
+
+    /// How loud the whole sound output plays, on top of the sources' volumes: 1.0 as mixed,
+    /// 0.0 silent. For the frontend's volume controls (see [`crate::volume`]).
+    ///
+    /// ```
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// nes.set_master_volume(0.25);
+    /// assert_eq!(nes.master_volume(), 0.25);
+    /// ```
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.cpu.bus.apu_mut().set_master_volume(volume);
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.cpu.bus.apu().master_volume()
+    }
+
+    /// Silences one sound channel (or brings it back), to hear what the others play. The
+    /// cartridge's sound chip counts as one channel.
+    ///
+    /// ```
+    /// use runesco::apu::mixer::Channel;
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// nes.set_channel_muted(Channel::Noise, true);
+    /// assert!(nes.channel_muted(Channel::Noise));
+    ///
+    /// nes.solo_channel(Channel::Triangle); // only the triangle plays
+    /// assert!(nes.channel_muted(Channel::Pulse1) && !nes.channel_muted(Channel::Triangle));
+    /// nes.solo_channel(Channel::Triangle); // and now everything again
+    /// assert!(!nes.channel_muted(Channel::Noise));
+    /// ```
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.cpu.bus.apu_mut().set_muted(channel, muted);
+    }
+
+    pub fn channel_muted(&self, channel: Channel) -> bool {
+        self.cpu.bus.apu().muted(channel)
+    }
+
+    /// Mutes every channel but `channel`, or unmutes them all if it was already the only one
+    /// playing.
+    pub fn solo_channel(&mut self, channel: Channel) {
+        self.cpu.bus.apu_mut().solo(channel);
+    }
+
+    /// Starts writing the sound output to a .wav file, as it's played: samples go to the
+    /// recorder at the end of every frame. It keeps recording across
+    /// [`Nes::insert_cartridge`], until [`Nes::stop_audio_recording`].
+    ///
+    /// ```
+    /// use runesco::audio_recorder::AudioRecorder;
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// let path = std::env::temp_dir().join("runesco-doctest.wav");
+    /// nes.start_audio_recording(AudioRecorder::create(&path).unwrap());
+    /// nes.run_frame();
+    /// let recorder = nes.stop_audio_recording().unwrap();
+    /// assert!(recorder.samples() > 0);
+    /// recorder.finish().unwrap();
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    pub fn start_audio_recording(&mut self, recorder: AudioRecorder) {
+        self.audio_recorder = Some(recorder);
+    }
+
+    /// Takes the recorder back, for [`AudioRecorder::finish`] to complete the file.
+    pub fn stop_audio_recording(&mut self) -> Option<AudioRecorder> {
+        self.audio_recorder.take()
+    }
+
+    pub fn is_recording_audio(&self) -> bool {
+        self.audio_recorder.is_some()
+    }
+
+    /// Hands the sound to `callback` at the end of every frame, the frame's samples at once
+    /// (mono, -1.0..=1.0, at [`crate::audio::SAMPLE_RATE`]), for embedders that play or
+    /// process it themselves rather than drain [`Nes::audio`]. Replaces any earlier callback;
+    /// it stays set across [`Nes::insert_cartridge`].
+    ///
+    /// ```
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    /// # use runesco::nes::Nes;
+    /// # let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+    /// let heard = Rc::new(Cell::new(0));
+    /// let counter = heard.clone();
+    /// nes.set_audio_callback(move |samples| counter.set(counter.get() + samples.len()));
+    /// for _ in 0..60 {
+    ///     nes.run_frame();
+    /// }
+    /// assert!(heard.get() > 40_000); // about a second
+    /// nes.clear_audio_callback();
+    /// ```
+    pub fn set_audio_callback(&mut self, callback: impl FnMut(&[f32]) + 'static) {
+        self.audio_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_audio_callback(&mut self) {
+        self.audio_callback = None;
+    }
+
+    /// The cheat codes applied to the CPU's reads; none until some are added. They belong to the
+    /// cartridge, so [`Nes::insert_cartridge`] starts the new game without any.
+    ///
+    /// ```
+    /// use runesco::cheats::Cheat;
+    /// # use runesco::nes::Nes;
+    /// # let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.cheats_mut().add(Cheat::parse("0300:05").unwrap());
+    /// assert_eq!(nes.cheats().list().len(), 1);
+    /// ```
+    pub fn cheats(&self) -> &Cheats {
+        self.cpu.bus.cheats()
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        self.cpu.bus.cheats_mut()
+    }
+
+    /// Starts counting every CPU read and write by region of the address space and by PPU
+    /// register (see [`crate::coverage`]), from zero.
+    ///
+    /// ```
+    /// use runesco::nes::Nes;
+    ///
+    /// let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.start_coverage();
+    /// nes.run_frame();
+    /// let coverage = nes.coverage().unwrap();
+    /// assert!(coverage.region("PRG-ROM").unwrap().reads > 0);
+    /// println!("{}", coverage.report());
+    /// ```
+    pub fn start_coverage(&mut self) {
+        *self.cpu.bus.coverage_mut() = Some(Coverage::new());
+    }
+
+    /// What's been counted since [`Nes::start_coverage`].
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.cpu.bus.coverage()
+    }
+
+    /// SHA-1 of the cartridge's ROM data (PRG, CHR and any disk sides, without the header),
+    /// as printed at startup and in the `--json` result.
+    pub fn rom_sha1(&self) -> &str {
+        &self.rom_sha1
+    }
+
+    /// The TV system the cartridge says it was made for. Emulation is NTSC either way for now.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Sound output waiting for the frontend's audio device (see [`crate::audio`]).
+    pub fn audio(&mut self) -> &mut AudioBuffer {
+        &mut self.audio
+    }
+
+    pub fn joypads(&mut self) -> (&mut Joypad, &mut Joypad) {
+        self.cpu.bus.joypads()
+    }
+
+    /// Plugs an ASCII Turbo File into the expansion port (`None` unplugs it), for the games
+    /// that save to one. It stays plugged in across [`Nes::insert_cartridge`]. See
+    /// [`crate::turbo_file`].
+    ///
+    /// ```
+    /// use runesco::turbo_file::TurboFile;
+    /// # use runesco::nes::Nes;
+    /// # let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.attach_turbo_file(Some(TurboFile::new()));
+    /// nes.insert_cartridge(runesco::cartridge::Rom::new(&rom).unwrap()).unwrap();
+    /// assert!(nes.turbo_file().is_some());
+    /// ```
+    pub fn attach_turbo_file(&mut self, turbo_file: Option<TurboFile>) {
+        match turbo_file {
+            Some(turbo_file) => self.cpu.bus.plug(turbo_file),
+            None => {
+                self.cpu.bus.unplug::<TurboFile>();
+            }
+        }
+    }
+
+    pub fn turbo_file(&self) -> Option<&TurboFile> {
+        self.cpu.bus.device()
+    }
+
+    /// Holds the microphone on the Famicom's second controller loud (`true`) or quiet, as the
+    /// game reads it in bit 2 of $4016. See [`Bus::set_microphone`].
+    ///
+    /// ```
+    /// # use runesco::nes::Nes;
+    /// # let rom = std::fs::read("nestest.nes").unwrap();
+    /// let mut nes = Nes::new(&rom).unwrap();
+    /// nes.set_microphone(true);
+    /// assert!(nes.microphone());
+    /// ```
+    pub fn set_microphone(&mut self, loud: bool) {
+        self.cpu.bus.set_microphone(loud);
+    }
+
+    pub fn microphone(&self) -> bool {
+        self.cpu.bus.microphone()
+    }
+
+    /// The coin slots, service button and DIP switches of a Vs. System cabinet; `None` for
+    /// ordinary cartridges.
+    pub fn vs_panel(&mut self) -> Option<&mut VsPanel> {
+        self.cpu.bus.vs_panel()
+    }
+
+    pub fn compositor(&mut self) -> &mut Compositor {
+        &mut self.compositor
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU<'static> {
+        &mut self.cpu
+    }
+}
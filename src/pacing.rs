@@ -0,0 +1,88 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::audio::SAMPLE_RATE;
+
+// When the frontend shows the next picture. The console makes FRAME_RATE pictures a second,
+// which no display does exactly, and its sound card runs on a clock of its own too; tying
+// the emulation to either one lets the other drift. Audio is the one that can't be allowed
+// to: a queue that runs dry crackles, one that keeps growing puts the sound further and
+// further behind the picture. So the audio device is the master. Every picture, the pacer
+// looks at how much sound the device still has queued and makes the next frame a little
+// shorter when there's less than TARGET_QUEUED, a little longer when there's more. A change
+// of at most MAX_ADJUST (half a percent) is far too small to hear as pitch or see as speed,
+// and still catches up with any real device's clock error.
+//
+// The pictures aren't synced to the display (no vsync), so one in a few hundred is shown
+// twice or not at all on a 60Hz screen. A variable refresh display can follow the pacer
+// instead: with `pacing = "vrr"` in the config (see crate::video_config) each picture is
+// presented with vsync the moment it's due, and the display refreshes for it. That's only for
+// such displays: on a fixed-rate one, presenting blocks until its next refresh, so its clock
+// sets the pace rather than the audio device's, and the sound queue slowly runs dry or backs
+// up. Without an audio device the pacer runs off the system clock alone, at the nominal rate.
+// See: https://docs.libretro.com/development/cores/dynamic-rate-control/
+
+pub const FRAME_RATE: f64 = 60.0988; // NTSC
+pub const TARGET_QUEUED: u32 = SAMPLE_RATE / 20; // 50ms of sound waiting: about 3 frames
+pub const MAX_ADJUST: f64 = 0.005;
+const MAX_LAG: u32 = 4; // frames behind schedule before the pacer gives up catching up
+
+pub struct FramePacer {
+    next: Instant, // when the next picture is due
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        FramePacer { next: Instant::now() }
+    }
+
+    // How long the next frame should take, with `queued` samples waiting in the audio device
+    // (None without one).
+    pub fn period(queued: Option<u32>) -> Duration {
+        let nominal = 1.0 / FRAME_RATE;
+        let Some(queued) = queued else {
+            return Duration::from_secs_f64(nominal);
+        };
+        let fill = (queued as f64 / TARGET_QUEUED as f64 - 1.0).clamp(-1.0, 1.0);
+        Duration::from_secs_f64(nominal * (1.0 + fill * MAX_ADJUST))
+    }
+
+    // Sleeps until the next picture is due. After a long hold-up (a dialog, the debugger, a
+    // slow disk) the schedule starts over from now instead of rushing through the frames
+    // that were missed.
+    pub fn wait(&mut self, queued: Option<u32>) {
+        let period = FramePacer::period(queued);
+        self.next += period;
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        } else if now - self.next > period * MAX_LAG {
+            self.next = now;
+        }
+    }
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_audio_fill_nudges_the_period() {
+        let nominal = FramePacer::period(None).as_secs_f64();
+        assert!((nominal * FRAME_RATE - 1.0).abs() < 1e-6);
+        assert_eq!(FramePacer::period(Some(TARGET_QUEUED)).as_secs_f64(), nominal);
+
+        // running dry: frames come sooner, so more sound is made; overfull: later
+        let starving = FramePacer::period(Some(0)).as_secs_f64();
+        let overfull = FramePacer::period(Some(TARGET_QUEUED * 10)).as_secs_f64();
+        assert!((starving / nominal - (1.0 - MAX_ADJUST)).abs() < 1e-6); // Durations round to the ns
+        assert!((overfull / nominal - (1.0 + MAX_ADJUST)).abs() < 1e-6);
+        assert!(FramePacer::period(Some(TARGET_QUEUED / 2)).as_secs_f64() < nominal);
+    }
+}
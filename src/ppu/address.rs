@@ -51,6 +51,16 @@ impl AddrRegister {
         self.hi_ptr = true;
     }
 
+    // For savestates: the address and which byte the next $2006 write goes to.
+    pub fn latch(&self) -> bool {
+        self.hi_ptr
+    }
+
+    pub fn restore(&mut self, addr: u16, hi_ptr: bool) {
+        self.set(addr);
+        self.hi_ptr = hi_ptr;
+    }
+
     pub fn get(&self) -> u16 {
         // get full address
         ((self.value.0 as u16) << 8) | (self.value.1 as u16)
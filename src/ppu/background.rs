@@ -0,0 +1,301 @@
+// The background as the PPU draws it: a dot at a time, from nametable, attribute and pattern
+// bytes fetched while the picture is being drawn. Whatever the game changes mid-frame (the
+// scroll for a status bar, the CHR bank for a split, the nametable) shows from the dot it
+// changed on, the way the console shows it.
+// See: https://www.nesdev.org/wiki/PPU_scrolling and https://www.nesdev.org/wiki/PPU_rendering
+//
+// Scrolling goes through the PPU's internal registers rather than ScrollRegister and
+// AddrRegister, which only keep what the CPU wrote:
+//
+//   v: yyy NN YYYYY XXXXX   the VRAM address being fetched from: fine Y, nametable,
+//   t: yyy NN YYYYY XXXXX   coarse Y, coarse X; t is where the next line/frame starts
+//   fine_x: 0-7             which pixel of the tile on the left comes out first
+//   w: first or second write, shared by $2005 and $2006 and cleared by reading $2002
+//
+// $2000, $2005 and $2006 write into t (the second $2006 write also copies it into v), and
+// NesPPU::render_dot moves v along the picture: coarse X after every tile, Y at dot 256, X
+// back from t at dot 257, Y back from t on dots 280-304 of the pre-render line.
+//
+// Every 8 dots the next tile's bytes are fetched into the latches below; on the next dot they
+// go into the low halves of the shift registers, which shift once a dot. The pixel coming out
+// is bit 15 - fine_x. The fetches are done in one go on the tile's last dot, where the console
+// reads the high pattern byte, rather than spread over its 8 dots.
+//
+// The pixels are kept as palette RAM indices (palette * 4 + colour, 0 where transparent) and
+// turned into colours when the frame is composed, see render::render.
+
+pub struct Background {
+    pub v: u16,
+    pub t: u16,
+    pub fine_x: u8,
+    pub w: bool, // the next $2005/$2006 write is the second
+
+    // the next tile, fetched and waiting to be shifted in
+    next_palette: u8,
+    next_low: u8,
+    next_high: u8,
+
+    pattern_low: u16,
+    pattern_high: u16,
+    palette_low: u16, // the palette's bits, a whole byte of each per tile
+    palette_high: u16,
+
+    pixels: Vec<u8>, // 256x240
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Background {
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            next_palette: 0,
+            next_low: 0,
+            next_high: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            palette_low: 0,
+            palette_high: 0,
+            pixels: vec![0; 256 * 240],
+        }
+    }
+
+    // $2000: the nametable bits.
+    pub fn write_ctrl(&mut self, data: u8) {
+        self.t = (self.t & !0x0C00) | ((data as u16 & 0b11) << 10);
+    }
+
+    // $2005: X on the first write, Y on the second.
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !0x001F) | (data as u16 >> 3);
+            self.fine_x = data & 0b111;
+        } else {
+            self.t = (self.t & !0x73E0) | ((data as u16 & 0b111) << 12) | ((data as u16 & 0xF8) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    // $2006: the high byte on the first write, the low one (and t into v) on the second.
+    pub fn write_addr(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    // $2002 reads.
+    pub fn reset_toggle(&mut self) {
+        self.w = false;
+    }
+
+    // For savestates: v, t, fine X and w, 6 bytes.
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(self.v.to_le_bytes());
+        data.extend(self.t.to_le_bytes());
+        data.extend([self.fine_x, self.w as u8]);
+        data
+    }
+
+    pub fn load(&mut self, data: &[u8; 6]) {
+        self.v = u16::from_le_bytes([data[0], data[1]]);
+        self.t = u16::from_le_bytes([data[2], data[3]]);
+        self.fine_x = data[4] & 0b111;
+        self.w = data[5] != 0;
+    }
+
+    // States from before v and t were saved kept only what was written to the registers: put
+    // back as if it was written again, as it was between frames.
+    pub fn restore(&mut self, ctrl: u8, scroll_x: u8, scroll_y: u8) {
+        self.write_ctrl(ctrl);
+        self.w = false;
+        self.write_scroll(scroll_x);
+        self.write_scroll(scroll_y);
+        self.v = self.t;
+    }
+
+    pub fn nametable_addr(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    pub fn attribute_addr(&self) -> u16 {
+        0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        self.v >> 12
+    }
+
+    // The tile fetched at v: its attribute byte and the two pattern bytes of this line.
+    pub fn fetched(&mut self, attribute: u8, low: u8, high: u8) {
+        // the attribute byte covers 4x4 tiles, 2 bits for each 2x2 quarter
+        let shift = ((self.v >> 4) & 0b100) | (self.v & 0b10);
+        self.next_palette = (attribute >> shift) & 0b11;
+        self.next_low = low;
+        self.next_high = high;
+    }
+
+    pub fn reload(&mut self) {
+        self.pattern_low = (self.pattern_low & 0xFF00) | self.next_low as u16;
+        self.pattern_high = (self.pattern_high & 0xFF00) | self.next_high as u16;
+        let fill = |bit: u8| if bit != 0 { 0xFF } else { 0x00 };
+        self.palette_low = (self.palette_low & 0xFF00) | fill(self.next_palette & 0b01);
+        self.palette_high = (self.palette_high & 0xFF00) | fill(self.next_palette & 0b10);
+    }
+
+    pub fn shift(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.palette_low <<= 1;
+        self.palette_high <<= 1;
+    }
+
+    // The pixel coming out of the shift registers.
+    pub fn pixel(&self) -> u8 {
+        let bit = 15 - self.fine_x as u16;
+        let colour = ((self.pattern_high >> bit) & 1) << 1 | ((self.pattern_low >> bit) & 1);
+        if colour == 0 {
+            return 0;
+        }
+        let palette = ((self.palette_high >> bit) & 1) << 1 | ((self.palette_low >> bit) & 1);
+        (palette << 2 | colour) as u8
+    }
+
+    pub fn increment_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400; // the next nametable over
+        } else {
+            self.v += 1;
+        }
+    }
+
+    pub fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800; // the nametable below
+        } else if coarse_y == 31 {
+            coarse_y = 0; // out in the attribute bytes: wraps without switching
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    pub fn copy_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    pub fn copy_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, pixel: u8) {
+        self.pixels[y * 256 + x] = pixel;
+    }
+
+    // The last frame's picture, or as far as the current one has got.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_scroll_writes() {
+        let mut background = Background::new();
+        background.write_ctrl(0b10);
+        background.write_scroll(0x7D); // X = 125: coarse 15, fine 5
+        background.write_scroll(0x5E); // Y = 94: coarse 11, fine 6
+        assert_eq!(background.t, 0x696F); // 110 10 01011 01111
+        assert_eq!(background.fine_x, 5);
+        assert_eq!(background.v, 0);
+
+        background.write_addr(0x23);
+        background.write_addr(0xC5);
+        assert_eq!(background.v, 0x23C5);
+        assert_eq!(background.nametable_addr(), 0x23C5);
+    }
+
+    #[test]
+    fn test_split_writes_share_the_toggle() {
+        // the mid-frame scroll split: nametable, Y, X, then the rest of the address
+        let mut background = Background::new();
+        background.write_addr(0x04); // nametable 1
+        background.write_scroll(0x5E); // Y = 94: coarse 11, fine 6
+        background.write_scroll(0x7D); // X = 125: coarse 15, fine 5
+        assert_eq!(background.t, 0x6400 | (11 << 5) | 15);
+        assert_eq!(background.fine_x, 5);
+        assert_eq!(background.v, 0); // not until the last write
+        background.write_addr(0x6F); // coarse X 15, coarse Y 11 again, as games pick it
+        assert_eq!(background.t, 0x656F);
+        assert_eq!(background.v, background.t);
+        assert!(!background.w);
+
+        background.write_addr(0x21);
+        background.reset_toggle(); // a $2002 read in between
+        background.write_scroll(0x08);
+        assert_eq!(background.t & 0x1F, 1); // taken as X
+        assert!(background.w);
+
+        let mut loaded = Background::new();
+        loaded.load(&background.save().try_into().unwrap());
+        assert_eq!((loaded.v, loaded.t, loaded.fine_x, loaded.w), (background.v, background.t, 0, true));
+    }
+
+    #[test]
+    fn test_increments_wrap_into_the_next_nametable() {
+        let mut background = Background::new();
+        background.v = 31; // the last column of nametable 0
+        background.increment_x();
+        assert_eq!(background.nametable_addr(), 0x2400);
+
+        background.v = 0x7000 | (29 << 5); // the last line of the last row
+        background.increment_y();
+        assert_eq!(background.nametable_addr(), 0x2800);
+        assert_eq!(background.fine_y(), 0);
+
+        background.t = 0x0400 | 3;
+        background.copy_x();
+        assert_eq!(background.nametable_addr(), 0x2C03);
+    }
+
+    #[test]
+    fn test_shifting_out_pixels() {
+        let mut background = Background::new();
+        background.v = 0b10; // the top right quarter of its attribute byte
+        background.fetched(0b00_00_11_00, 0b1000_0001, 0b0100_0001);
+        background.reload();
+        for _ in 0..8 {
+            background.shift();
+        }
+        assert_eq!(background.pixel(), 3 << 2 | 0b01);
+        background.shift();
+        assert_eq!(background.pixel(), 3 << 2 | 0b10);
+        background.shift();
+        assert_eq!(background.pixel(), 0); // transparent whatever the palette
+
+        background.fine_x = 5; // five pixels further along
+        assert_eq!(background.pixel(), 3 << 2 | 0b11);
+    }
+}
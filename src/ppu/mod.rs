@@ -1,23 +1,48 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::cartridge::Mirroring;
+use crate::mapper::nrom::Nrom;
+use crate::mapper::SharedMapper;
 
 use address::AddrRegister;
+use background::Background;
 use controller::ControlRegister;
 use mask::MaskRegister;
 use scroll::ScrollRegister;
 use status::StatusRegister;
+use tile_cache::{DecodedTile, TileCache};
+use write_log::{PpuWrite, WriteLog};
 
 pub mod address;
+pub mod background;
 pub mod controller;
 pub mod mask;
 pub mod scroll;
 pub mod status;
+pub mod tile_cache;
+pub mod write_log;
+
+// When things happened during one frame period, for the scanline ruler and RAM heatmap
+// overlays. A period runs from the start of vblank (scanline 241) to the end of the next
+// picture, so the ruler drawn at vblank shows a complete set: the NMI that started it and the
+// picture after.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrameTiming {
+    pub nmi: Option<(u16, usize)>, // (scanline, dot) where the PPU raised NMI
+    pub irq_lines: Vec<u16>,       // scanlines on which the cartridge raised IRQ
+    pub hook_lines: Vec<u16>,      // scanlines where Nes scanline hooks ran
+    pub ram_writes: Vec<u16>,      // CPU RAM ($0000-$07FF) written, once per write
+}
 
 pub struct NesPPU {
     pub chr_rom: Vec<u8>,        // visuals as stored on cartridge
+    pub chr_is_ram: bool,        // CHR-RAM instead of ROM: the game can write chr_rom through $2007
+    pub tile_cache: TileCache,   // chr_rom, pre-decoded into pixels for the renderer
     pub palette_table: [u8; 32], // essentially a table of colours (internal)
     pub vram: [u8; 2048],        // 2KiB of space to hold information on Background
     pub oam_data: [u8; 256],     // keeps track of sprites (internal)
-    pub mirroring: Mirroring,
+    pub mapper: SharedMapper,    // picks the CHR banks and the mirroring
 
     internal_data_buf: u8, // holds previously read data: a buffer
 
@@ -27,9 +52,17 @@ pub struct NesPPU {
     pub oam_addr: u8,
     pub scroll: ScrollRegister,
     pub status: StatusRegister,
+    pub background: Background, // drawn a dot at a time, with the scroll registers it draws with
 
     scanline: u16,
     cycles: usize,
+    frame: u64, // number of frames completed since power-on
+    frame_ready: bool, // set when vblank starts: the picture for this frame is complete
+    sprite_zero_hit_at: Option<(usize, u16)>, // (x, scanline) of this frame's sprite zero hit
+    timing: FrameTiming,      // the period in progress
+    last_timing: FrameTiming, // the last complete period
+    write_log: WriteLog,      // register writes of the last few frames, for the debugger
+    pub record_ram_writes: bool, // whether the Bus reports RAM writes: only while the heatmap is on
     pub nmi_interrupt: Option<u8>,
 
 }
@@ -40,12 +73,19 @@ impl NesPPU {
         NesPPU::new(vec![0; 2048], Mirroring::HORIZONTAL)
     }
 
+    // A PPU wired to a cartridge without a mapper (NROM) using the given mirroring.
     pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
-        // chr_rom and mirroring passed as parameters as they are
+        NesPPU::new_with_mapper(chr_rom, Rc::new(RefCell::new(Nrom::new(vec![], mirroring))))
+    }
+
+    pub fn new_with_mapper(chr_rom: Vec<u8>, mapper: SharedMapper) -> Self {
+        // chr_rom and mapper passed as parameters as they are
         // specific to each game and provided by the cartridge
         NesPPU {
+            tile_cache: TileCache::decode_async(chr_rom.clone()),
             chr_rom: chr_rom,
-            mirroring: mirroring,
+            chr_is_ram: false,
+            mapper,
             vram: [0; 2048], // VIDEO RAM
             oam_data: [0; 64 * 4],
             palette_table: [0; 32],
@@ -58,33 +98,63 @@ impl NesPPU {
             oam_addr: 0,
             scroll: ScrollRegister::new(),
             status: StatusRegister::new(),
+            background: Background::new(),
 
             scanline:0,
             cycles:0,
+            frame: 0,
+            frame_ready: false,
+            sprite_zero_hit_at: None,
+            timing: FrameTiming::default(),
+            last_timing: FrameTiming::default(),
+            write_log: WriteLog::new(),
+            record_ram_writes: false,
             nmi_interrupt: None,
         }
     }
 
     pub fn tick(&mut self, cycles: u8) -> bool { // returns true on NMI, for use case see Bus.
+        let before = self.cycles;
+        let (mut line, mut dot) = (self.scanline, before);
+        for _ in 0..cycles {
+            self.render_dot(line, dot);
+            dot += 1;
+            if dot == 341 {
+                (line, dot) = ((line + 1) % 262, 0);
+            }
+        }
         self.cycles += cycles as usize;
-        if self.cycles >= 341 {
-            if self.is_sprite_0_hit(self.cycles) { // gets mid-frame progress status of PPU
-                self.status.set_sprite_zero_hit(true);
+
+        // Around dot 260 of every rendered line (visible lines and the pre-render line 261)
+        // the PPU starts fetching sprite tiles, so A12 goes high: scanline counters count that.
+        let rendering = self.mask.show_background() || self.mask.show_sprites();
+        if rendering && before < 260 && self.cycles >= 260 && (self.scanline < 240 || self.scanline == 261) {
+            let mut mapper = self.mapper.borrow_mut();
+            let irq_before = mapper.irq_pending();
+            mapper.clock_scanline();
+            if !irq_before && mapper.irq_pending() {
+                self.timing.irq_lines.push(self.scanline);
             }
+        }
 
+        if self.cycles >= 341 {
             self.cycles = self.cycles - 341;
             self.scanline += 1;
  
             if self.scanline == 241 {
+                self.last_timing = std::mem::take(&mut self.timing);
+                self.frame_ready = true;
                 self.status.set_vblank_status(true);
-                self.status.set_sprite_zero_hit(false); // prepares sprite hit for the next frame
                 if self.ctrl.generate_vblank_nmi() {
                     self.nmi_interrupt = Some(1);
+                    self.timing.nmi = Some((self.scanline, self.cycles));
                 }
             }
  
             if self.scanline >= 262 {
                 self.scanline = 0;
+                self.frame += 1;
+                self.sprite_zero_hit_at = None;
                 self.nmi_interrupt = None;
                 self.status.set_sprite_zero_hit(false); // [?] redundant
                 self.status.reset_vblank_status();
@@ -94,14 +164,221 @@ impl NesPPU {
         return false;
     }
 
-    pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
-        self.nmi_interrupt.take()
+    fn rendering(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    // One dot of the background pipeline, see background.rs. Only the visible lines and the
+    // pre-render line (261) fetch; with rendering off v stays put and the background is blank.
+    fn render_dot(&mut self, scanline: u16, dot: usize) {
+        if scanline >= 240 && scanline != 261 {
+            return;
+        }
+        if scanline == 261 && dot == 1 {
+            self.status.set_sprite_zero_hit(false); // kept through vblank, for the next frame
+        }
+        let rendering = self.rendering();
+        if rendering {
+            if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+                self.background.shift();
+            }
+            if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+                match dot % 8 {
+                    1 => self.background.reload(),
+                    0 => {
+                        self.fetch_background_tile();
+                        self.background.increment_x();
+                    }
+                    _ => {}
+                }
+            }
+            match dot {
+                256 => self.background.increment_y(),
+                257 => {
+                    self.background.reload();
+                    self.background.copy_x();
+                }
+                280..=304 if scanline == 261 => self.background.copy_y(),
+                337 => self.background.reload(),
+                _ => {}
+            }
+        }
+
+        if scanline < 240 && (1..=256).contains(&dot) {
+            let x = dot - 1;
+            let shown = rendering && self.mask.show_background() && (x >= 8 || self.mask.leftmost_8pxl_background());
+            let pixel = if shown { self.background.pixel() } else { 0 };
+            self.background.set_pixel(x, scanline as usize, pixel);
+            if pixel != 0 && !self.status.contains(StatusRegister::SPRITE_ZERO_HIT) {
+                self.check_sprite_zero_hit(x, scanline);
+            }
+        }
+    }
+
+    // Sprite zero hit: the first dot of the frame where an opaque pixel of sprite zero is over
+    // an opaque background pixel (the one at `x` is). Games wait for it to time a split, such
+    // as SMB's status bar, so it's set on the dot the pixel is drawn. Never on the last column,
+    // nor in the leftmost 8 while sprites are clipped there.
+    // See: https://www.nesdev.org/wiki/PPU_OAM#Sprite_zero_hits
+    fn check_sprite_zero_hit(&mut self, x: usize, scanline: u16) {
+        if !self.mask.show_sprites() || x == 255 || (x < 8 && !self.mask.leftmost_8pxl_sprite()) {
+            return;
+        }
+        if self.sprite_zero_opaque(x, scanline) {
+            self.status.set_sprite_zero_hit(true);
+            self.sprite_zero_hit_at = Some((x, scanline));
+        }
+    }
+
+    // Whether sprite zero has an opaque pixel at (x, scanline). Sprites show from the line
+    // after their OAM Y; 8x16 ones take their two tiles from the bank bit 0 of the tile picks.
+    fn sprite_zero_opaque(&self, x: usize, scanline: u16) -> bool {
+        let [top, tile, attributes, left] = [0, 1, 2, 3].map(|i| self.oam_data[i]);
+        let height = self.ctrl.sprite_size() as usize;
+        let row = (scanline as usize).wrapping_sub(top as usize + 1);
+        let column = x.wrapping_sub(left as usize);
+        if row >= height || column >= 8 {
+            return false;
+        }
+        let row = if attributes & 0x80 != 0 { height - 1 - row } else { row } as u16;
+        let column = if attributes & 0x40 != 0 { 7 - column } else { column };
+        let addr = if height == 16 {
+            (tile as u16 & 1) * 0x1000 + (tile as u16 & 0xFE) * 16 + (row / 8) * 16 + row % 8
+        } else {
+            self.ctrl.sprt_pattern_addr() + tile as u16 * 16 + row
+        };
+        let low = self.chr_rom[self.map_chr(addr)];
+        let high = self.chr_rom[self.map_chr(addr + 8)];
+        (low | high) >> (7 - column) & 1 != 0
+    }
+
+    // The nametable, attribute and pattern bytes of the tile at v. Pattern bytes go through
+    // the mapper's banks as they are now, which is what makes mid-frame CHR switches show.
+    fn fetch_background_tile(&mut self) {
+        let tile = self.peek_nametable(self.background.nametable_addr());
+        let attribute = self.peek_nametable(self.background.attribute_addr());
+        let addr = self.ctrl.bknd_pattern_addr() + tile as u16 * 16 + self.background.fine_y();
+        let low = self.chr_rom[self.map_chr(addr)];
+        let high = self.chr_rom[self.map_chr(addr + 8)];
+        self.mapper.borrow_mut().notify_chr_fetch(addr + 8);
+        self.background.fetched(attribute, low, high);
+    }
+
+    fn peek_nametable(&self, addr: u16) -> u8 {
+        match self.chr_nametable_addr(addr) {
+            Some(offset) => self.chr_rom[offset],
+            None => self.vram[self.mirror_vram_addr(addr) as usize],
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Returns true once per frame, at the start of vblank (whether or not NMIs are enabled).
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    // Where sprite zero hit was first flagged this frame, as (x, scanline).
+    // Kept until the next frame starts, so it can still be drawn during vblank.
+    pub fn sprite_zero_hit_at(&self) -> Option<(usize, u16)> {
+        self.sprite_zero_hit_at
+    }
+
+    // Registers and timing for savestates; the memories (VRAM, OAM, palettes) are saved as they are.
+    pub fn save_registers(&self) -> Vec<u8> {
+        let addr = self.addr.get();
+        let mut data = vec![
+            self.ctrl.bits(),
+            self.mask.bits(),
+            self.status.snapshot(),
+            self.oam_addr,
+            (addr >> 8) as u8,
+            addr as u8,
+            self.addr.latch() as u8,
+            self.scroll.scroll_x,
+            self.scroll.scroll_y,
+            self.scroll.scroll_switch as u8,
+            self.internal_data_buf,
+            self.nmi_interrupt.is_some() as u8,
+        ];
+        data.extend(self.scanline.to_le_bytes());
+        data.extend((self.cycles as u16).to_le_bytes());
+        data.extend(self.frame.to_le_bytes());
+        data.extend(self.background.save());
+        data
+    }
+
+    pub fn load_registers(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != 30 && data.len() != 24 {
+            return Err(format!("PPU registers: expected 30 bytes, got {}", data.len()));
+        }
+        self.ctrl.update(data[0]);
+        self.mask.update(data[1]);
+        self.status = StatusRegister::from_bits_truncate(data[2]);
+        self.oam_addr = data[3];
+        self.addr.restore(u16::from_be_bytes([data[4], data[5]]), data[6] != 0);
+        self.scroll.scroll_x = data[7];
+        self.scroll.scroll_y = data[8];
+        self.scroll.scroll_switch = data[9] != 0;
+        self.internal_data_buf = data[10];
+        self.nmi_interrupt = if data[11] != 0 { Some(1) } else { None };
+        self.scanline = u16::from_le_bytes([data[12], data[13]]);
+        self.cycles = u16::from_le_bytes([data[14], data[15]]) as usize;
+        self.frame = u64::from_le_bytes(data[16..24].try_into().unwrap());
+        match data.get(24..30) {
+            Some(background) => self.background.load(background.try_into().unwrap()),
+            None => self.background.restore(data[0], data[7], data[8]), // older states
+        }
+        Ok(())
+    }
+
+    // Replaces CHR-RAM contents (savestates), re-decoding every tile.
+    pub fn load_chr_ram(&mut self, chr: &[u8]) {
+        self.chr_rom.copy_from_slice(chr);
+        self.tile_cache = TileCache::decode_async(self.chr_rom.clone());
+    }
+
+    pub fn frame_timing(&self) -> &FrameTiming {
+        &self.last_timing
+    }
+
+    pub fn record_hook(&mut self, scanline: u16) {
+        self.timing.hook_lines.push(scanline);
+    }
+
+    // The Bus tells the PPU about RAM writes only so the heatmap can find them with the rest
+    // of the frame's timing, and only while record_ram_writes is set: otherwise every STA to
+    // RAM would grow a list nobody reads.
+    pub fn record_ram_write(&mut self, addr: u16) {
+        self.timing.ram_writes.push(addr);
     }
 
-    fn is_sprite_0_hit(&self, cycle: usize) -> bool {
-        let y = self.oam_data[0] as usize;
-        let x = self.oam_data[3] as usize;
-        (y == self.scanline as usize) && x <= cycle && self.mask.show_sprites()
+    // Called by the Bus before every register write ($2000-$2007 and $4014) takes effect.
+    pub fn log_write(&mut self, addr: u16, value: u8) {
+        let write = PpuWrite { frame: self.frame, scanline: self.scanline, dot: self.cycles, addr, value };
+        self.write_log.record(write);
+    }
+
+    pub fn write_log(&self) -> &WriteLog {
+        &self.write_log
+    }
+
+    pub fn write_log_mut(&mut self) -> &mut WriteLog {
+        &mut self.write_log
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn cycle(&self) -> usize { // PPU dot within the current scanline (0..341)
+        self.cycles
+    }
+
+    pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
+        self.nmi_interrupt.take()
     }
 
     // For some reasoning
@@ -118,6 +395,24 @@ impl NesPPU {
     // Vertical:
     //   [ A ] [ B ]
     //   [ a ] [ b ]
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().mirroring() // some mappers change it at runtime
+    }
+
+    // Pattern table address ($0000-$1FFF) -> index into chr_rom, through the mapper's banks.
+    pub fn map_chr(&self, addr: u16) -> usize {
+        self.mapper.borrow().map_chr(addr)
+    }
+
+    // The decoded tile at pattern table address `addr`, as the renderer fetches it.
+    // The mapper is told about the fetch afterwards (the PPU reads the tile's first row high
+    // plane at addr + 8), since some mappers switch banks based on which tiles get drawn.
+    pub fn fetch_tile(&self, addr: u16) -> &DecodedTile {
+        let tile = self.tile_cache.tile(self.map_chr(addr) / 16);
+        self.mapper.borrow_mut().notify_chr_fetch(addr + 8);
+        tile
+    }
+
     pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
         let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
 
@@ -125,7 +420,9 @@ impl NesPPU {
 
         let name_table = vram_index / 0x400; // to the name table index
 
-        match (&self.mirroring, name_table) {
+        match (&self.mirroring(), name_table) {
+            (Mirroring::SINGLE_SCREEN_A, _) => vram_index % 0x400,
+            (Mirroring::SINGLE_SCREEN_B, _) => 0x400 + vram_index % 0x400,
             (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
             (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
@@ -134,13 +431,39 @@ impl NesPPU {
         }
     }
 
+    // Where in CHR memory a nametable address ($2000-$2FFF) is, on boards that bring their
+    // own nametable RAM (see Mapper::chr_nametable).
+    fn chr_nametable_addr(&self, addr: u16) -> Option<usize> {
+        let index = (addr as usize - 0x2000) / 0x400 % 4;
+        let offset = self.mapper.borrow().chr_nametable(index)?;
+        Some(offset + addr as usize % 0x400)
+    }
+
+    // Nametable `index` (0-3) as the renderer reads it.
+    pub fn nametable(&self, index: usize) -> &[u8] {
+        match self.mapper.borrow().chr_nametable(index) {
+            Some(offset) => &self.chr_rom[offset..offset + 0x400],
+            None => {
+                let start = self.mirror_vram_addr(0x2000 + index as u16 * 0x400) as usize;
+                &self.vram[start..start + 0x400]
+            }
+        }
+    }
+
     pub fn write_to_data(&mut self, value: u8) {
         let addr = self.addr.get();
         match addr {
-            0..=0x1fff => println!("attempt to write to chr rom space {}", addr), 
-            0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = value;
+            0..=0x1fff if self.chr_is_ram => {
+                let offset = self.map_chr(addr);
+                self.chr_rom[offset] = value;
+                let tile = offset / 16;
+                self.tile_cache.update(tile, &self.chr_rom[tile * 16..tile * 16 + 16]);
             }
+            0..=0x1fff => eprintln!("attempt to write to chr rom space {}", addr), 
+            0x2000..=0x2fff => match self.chr_nametable_addr(addr) {
+                Some(offset) => self.chr_rom[offset] = value,
+                None => self.vram[self.mirror_vram_addr(addr) as usize] = value,
+            },
             0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reality", addr),
 
             //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
@@ -173,21 +496,26 @@ impl NesPPU {
         self.status.reset_vblank_status();
         self.addr.reset_latch();
         self.scroll.reset_scroll_switch();
+        self.background.reset_toggle();
         data
     }
 
     pub fn write_to_oam_data(&mut self, value: u8) {
-        println!("recieved write");
+        eprintln!("recieved write");
         self.oam_data[self.oam_addr as usize] = value;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
     pub fn write_to_ppu_addr(&mut self, value: u8) {
-        self.addr.update(value);
+        self.background.write_addr(value);
+        // $2007 goes through v, which only changes on the second write
+        let addr = if self.background.w { self.addr.get() } else { self.background.v };
+        self.addr.restore(addr, !self.background.w);
     }
 
     pub fn write_to_ctrl(&mut self, value: u8) {
         self.ctrl.update(value);
+        self.background.write_ctrl(value);
     }
 
     pub fn write_to_mask(&mut self, value: u8) {
@@ -199,11 +527,26 @@ impl NesPPU {
     }
 
     pub fn write_to_scroll(&mut self, value: u8) {
-        self.scroll.write(value);
+        // shares its first/second toggle with $2006, see Background
+        if self.background.w {
+            self.scroll.scroll_y = value;
+        } else {
+            self.scroll.scroll_x = value;
+        }
+        self.background.write_scroll(value);
+        self.scroll.scroll_switch = self.background.w;
     }
 
     fn increment_vram_addr(&mut self) {
         self.addr.increment(self.ctrl.vram_addr_increment());
+        // $2007 goes through v too. While the picture is being drawn the console bumps it
+        // the way rendering does, which some games use as a trick; otherwise it's the address.
+        if self.rendering() && (self.scanline < 240 || self.scanline == 261) {
+            self.background.increment_x();
+            self.background.increment_y();
+        } else {
+            self.background.v = self.addr.get();
+        }
     }
 
     pub fn read_data(&mut self) -> u8 {
@@ -213,12 +556,13 @@ impl NesPPU {
         match addr {
             0..=0x1fff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.chr_rom[addr as usize];
+                self.internal_data_buf = self.chr_rom[self.map_chr(addr)];
+                self.mapper.borrow_mut().notify_chr_fetch(addr);
                 result
             }
             0x2000..=0x2fff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.internal_data_buf = self.peek_nametable(addr);
                 result
             }
             0x3000..=0x3eff => panic!(
@@ -245,6 +589,121 @@ pub mod test {
         assert_eq!(ppu.vram[0x0305], 0x66);
     }
 
+    #[test]
+    fn test_chr_ram_writes() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        ppu.chr_is_ram = true;
+        ppu.write_to_ctrl(0);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0b1000_0000); // tile 1, row 0, low plane
+
+        assert_eq!(ppu.chr_rom[0x10], 0b1000_0000);
+        assert_eq!(ppu.fetch_tile(0x10)[0], 1);
+
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0b1000_0000);
+    }
+
+    #[test]
+    fn test_cartridge_nametables() {
+        let gtrom = crate::mapper::gtrom::Gtrom::new(vec![0; 0x8000], false);
+        let mut ppu = NesPPU::new_with_mapper(vec![0; 0x8000], Rc::new(RefCell::new(gtrom)));
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66); // the fourth nametable, a four-screen board's own RAM
+
+        assert_eq!(ppu.chr_rom[0x4C05], 0x66);
+        assert_eq!(ppu.nametable(3)[5], 0x66);
+        assert_eq!(ppu.vram, [0; 2048]);
+    }
+
+    #[test]
+    fn test_mid_frame_nametable_switch() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: colour 1 all over
+        let mut ppu = NesPPU::new(chr, Mirroring::VERTICAL);
+        ppu.vram[0x400..0x7C0].fill(1); // nametable 1 all tile 1, nametable 0 all tile 0
+        ppu.write_to_mask(0b0000_1010); // background on, left column too
+
+        let tick_to = |ppu: &mut NesPPU, scanline: u16| {
+            while ppu.scanline() != scanline {
+                ppu.tick(3);
+            }
+        };
+        tick_to(&mut ppu, 100);
+        ppu.write_to_ctrl(0b01); // a status bar split: the rest of the picture from nametable 1
+        tick_to(&mut ppu, 241);
+
+        let pixels = ppu.background.pixels();
+        assert_eq!(pixels[50 * 256 + 10], 0);
+        assert_eq!(pixels[99 * 256 + 255], 0);
+        assert_eq!(pixels[101 * 256], 1); // from the next line on
+        assert_eq!(pixels[239 * 256 + 200], 1);
+    }
+
+    #[test]
+    fn test_registers_keep_v_mid_frame() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_mask(0b0000_1010);
+        while ppu.scanline() != 100 {
+            ppu.tick(3);
+        }
+        for value in [0x04, 0x5E, 0x7D, 0x6F] { // $2006, $2005, $2005, $2006: a split
+            if value == 0x5E || value == 0x7D {
+                ppu.write_to_scroll(value);
+            } else {
+                ppu.write_to_ppu_addr(value);
+            }
+        }
+        assert_eq!(ppu.background.v, 0x656F);
+        assert_eq!((ppu.scroll.scroll_x, ppu.scroll.scroll_y), (0x7D, 0x5E));
+        ppu.write_to_scroll(0x20); // and a first write left pending
+
+        let mut loaded = NesPPU::new_empty_rom();
+        loaded.load_registers(&ppu.save_registers()).unwrap();
+        assert_eq!(loaded.background.v, ppu.background.v);
+        assert_eq!(loaded.background.t, ppu.background.t);
+        assert_eq!(loaded.background.fine_x, 0);
+        assert!(loaded.background.w);
+        assert!(loaded.load_registers(&ppu.save_registers()[..24]).is_ok()); // states from before
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF); // tile 1: opaque all over
+        chr[0x20..0x28].fill(0x01); // tile 2: only its right column
+        let mut ppu = NesPPU::new(chr, Mirroring::VERTICAL);
+        ppu.vram[..0x3C0].fill(1);
+        ppu.oam_data[..4].copy_from_slice(&[49, 2, 0x40, 100]); // flipped: the column on the left
+        ppu.write_to_mask(0b0001_1110);
+
+        let hit = |ppu: &NesPPU| ppu.status.snapshot() & 0x40 != 0;
+        while !hit(&ppu) {
+            ppu.tick(1);
+        }
+        assert_eq!((ppu.scanline(), ppu.cycle()), (50, 102)); // the dot that drew x = 100
+        assert_eq!(ppu.sprite_zero_hit_at(), Some((100, 50)));
+
+        while ppu.scanline() != 261 {
+            ppu.tick(1);
+        }
+        assert!(hit(&ppu)); // until the pre-render line
+        ppu.tick(2);
+        assert!(!hit(&ppu));
+
+        // under the sprite clip in the leftmost 8 pixels: no hit all frame
+        ppu.oam_data[3] = 3;
+        ppu.write_to_mask(0b0001_1010);
+        while ppu.scanline() != 240 {
+            ppu.tick(1);
+            assert!(!hit(&ppu));
+        }
+    }
+
     #[test]
     fn test_ppu_vram_reads() {
         let mut ppu = NesPPU::new_empty_rom();
@@ -0,0 +1,93 @@
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+
+// Every 8x8 tile in CHR is stored as two 8-byte bit planes. The background pipeline (see
+// ppu::background) shifts the planes out as they are; sprites are drawn from here.
+// Turning those planes into 2-bit pixel values takes a shift, two masks and an OR per pixel,
+// for every tile, every frame. Since CHR ROM never changes, we can do that work once when
+// the ROM is loaded and let the renderer do nothing but palette lookups. Cartridges with
+// CHR-RAM re-decode a tile whenever the game writes to it (see update).
+//
+// The whole CHR ROM is decoded (not just the banks currently visible), so switching
+// banks later only changes which decoded tiles get indexed.
+
+pub type DecodedTile = [u8; 64]; // one 2-bit colour index per pixel, row major
+
+pub struct TileCache {
+    pending: Mutex<Option<JoinHandle<Vec<DecodedTile>>>>, // the worker thread, until first use
+    tiles: OnceLock<Vec<DecodedTile>>,
+}
+
+impl TileCache {
+    // Starts decoding on a worker thread so loading a big ROM doesn't stall startup.
+    pub fn decode_async(chr: Vec<u8>) -> Self {
+        let worker = thread::spawn(move || decode_all(&chr));
+        TileCache {
+            pending: Mutex::new(Some(worker)),
+            tiles: OnceLock::new(),
+        }
+    }
+
+    // `index` is the tile's byte offset in CHR divided by 16.
+    // The first call waits for the worker if it hasn't finished yet.
+    pub fn tile(&self, index: usize) -> &DecodedTile {
+        &self.tiles()[index]
+    }
+
+    // CHR-RAM: the game wrote into tile `index`, whose 16 bytes are now `tile`.
+    pub fn update(&mut self, index: usize, tile: &[u8]) {
+        self.tiles();
+        if let Some(tiles) = self.tiles.get_mut() {
+            tiles[index] = decode_tile(tile);
+        }
+    }
+
+    fn tiles(&self) -> &Vec<DecodedTile> {
+        self.tiles.get_or_init(|| {
+            let worker = self.pending.lock().unwrap().take();
+            worker
+                .expect("tile cache worker already consumed")
+                .join()
+                .expect("tile cache worker panicked")
+        })
+    }
+}
+
+fn decode_all(chr: &[u8]) -> Vec<DecodedTile> {
+    chr.chunks_exact(16).map(decode_tile).collect()
+}
+
+fn decode_tile(tile: &[u8]) -> DecodedTile {
+    let mut pixels = [0u8; 64];
+    for y in 0..8 {
+        let lower_plane = tile[y];
+        let upper_plane = tile[y + 8];
+        for x in 0..8 {
+            let bit = 7 - x; // leftmost pixel lives in the most significant bit
+            let value = (((upper_plane >> bit) & 1) << 1) | ((lower_plane >> bit) & 1);
+            pixels[y * 8 + x] = value;
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_tile_planes() {
+        let mut chr = vec![0u8; 32];
+        chr[16] = 0b1000_0001; // tile 1, row 0, low plane
+        chr[16 + 8] = 0b1100_0000; // tile 1, row 0, high plane
+
+        let cache = TileCache::decode_async(chr);
+        let tile = cache.tile(1);
+
+        assert_eq!(tile[0], 3);
+        assert_eq!(tile[1], 2);
+        assert_eq!(tile[2], 0);
+        assert_eq!(tile[7], 1);
+        assert_eq!(cache.tile(0), &[0; 64]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+// Every write to the PPU registers ($2000-$2007, and $4014 for sprite DMA) over the last few
+// frames, stamped with where the PPU was when it happened. Rendering bugs are usually a
+// question of who wrote what when ("who changed the scroll mid-frame?", "did the game turn
+// rendering off before updating VRAM?"), and this answers them without tracing every
+// instruction. A few hundred writes a frame is typical, so it's always on.
+
+pub const DEFAULT_FRAMES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PpuWrite {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: usize,
+    pub addr: u16, // $2000-$2007 (mirrors already folded down) or $4014
+    pub value: u8,
+}
+
+pub fn register_name(addr: u16) -> &'static str {
+    match addr {
+        0x2000 => "PPUCTRL",
+        0x2001 => "PPUMASK",
+        0x2002 => "PPUSTATUS",
+        0x2003 => "OAMADDR",
+        0x2004 => "OAMDATA",
+        0x2005 => "PPUSCROLL",
+        0x2006 => "PPUADDR",
+        0x2007 => "PPUDATA",
+        0x4014 => "OAMDMA",
+        _ => "?",
+    }
+}
+
+impl fmt::Display for PpuWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame {} line {:3} dot {:3}  ${:04X} {:<9} = {:02X}",
+            self.frame,
+            self.scanline,
+            self.dot,
+            self.addr,
+            register_name(self.addr),
+            self.value
+        )
+    }
+}
+
+pub struct WriteLog {
+    entries: VecDeque<PpuWrite>,
+    frames: usize, // how many frames to keep, 0 to keep nothing
+}
+
+impl WriteLog {
+    pub fn new() -> Self {
+        WriteLog { entries: VecDeque::new(), frames: DEFAULT_FRAMES }
+    }
+
+    pub fn set_frames(&mut self, frames: usize) {
+        self.frames = frames;
+        if let Some(last) = self.entries.back().map(|write| write.frame) {
+            self.forget_before(last);
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn record(&mut self, write: PpuWrite) {
+        if self.frames == 0 {
+            return;
+        }
+        self.forget_before(write.frame);
+        self.entries.push_back(write);
+    }
+
+    // Drops what is too old to keep once frame `now` is being logged.
+    fn forget_before(&mut self, now: u64) {
+        while self.entries.front().is_some_and(|old| old.frame + self.frames as u64 <= now) {
+            self.entries.pop_front();
+        }
+    }
+
+    // Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &PpuWrite> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // One line per write, oldest first, only those to `addr` if given.
+    pub fn report(&self, addr: Option<u16>) -> String {
+        let mut report = String::new();
+        for write in self.iter().filter(|write| addr.is_none_or(|addr| write.addr == addr)) {
+            report.push_str(&write.to_string());
+            report.push('\n');
+        }
+        report
+    }
+}
+
+impl Default for WriteLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn write(frame: u64, addr: u16, value: u8) -> PpuWrite {
+        PpuWrite { frame, scanline: 120, dot: 256, addr, value }
+    }
+
+    #[test]
+    fn test_keeps_last_frames() {
+        let mut log = WriteLog::new();
+        log.set_frames(2);
+        log.record(write(1, 0x2005, 0x10));
+        log.record(write(2, 0x2005, 0x20));
+        log.record(write(2, 0x2001, 0x1E));
+        assert_eq!(log.len(), 3);
+        log.record(write(3, 0x2005, 0x30)); // frame 1 is gone
+        assert_eq!(log.iter().map(|write| write.value).collect::<Vec<_>>(), [0x20, 0x1E, 0x30]);
+
+        assert_eq!(
+            log.report(Some(0x2005)),
+            "frame 2 line 120 dot 256  $2005 PPUSCROLL = 20\n\
+             frame 3 line 120 dot 256  $2005 PPUSCROLL = 30\n"
+        );
+
+        log.set_frames(1);
+        assert_eq!(log.len(), 1);
+        log.set_frames(0);
+        log.record(write(4, 0x2000, 0x80));
+        assert_eq!(log.len(), 0);
+    }
+}
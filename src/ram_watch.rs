@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::nes::Nes;
+
+// Rules that watch a byte of memory from frame to frame and fire when the game does something
+// worth noticing: a level counter going up, a "game over" flag being set. Anything that wants
+// to react to the game's state without knowing the game's code can build on them (see
+// crate::speedrun). A rule is an address and a condition, written as:
+//
+//   $075F changes       the byte differs from the frame before
+//   $0770 = 1           the byte becomes 1 (also !=, < and >, values in hex with $ or 0x)
+//
+// Comparisons fire on the frame they become true, not on every frame they stay true, so
+// "$0770 = 1" fires once each time the game gets there. Nothing fires on the first frame a
+// rule sees: there's no "before" to compare with yet.
+//
+// The addresses are the ones freeze cheats take (see crate::cheats): the console's RAM
+// ($0000-$1FFF, mirrored every 2KiB) and the cartridge's ($6000-$7FFF), where games keep
+// their state. They're read with Bus::peek, which has no side effects, so watching never
+// disturbs the game.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Changes,
+    Equals(u8),
+    NotEquals(u8),
+    Below(u8),
+    Above(u8),
+}
+
+impl Condition {
+    fn holds(&self, value: u8) -> bool {
+        match *self {
+            Condition::Changes => false,
+            Condition::Equals(n) => value == n,
+            Condition::NotEquals(n) => value != n,
+            Condition::Below(n) => value < n,
+            Condition::Above(n) => value > n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub addr: u16,
+    pub condition: Condition,
+    last: Option<u8>, // the byte the frame before
+}
+
+impl Rule {
+    pub fn new(addr: u16, condition: Condition) -> Rule {
+        Rule { addr, condition, last: None }
+    }
+
+    pub fn parse(text: &str) -> Result<Rule, String> {
+        let bad = || format!("'{}' is not a rule: expected an address then 'changes' or = != < > a value", text.trim());
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let addr = words.first().and_then(|addr| parse_hex(addr)).ok_or_else(bad)?;
+        if !matches!(addr, 0x0000..=0x1FFF | 0x6000..=0x7FFF) {
+            return Err(format!("${:04X} can't be watched: only RAM, $0000-$1FFF or $6000-$7FFF", addr));
+        }
+        let condition = match words[1..] {
+            ["changes"] => Condition::Changes,
+            [op, value] => {
+                let value = parse_hex(value).and_then(|value| u8::try_from(value).ok()).ok_or_else(bad)?;
+                match op {
+                    "=" | "==" => Condition::Equals(value),
+                    "!=" => Condition::NotEquals(value),
+                    "<" => Condition::Below(value),
+                    ">" => Condition::Above(value),
+                    _ => return Err(bad()),
+                }
+            }
+            _ => return Err(bad()),
+        };
+        Ok(Rule::new(addr, condition))
+    }
+
+    // Looks at the byte for this frame, returns whether the rule fires.
+    pub fn check(&mut self, value: u8) -> bool {
+        let fired = match self.last {
+            None => false,
+            Some(last) if self.condition == Condition::Changes => value != last,
+            Some(last) => self.condition.holds(value) && !self.condition.holds(last),
+        };
+        self.last = Some(value);
+        fired
+    }
+
+    // Starts over, as if it had never seen the byte.
+    pub fn forget(&mut self) {
+        self.last = None;
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.condition {
+            Condition::Changes => write!(f, "${:04X} changes", self.addr),
+            Condition::Equals(n) => write!(f, "${:04X} = ${:02X}", self.addr, n),
+            Condition::NotEquals(n) => write!(f, "${:04X} != ${:02X}", self.addr, n),
+            Condition::Below(n) => write!(f, "${:04X} < ${:02X}", self.addr, n),
+            Condition::Above(n) => write!(f, "${:04X} > ${:02X}", self.addr, n),
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+// The byte a rule watches, as the CPU would read it (through the mapper's banking at $6000).
+pub fn peek(nes: &Nes, addr: u16) -> u8 {
+    nes.peek(addr)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Rule::parse("$075F changes"), Ok(Rule::new(0x075F, Condition::Changes)));
+        assert_eq!(Rule::parse("0x0770 == 1"), Ok(Rule::new(0x0770, Condition::Equals(1))));
+        assert_eq!(Rule::parse("6000 > FF"), Ok(Rule::new(0x6000, Condition::Above(0xFF))));
+        let rule = Rule::parse("$00B4 != 3").unwrap();
+        assert_eq!(Rule::parse(&rule.to_string()), Ok(rule));
+
+        assert!(Rule::parse("$0770 = 100").is_err()); // more than a byte
+        assert!(Rule::parse("$0770 is 1").is_err());
+        assert!(Rule::parse("$0770").is_err());
+        assert_eq!(
+            Rule::parse("$8000 changes").unwrap_err(),
+            "$8000 can't be watched: only RAM, $0000-$1FFF or $6000-$7FFF"
+        );
+    }
+
+    #[test]
+    fn test_fires_on_edges() {
+        let mut changes = Rule::new(0, Condition::Changes);
+        let fired: Vec<bool> = [1, 1, 2, 2, 3].iter().map(|&value| changes.check(value)).collect();
+        assert_eq!(fired, [false, false, true, false, true]);
+
+        let mut equals = Rule::new(0, Condition::Equals(2));
+        let fired: Vec<bool> = [2, 1, 2, 2, 0, 2].iter().map(|&value| equals.check(value)).collect();
+        assert_eq!(fired, [false, false, true, false, false, true]); // not on the first frame
+
+        let mut above = Rule::new(0, Condition::Above(4));
+        assert!(!above.check(3));
+        assert!(above.check(5));
+        above.forget();
+        assert!(!above.check(6));
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::console::Outcome;
+
+// Remote control over TCP, for debugging from an editor or IDE plugin (see `--remote` in
+// main.rs). The commands are the console's (crate::console), one per line, so anything that
+// works when typed also works over the socket. Every command is answered by its output
+// lines, then a last line that is either "ok" or "error: <message>":
+//
+//   > break c000
+//   < breakpoint at $C000
+//   < ok
+//   > resume
+//   < ok
+//   < * stopped breakpoint at $C000
+//
+// Lines starting with "* " are events sent on their own: "* stopped <reason>" when the
+// debugger stops emulation. One client at a time; a second connection waits until the first
+// one hangs up.
+//
+// Nothing here blocks: the frontend polls between frames, so the game keeps running while
+// nobody talks to it.
+
+pub struct RemoteServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    pending: Vec<u8>, // received, not a whole line yet
+}
+
+impl RemoteServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(RemoteServer { listener, client: None })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // The next command line from the client, if a whole one has arrived.
+    pub fn poll(&mut self) -> Option<String> {
+        if self.client.is_none() {
+            let (stream, _) = self.listener.accept().ok()?;
+            stream.set_nonblocking(true).ok()?;
+            let _ = stream.set_nodelay(true); // replies are small and someone is waiting on them
+            self.client = Some(Client { stream, pending: vec![] });
+        }
+
+        let client = self.client.as_mut()?;
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Some(end) = client.pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.pending.drain(..=end).collect();
+                return Some(String::from_utf8_lossy(&line).trim_end().to_string());
+            }
+            match client.stream.read(&mut buf) {
+                Ok(0) => break, // hung up
+                Ok(n) => client.pending.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        self.client = None;
+        None
+    }
+
+    // Answers the last command. Quit gets a plain "ok", the caller decides what it means.
+    pub fn reply(&mut self, result: &Result<Outcome, String>) {
+        let text = match result {
+            Ok(Outcome::Output(text)) if text.is_empty() => "ok\n".to_string(),
+            Ok(Outcome::Output(text)) => format!("{}\nok\n", text),
+            Ok(Outcome::Quit) => "ok\n".to_string(),
+            Ok(Outcome::Action(action)) => format!("{}\nok\n", action), // the caller didn't perform it
+            Err(err) => format!("error: {}\n", err),
+        };
+        self.send(&text);
+    }
+
+    // An unrequested event, like the debugger stopping.
+    pub fn notify(&mut self, event: &str) {
+        self.send(&format!("* {}\n", event));
+    }
+
+    fn send(&mut self, text: &str) {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return,
+        };
+        // blocking for the write, a long history shouldn't be cut off by a full socket buffer
+        let sent = client
+            .stream
+            .set_nonblocking(false)
+            .and_then(|_| client.stream.write_all(text.as_bytes()))
+            .and_then(|_| client.stream.set_nonblocking(true));
+        if sent.is_err() {
+            self.client = None;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::console::Console;
+    use crate::nes::Nes;
+    use std::io::{BufRead, BufReader};
+
+    fn next_command(server: &mut RemoteServer) -> String {
+        for _ in 0..1000 {
+            if let Some(line) = server.poll() {
+                return line;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("no command arrived");
+    }
+
+    #[test]
+    fn test_commands_over_tcp() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let mut console = Console::new();
+        let mut server = RemoteServer::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.write_all(b"poke 10 ab\npeek 10 2\njump\n").unwrap();
+
+        for _ in 0..3 {
+            let line = next_command(&mut server);
+            let result = console.execute(&mut nes, &line);
+            server.reply(&result);
+        }
+        server.notify("stopped breakpoint at $C000");
+        assert!(server.connected());
+
+        let lines: Vec<String> = BufReader::new(client).lines().take(5).map(Result::unwrap).collect();
+        assert_eq!(
+            lines,
+            ["ok", "0010: AB 00", "ok", "error: can't do 'jump' (try 'help')", "* stopped breakpoint at $C000"]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use crate::ppu::NesPPU;
+use crate::render::palette::{self, Colors};
+use crate::render::{self, frame::Frame};
+
+// Everything drawn on top of (or instead of) the raw PPU picture goes through here:
+//
+//   base frame (render::render) -> filters -> overlays -> output surface (SDL texture)
+//
+// Filters change how the picture looks (NTSC artifacts, scanlines, ...), overlays draw
+// extra information on top of it (OSD text, input display, debug views). All filters run
+// before any overlay, so overlays stay crisp no matter which filters are active. Within each
+// group, stages run in the order they were registered.
+
+pub trait Stage {
+    fn name(&self) -> &str; // used to look the stage up when toggling it
+    fn apply(&mut self, ppu: &NesPPU, frame: &mut Frame);
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Layer {
+    Filter,
+    Overlay,
+}
+
+struct Entry {
+    layer: Layer,
+    enabled: bool,
+    stage: Box<dyn Stage>,
+}
+
+pub struct Compositor {
+    entries: Vec<Entry>,
+    colors: Colors, // what the 64 palette entries look like, see render::palette
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Compositor { entries: vec![], colors: palette::SYSTEM_PALLETE }
+    }
+
+    pub fn set_colors(&mut self, colors: Colors) {
+        self.colors = colors;
+    }
+
+    pub fn add_filter(&mut self, stage: Box<dyn Stage>, enabled: bool) {
+        self.add(Layer::Filter, stage, enabled);
+    }
+
+    pub fn add_overlay(&mut self, stage: Box<dyn Stage>, enabled: bool) {
+        self.add(Layer::Overlay, stage, enabled);
+    }
+
+    fn add(&mut self, layer: Layer, stage: Box<dyn Stage>, enabled: bool) {
+        self.entries.push(Entry { layer, enabled, stage });
+    }
+
+    // Returns false if no stage with that name is registered.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.entries.iter_mut().find(|e| e.stage.name() == name) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn toggle(&mut self, name: &str) -> bool {
+        let enabled = !self.is_enabled(name);
+        self.set_enabled(name, enabled) && enabled
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.entries.iter().any(|e| e.stage.name() == name && e.enabled)
+    }
+
+    // Every filter's name, and whether it's on.
+    pub fn filters(&self) -> Vec<(&str, bool)> {
+        self.entries.iter().filter(|e| e.layer == Layer::Filter).map(|e| (e.stage.name(), e.enabled)).collect()
+    }
+
+    // Draws the current PPU state into `frame` and runs every enabled stage over it.
+    pub fn compose(&mut self, ppu: &NesPPU, frame: &mut Frame) {
+        render::render_with_colors(ppu, frame, &self.colors);
+
+        for layer in [Layer::Filter, Layer::Overlay] {
+            for entry in self.entries.iter_mut() {
+                if entry.layer == layer && entry.enabled {
+                    entry.stage.apply(ppu, frame);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Compositor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // Writes its id into the first byte of the frame, so the last stage to run wins.
+    struct Marker(&'static str, u8);
+
+    impl Stage for Marker {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn apply(&mut self, _ppu: &NesPPU, frame: &mut Frame) {
+            frame.data[0] = self.1;
+        }
+    }
+
+    #[test]
+    fn test_overlays_run_after_filters() {
+        let ppu = NesPPU::new_empty_rom();
+        let mut frame = Frame::new();
+        let mut compositor = Compositor::new();
+        compositor.add_overlay(Box::new(Marker("osd", 1)), true);
+        compositor.add_filter(Box::new(Marker("scanlines", 2)), true);
+
+        compositor.compose(&ppu, &mut frame);
+        assert_eq!(frame.data[0], 1);
+    }
+
+    #[test]
+    fn test_disabled_stages_are_skipped() {
+        let ppu = NesPPU::new_empty_rom();
+        let mut frame = Frame::new();
+        let mut compositor = Compositor::new();
+        compositor.add_filter(Box::new(Marker("first", 1)), true);
+        compositor.add_filter(Box::new(Marker("second", 2)), false);
+
+        compositor.compose(&ppu, &mut frame);
+        assert_eq!(frame.data[0], 1);
+
+        assert!(compositor.toggle("second"));
+        compositor.compose(&ppu, &mut frame);
+        assert_eq!(frame.data[0], 2);
+
+        assert!(!compositor.set_enabled("missing", true));
+    }
+}
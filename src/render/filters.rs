@@ -0,0 +1,99 @@
+use crate::ppu::NesPPU;
+use crate::render::compositor::Stage;
+use crate::render::frame::Frame;
+
+// Filters: compositor stages that change the picture itself (see compositor.rs).
+
+// Photosensitivity safety: softens flashes. Plenty of games flash the whole screen for
+// explosions, lightning or hits, often by cycling the palette every frame, and sudden large
+// changes in brightness like that can trigger seizures in photosensitive players.
+//
+// The filter compares the picture's average brightness (luma) with the previous picture it
+// let through. When it jumps by more than `threshold` (a fraction of full brightness), the new
+// picture is blended with the previous one so the average only moves by `threshold`: a flash
+// becomes a mild pulse, and a real scene change still arrives, faded in over a few frames.
+// Whole-screen brightness is what makes a flash dangerous, so small flickering sprites pass
+// untouched.
+pub const DEFAULT_FLASH_THRESHOLD: f32 = 0.1;
+
+pub struct FlashFilter {
+    threshold: f32,
+    previous: Option<(Vec<u8>, f32)>, // the last picture shown and its luma
+}
+
+impl FlashFilter {
+    pub fn new(threshold: f32) -> Self {
+        FlashFilter { threshold: threshold.clamp(0.01, 1.0), previous: None }
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+}
+
+impl Stage for FlashFilter {
+    fn name(&self) -> &str {
+        "flash"
+    }
+
+    fn apply(&mut self, _ppu: &NesPPU, frame: &mut Frame) {
+        let mut brightness = luma(&frame.data);
+        if let Some((previous, previous_luma)) = &self.previous {
+            let change = (brightness - previous_luma).abs();
+            if change > self.threshold {
+                let t = self.threshold / change;
+                for (new, old) in frame.data.iter_mut().zip(previous) {
+                    *new = (*old as f32 + (*new as f32 - *old as f32) * t).round() as u8;
+                }
+                brightness = luma(&frame.data);
+            }
+        }
+        self.previous = Some((frame.data.clone(), brightness));
+    }
+}
+
+// Average brightness of an RGB24 picture, 0.0-1.0 (ITU-R BT.601 weights).
+fn luma(rgb: &[u8]) -> f32 {
+    let sum: f32 = rgb.chunks_exact(3).map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32).sum();
+    sum / (rgb.len() / 3) as f32 / 255.0
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn filled(value: u8) -> Frame {
+        let mut frame = Frame::new();
+        frame.data.fill(value);
+        frame
+    }
+
+    #[test]
+    fn test_flash_is_softened() {
+        let ppu = NesPPU::new_empty_rom();
+        let mut filter = FlashFilter::new(0.1);
+
+        let mut black = filled(0);
+        filter.apply(&ppu, &mut black);
+        assert!(black.data.iter().all(|&b| b == 0));
+
+        // black to white in one frame: only a tenth of the way
+        let mut white = filled(255);
+        filter.apply(&ppu, &mut white);
+        let first = white.data[0];
+        assert!((25..=26).contains(&first));
+        assert!(white.data.iter().all(|&b| b == first));
+
+        // and it keeps fading in while the picture stays white
+        let mut white = filled(255);
+        filter.apply(&ppu, &mut white);
+        assert!(white.data[0] > first && white.data[0] <= 2 * first);
+
+        // small changes pass untouched
+        let mut filter = FlashFilter::new(0.1);
+        filter.apply(&ppu, &mut filled(100));
+        let mut slightly = filled(110);
+        filter.apply(&ppu, &mut slightly);
+        assert!(slightly.data.iter().all(|&b| b == 110));
+    }
+}
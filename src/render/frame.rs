@@ -1,10 +1,14 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
 pub struct Frame {
     pub data: Vec<u8>,
 }
 
 impl Frame {
-    const WIDTH: usize = 256;
-    const HIGHT: usize = 240;
+    pub const WIDTH: usize = 256;
+    pub const HIGHT: usize = 240;
 
     pub fn new() -> Self {
         Frame {
@@ -23,4 +27,15 @@ impl Frame {
             self.data[base + 2] = rgb.2;
         }
     }
+
+    // Lossless, so two dumps of the same frame compare equal byte for byte.
+    pub fn write_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, Frame::WIDTH as u32, Frame::HIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::ppu::NesPPU;
+use crate::render::compositor::Stage;
+use crate::render::frame::Frame;
+
+// On-screen display: short text messages drawn over the picture for a while ("State saved",
+// warnings about the cartridge, ...). The frontend keeps an OsdMessages handle to post them,
+// the Osd stage itself lives in the compositor like any other overlay.
+//
+// The font is a tiny built-in 3x5 one drawn at 2x, so there is nothing to load and the
+// text stays readable at the NES's 256x240. Lowercase is shown as uppercase.
+
+pub const SCALE: usize = 2;
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+pub const ADVANCE: usize = (GLYPH_WIDTH + 1) * SCALE; // 8 pixels per character
+pub const LINE_HEIGHT: usize = (GLYPH_HEIGHT + 2) * SCALE;
+pub const MARGIN: usize = 4;
+const COLUMNS: usize = (Frame::WIDTH - 2 * MARGIN) / ADVANCE;
+
+const TEXT: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+const INFO_BACKGROUND: (u8, u8, u8) = (0x00, 0x00, 0x00);
+const WARNING_BACKGROUND: (u8, u8, u8) = (0xA0, 0x00, 0x00);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Warning, // red background
+}
+
+struct Message {
+    text: String,
+    level: Level,
+    frames_left: u32,
+}
+
+// Posts messages to an Osd from anywhere in the frontend.
+#[derive(Clone, Default)]
+pub struct OsdMessages(Rc<RefCell<VecDeque<Message>>>);
+
+impl OsdMessages {
+    // Shown for `frames` frames, below any message already on screen.
+    pub fn show(&self, text: &str, level: Level, frames: u32) {
+        self.0.borrow_mut().push_back(Message { text: text.to_string(), level, frames_left: frames });
+    }
+
+    pub fn info(&self, text: &str) {
+        self.show(text, Level::Info, 120);
+    }
+
+    pub fn warning(&self, text: &str) {
+        self.show(text, Level::Warning, 600);
+    }
+
+    // Takes everything off the screen at once.
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+}
+
+pub struct Osd {
+    messages: OsdMessages,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd { messages: OsdMessages::default() }
+    }
+
+    pub fn messages(&self) -> OsdMessages {
+        self.messages.clone()
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for Osd {
+    fn name(&self) -> &str {
+        "osd"
+    }
+
+    fn apply(&mut self, _ppu: &NesPPU, frame: &mut Frame) {
+        let mut messages = self.messages.0.borrow_mut();
+        let mut y = MARGIN;
+        for message in messages.iter_mut() {
+            for line in wrap(&message.text) {
+                if y + LINE_HEIGHT > Frame::HIGHT {
+                    break;
+                }
+                let background = match message.level {
+                    Level::Info => INFO_BACKGROUND,
+                    Level::Warning => WARNING_BACKGROUND,
+                };
+                fill(frame, MARGIN, y, line.len() * ADVANCE + SCALE, LINE_HEIGHT, background);
+                draw_text(frame, MARGIN + SCALE, y + SCALE, &line, TEXT);
+                y += LINE_HEIGHT;
+            }
+            message.frames_left = message.frames_left.saturating_sub(1);
+        }
+        messages.retain(|message| message.frames_left > 0);
+    }
+}
+
+// Splits at spaces to fit the screen; words longer than a line are cut.
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > COLUMNS {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+        while line.len() > COLUMNS {
+            let rest = line.split_off(COLUMNS);
+            lines.push(std::mem::replace(&mut line, rest));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn fill(frame: &mut Frame, x: usize, y: usize, width: usize, height: usize, rgb: (u8, u8, u8)) {
+    for py in y..(y + height).min(Frame::HIGHT) {
+        for px in x..(x + width).min(Frame::WIDTH) {
+            frame.set_pixel(px, py, rgb);
+        }
+    }
+}
+
+pub fn draw_text(frame: &mut Frame, x: usize, y: usize, text: &str, rgb: (u8, u8, u8)) {
+    for (i, c) in text.chars().enumerate() {
+        let bits = glyph(c);
+        for row in 0..GLYPH_HEIGHT {
+            for column in 0..GLYPH_WIDTH {
+                let bit = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - column);
+                if bits >> bit & 1 == 1 {
+                    fill(frame, x + i * ADVANCE + column * SCALE, y + row * SCALE, SCALE, SCALE, rgb);
+                }
+            }
+        }
+    }
+}
+
+// Rows top to bottom, 3 bits each, leftmost pixel in the high bit.
+fn glyph(c: char) -> u16 {
+    match c.to_ascii_uppercase() {
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b110_001_010_100_111,
+        '3' => 0b110_001_010_001_110,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_110_001_110,
+        '6' => 0b011_100_111_101_111,
+        '7' => 0b111_001_010_010_010,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_110,
+        ' ' => 0,
+        '.' => 0b000_000_000_000_010,
+        ',' => 0b000_000_000_010_100,
+        ':' => 0b000_010_000_010_000,
+        '!' => 0b010_010_010_000_010,
+        '-' => 0b000_000_111_000_000,
+        '+' => 0b000_010_111_010_000,
+        '=' => 0b000_111_000_111_000,
+        '/' => 0b001_001_010_100_100,
+        '(' => 0b001_010_010_010_001,
+        ')' => 0b100_010_010_010_100,
+        '<' => 0b001_010_100_010_001,
+        '>' => 0b100_010_001_010_100,
+        '\'' => 0b010_010_000_000_000,
+        '_' => 0b000_000_000_000_111,
+        '#' => 0b101_111_101_111_101,
+        '$' => 0b011_110_010_011_110,
+        '%' => 0b101_001_010_100_101,
+        '*' => 0b000_101_010_101_000,
+        _ => 0b110_001_010_000_010, // '?'
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn pixel(frame: &Frame, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * Frame::WIDTH + x) * 3;
+        (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("hello   world"), ["hello world"]);
+        let long = "mapper 206 is not supported, running it as mapper 4 (MMC3) instead";
+        let lines = wrap(long);
+        assert!(lines.len() > 1 && lines.iter().all(|line| line.len() <= COLUMNS));
+        assert_eq!(lines.join(" "), long);
+        assert_eq!(wrap(&"x".repeat(COLUMNS + 1)).len(), 2);
+    }
+
+    #[test]
+    fn test_messages_expire() {
+        let ppu = &NesPPU::new_empty_rom();
+
+        let mut osd = Osd::new();
+        let messages = osd.messages();
+        messages.show("I", Level::Warning, 2);
+
+        let mut frame = Frame::new();
+        osd.apply(ppu, &mut frame);
+        // background box, and the top of the I's bar
+        assert_eq!(pixel(&frame, MARGIN, MARGIN), WARNING_BACKGROUND);
+        assert_eq!(pixel(&frame, MARGIN + SCALE, MARGIN + SCALE), TEXT);
+
+        osd.apply(ppu, &mut Frame::new());
+        assert!(messages.is_empty());
+        let mut frame = Frame::new();
+        osd.apply(ppu, &mut frame);
+        assert_eq!(pixel(&frame, MARGIN, MARGIN), (0, 0, 0));
+    }
+}
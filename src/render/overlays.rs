@@ -0,0 +1,434 @@
+use crate::apu::scope::{self, Scope, SharedScope};
+use crate::ppu::NesPPU;
+use crate::render::compositor::Stage;
+use crate::render::frame::Frame;
+use crate::render::osd;
+
+// Debug overlays: compositor stages that draw emulator state on top of the picture.
+// They are registered disabled and toggled from the frontend.
+
+const MARKER: (u8, u8, u8) = (0xFF, 0xFF, 0x00); // yellow
+const LINE: (u8, u8, u8) = (0xFF, 0x00, 0xFF);   // magenta
+const RULER: (u8, u8, u8) = (0x40, 0x40, 0x40);  // dark grey
+const TICK: (u8, u8, u8) = (0xC0, 0xC0, 0xC0);   // light grey
+const VBLANK: (u8, u8, u8) = (0xC0, 0x00, 0x00); // red
+const NMI: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);    // white
+const IRQ: (u8, u8, u8) = (0x00, 0xFF, 0xFF);    // cyan
+const HOOK: (u8, u8, u8) = (0x00, 0xFF, 0x00);   // green
+const GRID: (u8, u8, u8) = (0x10, 0x10, 0x10);   // near black
+
+// Marks where sprite zero hit fired this frame: a dotted line along the scanline and a box
+// around the pixel. Games like SMB split the screen at that point (status bar vs playfield),
+// so an off-by-a-line hit shows up as the box sitting on the wrong row of the sprite.
+pub struct SpriteZeroOverlay;
+
+impl Stage for SpriteZeroOverlay {
+    fn name(&self) -> &str {
+        "sprite0"
+    }
+
+    fn apply(&mut self, ppu: &NesPPU, frame: &mut Frame) {
+        let (x, y) = match ppu.sprite_zero_hit_at() {
+            Some((x, scanline)) => (x, scanline as usize),
+            None => return,
+        };
+        if y >= Frame::HIGHT {
+            return;
+        }
+
+        // every other pixel, so the picture under it stays visible
+        for line_x in (0..Frame::WIDTH).step_by(2) {
+            frame.set_pixel(line_x, y, LINE);
+        }
+
+        // 7x7 box, centred on the hit pixel and clipped to the screen
+        for d in 0..=6 {
+            let (left, top) = (x as isize - 3, y as isize - 3);
+            for (px, py) in [(left + d, top), (left + d, top + 6), (left, top + d), (left + 6, top + d)] {
+                if px >= 0 && py >= 0 && (px as usize) < Frame::WIDTH && (py as usize) < Frame::HIGHT {
+                    frame.set_pixel(px as usize, py as usize, MARKER);
+                }
+            }
+        }
+    }
+}
+
+const SCANLINES: usize = 262; // per frame, including vblank and the pre-render line
+const VBLANK_LINES: std::ops::Range<usize> = 241..261;
+const RULER_WIDTH: usize = 8;
+
+// A ruler down the right edge covering the whole frame period (all 262 scanlines squeezed into
+// the picture's 240 rows), with a tick every 8 lines and a longer one every 32. It marks where
+// vblank is, where NMI fired, where the cartridge raised IRQ and where scanline hooks ran.
+// IRQ and hook lines that fall inside the picture are also drawn across it, since that's
+// usually where a split screen changes scroll or banks.
+// See NesPPU::frame_timing for which frame the marks belong to.
+pub struct ScanlineRuler;
+
+impl ScanlineRuler {
+    fn row(scanline: usize) -> usize {
+        scanline * Frame::HIGHT / SCANLINES
+    }
+
+    fn mark(frame: &mut Frame, scanline: usize, colour: (u8, u8, u8)) {
+        let y = Self::row(scanline);
+        for x in (Frame::WIDTH - RULER_WIDTH * 2)..Frame::WIDTH {
+            frame.set_pixel(x, y, colour);
+        }
+    }
+
+    fn across(frame: &mut Frame, scanline: usize, colour: (u8, u8, u8)) {
+        if scanline < Frame::HIGHT {
+            for x in (0..Frame::WIDTH - RULER_WIDTH).step_by(4) {
+                frame.set_pixel(x, scanline, colour);
+            }
+        }
+    }
+}
+
+impl Stage for ScanlineRuler {
+    fn name(&self) -> &str {
+        "ruler"
+    }
+
+    fn apply(&mut self, ppu: &NesPPU, frame: &mut Frame) {
+        let left = Frame::WIDTH - RULER_WIDTH;
+        for scanline in 0..SCANLINES {
+            let y = Self::row(scanline);
+            let colour = if VBLANK_LINES.contains(&scanline) { VBLANK } else { RULER };
+            for x in left..Frame::WIDTH {
+                frame.set_pixel(x, y, colour);
+            }
+        }
+        for scanline in (0..SCANLINES).step_by(8) {
+            let len = if scanline % 32 == 0 { 5 } else { 2 };
+            for x in left..left + len {
+                frame.set_pixel(x, Self::row(scanline), TICK);
+            }
+        }
+
+        let timing = ppu.frame_timing();
+        for line in &timing.hook_lines {
+            Self::mark(frame, *line as usize, HOOK);
+            Self::across(frame, *line as usize, HOOK);
+        }
+        for line in &timing.irq_lines {
+            Self::mark(frame, *line as usize, IRQ);
+            Self::across(frame, *line as usize, IRQ);
+        }
+        if let Some((scanline, _dot)) = timing.nmi {
+            Self::mark(frame, scanline as usize, NMI);
+        }
+    }
+}
+
+const HEATMAP_COLUMNS: usize = 64;
+const HEATMAP_CELL: usize = 4; // pixels, including a 1 pixel gap
+const HEATMAP_TOP: usize = Frame::HIGHT - 32 * HEATMAP_CELL;
+const HEAT_DECAY: f32 = 0.9; // per frame: a burst of writes fades out in about a second
+const HEAT_FULL: f32 = 8.0;  // this much heat is drawn at full brightness
+
+// Which of the 2KiB of CPU RAM the game writes, for ROM hackers looking for where it keeps
+// lives, timers or positions: one cell per byte, 64 to a row ($0000 top left, $07C0 starts
+// the bottom row), drawn over the bottom half of the picture. Every write heats a cell up and
+// the heat fades over time, so doing something in the game (jumping, losing a life) lights up
+// the addresses it touches: dark red for the odd write, up to yellow for every frame.
+pub struct RamHeatmap {
+    heat: Vec<f32>,
+}
+
+impl RamHeatmap {
+    pub fn new() -> Self {
+        RamHeatmap { heat: vec![0.0; 2048] }
+    }
+
+    fn colour(heat: f32) -> (u8, u8, u8) {
+        let t = (heat / HEAT_FULL).min(1.0);
+        // black -> red over the first half, red -> yellow over the second
+        let red = (t * 2.0).min(1.0);
+        let green = (t * 2.0 - 1.0).max(0.0);
+        ((0x30 as f32 + red * 0xCF as f32) as u8, (green * 255.0) as u8, 0)
+    }
+}
+
+impl Default for RamHeatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for RamHeatmap {
+    fn name(&self) -> &str {
+        "heatmap"
+    }
+
+    fn apply(&mut self, ppu: &NesPPU, frame: &mut Frame) {
+        self.heat.iter_mut().for_each(|heat| *heat *= HEAT_DECAY);
+        for &addr in &ppu.frame_timing().ram_writes {
+            self.heat[addr as usize & 0x7FF] += 1.0;
+        }
+
+        for y in HEATMAP_TOP..Frame::HIGHT {
+            for x in 0..Frame::WIDTH {
+                frame.set_pixel(x, y, GRID);
+            }
+        }
+        for (addr, &heat) in self.heat.iter().enumerate() {
+            if heat < 0.05 {
+                continue;
+            }
+            let left = addr % HEATMAP_COLUMNS * HEATMAP_CELL;
+            let top = HEATMAP_TOP + addr / HEATMAP_COLUMNS * HEATMAP_CELL;
+            for y in top..top + HEATMAP_CELL - 1 {
+                for x in left..left + HEATMAP_CELL - 1 {
+                    frame.set_pixel(x, y, Self::colour(heat));
+                }
+            }
+        }
+    }
+}
+
+const LANE_HEIGHT: usize = 28; // pixels per channel, the label included
+const LANE_LABEL: (u8, u8, u8) = (0x80, 0x80, 0x80);
+const WAVES: [(u8, u8, u8); 5] = [
+    (0xFF, 0x60, 0x60), // pulse 1: red
+    (0xFF, 0xC0, 0x40), // pulse 2: orange
+    (0x40, 0xC0, 0xFF), // triangle: blue
+    (0xC0, 0xC0, 0xC0), // noise: grey
+    (0x60, 0xFF, 0x60), // DMC: green
+];
+const QUARTER_FRAME: (u8, u8, u8) = (0x80, 0x80, 0x80);
+const HALF_FRAME: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+
+// The 2A03's five channels as a scope over the top of the picture, for working on the APU or
+// just watching the music: one lane per channel with its waveform across the last frame (see
+// crate::apu::scope), labelled with the loudest level it reached. Under the lanes, the frame
+// counter: a tick where each quarter frame fell in the frame (tall for half frames), and its
+// mode and how far through its sequence it is, "4-step 2/4".
+pub struct ApuVisualizer {
+    scope: SharedScope,
+}
+
+impl ApuVisualizer {
+    pub fn new() -> Self {
+        ApuVisualizer { scope: Scope::shared() }
+    }
+
+    // For the APU to record into: see Nes::set_apu_scope.
+    pub fn scope(&self) -> SharedScope {
+        self.scope.clone()
+    }
+
+    fn lane(frame: &mut Frame, scope: &Scope, channel: usize) {
+        let top = channel * LANE_HEIGHT;
+        osd::fill(frame, 0, top, Frame::WIDTH, LANE_HEIGHT - 1, GRID);
+        let full = if channel == 4 { 127 } else { 15 }; // the DMC's level is 7 bits
+        let height = LANE_HEIGHT - 4;
+        let y = |level: u8| top + 1 + height - level as usize * height / full;
+        let mut loudest = 0;
+        for (x, spans) in scope.columns.iter().enumerate() {
+            let span = spans[channel];
+            if span.is_empty() {
+                continue;
+            }
+            loudest = loudest.max(span.high);
+            for py in y(span.high)..=y(span.low) {
+                frame.set_pixel(x, py, WAVES[channel]);
+            }
+        }
+        let label = format!("{} {}", scope::CHANNELS[channel], loudest);
+        osd::draw_text(frame, 2, top + 1, &label, LANE_LABEL);
+    }
+
+    fn frame_counter(frame: &mut Frame, scope: &Scope) {
+        let top = scope::CHANNELS.len() * LANE_HEIGHT;
+        osd::fill(frame, 0, top, Frame::WIDTH, osd::LINE_HEIGHT, GRID);
+        for step in &scope.steps {
+            let (len, colour) = if step.half { (osd::LINE_HEIGHT, HALF_FRAME) } else { (osd::LINE_HEIGHT / 2, QUARTER_FRAME) };
+            for y in top + osd::LINE_HEIGHT - len..top + osd::LINE_HEIGHT {
+                frame.set_pixel(step.column, y, colour);
+            }
+        }
+        let steps = if scope.five_step { 5 } else { 4 };
+        let text = format!("{}-step {}/{}", steps, scope.sequence_step, steps);
+        let x = Frame::WIDTH - osd::MARGIN - text.len() * osd::ADVANCE;
+        osd::draw_text(frame, x, top + osd::SCALE, &text, LANE_LABEL);
+    }
+}
+
+impl Default for ApuVisualizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for ApuVisualizer {
+    fn name(&self) -> &str {
+        "apu"
+    }
+
+    fn apply(&mut self, _ppu: &NesPPU, frame: &mut Frame) {
+        let mut scope = self.scope.borrow_mut();
+        scope.wanted = true; // and the next frame is recorded
+        for channel in 0..scope::CHANNELS.len() {
+            Self::lane(frame, &scope, channel);
+        }
+        Self::frame_counter(frame, &scope);
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn pixel(frame: &Frame, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * Frame::WIDTH + x) * 3;
+        (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+    }
+
+    #[test]
+    fn test_sprite_zero_marker() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.chr_rom[..8].fill(0xFF); // tile 0 opaque, and everywhere in the background
+        ppu.oam_data[0] = 29; // sprite zero from (100, 30)
+        ppu.oam_data[3] = 100;
+        ppu.write_to_mask(0b0001_1110); // show sprites and background
+        for _ in 0..31 {
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+        assert_eq!(ppu.sprite_zero_hit_at(), Some((100, 30)));
+
+        let mut frame = Frame::new();
+        SpriteZeroOverlay.apply(&ppu, &mut frame);
+        assert_eq!(pixel(&frame, 0, 30), LINE);
+        assert_eq!(pixel(&frame, 97, 27), MARKER);
+        assert_eq!(pixel(&frame, 103, 33), MARKER);
+        assert_eq!(pixel(&frame, 100, 31), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_ruler_marks_vblank_and_nmi() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b1000_0000); // NMI on vblank
+        for _ in 0..241 {
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+        ppu.record_hook(100);
+        for _ in 241..262 + 241 {
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+        // the second vblank closed the period with the NMI and the hook in it
+        assert_eq!(ppu.frame_timing().nmi, Some((241, 0)));
+        assert_eq!(ppu.frame_timing().hook_lines, vec![100]);
+
+        let mut frame = Frame::new();
+        ScanlineRuler.apply(&ppu, &mut frame);
+        assert_eq!(pixel(&frame, 255, ScanlineRuler::row(250)), VBLANK);
+        assert_eq!(pixel(&frame, 255, ScanlineRuler::row(241)), NMI);
+        assert_eq!(pixel(&frame, 0, 100), HOOK);
+        assert_eq!(pixel(&frame, 255, ScanlineRuler::row(20)), RULER);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let mut ppu = NesPPU::new_empty_rom();
+        for _ in 0..20 {
+            ppu.record_ram_write(0x0041); // row 1, column 1
+        }
+        for _ in 0..241 {
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+        assert_eq!(ppu.frame_timing().ram_writes.len(), 20);
+
+        let mut heatmap = RamHeatmap::new();
+        let mut frame = Frame::new();
+        heatmap.apply(&ppu, &mut frame);
+        let (x, y) = (HEATMAP_CELL, HEATMAP_TOP + HEATMAP_CELL);
+        assert_eq!(pixel(&frame, x, y), (0xFF, 0xFF, 0)); // hot
+        assert_eq!(pixel(&frame, x + HEATMAP_CELL - 1, y), GRID); // the gap
+        assert_eq!(pixel(&frame, 0, HEATMAP_TOP), GRID); // never written
+        assert_eq!(pixel(&frame, 0, 0), (0, 0, 0)); // the picture above is left alone
+
+        // and it cools down once the writes stop
+        let mut ppu = NesPPU::new_empty_rom();
+        for _ in 0..241 {
+            ppu.tick(255);
+            ppu.tick(86);
+        }
+        for _ in 0..10 {
+            heatmap.apply(&ppu, &mut frame);
+        }
+        assert!(pixel(&frame, x, y).1 < 0xFF);
+    }
+
+    // Counts the RAM writes each frame period brought, whatever's on.
+    struct WriteCounter(Rc<RefCell<Vec<usize>>>);
+
+    impl Stage for WriteCounter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn apply(&mut self, ppu: &NesPPU, _frame: &mut Frame) {
+            self.0.borrow_mut().push(ppu.frame_timing().ram_writes.len());
+        }
+    }
+
+    #[test]
+    fn test_heatmap_only_records_while_on() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let counts = Rc::new(RefCell::new(vec![]));
+        nes.compositor().add_overlay(Box::new(RamHeatmap::new()), false);
+        nes.compositor().add_overlay(Box::new(WriteCounter(counts.clone())), true);
+        let run = |nes: &mut Nes, frames| {
+            for _ in 0..frames {
+                nes.run_frame();
+            }
+            std::mem::take(&mut *counts.borrow_mut())
+        };
+        assert!(run(&mut nes, 5).iter().all(|&writes| writes == 0));
+
+        nes.compositor().toggle("heatmap");
+        // the Nes looks at the heatmap as each frame ends, so it's a period behind either way
+        assert!(run(&mut nes, 3)[1..].iter().all(|&writes| writes > 0));
+
+        nes.compositor().toggle("heatmap");
+        assert_eq!(run(&mut nes, 3)[1..], [0, 0]);
+    }
+
+    #[test]
+    fn test_apu_visualizer() {
+        let ppu = NesPPU::new_empty_rom();
+        let mut visualizer = ApuVisualizer::new();
+        let mut apu = crate::apu::Apu::new();
+        apu.set_scope(Some(visualizer.scope()));
+        let mut frame = Frame::new();
+        visualizer.apply(&ppu, &mut frame); // nothing recorded yet: asks for the next frame
+
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b1001_1010); // 50% duty, constant volume 10
+        apu.write_register(0x4002, 0xFF);
+        apu.write_register(0x4003, 0b1111_1000);
+        for _ in 0..29780 / 4 {
+            apu.tick(4);
+        }
+        apu.end_frame(|_| {});
+        visualizer.apply(&ppu, &mut frame);
+
+        // pulse 1 goes between 0 and 10, pulse 2 stays at 0: a line along the bottom of its lane
+        let (high, bottom) = (1 + LANE_HEIGHT - 4 - 10 * (LANE_HEIGHT - 4) / 15, LANE_HEIGHT - 3);
+        assert!((0..Frame::WIDTH).any(|x| pixel(&frame, x, high) == WAVES[0]));
+        assert!((0..Frame::WIDTH).all(|x| pixel(&frame, x, LANE_HEIGHT + high) != WAVES[1]));
+        assert_eq!(pixel(&frame, 200, LANE_HEIGHT + bottom), WAVES[1]);
+        let counter = scope::CHANNELS.len() * LANE_HEIGHT + osd::LINE_HEIGHT - 1;
+        assert_eq!(pixel(&frame, 64, counter), QUARTER_FRAME); // 7457 cycles in
+        assert_eq!(pixel(&frame, 128, counter), HALF_FRAME);
+    }
+}
@@ -14,4 +14,35 @@ pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
    (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
-];
\ No newline at end of file
+];
+
+pub type Colors = [(u8, u8, u8); 64];
+
+// A .pal palette file: the 64 colours as RGB bytes, 192 in all. Files with 512 colours (the 64
+// repeated for each combination of the colour emphasis bits) are accepted too; emphasis isn't
+// emulated, so only the first 64 are used. This is how the Vs. System's PPUs are shown: they
+// order and mix their colours differently from the console's, so each game needs its own file.
+pub fn from_pal_file(raw: &[u8]) -> Result<Colors, String> {
+    if raw.len() != 64 * 3 && raw.len() != 512 * 3 {
+        return Err(format!("a palette file has 192 or 1536 bytes, not {}", raw.len()));
+    }
+    let mut colors = [(0, 0, 0); 64];
+    for (color, rgb) in colors.iter_mut().zip(raw.chunks_exact(3)) {
+        *color = (rgb[0], rgb[1], rgb[2]);
+    }
+    Ok(colors)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_pal_file() {
+        let raw: Vec<u8> = (0..192).map(|i| i as u8).collect();
+        let colors = from_pal_file(&raw).unwrap();
+        assert_eq!(colors[0], (0, 1, 2));
+        assert_eq!(colors[63], (189, 190, 191));
+        assert!(from_pal_file(&raw[..100]).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+// A small seeded pseudo-random number generator, for whatever gets added on top of the
+// emulation and wants randomness. Nothing in the emulator draws from it yet; frontends and
+// tools reach it through Nes::rng.
+//
+// Real hardware has no RNG: anything that *looks* random on an NES comes from
+// the game's own code. Any randomness we add on top of the emulation must
+// therefore be fully reproducible, or movies and spectators will desync.
+//
+// Two rules make that work:
+// - The generator is seeded explicitly (never from the clock).
+// - Every frame gets its own stream, derived from (seed, frame number). So the
+//   values drawn during frame N do not depend on how many values were drawn
+//   during frames 0..N-1, and toggling a feature mid-session does not shift
+//   the randomness seen by every other feature afterwards.
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+pub struct Rng {
+    seed: u64,  // session seed, chosen once and kept in the movie header
+    state: u64, // current position in this frame's stream
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng { seed, state: 0 };
+        rng.begin_frame(0);
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn reseed(&mut self, seed: u64, frame: u64) {
+        self.seed = seed;
+        self.begin_frame(frame);
+    }
+
+    // Called by the Nes as each frame ends, with the frames run so far.
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.state = mix(self.seed ^ frame.wrapping_mul(GOLDEN_GAMMA));
+    }
+
+    // SplitMix64: tiny, fast, and good enough for anything that isn't cryptography.
+    // See: https://prng.di.unimi.it/splitmix64.c
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        mix(self.state)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8 // the high bits are the best mixed ones
+    }
+
+    // Returns a value in 0..bound (bound must be non-zero).
+    pub fn next_below(&mut self, bound: u32) -> u32 {
+        assert!(bound > 0);
+        (((self.next_u64() >> 32) * bound as u64) >> 32) as u32
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.next_u8();
+        }
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_same_seed_same_stream() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(42).next_u64(), Rng::new(43).next_u64());
+    }
+
+    #[test]
+    fn test_frame_streams_are_independent_of_history() {
+        let mut a = Rng::new(7);
+        a.begin_frame(10);
+        let expected = a.next_u64();
+
+        // draw a different amount of values in earlier frames: frame 10 must not care
+        let mut b = Rng::new(7);
+        for frame in 0..10 {
+            b.begin_frame(frame);
+            for _ in 0..frame {
+                b.next_u64();
+            }
+        }
+        b.begin_frame(10);
+        assert_eq!(b.next_u64(), expected);
+    }
+
+    // The stream each frame gets, drawn once the frame has run.
+    fn streams(nes: &mut Nes, frames: usize, power_cycle_at: usize) -> Vec<u64> {
+        (0..frames)
+            .map(|frame| {
+                if frame == power_cycle_at {
+                    nes.power_cycle();
+                }
+                nes.run_frame();
+                nes.rng().next_u64()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replays_across_a_power_cycle() {
+        let rom = std::fs::read("nestest.nes").unwrap();
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.rng().reseed(99, 0);
+        nes.record_movie(false);
+        let recorded = streams(&mut nes, 6, 3);
+        // the console starts over, the frame count doesn't: no stream comes round again
+        let mut distinct = recorded.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), recorded.len());
+
+        let movie = nes.stop_recording().unwrap();
+        assert_eq!(movie.seed, 99);
+        let mut replay = Nes::new(&rom).unwrap();
+        replay.play_movie(movie);
+        assert_eq!(streams(&mut replay, 6, usize::MAX), recorded); // the movie power cycles it
+    }
+
+    #[test]
+    fn test_next_below_stays_in_range() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            assert!(rng.next_below(6) < 6);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::mmc3::IrqRevision;
+
+// Identifies ROMs by the hash of their PRG+CHR data, to name them and to fix bad headers.
+// Plenty of iNES files in circulation have the wrong mapper, mirroring or battery flag (the
+// header was written by hand for years), while the ROM data itself is the same everywhere, so
+// a small table keyed by its hash overrides the header. The table is text (see romdb.txt for
+// the format); a small one is bundled, and more can be loaded with --romdb.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    hash: String, // uppercase hex: CRC32 or SHA-1
+    pub mapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub mmc3_irq: Option<IrqRevision>,
+    pub prg_ram_size: Option<usize>,
+    pub title: String,
+}
+
+pub struct Database {
+    entries: Vec<Entry>,
+}
+
+impl Database {
+    pub fn empty() -> Self {
+        Database { entries: vec![] }
+    }
+
+    pub fn bundled() -> Self {
+        Database::parse(include_str!("romdb.txt")).expect("the bundled romdb.txt is broken")
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entries = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |what: &str| format!("line {}: bad {} in '{}'", n + 1, what, line);
+            // fields are separated by any run of spaces or tabs, so columns can be lined up
+            let mut words = line.split_whitespace().peekable();
+            let missing = || format!("line {}: expected hash, mapper, mirroring, battery and title", n + 1);
+            let mut field = || words.next().ok_or_else(missing);
+            let (hash, mapper, mirroring, battery) = (field()?, field()?, field()?, field()?);
+            if !(hash.len() == 8 || hash.len() == 40) || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(bad("hash"));
+            }
+            // options come before the title, as name=value
+            let mut mmc3_irq = None;
+            let mut prg_ram_size = None;
+            while let Some(option) = words.next_if(|word| word.contains('=')) {
+                match option.split_once('=') {
+                    Some(("mmc3", "sharp")) => mmc3_irq = Some(IrqRevision::Sharp),
+                    Some(("mmc3", "nec")) => mmc3_irq = Some(IrqRevision::Nec),
+                    Some(("wram", size)) => {
+                        let kib = size.strip_suffix('k').and_then(|kib| kib.parse::<usize>().ok());
+                        match kib.filter(|kib| kib.is_power_of_two() && (1..=32).contains(kib)) {
+                            Some(kib) => prg_ram_size = Some(kib * 1024),
+                            None => return Err(bad("option")),
+                        }
+                    }
+                    _ => return Err(bad("option")),
+                }
+            }
+            let title = words.collect::<Vec<_>>().join(" ");
+            if title.is_empty() {
+                return Err(missing());
+            }
+            entries.push(Entry {
+                hash: hash.to_ascii_uppercase(),
+                mapper: match mapper {
+                    "-" => None,
+                    _ => Some(mapper.parse().map_err(|_| bad("mapper"))?),
+                },
+                mirroring: match mirroring {
+                    "-" => None,
+                    "h" => Some(Mirroring::HORIZONTAL),
+                    "v" => Some(Mirroring::VERTICAL),
+                    "4" => Some(Mirroring::FOUR_SCREEN),
+                    _ => return Err(bad("mirroring")),
+                },
+                battery: match battery {
+                    "-" => None,
+                    "y" => Some(true),
+                    "n" => Some(false),
+                    _ => return Err(bad("battery flag")),
+                },
+                mmc3_irq,
+                prg_ram_size,
+                title,
+            });
+        }
+        Ok(Database { entries })
+    }
+
+    // Entries added later win, so a user's file can correct the bundled one.
+    pub fn extend(&mut self, other: Database) {
+        self.entries.splice(0..0, other.entries);
+    }
+
+    pub fn lookup(&self, rom: &Rom) -> Option<&Entry> {
+        let crc = format!("{:08X}", rom.crc32());
+        let sha1 = rom.sha1();
+        self.entries.iter().find(|entry| entry.hash == crc || entry.hash == sha1)
+    }
+}
+
+// Applies an entry's corrections, returning what changed (for the log).
+pub fn apply(entry: &Entry, rom: &mut Rom) -> Vec<String> {
+    let mut changes = vec![];
+    if let Some(mapper) = entry.mapper.filter(|m| *m != rom.mapper) {
+        changes.push(format!("mapper {} -> {}", rom.mapper, mapper));
+        rom.mapper = mapper;
+    }
+    if let Some(mirroring) = entry.mirroring.filter(|m| *m != rom.screen_mirroring) {
+        changes.push(format!("mirroring {:?} -> {:?}", rom.screen_mirroring, mirroring));
+        rom.screen_mirroring = mirroring;
+    }
+    if let Some(battery) = entry.battery.filter(|b| *b != rom.battery) {
+        changes.push(format!("battery {} -> {}", rom.battery, battery));
+        rom.battery = battery;
+    }
+    if let Some(revision) = entry.mmc3_irq.filter(|r| *r != rom.mmc3_irq) {
+        changes.push(format!("MMC3 IRQ {:?} -> {:?}", rom.mmc3_irq, revision));
+        rom.mmc3_irq = revision;
+    }
+    if let Some(size) = entry.prg_ram_size.filter(|s| *s != rom.prg_ram_size) {
+        changes.push(format!("PRG-RAM {}KiB -> {}KiB", rom.prg_ram_size / 1024, size / 1024));
+        rom.prg_ram_size = size;
+    }
+    changes
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_identifies_nestest() {
+        let rom = Rom::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        assert_eq!(rom.crc32(), 0x158B0388);
+        assert_eq!(rom.sha1(), "4131307F0F69F2A5C54B7D438328C5B2A5ED0820");
+
+        let db = Database::bundled();
+        assert_eq!(db.lookup(&rom).unwrap().title, "nestest (kevtris CPU test)");
+    }
+
+    #[test]
+    fn test_overrides_header() {
+        let mut rom = Rom::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let mut db = Database::bundled();
+        db.extend(Database::parse("4131307f0f69f2a5c54b7d438328c5b2a5ed0820 3 v - Not nestest\n").unwrap());
+
+        let entry = db.lookup(&rom).unwrap().clone();
+        assert_eq!(entry.title, "Not nestest");
+        assert_eq!(apply(&entry, &mut rom), vec!["mapper 0 -> 3", "mirroring HORIZONTAL -> VERTICAL"]);
+        assert_eq!(rom.mapper, 3);
+        assert!(apply(&entry, &mut rom).is_empty());
+
+        // options go between the battery flag and the title
+        let db = Database::parse("158B0388 4 - - mmc3=nec Old MMC3 game\n").unwrap();
+        let entry = db.lookup(&rom).unwrap();
+        assert_eq!((entry.mmc3_irq, entry.title.as_str()), (Some(IrqRevision::Nec), "Old MMC3 game"));
+        assert_eq!(apply(entry, &mut rom), vec!["mapper 3 -> 4", "MMC3 IRQ Sharp -> Nec"]);
+        assert_eq!(rom.mmc3_irq, IrqRevision::Nec);
+        assert!(Database::parse("158B0388 4 - - mmc3=new Bad option").is_err());
+
+        let db = Database::parse("158B0388 - - y wram=2k Family BASIC\n").unwrap();
+        assert_eq!(apply(db.lookup(&rom).unwrap(), &mut rom), vec!["battery false -> true", "PRG-RAM 8KiB -> 2KiB"]);
+        assert_eq!(rom.prg_ram_size, 0x800);
+        assert!(Database::parse("158B0388 - - - wram=3k Bad size").is_err());
+
+        assert!(Database::parse("158B0388 0 x n Bad mirroring").is_err());
+        assert!(Database::parse("158B0388 0 h").is_err());
+        assert!(Database::parse("158B0388 4 - - mmc3=nec").is_err()); // options but no title
+    }
+
+    #[test]
+    fn test_aligned_columns() {
+        // the way a maintained list is laid out: columns lined up with spaces and tabs
+        let text = "# hash                                   mapper mirr batt title\n\
+                    4131307F0F69F2A5C54B7D438328C5B2A5ED0820  0      h    n    nestest  (SHA-1)\n\
+                    158B0388\t\t4\tv\t-\twram=2k\t\tnestest, as MMC3\n";
+        let db = Database::parse(text).unwrap();
+        let rom = Rom::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let entry = db.lookup(&rom).unwrap();
+        assert_eq!((entry.mapper, entry.battery), (Some(0), Some(false)));
+        assert_eq!(entry.title, "nestest (SHA-1)");
+
+        let db = Database::parse(text.lines().nth(2).unwrap()).unwrap();
+        let entry = db.lookup(&rom).unwrap();
+        assert_eq!((entry.mapper, entry.mirroring, entry.prg_ram_size), (Some(4), Some(Mirroring::VERTICAL), Some(0x800)));
+        assert_eq!(entry.title, "nestest, as MMC3");
+    }
+}
@@ -0,0 +1,363 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use crate::cpu::CPU;
+use crate::storage::Storage;
+
+// Savestates: a snapshot of the whole console that can be written to disk and loaded back.
+//
+// The file is a magic/version header followed by tagged chunks, each one a 4 byte tag, a
+// little-endian u32 length and the data:
+//
+//   "CPU "  registers and the CPU cycle count
+//   "RAM "  2KiB work RAM            "PRAM"  cartridge PRG-RAM
+//   "VRAM"  2KiB nametable RAM       "OAM "  sprite memory       "PAL "  palette RAM
+//   "PPU "  PPU registers and timing (see NesPPU::save_registers)
+//   "CHR "  pattern tables, only for cartridges with CHR-RAM
+//   "APU "  the APU's channels, frame counter and IRQs (see Apu::save_state)
+//   "MAPR"  the mapper's registers, IRQ counters and sound channels (see Mapper::save_state)
+//
+// Keeping the memories in separate chunks is what makes two states easy to compare (see
+// `diff`): when a game glitches after loading, the first place RAM or VRAM differs from a
+// good state usually points straight at the culprit. Unknown chunks are skipped on load, so
+// newer files still load the parts an older build understands.
+//
+// Loading a state into a different game fails on the mapper chunk or the memory sizes, most of
+// the time; the frontend also keeps states next to the ROM they belong to.
+//
+// Not saved yet: the controllers' shift registers and strobe (and those of the devices plugged
+// in beside them), the last value on the CPU's data bus (see Bus::read_open_bus), and whether
+// the CPU has jammed. A state taken in the middle of a controller read picks the read up again
+// from the first button, and an open bus read right after a load sees whatever was on the bus
+// before it. The frame count, movies and everything else of the frontend's stay as they are.
+
+const MAGIC: &[u8; 4] = b"RNSS";
+const VERSION: u8 = 1;
+
+pub type Tag = [u8; 4];
+
+pub struct SaveState {
+    chunks: Vec<(Tag, Vec<u8>)>,
+}
+
+impl SaveState {
+    pub fn capture(cpu: &CPU) -> Self {
+        let mut regs = vec![cpu.register_a, cpu.register_x, cpu.register_y, cpu.stack_pointer, cpu.status];
+        regs.extend(cpu.program_counter.to_le_bytes());
+        regs.extend((cpu.bus.cycles() as u64).to_le_bytes());
+
+        let ppu = cpu.bus.ppu();
+        let mut chunks = vec![
+            (*b"CPU ", regs),
+            (*b"RAM ", cpu.bus.ram().to_vec()),
+            (*b"PRAM", cpu.bus.prg_ram().to_vec()),
+            (*b"VRAM", ppu.vram.to_vec()),
+            (*b"OAM ", ppu.oam_data.to_vec()),
+            (*b"PAL ", ppu.palette_table.to_vec()),
+            (*b"PPU ", ppu.save_registers()),
+            (*b"APU ", cpu.bus.apu().save_state()),
+        ];
+        if ppu.chr_is_ram {
+            chunks.push((*b"CHR ", ppu.chr_rom.clone()));
+        }
+        chunks.push((*b"MAPR", cpu.bus.mapper().borrow().save_state()));
+        SaveState { chunks }
+    }
+
+    // Checks every chunk before touching the console, so a bad file leaves it as it was.
+    pub fn restore(&self, cpu: &mut CPU) -> Result<(), String> {
+        let regs = self.expect(b"CPU ", 15)?;
+        let ram = self.expect(b"RAM ", 2048)?;
+        let prg_ram = self.expect(b"PRAM", cpu.bus.prg_ram().len())?;
+        let vram = self.expect(b"VRAM", 2048)?;
+        let oam = self.expect(b"OAM ", 256)?;
+        let palette = self.expect(b"PAL ", 32)?;
+        let ppu_regs = self.chunk(b"PPU ").ok_or("savestate has no PPU chunk")?;
+        let chr = match self.chunk(b"CHR ") {
+            Some(chr) if cpu.bus.ppu().chr_is_ram && chr.len() == cpu.bus.ppu().chr_rom.len() => Some(chr),
+            Some(_) => return Err("savestate CHR-RAM doesn't match this cartridge".to_string()),
+            None => None,
+        };
+        // Last to be checked and first to be loaded: the mapper checks its chunk as it reads it,
+        // setting its registers as it goes, so a bad chunk (or APU or PPU registers, checked
+        // next) puts back the ones it had, and the APU its own. States from before mappers or
+        // the APU were saved keep the current banks and sound.
+        let mapper = cpu.bus.mapper().clone();
+        let (mapper_before, apu_before) = (mapper.borrow().save_state(), cpu.bus.apu().save_state());
+        let loaded = match self.chunk(b"MAPR") {
+            Some(data) => mapper.borrow_mut().load_state(data),
+            None => Ok(()),
+        };
+        let loaded = loaded.and_then(|_| match self.chunk(b"APU ") {
+            Some(data) => cpu.bus.apu_mut().load_state(data),
+            None => Ok(()),
+        });
+        if let Err(err) = loaded.and_then(|_| cpu.bus.ppu_mut().load_registers(ppu_regs)) {
+            mapper.borrow_mut().load_state(&mapper_before).expect("the mapper's own state loads back");
+            cpu.bus.apu_mut().load_state(&apu_before).expect("the APU's own state loads back");
+            return Err(err);
+        }
+        cpu.register_a = regs[0];
+        cpu.register_x = regs[1];
+        cpu.register_y = regs[2];
+        cpu.stack_pointer = regs[3];
+        cpu.status = regs[4];
+        cpu.program_counter = u16::from_le_bytes([regs[5], regs[6]]);
+        cpu.bus.restore_cycles(u64::from_le_bytes(regs[7..15].try_into().unwrap()) as usize);
+        cpu.bus.ram_mut().copy_from_slice(ram);
+        cpu.bus.prg_ram_mut().copy_from_slice(prg_ram);
+
+        let ppu = cpu.bus.ppu_mut();
+        ppu.vram.copy_from_slice(vram);
+        ppu.oam_data.copy_from_slice(oam);
+        ppu.palette_table.copy_from_slice(palette);
+        if let Some(chr) = chr {
+            ppu.load_chr_ram(chr);
+        }
+        Ok(())
+    }
+
+    pub fn chunk(&self, tag: &Tag) -> Option<&[u8]> {
+        self.chunks.iter().find(|(t, _)| t == tag).map(|(_, data)| data.as_slice())
+    }
+
+    fn expect(&self, tag: &Tag, len: usize) -> Result<&[u8], String> {
+        match self.chunk(tag) {
+            Some(data) if data.len() == len => Ok(data),
+            Some(data) => Err(format!("savestate chunk {}: expected {} bytes, got {}", tag_name(tag), len, data.len())),
+            None => Err(format!("savestate has no {} chunk", tag_name(tag))),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        for (tag, data) in &self.chunks {
+            out.extend(tag);
+            out.extend((data.len() as u32).to_le_bytes());
+            out.extend(data);
+        }
+        out
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, String> {
+        if raw.len() < 5 || &raw[0..4] != MAGIC {
+            return Err("not a savestate".to_string());
+        }
+        if raw[4] != VERSION {
+            return Err(format!("unsupported savestate version {}", raw[4]));
+        }
+
+        let mut chunks = vec![];
+        let mut rest = &raw[5..];
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err("savestate is truncated".to_string());
+            }
+            let tag: Tag = rest[0..4].try_into().unwrap();
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            if rest.len() - 8 < len {
+                return Err(format!("savestate chunk {} is truncated", tag_name(&tag)));
+            }
+            chunks.push((tag, rest[8..8 + len].to_vec()));
+            rest = &rest[8 + len..];
+        }
+        Ok(SaveState { chunks })
+    }
+}
+
+// State files, through the frontend's storage (see crate::storage). Errors name the file.
+pub fn write_file(storage: &dyn Storage, path: &Path, state: &SaveState) -> io::Result<()> {
+    storage.write(path, &state.to_bytes())
+}
+
+pub fn read_file(storage: &dyn Storage, path: &Path) -> Result<SaveState, String> {
+    match storage.read(path) {
+        Ok(Some(raw)) => SaveState::from_bytes(&raw).map_err(|err| format!("{}: {}", path.display(), err)),
+        Ok(None) => Err(format!("{}: no such savestate", path.display())),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+fn tag_name(tag: &Tag) -> String {
+    String::from_utf8_lossy(tag).trim_end().to_string()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChunkDiff {
+    Same,
+    Differs { first_offset: usize, count: usize }, // count: how many bytes differ in total
+    Resized { a: usize, b: usize },
+    OnlyInA,
+    OnlyInB,
+}
+
+// Compares two states chunk by chunk, in the order the chunks appear in `a` (then any that
+// only `b` has).
+pub fn diff(a: &SaveState, b: &SaveState) -> Vec<(Tag, ChunkDiff)> {
+    let mut result = vec![];
+    for (tag, data_a) in &a.chunks {
+        let verdict = match b.chunk(tag) {
+            None => ChunkDiff::OnlyInA,
+            Some(data_b) if data_b.len() != data_a.len() => ChunkDiff::Resized { a: data_a.len(), b: data_b.len() },
+            Some(data_b) => {
+                let mut differing = data_a.iter().zip(data_b).enumerate().filter(|(_, (x, y))| x != y);
+                match differing.next() {
+                    None => ChunkDiff::Same,
+                    Some((first_offset, _)) => ChunkDiff::Differs { first_offset, count: 1 + differing.count() },
+                }
+            }
+        };
+        result.push((*tag, verdict));
+    }
+    for (tag, _) in &b.chunks {
+        if a.chunk(tag).is_none() {
+            result.push((*tag, ChunkDiff::OnlyInB));
+        }
+    }
+    result
+}
+
+// Where a chunk lives in the console's address space, so offsets can be shown as addresses.
+// The PPU sees VRAM at $2000 and palettes at $3F00; OAM has its own 8 bit address space.
+fn base_address(tag: &Tag) -> Option<u16> {
+    match tag {
+        b"RAM " => Some(0x0000),
+        b"PRAM" => Some(0x6000),
+        b"VRAM" => Some(0x2000),
+        b"PAL " => Some(0x3F00),
+        b"OAM " | b"CHR " => Some(0x0000),
+        _ => None,
+    }
+}
+
+pub fn diff_report(a: &SaveState, b: &SaveState) -> String {
+    let mut out = String::new();
+    for (tag, verdict) in diff(a, b) {
+        let name = tag_name(&tag);
+        let _ = match verdict {
+            ChunkDiff::Same => writeln!(out, "{:<5} same", name),
+            ChunkDiff::Differs { first_offset, count } => match base_address(&tag) {
+                Some(base) => writeln!(
+                    out,
+                    "{:<5} differs: {} byte(s), first at ${:04X}",
+                    name,
+                    count,
+                    base as usize + first_offset
+                ),
+                None => writeln!(out, "{:<5} differs: {} byte(s), first at offset {}", name, count, first_offset),
+            },
+            ChunkDiff::Resized { a, b } => writeln!(out, "{:<5} size differs: {} vs {} bytes", name, a, b),
+            ChunkDiff::OnlyInA => writeln!(out, "{:<5} only in the first state", name),
+            ChunkDiff::OnlyInB => writeln!(out, "{:<5} only in the second state", name),
+        };
+    }
+    out
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::nes::Nes;
+
+    fn nestest() -> Nes {
+        Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut nes = nestest();
+        nes.run_frame();
+        let saved = nes.save_state().to_bytes();
+        let (pc, ram) = (nes.cpu().program_counter, *nes.ram());
+
+        nes.run_frame();
+        nes.ram_mut()[0x10] ^= 0xFF;
+        nes.load_state(&SaveState::from_bytes(&saved).unwrap()).unwrap();
+        assert_eq!(nes.cpu().program_counter, pc);
+        assert_eq!(*nes.ram(), ram);
+        assert_eq!(nes.save_state().to_bytes(), saved);
+
+        assert!(SaveState::from_bytes(&saved[..saved.len() - 1]).is_err());
+        assert!(SaveState::from_bytes(b"NES\x1a").is_err());
+    }
+
+    #[test]
+    fn test_bad_mapper_chunk_changes_nothing() {
+        let mut rom = crate::cartridge::Rom::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        rom.mapper = 1; // MMC1: five registers, read one at a time
+        let mut nes = Nes::from_rom(rom).unwrap();
+        nes.run_frame();
+        let mapper_before = nes.cpu().bus.mapper().borrow().save_state();
+        let ram = *nes.ram();
+
+        let mut state = nes.save_state();
+        nes.ram_mut()[0x10] ^= 0xFF;
+        let ram_after = *nes.ram();
+        for (tag, data) in &mut state.chunks {
+            if tag == b"MAPR" {
+                *data = vec![9, 9, 9]; // truncated after three of them
+            }
+        }
+        assert!(nes.load_state(&state).unwrap_err().contains("truncated"));
+        assert_eq!(nes.cpu().bus.mapper().borrow().save_state(), mapper_before);
+        assert_eq!(*nes.ram(), ram_after);
+        assert_ne!(ram_after, ram);
+    }
+
+    // The CPU cycle the APU next raises its frame IRQ on, acknowledging the one up now; None if
+    // it doesn't within two frames.
+    fn next_frame_irq(nes: &mut Nes) -> Option<usize> {
+        nes.cpu().bus.apu_mut().read_status();
+        for _ in 0..20_000 {
+            nes.step_instruction();
+            if nes.cpu().bus.apu().irq_pending() {
+                return Some(nes.cpu().bus.cycles());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_apu_timeline() {
+        let mut nes = nestest();
+        nes.run_frame();
+        nes.run_frame();
+        let state = nes.save_state();
+        let irq = next_frame_irq(&mut nes);
+        assert!(irq.is_some());
+
+        // somewhere else in the sequence, in 5-step mode, which never raises it
+        nes.run_frame();
+        nes.cpu().bus.apu_mut().write_register(0x4017, 0x80);
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.save_state().chunk(b"APU "), state.chunk(b"APU "));
+        assert_eq!(next_frame_irq(&mut nes), irq);
+
+        // a chunk that doesn't fit changes nothing
+        let mut bad = nes.save_state();
+        let apu = nes.cpu().bus.apu().save_state();
+        for (tag, data) in &mut bad.chunks {
+            if tag == b"APU " {
+                data.pop();
+            }
+        }
+        assert!(nes.load_state(&bad).unwrap_err().starts_with("APU state: expected"));
+        assert_eq!(nes.cpu().bus.apu().save_state(), apu);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut nes = nestest();
+        let a = nes.save_state();
+        nes.ram_mut()[0x0300] = 0x55;
+        nes.ram_mut()[0x0310] = 0x55;
+        let b = nes.save_state();
+
+        let result = diff(&a, &b);
+        assert!(result.contains(&(*b"RAM ", ChunkDiff::Differs { first_offset: 0x0300, count: 2 })));
+        assert!(result.contains(&(*b"VRAM", ChunkDiff::Same)));
+        assert!(diff_report(&a, &b).contains("RAM   differs: 2 byte(s), first at $0300"));
+    }
+}
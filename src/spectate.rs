@@ -0,0 +1,497 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::cheats::Cheat;
+use crate::movie::{InputEvent, Movie};
+use crate::nes::Nes;
+use crate::savestate::SaveState;
+
+// Spectators: friends watching a session from their own machine (see `--spectators` and
+// `--spectate` in main.rs). No video is sent. The emulation is deterministic, so the host
+// streams the movie it is recording and every spectator runs the game locally from power-on
+// with those inputs, which costs a few bytes per button press instead of a video stream.
+//
+// The stream is the movie text format (crate::movie), plus a "sync" line after every frame:
+//
+//   runesco-movie 1
+//   seed 0
+//   120 - 1 08
+//   sync 121              the inputs of the first 121 frames are all there
+//
+// A spectator can only run a frame once its inputs are complete, so it plays a little behind
+// the host; see Pacer for how far. Spectators joining late get the whole movie so far and
+// fast-forward through it.
+//
+// Replaying inputs only works if both sides run exactly the same thing, so before any input
+// is sent the two shake hands. The spectator says what it is running:
+//
+//   runesco-spectator 1
+//   version 0.1.0         the emulator's version: timing fixes change what a game does
+//   rom 4131307F...       SHA-1 of the ROM
+//   ready
+//
+// and the host either turns it away ("reject <why>", then hangs up) or describes the session
+// before the movie starts:
+//
+//   runesco-session 1
+//   version 0.1.0
+//   rom 4131307F...
+//   setting dmc-conflict on
+//   setting open-bus on
+//   setting bus-conflicts on
+//   cheat SXIOPO          one line per cheat code switched on (see crate::cheats)
+//   state 524E5353...     the console at power-on, as a hex encoded savestate
+//
+// Settings that change emulation are copied to the spectator instead of being compared, and
+// the starting state takes care of everything else the two consoles could disagree on before
+// the first frame, battery saves in particular. Cheats changed once the session is running
+// aren't sent: the spectator would drift.
+
+// Past this much unsent data a spectator is too far behind to ever catch up, and is dropped
+// instead of growing the host's memory forever.
+const MAX_OUTBOX: usize = 1 << 20;
+// A hello is a few short lines; anything longer isn't a spectator.
+const MAX_HELLO: usize = 1024;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// What both ends of a session have to agree on.
+pub struct Session {
+    pub version: String,
+    pub rom_sha1: String,
+    pub dmc_conflict: bool,
+    pub open_bus: bool,
+    pub bus_conflicts: bool,
+    pub cheats: Vec<String>, // the codes switched on
+    pub state: SaveState,
+}
+
+impl Session {
+    // The session `nes` would start; call before its first frame.
+    pub fn of(nes: &Nes) -> Self {
+        let cheats = nes.cheats().list().iter().filter(|cheat| cheat.enabled).map(|cheat| cheat.code.clone()).collect();
+        Session {
+            version: VERSION.to_string(),
+            rom_sha1: nes.rom_sha1().to_string(),
+            dmc_conflict: nes.dmc_read_conflict(),
+            open_bus: nes.open_bus(),
+            bus_conflicts: nes.bus_conflicts(),
+            cheats,
+            state: nes.save_state(),
+        }
+    }
+
+    fn header(&self) -> String {
+        let state: String = self.state.to_bytes().iter().map(|b| format!("{:02X}", b)).collect();
+        let cheats: String = self.cheats.iter().map(|code| format!("cheat {}\n", code)).collect();
+        format!(
+            "runesco-session 1\nversion {}\nrom {}\nsetting dmc-conflict {}\nsetting open-bus {}\nsetting bus-conflicts {}\n{}state {}\n",
+            self.version,
+            self.rom_sha1,
+            if self.dmc_conflict { "on" } else { "off" },
+            if self.open_bus { "on" } else { "off" },
+            if self.bus_conflicts { "on" } else { "off" },
+            cheats,
+            state
+        )
+    }
+
+    // Why a spectator saying `hello` can't watch, if it can't.
+    fn mismatch(&self, hello: &[String]) -> Option<String> {
+        let field = |name: &str| {
+            hello.iter().find_map(|line| line.strip_prefix(name).and_then(|rest| rest.strip_prefix(' ')))
+        };
+        if hello.first().map(String::as_str) != Some("runesco-spectator 1") {
+            return Some("not a runesco spectator".to_string());
+        }
+        match (field("version"), field("rom")) {
+            (Some(version), _) if version != self.version => {
+                Some(format!("the host runs runesco {}, you run {}", self.version, version))
+            }
+            (_, Some(rom)) if rom != self.rom_sha1 => {
+                Some(format!("the host plays a different ROM (SHA-1 {})", self.rom_sha1))
+            }
+            (Some(_), Some(_)) => None,
+            _ => Some("incomplete hello".to_string()),
+        }
+    }
+}
+
+pub struct SpectatorServer {
+    listener: TcpListener,
+    session: Session,
+    viewers: Vec<Viewer>,
+}
+
+struct Viewer {
+    stream: TcpStream,
+    hello: Option<Vec<u8>>, // handshake lines read so far, None once it's watching
+    closing: bool,          // turned away, hung up once the reason is sent
+    sent: usize,            // movie events already queued for this spectator
+    outbox: Vec<u8>,        // queued, not accepted by the socket yet
+}
+
+impl SpectatorServer {
+    // `session` is the host's, taken before the first frame (see Session::of).
+    pub fn bind(addr: impl ToSocketAddrs, session: Session) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(SpectatorServer { listener, session, viewers: vec![] })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Spectators watching, not counting those still shaking hands.
+    pub fn spectators(&self) -> usize {
+        self.viewers.iter().filter(|viewer| viewer.hello.is_none()).count()
+    }
+
+    // Call after every frame with the session's recording and the frames run so far.
+    // Never blocks: whatever a socket doesn't take now is sent on a later frame.
+    pub fn broadcast(&mut self, movie: &Movie, frames: u64) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            let _ = stream.set_nodelay(true);
+            self.viewers.push(Viewer { stream, hello: Some(vec![]), closing: false, sent: 0, outbox: vec![] });
+        }
+
+        for viewer in self.viewers.iter_mut() {
+            if viewer.hello.is_some() {
+                viewer.shake_hands(&self.session, movie);
+            }
+            if viewer.hello.is_some() || viewer.closing {
+                continue;
+            }
+            for event in &movie.events()[viewer.sent..] {
+                viewer.outbox.extend_from_slice(event.to_line().as_bytes());
+                viewer.outbox.push(b'\n');
+            }
+            viewer.sent = movie.events().len();
+            viewer.outbox.extend_from_slice(format!("sync {}\n", frames).as_bytes());
+        }
+        self.viewers.retain_mut(|viewer| {
+            viewer.flush().is_ok() && viewer.outbox.len() <= MAX_OUTBOX && !(viewer.closing && viewer.outbox.is_empty())
+        });
+    }
+}
+
+impl Viewer {
+    // Reads the spectator's hello as it trickles in, and answers it once it's complete.
+    fn shake_hands(&mut self, session: &Session, movie: &Movie) {
+        let Some(hello) = self.hello.as_mut() else { return };
+        let mut buf = [0u8; 256];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return self.turn_away("hung up during the handshake"),
+                Ok(n) => hello.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return self.turn_away("hung up during the handshake"),
+            }
+        }
+        let lines: Vec<String> = String::from_utf8_lossy(hello).lines().map(|line| line.trim().to_string()).collect();
+        if !lines.iter().any(|line| line == "ready") {
+            if hello.len() > MAX_HELLO {
+                self.turn_away("not a runesco spectator");
+            }
+            return;
+        }
+        match session.mismatch(&lines) {
+            Some(reason) => self.turn_away(&reason),
+            None => {
+                self.hello = None;
+                self.outbox.extend_from_slice(session.header().as_bytes());
+                self.outbox.extend_from_slice(Movie::new(movie.seed, movie.subframe).to_text().as_bytes());
+            }
+        }
+    }
+
+    fn turn_away(&mut self, reason: &str) {
+        self.hello = None;
+        self.closing = true;
+        self.outbox = format!("reject {}\n", reason).into_bytes();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outbox.drain(..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct SpectatorClient {
+    stream: Option<TcpStream>, // None once the host hung up
+    pending: Vec<u8>,          // received, not a whole line yet
+    header: Movie,             // seed and subframe, read before playback starts
+    started: bool,             // playing since the first input or sync
+    synced: u64,               // frames whose inputs have all arrived
+    pacer: Pacer,
+}
+
+impl SpectatorClient {
+    // Says hello with what `nes` is running; the host checks it matches its session.
+    pub fn connect(addr: impl ToSocketAddrs, nes: &Nes) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        let hello = format!("runesco-spectator 1\nversion {}\nrom {}\nready\n", VERSION, nes.rom_sha1());
+        stream.write_all(hello.as_bytes())?;
+        stream.set_nonblocking(true)?;
+        Ok(SpectatorClient {
+            stream: Some(stream),
+            pending: vec![],
+            header: Movie::new(0, false),
+            started: false,
+            synced: 0,
+            pacer: Pacer::new(),
+        })
+    }
+
+    pub fn connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Frames the host has run that we haven't yet.
+    pub fn buffered(&self, nes: &Nes) -> u64 {
+        self.synced.saturating_sub(nes.frame_count())
+    }
+
+    // Reads what the host sent and hands the inputs to `nes`, which must be fresh from
+    // power-on. Err if the stream isn't a session or the host turned us away.
+    pub fn poll(&mut self, nes: &mut Nes) -> Result<(), String> {
+        let mut buf = [0u8; 4096];
+        while let Some(stream) = self.stream.as_mut() {
+            match stream.read(&mut buf) {
+                Ok(0) => self.stream = None,
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => self.stream = None,
+            }
+        }
+
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            self.handle_line(nes, String::from_utf8_lossy(&line).trim())?;
+        }
+        Ok(())
+    }
+
+    fn handle_line(&mut self, nes: &mut Nes, line: &str) -> Result<(), String> {
+        let bad = || format!("not a runesco session: '{}'", line);
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => {}
+            ["runesco-session", "1"] | ["runesco-movie", "1"] => {}
+            ["reject", ..] => return Err(format!("The host turned us away: {}", line.trim_start_matches("reject").trim())),
+            ["version", version] if *version != VERSION => {
+                return Err(format!("The host runs runesco {}, this is {}", version, VERSION))
+            }
+            ["version", _] => {}
+            ["rom", sha1] if *sha1 != nes.rom_sha1() => {
+                return Err(format!("The host plays a different ROM (SHA-1 {})", sha1))
+            }
+            ["rom", _] => {}
+            ["setting", "dmc-conflict", on] => nes.set_dmc_read_conflict(*on == "on"),
+            ["setting", "open-bus", on] => nes.set_open_bus(*on == "on"),
+            ["setting", "bus-conflicts", on] => nes.set_bus_conflicts(*on == "on"),
+            ["cheat", code] if !self.started => nes.cheats_mut().add(Cheat::parse(code)?),
+            ["state", hex] if !self.started => {
+                let state = SaveState::from_bytes(&decode_hex(hex).ok_or_else(bad)?)?;
+                nes.load_state(&state)?;
+            }
+            ["seed", seed] if !self.started => self.header.seed = seed.parse().map_err(|_| bad())?,
+            ["subframe"] if !self.started => self.header.subframe = true,
+            ["sync", frames] => {
+                self.start(nes);
+                self.synced = frames.parse().map_err(|_| bad())?;
+            }
+            _ => {
+                let event = InputEvent::parse_line(line).ok_or_else(bad)?;
+                self.start(nes);
+                nes.queue_movie_input(event);
+            }
+        }
+        Ok(())
+    }
+
+    // Playback starts with an empty movie that the stream then extends.
+    fn start(&mut self, nes: &mut Nes) {
+        if !self.started {
+            self.started = true;
+            nes.play_movie(self.header.clone());
+        }
+    }
+
+    // How many frames to run before showing the next picture; call once per displayed frame.
+    pub fn frames_to_run(&mut self, nes: &Nes) -> u32 {
+        self.pacer.frames_to_run(self.synced, nes.frame_count())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() & 1 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// How far behind the host a spectator plays. Updates from the host don't arrive evenly: the
+// network bunches them up and leaves gaps. Playing each frame as soon as it arrives would
+// stutter at every gap, so the spectator keeps a few frames in hand: twice the average gap
+// between updates (measured in displayed frames), so ordinary jitter never empties it. When
+// it does run dry, playback waits until that many frames are buffered again; when it gets
+// too far ahead (joining late, the host's clock running slightly fast), it runs extra frames
+// per picture to catch up.
+pub const MIN_DELAY: u64 = 2;
+pub const MAX_DELAY: u64 = 30; // half a second
+const CATCH_UP_SLACK: u64 = 4;
+const MAX_FRAMES_PER_TICK: u32 = 8;
+
+pub struct Pacer {
+    average_gap: f32, // displayed frames between updates, moving average
+    since_update: u32,
+    last_synced: u64,
+    waiting: bool, // ran dry, refilling
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Pacer { average_gap: 1.0, since_update: 0, last_synced: 0, waiting: true }
+    }
+
+    // The latency aimed for, in frames.
+    pub fn delay(&self) -> u64 {
+        ((self.average_gap * 2.0).ceil() as u64).clamp(MIN_DELAY, MAX_DELAY)
+    }
+
+    pub fn frames_to_run(&mut self, synced: u64, played: u64) -> u32 {
+        self.since_update += 1;
+        if synced > self.last_synced {
+            self.average_gap = self.average_gap * 0.9 + self.since_update as f32 * 0.1;
+            self.since_update = 0;
+            self.last_synced = synced;
+        }
+
+        let buffered = synced.saturating_sub(played);
+        let delay = self.delay();
+        if buffered == 0 {
+            self.waiting = true;
+            return 0;
+        }
+        if self.waiting && buffered < delay {
+            return 0;
+        }
+        self.waiting = false;
+        if buffered > delay + CATCH_UP_SLACK {
+            ((buffered - delay) as u32).min(MAX_FRAMES_PER_TICK)
+        } else {
+            1
+        }
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::joypads::JoypadButton;
+
+    #[test]
+    fn test_pacer() {
+        let mut pacer = Pacer::new();
+        // steady updates: keeps MIN_DELAY frames in hand, then plays one per picture
+        let mut played = 0;
+        for synced in 1..=10 {
+            played += pacer.frames_to_run(synced, played) as u64;
+        }
+        assert_eq!(played, 10 - MIN_DELAY + 1);
+
+        // updates every 4 pictures, 4 frames at a time: the delay grows to cover the gaps
+        for tick in 0..400u64 {
+            let synced = 10 + tick / 4 * 4;
+            played += pacer.frames_to_run(synced, played) as u64;
+        }
+        assert!(pacer.delay() >= 6);
+
+        // far behind: catches up several frames per picture
+        let mut pacer = Pacer::new();
+        assert_eq!(pacer.frames_to_run(600, 0), MAX_FRAMES_PER_TICK);
+    }
+
+    #[test]
+    fn test_spectator_follows_host() {
+        let rom = std::fs::read("nestest.nes").unwrap();
+        let mut host = Nes::new(&rom).unwrap();
+        host.set_dmc_read_conflict(false);
+        host.set_open_bus(false);
+        host.set_bus_conflicts(false);
+        host.cheats_mut().add(Cheat::parse("0010:42").unwrap());
+        host.record_movie(false);
+        let mut server = SpectatorServer::bind("127.0.0.1:0", Session::of(&host)).unwrap();
+        let mut spectator = Nes::new(&rom).unwrap();
+        let mut client = SpectatorClient::connect(server.local_addr().unwrap(), &spectator).unwrap();
+
+        for frame in 0..30 {
+            host.joypads().0.set_button_pressed_status(JoypadButton::DOWN, frame % 6 < 3);
+            host.run_frame();
+            server.broadcast(host.recording().unwrap(), host.frame_count());
+        }
+        assert_eq!(server.spectators(), 1);
+
+        for _ in 0..1000 {
+            client.poll(&mut spectator).unwrap();
+            if client.buffered(&spectator) == 0 && spectator.frame_count() == 30 {
+                break;
+            }
+            for _ in 0..client.frames_to_run(&spectator) {
+                spectator.run_frame();
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(spectator.frame_count(), 30);
+        assert_eq!(spectator.ram(), host.ram());
+        assert!(!spectator.dmc_read_conflict()); // the host's settings
+        assert!(!spectator.open_bus());
+        assert!(!spectator.bus_conflicts());
+        assert_eq!(spectator.cheats().list(), host.cheats().list());
+    }
+
+    #[test]
+    fn test_mismatched_spectator_is_turned_away() {
+        let rom = std::fs::read("nestest.nes").unwrap();
+        let host = Nes::new(&rom).unwrap();
+        let mut session = Session::of(&host);
+        session.rom_sha1 = "0000000000000000000000000000000000000000".to_string();
+        let mut server = SpectatorServer::bind("127.0.0.1:0", session).unwrap();
+        let mut spectator = Nes::new(&rom).unwrap();
+        let mut client = SpectatorClient::connect(server.local_addr().unwrap(), &spectator).unwrap();
+
+        let movie = Movie::new(0, false);
+        for _ in 0..1000 {
+            server.broadcast(&movie, 0);
+            if let Err(err) = client.poll(&mut spectator) {
+                assert!(err.contains("different ROM"), "{}", err);
+                assert_eq!(server.spectators(), 0);
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("the spectator was never turned away");
+    }
+}
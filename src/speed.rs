@@ -0,0 +1,156 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::storage::Storage;
+
+// Game speed below 100%, for players who need more time to react: a co-op partner new to the
+// game, a hard section. Two controls:
+//
+//   - a persistent speed (25%-100%), remembered per game in a .speed file next to the ROM
+//     (game.nes -> game.speed) so it's there again next time;
+//   - a key held to slow down further, to half the persistent speed, for a tricky jump.
+//
+// The picture is still shown at the console's rate (see crate::pacing); slowing down means
+// some displayed frames don't run the console, so at 75% three out of four do. The only way
+// past 100% is another key held for fast-forward, FAST_FORWARD times the console's speed to
+// get through a cutscene or a long walk, whatever the persistent speed; the sound makes do
+// with a frame in every FAST_FORWARD (see crate::apu::stretch).
+
+pub const MIN_PERCENT: u32 = 25;
+pub const MAX_PERCENT: u32 = 100;
+pub const STEP_PERCENT: u32 = 5;
+pub const FAST_FORWARD: u32 = 4;
+
+pub struct SpeedControl {
+    percent: u32,
+    held: bool,  // the slow-down key is down
+    fast: bool,  // the fast-forward key is down
+    credit: f32, // fraction of a frame owed, carried over to the next picture
+}
+
+impl SpeedControl {
+    pub fn new(percent: u32) -> Self {
+        SpeedControl { percent: percent.clamp(MIN_PERCENT, MAX_PERCENT), held: false, fast: false, credit: 0.0 }
+    }
+
+    pub fn percent(&self) -> u32 {
+        self.percent
+    }
+
+    // Sets the persistent speed, within MIN_PERCENT..=MAX_PERCENT, returns it.
+    pub fn set(&mut self, percent: u32) -> u32 {
+        self.percent = percent.clamp(MIN_PERCENT, MAX_PERCENT);
+        self.percent
+    }
+
+    // Changes the persistent speed by `steps` STEP_PERCENTs (negative: slower), returns it.
+    pub fn adjust(&mut self, steps: i32) -> u32 {
+        // saturating: a console or remote can ask for any number of steps
+        let percent = (self.percent as i32).saturating_add(steps.saturating_mul(STEP_PERCENT as i32));
+        self.percent = (percent.max(0) as u32).clamp(MIN_PERCENT, MAX_PERCENT);
+        self.percent
+    }
+
+    pub fn hold_slow(&mut self, held: bool) {
+        self.held = held;
+    }
+
+    pub fn hold_fast(&mut self, fast: bool) {
+        self.fast = fast;
+    }
+
+    // The speed right now, as a fraction of full speed.
+    pub fn current(&self) -> f32 {
+        if self.fast {
+            return FAST_FORWARD as f32;
+        }
+        let speed = self.percent as f32 / 100.0;
+        if self.held {
+            speed / 2.0
+        } else {
+            speed
+        }
+    }
+
+    // How many frames to run before showing the next picture (0 or 1, FAST_FORWARD while
+    // fast-forwarding); call once per picture.
+    pub fn frames_to_run(&mut self) -> u32 {
+        self.credit += self.current();
+        let frames = self.credit as u32;
+        self.credit -= frames as f32;
+        frames
+    }
+}
+
+pub fn speed_path(rom_path: impl AsRef<Path>) -> PathBuf {
+    rom_path.as_ref().with_extension("speed")
+}
+
+// The game's saved speed, None if it was never changed.
+pub fn load(storage: &dyn Storage, path: impl AsRef<Path>) -> io::Result<Option<u32>> {
+    let Some(data) = storage.read(path.as_ref())? else {
+        return Ok(None);
+    };
+    let text = String::from_utf8_lossy(&data);
+    match text.trim().trim_end_matches('%').parse() {
+        Ok(percent) => Ok(Some(percent)),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("not a speed: '{}'", text.trim()))),
+    }
+}
+
+// Full speed is the default, so it removes the file instead of writing one.
+pub fn save(storage: &dyn Storage, path: impl AsRef<Path>, percent: u32) -> io::Result<()> {
+    if percent >= MAX_PERCENT {
+        return storage.remove(path.as_ref());
+    }
+    storage.write(path.as_ref(), format!("{}%\n", percent).as_bytes())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn frames_in(speed: &mut SpeedControl, pictures: usize) -> u32 {
+        (0..pictures).map(|_| speed.frames_to_run()).sum()
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut speed = SpeedControl::new(100);
+        assert_eq!(frames_in(&mut speed, 60), 60);
+
+        assert_eq!(speed.adjust(-5), 75);
+        assert_eq!(frames_in(&mut speed, 60), 45);
+
+        speed.hold_slow(true); // half of 75%
+        assert_eq!(frames_in(&mut speed, 80), 30);
+        speed.hold_slow(false);
+        assert_eq!(frames_in(&mut speed, 4), 3);
+
+        speed.hold_fast(true); // wins over both
+        speed.hold_slow(true);
+        assert_eq!(speed.frames_to_run(), FAST_FORWARD);
+        speed.hold_fast(false);
+        speed.hold_slow(false);
+
+        assert_eq!(speed.adjust(-100), MIN_PERCENT);
+        assert_eq!(speed.adjust(100), MAX_PERCENT);
+        assert_eq!(speed.adjust(i32::MAX), MAX_PERCENT);
+        assert_eq!(speed.adjust(i32::MIN), MIN_PERCENT);
+        assert_eq!(SpeedControl::new(400).percent(), MAX_PERCENT);
+    }
+
+    #[test]
+    fn test_saved_per_game() {
+        let storage = MemoryStorage::new();
+        let path = speed_path("roms/game.nes");
+        assert_eq!(path, Path::new("roms/game.speed"));
+
+        assert_eq!(load(&storage, &path).unwrap(), None);
+        save(&storage, &path, 60).unwrap();
+        assert_eq!(load(&storage, &path).unwrap(), Some(60));
+        save(&storage, &path, 100).unwrap(); // back to the default: nothing to remember
+        assert_eq!(storage.read(&path).unwrap(), None);
+    }
+}
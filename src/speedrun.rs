@@ -0,0 +1,336 @@
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::nes::Nes;
+use crate::pacing::FRAME_RATE;
+use crate::ppu::NesPPU;
+use crate::ram_watch::{self, Rule};
+use crate::render::compositor::Stage;
+use crate::render::frame::Frame;
+use crate::render::osd;
+
+// A speedrun timer that starts and splits by itself, from what the game keeps in RAM (see
+// crate::ram_watch), so the runner doesn't have to press anything and the splits land on the
+// same frame every time. The splits come from a text file (--splits), one per line as
+// `name: rule`, in the order they're reached:
+//
+//   # Super Mario Bros., warpless
+//   category: Warpless
+//   start: $0770 = 1       the title screen is left
+//   1-1: $0760 changes     the level number goes up
+//   1-2: $0760 changes
+//
+// The `start` rule starts a run, and starts it again whenever it fires (the runner reset the
+// game). Only the next split's rule counts; the last one ends the run. The time is the game's
+// time, the frames emulated at the console's FRAME_RATE, so slowing the game down (see
+// crate::speed) or a stall of the window doesn't count against the runner.
+//
+// When the console jumps (a state loaded, a reset: see Nes::jumps) the rules forget the byte
+// they saw last, which belongs to the timeline just left: loading a state from the middle of a
+// run mustn't look like the game starting it.
+//
+// The best run and the best time for each segment are kept for the session, and go to a
+// LiveSplit splits file (game.lss) on request, to carry on there or compare with other runs.
+// See: https://github.com/LiveSplit/LiveSplit
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    pub name: String,
+    pub rule: Rule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Splits {
+    pub category: String,
+    pub start: Rule,
+    pub splits: Vec<Split>,
+}
+
+pub fn parse_splits(text: &str) -> Result<Splits, String> {
+    let (mut category, mut start, mut splits) = (String::new(), None, vec![]);
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |err: String| format!("line {}: {}", number + 1, err);
+        let (name, rule) = line.split_once(':').ok_or_else(|| error(format!("'{}' is not 'name: rule'", line)))?;
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("category") {
+            category = rule.trim().to_string();
+            continue;
+        }
+        let rule = Rule::parse(rule).map_err(error)?;
+        if name.eq_ignore_ascii_case("start") {
+            start = Some(rule);
+        } else {
+            splits.push(Split { name: name.to_string(), rule });
+        }
+    }
+    let start = start.ok_or("no 'start' rule")?;
+    if splits.is_empty() {
+        return Err("no splits".to_string());
+    }
+    Ok(Splits { category, start, splits })
+}
+
+pub struct SpeedrunTimer {
+    splits: Splits,
+    frame: u64,                      // the last frame seen
+    jumps: u64,                      // the console's jumps at the last frame seen
+    run: Option<(u64, Vec<u64>)>,    // the frame it started, and the time of each split reached
+    attempts: u32,
+    best_run: Option<Vec<u64>>,      // the fastest finished run
+    best_segments: Vec<Option<u64>>, // by split, from any run
+}
+
+impl SpeedrunTimer {
+    pub fn new(splits: Splits) -> Self {
+        let best_segments = vec![None; splits.splits.len()];
+        SpeedrunTimer { splits, frame: 0, jumps: 0, run: None, attempts: 0, best_run: None, best_segments }
+    }
+
+    // Once a frame, after it ran: checks the rules. Returns what happened, for the OSD.
+    pub fn update(&mut self, nes: &Nes) -> Option<String> {
+        self.frame = nes.frame_count();
+        if nes.jumps() != self.jumps {
+            self.jumps = nes.jumps();
+            self.splits.start.forget();
+            self.splits.splits.iter_mut().for_each(|split| split.rule.forget());
+        }
+        // every rule looks at every frame, so the next split's knows what came before it
+        let started = self.splits.start.check(ram_watch::peek(nes, self.splits.start.addr));
+        let fired: Vec<bool> = self
+            .splits
+            .splits
+            .iter_mut()
+            .map(|split| split.rule.check(ram_watch::peek(nes, split.rule.addr)))
+            .collect();
+
+        if started {
+            self.run = Some((self.frame, vec![]));
+            self.attempts += 1;
+            return Some("Timer started".to_string());
+        }
+        let (start, times) = self.run.as_mut()?;
+        let next = times.len();
+        if next == self.splits.splits.len() || !fired[next] {
+            return None;
+        }
+        let time = self.frame - *start;
+        let segment = time - times.last().copied().unwrap_or(0);
+        times.push(time);
+        let best = &mut self.best_segments[next];
+        *best = Some(best.map_or(segment, |best| best.min(segment)));
+        if times.len() < self.splits.splits.len() {
+            return Some(format!("{} {}", self.splits.splits[next].name, format_time(time)));
+        }
+        if self.best_run.as_ref().is_none_or(|best| time < *best.last().unwrap()) {
+            self.best_run = Some(times.clone());
+            return Some(format!("Finished in {}, a new best", format_time(time)));
+        }
+        Some(format!("Finished in {}", format_time(time)))
+    }
+
+    // Stops the run; the next `start` begins another.
+    pub fn reset(&mut self) {
+        self.run = None;
+    }
+
+    // Frames since the run started, until it finished; None between runs.
+    pub fn elapsed(&self) -> Option<u64> {
+        let (start, times) = self.run.as_ref()?;
+        if times.len() == self.splits.splits.len() {
+            return times.last().copied();
+        }
+        Some(self.frame - start)
+    }
+
+    // The last split reached in this run, and its time.
+    pub fn last_split(&self) -> Option<(&str, u64)> {
+        let (_, times) = self.run.as_ref()?;
+        let time = *times.last()?;
+        Some((&self.splits.splits[times.len() - 1].name, time))
+    }
+
+    // The splits as a LiveSplit .lss file: the best run as the personal best, and the best
+    // segments. `game` is the game's name.
+    pub fn to_livesplit(&self, game: &str) -> String {
+        let mut xml = String::new();
+        let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let _ = writeln!(xml, r#"<Run version="1.7.0">"#);
+        let _ = writeln!(xml, "  <GameIcon />\n  <GameName>{}</GameName>", escape(game));
+        let _ = writeln!(xml, "  <CategoryName>{}</CategoryName>", escape(&self.splits.category));
+        let _ = writeln!(xml, "  <Offset>00:00:00</Offset>\n  <AttemptCount>{}</AttemptCount>", self.attempts);
+        let _ = writeln!(xml, "  <AttemptHistory />\n  <Segments>");
+        for (i, split) in self.splits.splits.iter().enumerate() {
+            let _ = writeln!(xml, "    <Segment>\n      <Name>{}</Name>\n      <Icon />", escape(&split.name));
+            let _ = writeln!(xml, "      <SplitTimes>\n        <SplitTime name=\"Personal Best\">");
+            if let Some(best) = &self.best_run {
+                let _ = writeln!(xml, "{}", times_xml(best[i], "          "));
+            }
+            let _ = writeln!(xml, "        </SplitTime>\n      </SplitTimes>\n      <BestSegmentTime>");
+            if let Some(best) = self.best_segments[i] {
+                let _ = writeln!(xml, "{}", times_xml(best, "        "));
+            }
+            let _ = writeln!(xml, "      </BestSegmentTime>\n      <SegmentHistory />\n    </Segment>");
+        }
+        let _ = writeln!(xml, "  </Segments>\n  <AutoSplitterSettings />\n</Run>");
+        xml
+    }
+}
+
+// Both of LiveSplit's timing methods: real time is game time here.
+fn times_xml(frames: u64, indent: &str) -> String {
+    let time = livesplit_time(frames);
+    format!("{indent}<RealTime>{time}</RealTime>\n{indent}<GameTime>{time}</GameTime>")
+}
+
+// 1:02.35, or 1:01:02.35 past the hour.
+pub fn format_time(frames: u64) -> String {
+    let centis = (frames as f64 / FRAME_RATE * 100.0) as u64;
+    let (hours, minutes, seconds) = (centis / 360_000, centis / 6000 % 60, centis / 100 % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}.{:02}", hours, minutes, seconds, centis % 100)
+    } else {
+        format!("{}:{:02}.{:02}", minutes, seconds, centis % 100)
+    }
+}
+
+// As .NET writes a TimeSpan: 00:01:02.3500000.
+fn livesplit_time(frames: u64) -> String {
+    let ticks = (frames as f64 / FRAME_RATE * 10_000_000.0).round() as u64; // 100ns each
+    let seconds = ticks / 10_000_000;
+    format!("{:02}:{:02}:{:02}.{:07}", seconds / 3600, seconds / 60 % 60, seconds % 60, ticks % 10_000_000)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+const TEXT: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+const SPLIT: (u8, u8, u8) = (0xFF, 0xFF, 0x00);
+const BACKGROUND: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+// The running time in the top right corner, and the last split under it. The frontend keeps
+// a handle to the timer (timer()) to update it and act on it; the overlay only draws it.
+pub struct TimerOverlay(Rc<RefCell<SpeedrunTimer>>);
+
+impl TimerOverlay {
+    pub fn new(timer: SpeedrunTimer) -> Self {
+        TimerOverlay(Rc::new(RefCell::new(timer)))
+    }
+
+    pub fn timer(&self) -> Rc<RefCell<SpeedrunTimer>> {
+        self.0.clone()
+    }
+}
+
+impl Stage for TimerOverlay {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn apply(&mut self, _ppu: &NesPPU, frame: &mut Frame) {
+        let timer = self.0.borrow();
+        let time = format_time(timer.elapsed().unwrap_or(0));
+        let mut lines = vec![(time, TEXT)];
+        if let Some((name, time)) = timer.last_split() {
+            lines.push((format!("{} {}", name, format_time(time)), SPLIT));
+        }
+        let mut y = osd::MARGIN;
+        for (line, rgb) in lines {
+            let width = (line.len() * osd::ADVANCE + osd::SCALE).min(Frame::WIDTH - osd::MARGIN);
+            let x = Frame::WIDTH - osd::MARGIN - width;
+            osd::fill(frame, x, y, width, osd::LINE_HEIGHT, BACKGROUND);
+            osd::draw_text(frame, x + osd::SCALE, y + osd::SCALE, &line, rgb);
+            y += osd::LINE_HEIGHT;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    const SPLITS: &str = "category: Any%\nstart: $0010 = 1\n1-1: $0011 changes  # the level\nEnd: $0012 = 1\n";
+
+    #[test]
+    fn test_parse_splits() {
+        let splits = parse_splits(SPLITS).unwrap();
+        assert_eq!(splits.category, "Any%");
+        assert_eq!(splits.start.addr, 0x10);
+        assert_eq!(splits.splits.iter().map(|split| split.name.as_str()).collect::<Vec<_>>(), ["1-1", "End"]);
+        assert_eq!(parse_splits("1-1: $0011 changes").unwrap_err(), "no 'start' rule");
+        assert_eq!(parse_splits("start: $0010 = 1").unwrap_err(), "no splits");
+        assert!(parse_splits("start: $0010 = 1\n1-1 $0011 changes").unwrap_err().starts_with("line 2: "));
+    }
+
+    #[test]
+    fn test_runs() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let mut timer = SpeedrunTimer::new(parse_splits(SPLITS).unwrap());
+        let frame = |nes: &mut Nes, timer: &mut SpeedrunTimer, ram: [u8; 3]| {
+            nes.run_frame();
+            nes.ram_mut()[0x10..0x13].copy_from_slice(&ram);
+            timer.update(nes)
+        };
+
+        frame(&mut nes, &mut timer, [0, 0, 0]);
+        assert_eq!(frame(&mut nes, &mut timer, [0, 0, 0]), None);
+        assert_eq!(frame(&mut nes, &mut timer, [0, 0, 1]), None); // not started: no split
+        assert_eq!(frame(&mut nes, &mut timer, [1, 0, 0]).as_deref(), Some("Timer started"));
+        for _ in 0..59 {
+            frame(&mut nes, &mut timer, [1, 0, 0]);
+        }
+        assert_eq!(timer.elapsed(), Some(59));
+        assert_eq!(frame(&mut nes, &mut timer, [1, 1, 0]).as_deref(), Some("1-1 0:00.99"));
+        assert_eq!(timer.last_split(), Some(("1-1", 60)));
+        assert_eq!(frame(&mut nes, &mut timer, [1, 1, 1]).as_deref(), Some("Finished in 0:01.01, a new best"));
+        frame(&mut nes, &mut timer, [1, 1, 1]);
+        assert_eq!(timer.elapsed(), Some(61)); // stopped
+
+        // the game is reset and started again
+        frame(&mut nes, &mut timer, [0, 0, 0]);
+        assert_eq!(frame(&mut nes, &mut timer, [1, 0, 0]).as_deref(), Some("Timer started"));
+        frame(&mut nes, &mut timer, [1, 1, 0]);
+        for _ in 0..69 {
+            frame(&mut nes, &mut timer, [1, 1, 0]);
+        }
+        assert_eq!(frame(&mut nes, &mut timer, [1, 1, 1]).as_deref(), Some("Finished in 0:01.18"));
+        timer.reset();
+        assert_eq!(timer.elapsed(), None);
+
+        let lss = timer.to_livesplit("Test <1>");
+        assert!(lss.contains("<GameName>Test &lt;1&gt;</GameName>"));
+        assert!(lss.contains("<AttemptCount>2</AttemptCount>"));
+        // personal best: the first run; best segments: the second run's
+        assert!(lss.contains("<RealTime>00:00:01.0149953</RealTime>"));
+        assert!(lss.contains("<RealTime>00:00:00.0166393</RealTime>"));
+        assert_eq!(format_time(60 * 60 * 61), "1:00:53.98");
+    }
+
+    #[test]
+    fn test_forgets_across_loads() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let mut timer = SpeedrunTimer::new(parse_splits(SPLITS).unwrap());
+        let frame = |nes: &mut Nes, timer: &mut SpeedrunTimer, ram: [u8; 3]| {
+            nes.run_frame();
+            nes.ram_mut()[0x10..0x13].copy_from_slice(&ram);
+            timer.update(nes)
+        };
+
+        frame(&mut nes, &mut timer, [0, 0, 0]);
+        assert_eq!(frame(&mut nes, &mut timer, [1, 0, 0]).as_deref(), Some("Timer started"));
+        let state = nes.save_state();
+        frame(&mut nes, &mut timer, [0, 0, 0]); // back on the title screen
+        nes.load_state(&state).unwrap();
+        assert_eq!(frame(&mut nes, &mut timer, [1, 0, 0]), None); // not 0 then 1: another timeline
+
+        frame(&mut nes, &mut timer, [1, 5, 0]);
+        nes.reset();
+        assert_eq!(frame(&mut nes, &mut timer, [1, 6, 0]), None); // no split for the change
+        assert_eq!(nes.jumps(), 2);
+    }
+}
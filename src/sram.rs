@@ -0,0 +1,189 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::nes::Nes;
+use crate::storage::Storage;
+
+// Battery-backed PRG-RAM ("SRAM") is how cartridges like Zelda keep save games with the power
+// off. We keep it in a .sav file next to the ROM (game.nes -> game.sav), the same name and raw
+// layout FCEUX, Mesen and Nestopia use: the whole $6000-$7FFF image, byte for byte, with no
+// header. Saves from them load as they are, and `sram import`/`sram export` in the console
+// move one in or out under any name.
+//
+// The image is as big as the board's RAM (see Rom::prg_ram_size), which is where tools differ
+// for boards with a small chip: some keep a 2KiB or 4KiB file, others the 8KiB window the chip
+// shows up repeated across (its upper address lines aren't connected). fit() takes either way
+// round, mirroring a small file or keeping the start of a big one; anything else of the wrong
+// size is refused rather than loaded into the wrong place.
+//
+// A few boards save elsewhere: Bandai's in a serial EEPROM, self-flashing homebrew in its own
+// PRG flash (see crate::mapper::SaveDevice). Their battery flag stands for that device, which
+// is kept instead, in its own file: game.eeprom (the raw chip) or game.flash (the whole chip
+// image).
+
+pub fn sav_path(rom_path: impl AsRef<Path>) -> PathBuf {
+    rom_path.as_ref().with_extension("sav")
+}
+
+// Where the game's save file goes: game.sav, or the save device's file.
+pub fn save_path(nes: &Nes, rom_path: impl AsRef<Path>) -> PathBuf {
+    match nes.save_device() {
+        Some(device) => rom_path.as_ref().with_extension(device.extension()),
+        None => sav_path(rom_path),
+    }
+}
+
+// Returns false if the cartridge keeps nothing or there is no save file yet.
+pub fn load(storage: &dyn Storage, nes: &mut Nes, path: impl AsRef<Path>) -> io::Result<bool> {
+    if !keeps_saves(nes) {
+        return Ok(false);
+    }
+    let Some(data) = storage.read(path.as_ref())? else {
+        return Ok(false);
+    };
+    restore(nes, &data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(true)
+}
+
+// Does nothing for cartridges that keep nothing.
+pub fn save(storage: &dyn Storage, nes: &Nes, path: impl AsRef<Path>) -> io::Result<()> {
+    match saved_memory(nes) {
+        Some(data) => storage.write(path.as_ref(), &data),
+        None => Ok(()),
+    }
+}
+
+// A save file brought in from elsewhere, as the console's `sram import`. Unlike load, a
+// missing file or a cartridge that keeps nothing is an error: the user asked for it.
+pub fn import(storage: &dyn Storage, nes: &mut Nes, path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    if !keeps_saves(nes) {
+        return Err(NOTHING_KEPT.to_string());
+    }
+    let data = storage.read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let data = data.ok_or_else(|| format!("{}: no such file", path.display()))?;
+    restore(nes, &data).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+pub fn export(storage: &dyn Storage, nes: &Nes, path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let data = saved_memory(nes).ok_or(NOTHING_KEPT)?;
+    storage.write(path, &data).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+const NOTHING_KEPT: &str = "this cartridge has no battery-backed RAM or save device";
+
+fn keeps_saves(nes: &Nes) -> bool {
+    nes.save_device().is_some() || nes.has_battery()
+}
+
+fn saved_memory(nes: &Nes) -> Option<Vec<u8>> {
+    if nes.save_device().is_some() {
+        Some(nes.save_device_data())
+    } else if nes.has_battery() {
+        Some(nes.prg_ram().to_vec())
+    } else {
+        None
+    }
+}
+
+fn restore(nes: &mut Nes, data: &[u8]) -> Result<(), String> {
+    if nes.save_device().is_some() {
+        return nes.load_save_device(data);
+    }
+    let ram = nes.prg_ram_mut();
+    let image = fit(data, ram.len())?;
+    ram.copy_from_slice(&image);
+    Ok(())
+}
+
+// The `size` byte image a save file stands for: the file itself, a smaller chip's worth
+// mirrored across it, or the first `size` bytes of the 8KiB window.
+pub fn fit(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mirrors = data.len() >= 1024
+        && data.len() < size
+        && data.len().is_power_of_two()
+        && size.is_multiple_of(data.len());
+    let window = data.len() == 0x2000 && size < data.len() && data.len().is_multiple_of(size);
+    if data.len() == size {
+        Ok(data.to_vec())
+    } else if mirrors {
+        Ok(data.repeat(size / data.len()))
+    } else if window {
+        Ok(data[..size].to_vec())
+    } else {
+        Err(format!("is {} bytes, expected a {} byte save RAM image", data.len(), size))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::storage::{FileStorage, MemoryStorage};
+
+    // NROM with the battery flag set, spinning on `JMP $8000`.
+    fn battery_rom() -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0b0000_0010, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xEA; 0x4000];
+        prg[0..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        raw
+    }
+
+    #[test]
+    fn test_sav_roundtrip() {
+        let path = std::env::temp_dir().join(format!("runesco-sram-test-{}.sav", std::process::id()));
+
+        let mut nes = Nes::new(&battery_rom()).unwrap();
+        assert!(nes.has_battery());
+        nes.prg_ram_mut()[0x10] = 0x42;
+        save(&FileStorage, &nes, &path).unwrap();
+
+        let mut reloaded = Nes::new(&battery_rom()).unwrap();
+        assert!(load(&FileStorage, &mut reloaded, &path).unwrap());
+        assert_eq!(reloaded.prg_ram()[0x10], 0x42);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!load(&FileStorage, &mut reloaded, &path).unwrap());
+    }
+
+    #[test]
+    fn test_import_checks_the_size() {
+        let storage = MemoryStorage::new();
+        let mut nes = Nes::new(&battery_rom()).unwrap();
+
+        let mut chip = vec![0; 0x800];
+        chip[0x10] = 0x42;
+        storage.write(Path::new("2k.sav"), &chip).unwrap();
+        import(&storage, &mut nes, "2k.sav").unwrap();
+        assert_eq!(nes.prg_ram()[0x10], 0x42);
+        assert_eq!(nes.prg_ram()[0x1810], 0x42); // mirrored
+
+        storage.write(Path::new("odd.sav"), &[0; 0x2001]).unwrap();
+        assert_eq!(
+            import(&storage, &mut nes, "odd.sav"),
+            Err("odd.sav: is 8193 bytes, expected a 8192 byte save RAM image".to_string())
+        );
+        assert!(import(&storage, &mut nes, "missing.sav").is_err());
+        assert_eq!(nes.prg_ram()[0x10], 0x42);
+
+        export(&storage, &nes, "out.sav").unwrap();
+        assert_eq!(storage.read(Path::new("out.sav")).unwrap().unwrap().len(), 0x2000);
+    }
+
+    #[test]
+    fn test_fit_window_to_small_chip() {
+        let mut window = vec![0; 0x800].repeat(4);
+        window[0x10] = 0x42;
+        assert_eq!(fit(&window, 0x800).unwrap().len(), 0x800);
+        assert_eq!(fit(&window, 0x800).unwrap()[0x10], 0x42);
+        assert!(fit(&window, 0x600).is_err());
+    }
+
+    #[test]
+    fn test_sav_path() {
+        assert_eq!(sav_path("roms/zelda.nes"), PathBuf::from("roms/zelda.sav"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::cmp::Ordering;
+
+use crate::cpu::CPU;
+use crate::render::frame::Frame;
+use crate::render::osd::{draw_text, ADVANCE, LINE_HEIGHT, MARGIN, SCALE};
+use crate::trace::{self, TraceEntry};
+
+// The step view (Pause in the window, or --step-view to start in it): the game stops and the
+// CPU is shown over its picture, one instruction per press of Space. For learning 6502
+// assembly on real programs: the registers and flags, the instructions just run and the ones
+// coming up, and the stack, all changing as each instruction runs.
+//
+//   A:00 X:00 Y:00 SP:FD PC:C001
+//   P:24 NV-BDIZC CYC 7            the flags that are set light up
+//
+//     C000 SEI                     the last PAST instructions run
+//   > C001 CLD                     the one about to run
+//     C002 LDX #$FF                and the NEXT after it, as laid out in memory
+//
+//   STACK                          page 1, where SP points and below: what's been pushed
+//   01E0 00 00 00 00 00 00 00 00   lights up
+//
+// The instructions coming up are read out of memory from PC on, so after a jump or a branch
+// the list is where the CPU would go if it didn't take it; it's right again on the next step.
+
+pub const PAST: usize = 2;
+pub const NEXT: usize = 6; // counting the one about to run
+const STACK_ROWS: usize = 4; // of 8 bytes
+
+const TEXT: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+const DIM: (u8, u8, u8) = (0x70, 0x70, 0x70);
+const CURRENT: (u8, u8, u8) = (0xFF, 0xFF, 0x00); // yellow
+
+const FLAGS: &str = "NV-BDIZC"; // bit 7 first
+
+pub struct StepView {
+    now: TraceEntry,          // registers, and where PC is
+    past: Vec<TraceEntry>,    // oldest first
+    next: Vec<(u16, String)>, // from PC on: address and instruction
+    stack: [u8; 0x100],       // $0100-$01FF
+}
+
+impl StepView {
+    // `past` is the CPU's recent history (see Nes::history), oldest first; the last PAST of it
+    // are shown.
+    pub fn capture(cpu: &CPU, past: &[TraceEntry]) -> Self {
+        let mut next = Vec::with_capacity(NEXT);
+        let mut addr = cpu.program_counter;
+        for _ in 0..NEXT {
+            let bytes = [0, 1, 2].map(|i| cpu.bus.peek(addr.wrapping_add(i)));
+            let (text, len) = trace::disassemble(addr, bytes);
+            next.push((addr, text));
+            addr = addr.wrapping_add(len);
+        }
+        StepView {
+            now: TraceEntry::capture(cpu),
+            past: past[past.len().saturating_sub(PAST)..].to_vec(),
+            next,
+            stack: std::array::from_fn(|i| cpu.bus.peek(0x100 + i as u16)),
+        }
+    }
+
+    // The instruction listing, as drawn: past, current (marked ">"), next.
+    pub fn listing(&self) -> Vec<String> {
+        let past = self.past.iter().map(|entry| (entry.pc, trace::disassemble(entry.pc, entry.bytes).0));
+        let line = |(i, (addr, text)): (usize, (u16, String))| {
+            let marker = if i == self.past.len() { ">" } else { " " };
+            format!("{} {:04X} {}", marker, addr, text)
+        };
+        past.chain(self.next.iter().cloned()).enumerate().map(line).collect()
+    }
+
+    // The first stack row shown: the one SP points into, unless that leaves too few below it.
+    fn stack_start(&self) -> usize {
+        ((self.now.sp as usize + 1).min(0xFF) & !7).min(0x100 - STACK_ROWS * 8)
+    }
+
+    // Dims the picture and draws the CPU over it.
+    pub fn draw(&self, frame: &mut Frame) {
+        for byte in frame.data.iter_mut() {
+            *byte /= 4;
+        }
+        let left = MARGIN + SCALE;
+        let column = |i: usize| left + i * ADVANCE;
+        let mut y = MARGIN;
+
+        let now = &self.now;
+        let registers = format!("A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} PC:{:04X}", now.a, now.x, now.y, now.sp, now.pc);
+        draw_text(frame, left, y, &registers, TEXT);
+        y += LINE_HEIGHT;
+        draw_text(frame, left, y, &format!("P:{:02X}", now.p), TEXT);
+        for (i, flag) in FLAGS.chars().enumerate() {
+            let set = now.p >> (7 - i) & 1 == 1;
+            draw_text(frame, column(5 + i), y, &flag.to_string(), if set { TEXT } else { DIM });
+        }
+        draw_text(frame, column(14), y, &format!("CYC {}", now.cycles), TEXT);
+        y += LINE_HEIGHT * 2;
+
+        for (i, text) in self.listing().iter().enumerate() {
+            let rgb = match i.cmp(&self.past.len()) {
+                Ordering::Less => DIM,
+                Ordering::Equal => CURRENT,
+                Ordering::Greater => TEXT,
+            };
+            draw_text(frame, left, y, text, rgb);
+            y += LINE_HEIGHT;
+        }
+
+        y += LINE_HEIGHT;
+        draw_text(frame, left, y, "STACK", TEXT);
+        y += LINE_HEIGHT;
+        let start = self.stack_start();
+        for row in 0..STACK_ROWS {
+            let offset = start + row * 8;
+            draw_text(frame, left, y, &format!("01{:02X}", offset), DIM);
+            for i in 0..8 {
+                let pushed = offset + i > now.sp as usize;
+                let byte = format!("{:02X}", self.stack[offset + i]);
+                draw_text(frame, column(5 + i * 3), y, &byte, if pushed { TEXT } else { DIM });
+            }
+            y += LINE_HEIGHT;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_listing_follows_the_cpu() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let start = nes.cpu().program_counter;
+        let past: Vec<TraceEntry> = nes.history().iter().copied().collect();
+        let view = StepView::capture(nes.cpu(), &past);
+        let listing = view.listing();
+        assert_eq!(listing.len(), NEXT); // nothing run yet
+        assert_eq!(listing[0], format!("> {:04X} SEI", start));
+
+        for _ in 0..3 {
+            nes.step_instruction();
+        }
+        let past: Vec<TraceEntry> = nes.history().iter().copied().collect();
+        let view = StepView::capture(nes.cpu(), &past);
+        let listing = view.listing();
+        assert_eq!(listing.len(), PAST + NEXT);
+        assert_eq!(listing[PAST - 1], listing_line(&past[2]));
+        assert!(listing[PAST].starts_with(&format!("> {:04X} ", nes.cpu().program_counter)));
+
+        let mut frame = Frame::new();
+        frame.data.fill(0x80);
+        view.draw(&mut frame);
+        assert!(frame.data.contains(&0x20) && frame.data.contains(&0xFF)); // dimmed, and text
+    }
+
+    fn listing_line(entry: &TraceEntry) -> String {
+        format!("  {:04X} {}", entry.pc, trace::disassemble(entry.pc, entry.bytes).0)
+    }
+
+    #[test]
+    fn test_stack_rows() {
+        let mut nes = Nes::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        let view = |nes: &mut Nes, sp: u8| {
+            nes.cpu().stack_pointer = sp;
+            StepView::capture(nes.cpu(), &[]).stack_start()
+        };
+        assert_eq!(view(&mut nes, 0xFD), 0xE0); // near the top: the last rows
+        assert_eq!(view(&mut nes, 0xFF), 0xE0); // empty
+        assert_eq!(view(&mut nes, 0x9A), 0x98);
+        assert_eq!(view(&mut nes, 0x00), 0x00);
+    }
+}
@@ -0,0 +1,93 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where battery saves, savestates and settings are kept. The desktop frontend uses files
+// (FileStorage); a browser build would keep them in localStorage or IndexedDB and a libretro
+// core in the directories its frontend hands out, by implementing Storage over those. Code
+// that persists anything takes a &dyn Storage instead of calling std::fs.
+//
+// Entries are named by the paths the desktop frontend uses (game.sav next to game.nes,
+// input.toml); other backends are free to treat them as plain keys.
+
+pub trait Storage {
+    // Ok(None) if nothing is stored under `path`.
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>>;
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+    // Removing something that isn't there is not an error.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+}
+
+pub struct FileStorage;
+
+impl Storage for FileStorage {
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        std::fs::write(path, data)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Keeps everything in memory, gone when dropped: for tests, and for embedders that persist
+// nothing (or save the whole map their own way).
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: RefCell<HashMap<PathBuf, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.entries.borrow().get(path).cloned())
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.entries.borrow_mut().insert(path.to_path_buf(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.entries.borrow_mut().remove(path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn round_trip(storage: &dyn Storage, path: &Path) {
+        assert_eq!(storage.read(path).unwrap(), None);
+        storage.write(path, b"saved").unwrap();
+        assert_eq!(storage.read(path).unwrap().as_deref(), Some(&b"saved"[..]));
+        storage.remove(path).unwrap();
+        assert_eq!(storage.read(path).unwrap(), None);
+        storage.remove(path).unwrap(); // already gone
+    }
+
+    #[test]
+    fn test_backends() {
+        round_trip(&MemoryStorage::new(), Path::new("game.sav"));
+        let path = std::env::temp_dir().join(format!("runesco-storage-{}.sav", std::process::id()));
+        round_trip(&FileStorage, &path);
+    }
+}
@@ -0,0 +1,62 @@
+// Reading results from test ROMs without looking at the screen. blargg's test ROMs (and most
+// newer ones that copy their convention) report through PRG-RAM:
+//
+//   $6001-$6003  DE B0 61 once the rest is valid
+//   $6000        status: $80 still running, $81 needs the reset button pressed, anything
+//                else is the final result (0: passed, other values: the failing test's code)
+//   $6004-       the text the ROM prints on screen, NUL-terminated
+//
+// See: https://github.com/christopherpow/nes-test-roms (the readme of any blargg suite)
+
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const MAX_TEXT: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestStatus {
+    pub code: u8,
+    pub text: String,
+}
+
+impl TestStatus {
+    // `prg_ram` is $6000-$7FFF. None if no test result is there.
+    pub fn read(prg_ram: &[u8]) -> Option<TestStatus> {
+        if prg_ram.len() < 4 || prg_ram[1..4] != SIGNATURE {
+            return None;
+        }
+        let text: Vec<u8> = prg_ram[4..].iter().take(MAX_TEXT).take_while(|&&b| b != 0).copied().collect();
+        Some(TestStatus { code: prg_ram[0], text: String::from_utf8_lossy(&text).into_owned() })
+    }
+
+    pub fn running(&self) -> bool {
+        self.code == 0x80
+    }
+
+    pub fn needs_reset(&self) -> bool {
+        self.code == 0x81
+    }
+
+    pub fn passed(&self) -> bool {
+        self.code == 0
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let mut prg_ram = vec![0; 0x2000];
+        assert_eq!(TestStatus::read(&prg_ram), None);
+
+        prg_ram[0] = 0x80;
+        prg_ram[1..4].copy_from_slice(&SIGNATURE);
+        assert!(TestStatus::read(&prg_ram).unwrap().running());
+
+        prg_ram[0] = 3;
+        prg_ram[4..4 + 11].copy_from_slice(b"Failed #3\n\0");
+        let status = TestStatus::read(&prg_ram).unwrap();
+        assert!(!status.passed() && !status.running());
+        assert_eq!(status.text, "Failed #3\n");
+    }
+}
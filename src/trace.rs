@@ -5,6 +5,7 @@ use crate::cpu::Mem;
 use crate::cpu::CPU;
 use crate::opcodes;
 use std::collections::HashMap;
+use std::fmt;
 
 pub fn trace(cpu: &mut CPU) -> String {
     let ref opscodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
@@ -125,13 +126,112 @@ pub fn trace(cpu: &mut CPU) -> String {
         .trim()
         .to_string();
 
+    format_line(&asm_str, &TraceEntry::capture(cpu))
+}
+
+// same layout as the reference nestest.log, so the two can be diffed line by line
+fn format_line(asm_str: &str, state: &TraceEntry) -> String {
     format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
-        asm_str, cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer,
+        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{:3},{:3} CYC:{}",
+        asm_str, state.a, state.x, state.y, state.p, state.sp, state.scanline, state.dot, state.cycles,
     )
     .to_ascii_uppercase()
 }
 
+// The CPU state right before an instruction runs, captured without formatting anything or
+// touching I/O registers, so it is cheap enough to record for every instruction. It is turned
+// into a trace line only when displayed. By then memory has moved on, so unlike trace() the
+// line shows operands as written ("LDA $0200,X"), not what they resolved to.
+// It is also what Nes::set_instruction_hook hands out, under the name CpuSnapshot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub bytes: [u8; 3], // opcode and (up to) two operand bytes
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub scanline: u16,
+    pub dot: usize,
+    pub cycles: usize,
+}
+
+pub type CpuSnapshot = TraceEntry;
+
+impl TraceEntry {
+    pub fn capture(cpu: &CPU) -> Self {
+        let pc = cpu.program_counter;
+        let ppu = cpu.bus.ppu();
+        TraceEntry {
+            pc,
+            bytes: [cpu.bus.peek(pc), cpu.bus.peek(pc.wrapping_add(1)), cpu.bus.peek(pc.wrapping_add(2))],
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            p: cpu.status,
+            sp: cpu.stack_pointer,
+            scanline: ppu.scanline(),
+            dot: ppu.cycle(),
+            cycles: cpu.bus.cycles(),
+        }
+    }
+}
+
+// An instruction's operand as written, from its bytes and where it is (for branches).
+fn operand(ops: &opcodes::OpCode, pc: u16, bytes: [u8; 3]) -> String {
+    let byte = bytes[1];
+    let word = u16::from_le_bytes([bytes[1], bytes[2]]);
+    match ((ops.len as usize).clamp(1, 3), &ops.mode) {
+        (1, _) => match ops.code {
+            0x0a | 0x4a | 0x2a | 0x6a => "A".to_string(),
+            _ => String::new(),
+        },
+        (2, AddressingMode::Immediate) => format!("#${:02x}", byte),
+        (2, AddressingMode::ZeroPage) => format!("${:02x}", byte),
+        (2, AddressingMode::ZeroPage_X) => format!("${:02x},X", byte),
+        (2, AddressingMode::ZeroPage_Y) => format!("${:02x},Y", byte),
+        (2, AddressingMode::Indirect_X) => format!("(${:02x},X)", byte),
+        (2, AddressingMode::Indirect_Y) => format!("(${:02x}),Y", byte),
+        (2, _) => format!("${:04x}", pc.wrapping_add(2).wrapping_add(byte as i8 as u16)), // branch
+        (_, AddressingMode::Absolute_X) => format!("${:04x},X", word),
+        (_, AddressingMode::Absolute_Y) => format!("${:04x},Y", word),
+        (_, AddressingMode::NoneAddressing) if ops.code == 0x6c => format!("(${:04x})", word),
+        (_, _) => format!("${:04x}", word),
+    }
+}
+
+// The instruction starting with `bytes` at `pc`, as written ("LDA $0200,X"), and how many
+// bytes long it is; for listings. Opcodes that aren't in the table take one byte: "???".
+pub fn disassemble(pc: u16, bytes: [u8; 3]) -> (String, u16) {
+    match opcodes::OPCODES_MAP.get(&bytes[0]) {
+        Some(ops) => {
+            let text = format!("{} {}", ops.mnemonic, operand(ops, pc, bytes));
+            (text.trim_end().to_string(), ops.len.clamp(1, 3) as u16)
+        }
+        None => ("???".to_string(), 1),
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ops = match opcodes::OPCODES_MAP.get(&self.bytes[0]) {
+            Some(ops) => ops,
+            None => return write!(f, "{:04x}  {:02x}       ???", self.pc, self.bytes[0]),
+        };
+        let len = (ops.len as usize).clamp(1, 3);
+        let operand = operand(ops, self.pc, self.bytes);
+
+        let hex_str = self.bytes[..len]
+            .iter()
+            .map(|z| format!("{:02x}", z))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let asm_str = format!("{:04x}  {:8} {: >4} {}", self.pc, hex_str, ops.mnemonic, operand);
+        write!(f, "{}", format_line(asm_str.trim(), self))
+    }
+}
+
 /*#[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,117 @@
+// ASCII's Turbo File: battery-backed RAM in a box on the Famicom's expansion port, for the
+// games whose cartridges had no room for a battery of their own (Wizardry, Derby Stallion,
+// Best Play Pro Yakyuu...). One Turbo File holds the saves of every game that knows it. The
+// game talks to it a bit at a time through the controller port lines:
+//
+//   $4016 write: .... .CRD   D: the bit to store, R: 0 holds the address at bit 0,
+//                            C: a 1 -> 0 edge stores D at the address and moves on a bit
+//   $4017 read:  .... .B..   B: the bit at the address
+//
+// Every clock stores a bit, so a game that only reads puts back the bit it just read. The
+// bits of a byte go LSB first.
+//
+// The contents live in a file the frontend names (--turbo-file), raw, 8KiB like the original
+// Turbo File; it stays plugged in when the game changes, like the real box would.
+// See: https://www.nesdev.org/wiki/Turbo_File
+
+use crate::joypads::{InputDevice, PORT_2};
+
+pub const SIZE: usize = 0x2000;
+
+#[derive(Debug, Clone)]
+pub struct TurboFile {
+    data: Vec<u8>,
+    position: usize, // in bits
+    last_write: u8,
+}
+
+impl TurboFile {
+    pub fn new() -> Self {
+        TurboFile { data: vec![0; SIZE], position: 0, last_write: 0 }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != SIZE {
+            return Err(format!("is {} bytes, expected a {} byte Turbo File image", data.len(), SIZE));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    // What it drives on $4017.
+    pub fn bits_4017(&self) -> u8 {
+        (self.data[self.position / 8] >> (self.position % 8) & 1) << 2
+    }
+}
+
+impl InputDevice for TurboFile {
+    fn name(&self) -> &str {
+        "Turbo File"
+    }
+
+    // Every $4016 write, strobes included: the lines are the same.
+    fn write(&mut self, value: u8) {
+        if value & 0b010 == 0 {
+            self.position = 0;
+        }
+        if value & 0b100 == 0 && self.last_write & 0b100 != 0 {
+            let (byte, bit) = (self.position / 8, self.position % 8);
+            self.data[byte] = (self.data[byte] & !(1 << bit)) | (value & 1) << bit;
+            self.position = (self.position + 1) % (SIZE * 8);
+        }
+        self.last_write = value;
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr == PORT_2 {
+            self.bits_4017()
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for TurboFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // Stores `bits` from the start, the way a game does: reset, then data and clock.
+    fn store(turbo_file: &mut TurboFile, bits: &[u8]) {
+        turbo_file.write(0b000);
+        for &bit in bits {
+            turbo_file.write(0b110 | bit);
+            turbo_file.write(0b010 | bit);
+        }
+    }
+
+    #[test]
+    fn test_write_then_read_back() {
+        let mut turbo_file = TurboFile::new();
+        store(&mut turbo_file, &[1, 0, 1, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(&turbo_file.data()[0..2], [0b0000_1101, 0b1]);
+
+        // reading: each bit goes back as it was read
+        turbo_file.write(0b000);
+        let mut byte = 0;
+        for bit in 0..8 {
+            let read = turbo_file.bits_4017() >> 2;
+            byte |= read << bit;
+            turbo_file.write(0b110 | read);
+            turbo_file.write(0b010 | read);
+        }
+        assert_eq!(byte, 0b0000_1101);
+        assert_eq!(turbo_file.bits_4017(), 0b100); // the ninth bit
+
+        assert!(turbo_file.load(&[0; 100]).is_err());
+    }
+}
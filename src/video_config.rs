@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+// How the picture was shown last time, kept in the [video] section of the config file (see
+// crate::input_config) so the next run opens the same way:
+//
+//   [video]
+//   window = "100,80,768,720"   x, y, width, height
+//   fullscreen = "true"
+//   filters = "flash"           the compositor's filters that were on, by name
+//   palette = "rp2c04.pal"
+//   pacing = "vrr"              how pictures reach the display, see Pacing
+//
+// The frontend writes it when the window closes. Whatever is missing keeps its default: a 3x
+// window in the middle of the screen, the filters the command line asks for, the built-in
+// colours. Options given on the command line win over saved ones.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// How pictures are handed to the display. Either way the pacer decides when (see
+// crate::pacing); what differs is what the display does with them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Pacing {
+    #[default]
+    Timer, // no vsync: shown at once, torn wherever the display happens to be scanning
+    // vsync, for variable refresh displays (FreeSync, G-Sync): they start a refresh whenever
+    // a picture comes, so it's shown whole at the emulation's own 60.0988Hz. On a fixed 60Hz
+    // display the pictures wait for its refreshes instead, which takes the pace away from the
+    // audio device: don't use it there.
+    Vrr,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VideoConfig {
+    pub window: Option<WindowGeometry>, // as it was before going fullscreen
+    pub fullscreen: bool,
+    pub filters: Option<Vec<String>>, // None: never saved, leave them as they are
+    pub palette: Option<PathBuf>,     // None: the built-in one
+    pub pacing: Pacing,
+}
+
+impl VideoConfig {
+    // One `name = "value"` line of the section.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "window" => {
+                let numbers: Result<Vec<i64>, _> = value.split(',').map(|n| n.trim().parse()).collect();
+                let window = match numbers.as_deref().unwrap_or_default() {
+                    &[x, y, width, height] if width > 0 && height > 0 => WindowGeometry {
+                        x: x as i32,
+                        y: y as i32,
+                        width: width as u32,
+                        height: height as u32,
+                    },
+                    _ => return Err("expected the window as \"x,y,width,height\"".to_string()),
+                };
+                self.window = Some(window);
+            }
+            "fullscreen" => {
+                self.fullscreen = value.parse().map_err(|_| "expected \"true\" or \"false\"".to_string())?;
+            }
+            "filters" => self.filters = Some(value.split([' ', ',']).filter(|f| !f.is_empty()).map(String::from).collect()),
+            "palette" => self.palette = (!value.is_empty()).then(|| PathBuf::from(value)),
+            "pacing" => {
+                self.pacing = match value {
+                    "timer" => Pacing::Timer,
+                    "vrr" => Pacing::Vrr,
+                    _ => return Err("expected \"timer\" or \"vrr\"".to_string()),
+                }
+            }
+            _ => return Err("unknown video setting".to_string()),
+        }
+        Ok(())
+    }
+
+    // The section, header and all; nothing if every setting is the default.
+    pub fn to_text(&self) -> String {
+        if *self == VideoConfig::default() {
+            return String::new();
+        }
+        let mut text = String::from("\n[video]\n");
+        if let Some(window) = self.window {
+            text.push_str(&format!("window = \"{},{},{},{}\"\n", window.x, window.y, window.width, window.height));
+        }
+        if self.fullscreen {
+            text.push_str("fullscreen = \"true\"\n");
+        }
+        if let Some(filters) = &self.filters {
+            text.push_str(&format!("filters = \"{}\"\n", filters.join(" ")));
+        }
+        if let Some(palette) = &self.palette {
+            text.push_str(&format!("palette = \"{}\"\n", palette.display()));
+        }
+        if self.pacing == Pacing::Vrr {
+            text.push_str("pacing = \"vrr\"\n");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let mut video = VideoConfig::default();
+        assert_eq!(video.to_text(), "");
+        video.set("window", "-10, 80,768,720").unwrap();
+        video.set("fullscreen", "true").unwrap();
+        video.set("filters", "flash, ntsc").unwrap();
+        video.set("palette", "rp2c04.pal").unwrap();
+        video.set("pacing", "vrr").unwrap();
+        assert_eq!(video.window, Some(WindowGeometry { x: -10, y: 80, width: 768, height: 720 }));
+        assert_eq!(video.filters.as_deref(), Some(&["flash".to_string(), "ntsc".to_string()][..]));
+        assert_eq!(
+            video.to_text(),
+            "\n[video]\nwindow = \"-10,80,768,720\"\nfullscreen = \"true\"\nfilters = \"flash ntsc\"\npalette = \"rp2c04.pal\"\npacing = \"vrr\"\n"
+        );
+
+        assert!(video.set("window", "10,10,0,720").is_err());
+        assert!(video.set("window", "10,10").is_err());
+        assert!(video.set("window", "10,10,768,720,big").is_err());
+        assert!(video.set("window", "10,ten,10,768,720").is_err()); // not read as 10,10,768,720
+        assert!(video.set("fullscreen", "yes").is_err());
+        assert!(video.set("vsync", "true").is_err());
+        assert!(video.set("pacing", "adaptive").is_err());
+        video.set("pacing", "timer").unwrap();
+        assert_eq!(video.pacing, Pacing::Timer);
+        video.set("filters", "").unwrap();
+        assert_eq!(video.filters, Some(vec![])); // all off, which isn't the same as never saved
+        video.set("palette", "").unwrap();
+        assert_eq!(video.palette, None);
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::storage::Storage;
+
+// How loud the emulator plays, on top of the mixer's per-source volumes and channel mutes
+// (see crate::apu::mixer), which are about the game's sound rather than the player's room.
+// Two volumes, multiplied together:
+//
+//   - the master volume, for everything: kept in volume.txt in the current directory, next to
+//     input.toml;
+//   - the game's volume, for the games much louder than the rest (the expansion audio ones
+//     especially): remembered per game in a .volume file next to the ROM (game.nes ->
+//     game.volume), like its speed (see crate::speed).
+//
+// Muting silences everything without touching either, so unmuting brings back what was set.
+// It isn't remembered: a game that starts silent looks broken.
+
+pub const MAX_PERCENT: u32 = 100;
+pub const STEP_PERCENT: u32 = 10;
+pub const MASTER_PATH: &str = "volume.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Volume {
+    Master,
+    Game,
+}
+
+impl Volume {
+    // As the console and remote take them: "volume 50", "game-volume -1".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Volume::Master => "volume",
+            Volume::Game => "game-volume",
+        }
+    }
+}
+
+pub struct VolumeControl {
+    percents: [u32; 2], // by Volume
+    muted: bool,
+}
+
+impl VolumeControl {
+    pub fn new(master: u32, game: u32) -> Self {
+        VolumeControl { percents: [master.min(MAX_PERCENT), game.min(MAX_PERCENT)], muted: false }
+    }
+
+    pub fn percent(&self, volume: Volume) -> u32 {
+        self.percents[volume as usize]
+    }
+
+    // Sets `volume`, up to MAX_PERCENT, returns it.
+    pub fn set(&mut self, volume: Volume, percent: u32) -> u32 {
+        self.percents[volume as usize] = percent.min(MAX_PERCENT);
+        self.percents[volume as usize]
+    }
+
+    // Changes `volume` by `steps` STEP_PERCENTs (negative: quieter), returns it.
+    pub fn adjust(&mut self, volume: Volume, steps: i32) -> u32 {
+        // saturating: a console or remote can ask for any number of steps
+        let percent = (self.percent(volume) as i32).saturating_add(steps.saturating_mul(STEP_PERCENT as i32));
+        self.set(volume, percent.max(0) as u32)
+    }
+
+    // Returns whether it's muted now.
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.muted
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    // What the mixer's output is multiplied by (see Nes::set_master_volume).
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.percents.iter().map(|&percent| percent as f32 / 100.0).product()
+    }
+}
+
+pub fn game_path(rom_path: impl AsRef<Path>) -> PathBuf {
+    rom_path.as_ref().with_extension("volume")
+}
+
+// The saved volume, None if it was never changed.
+pub fn load(storage: &dyn Storage, path: impl AsRef<Path>) -> io::Result<Option<u32>> {
+    let Some(data) = storage.read(path.as_ref())? else {
+        return Ok(None);
+    };
+    let text = String::from_utf8_lossy(&data);
+    match text.trim().trim_end_matches('%').parse() {
+        Ok(percent) => Ok(Some(percent)),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("not a volume: '{}'", text.trim()))),
+    }
+}
+
+// Full volume is the default, so it removes the file instead of writing one.
+pub fn save(storage: &dyn Storage, path: impl AsRef<Path>, percent: u32) -> io::Result<()> {
+    if percent >= MAX_PERCENT {
+        return storage.remove(path.as_ref());
+    }
+    storage.write(path.as_ref(), format!("{}%\n", percent).as_bytes())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_volumes_and_mute() {
+        let mut volume = VolumeControl::new(80, 100);
+        assert_eq!(volume.gain(), 0.8);
+        assert_eq!(volume.adjust(Volume::Game, -5), 50);
+        assert!((volume.gain() - 0.4).abs() < 1e-6);
+        assert_eq!(volume.adjust(Volume::Master, 5), MAX_PERCENT);
+        assert_eq!(volume.adjust(Volume::Master, -20), 0);
+        assert_eq!(volume.adjust(Volume::Master, i32::MAX), MAX_PERCENT);
+        assert_eq!(volume.adjust(Volume::Master, i32::MIN), 0);
+        assert_eq!(volume.set(Volume::Master, 250), MAX_PERCENT);
+
+        assert!(volume.toggle_mute());
+        assert_eq!(volume.gain(), 0.0);
+        assert!(!volume.toggle_mute());
+        assert_eq!(volume.gain(), 0.5); // as it was
+    }
+
+    #[test]
+    fn test_saved() {
+        let storage = MemoryStorage::new();
+        let path = game_path("roms/game.nes");
+        assert_eq!(path, Path::new("roms/game.volume"));
+
+        assert_eq!(load(&storage, &path).unwrap(), None);
+        save(&storage, &path, 30).unwrap();
+        assert_eq!(load(&storage, &path).unwrap(), Some(30));
+        save(&storage, &path, 100).unwrap();
+        assert_eq!(storage.read(&path).unwrap(), None);
+    }
+}
@@ -0,0 +1,65 @@
+// The Nintendo Vs. System: arcade cabinets running NES games on NES hardware, plus a coin
+// slot, a service button and DIP switches for the operator (difficulty, lives, coins per
+// credit). Games find them in the unused bits of the controller ports:
+//
+//   $4016 read:  .CCD DS.B   B: player 1's controller, S: service button, DD: DIP switches 1-2,
+//                            CC: coin slots 1 and 2
+//   $4017 read:  DDDD DD.B   B: player 2's controller, DDDDDD: DIP switches 3-8
+//   $4016 write: .... .B.S   S: controller strobe, B: the cartridge's bank (see mapper::mapper99)
+//
+// A switch that is on reads as 1. The coin bits are held for as long as the frontend says a
+// coin is going in; games want to see them for a frame or more, like a real coin mechanism.
+// See: https://www.nesdev.org/wiki/Vs._System
+//
+// The Vs. System's PPUs mix their colours differently from the console's; the frontend shows
+// them with a palette file (see render::palette::from_pal_file).
+
+#[derive(Debug, Clone, Default)]
+pub struct VsPanel {
+    pub dip_switches: u8, // bit 0: switch 1 ... bit 7: switch 8
+    coins: [bool; 2],
+    service: bool,
+}
+
+impl VsPanel {
+    pub fn new(dip_switches: u8) -> Self {
+        VsPanel { dip_switches, ..Default::default() }
+    }
+
+    // `slot` is 0 or 1.
+    pub fn set_coin(&mut self, slot: usize, inserting: bool) {
+        self.coins[slot] = inserting;
+    }
+
+    pub fn set_service(&mut self, pressed: bool) {
+        self.service = pressed;
+    }
+
+    // The bits $4016 and $4017 reads get on top of the controller's.
+    pub fn bits_4016(&self) -> u8 {
+        (self.service as u8) << 2 | (self.dip_switches & 0b11) << 3 | (self.coins[0] as u8) << 5 | (self.coins[1] as u8) << 6
+    }
+
+    pub fn bits_4017(&self) -> u8 {
+        self.dip_switches & 0b1111_1100
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_panel_bits() {
+        let mut panel = VsPanel::new(0b1010_0110);
+        assert_eq!(panel.bits_4016(), 0b0001_0000); // switch 2
+        assert_eq!(panel.bits_4017(), 0b1010_0100);
+
+        panel.set_coin(0, true);
+        panel.set_service(true);
+        assert_eq!(panel.bits_4016(), 0b0011_0100);
+        panel.set_coin(0, false);
+        panel.set_coin(1, true);
+        assert_eq!(panel.bits_4016(), 0b0101_0100);
+    }
+}
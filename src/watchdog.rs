@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Notices when the frontend stops making progress: a filter or overlay that loops forever, a
+// lock that is never released, a debugger hook that takes minutes. Emulation, drawing and
+// event handling all run on the frontend's one thread (the Nes isn't Send), so the watching
+// happens on a thread of its own: the loop calls beat() as it moves from one part of its work
+// to the next, and when no beat comes for `timeout` the watchdog writes what the loop was
+// busy with to stderr. That thread can't touch the emulator, so the rest is up to the loop:
+// once it gets going again take_stall() says what happened, and the frontend asks the player
+// what to do about it. For unattended sessions, `kill_after` ends the process if the loop
+// never comes back.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stall {
+    pub activity: &'static str, // what the loop was doing, as given to beat()
+    pub duration: Duration,
+}
+
+struct Shared {
+    started: Instant,
+    last_beat: AtomicU64, // milliseconds after `started`
+    activity: Mutex<&'static str>,
+    stall: Mutex<Option<Stall>>, // the longest stall not yet taken
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+pub struct Watchdog {
+    shared: Arc<Shared>,
+}
+
+impl Watchdog {
+    pub fn start(timeout: Duration, kill_after: Option<Duration>) -> Self {
+        let shared = Arc::new(Shared {
+            started: Instant::now(),
+            last_beat: AtomicU64::new(0),
+            activity: Mutex::new("starting"),
+            stall: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+        });
+        let watched = shared.clone();
+        thread::spawn(move || watch(&watched, timeout, kill_after));
+        Watchdog { shared }
+    }
+
+    // The loop is alive and about to do `activity`.
+    pub fn beat(&self, activity: &'static str) {
+        *self.shared.activity.lock().unwrap() = activity;
+        self.shared.last_beat.store(self.shared.now(), Ordering::Relaxed);
+    }
+
+    // The longest stall since the last call, if there was one.
+    pub fn take_stall(&self) -> Option<Stall> {
+        self.shared.stall.lock().unwrap().take()
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+fn watch(shared: &Shared, timeout: Duration, kill_after: Option<Duration>) {
+    let mut reported_beat = None;
+    while !shared.stopped.load(Ordering::Relaxed) {
+        thread::sleep((timeout / 4).max(Duration::from_millis(10)));
+        let beat = shared.last_beat.load(Ordering::Relaxed);
+        let stalled_for = Duration::from_millis(shared.now().saturating_sub(beat));
+        if stalled_for < timeout {
+            continue;
+        }
+        let activity = *shared.activity.lock().unwrap();
+        if reported_beat != Some(beat) {
+            eprintln!("Watchdog: no progress for {:.1}s, stuck {}", stalled_for.as_secs_f32(), activity);
+            reported_beat = Some(beat);
+        }
+        let mut stall = shared.stall.lock().unwrap();
+        if stall.as_ref().is_none_or(|stall| stall.duration < stalled_for) {
+            *stall = Some(Stall { activity, duration: stalled_for });
+        }
+        if kill_after.is_some_and(|limit| stalled_for >= limit) {
+            eprintln!("Watchdog: giving up after {:.1}s, stuck {}", stalled_for.as_secs_f32(), activity);
+            std::process::exit(3);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_stall_is_noticed() {
+        let watchdog = Watchdog::start(Duration::from_millis(100), None);
+        watchdog.beat("running the game");
+        thread::sleep(Duration::from_millis(10));
+        watchdog.beat("drawing");
+        assert_eq!(watchdog.take_stall(), None);
+
+        thread::sleep(Duration::from_millis(400)); // stuck drawing
+        watchdog.beat("running the game");
+        let stall = watchdog.take_stall().unwrap();
+        assert_eq!(stall.activity, "drawing");
+        assert!(stall.duration >= Duration::from_millis(100));
+        assert_eq!(watchdog.take_stall(), None);
+    }
+}